@@ -0,0 +1,70 @@
+use std::ops::RangeInclusive;
+use std::sync::{Arc, RwLock};
+use crate::btree::node::Node;
+use crate::MVCC::visibility::{version_visible_as_of, with_versions};
+use crate::transactions::transactions::{Transaction, TransactionStatus};
+
+/// Why a read as of a past txid can't be answered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AsOfError {
+    /// Garbage collection already removed versions that were live at that point. Reads are possible from `horizon` on.
+    BeforeGcHorizon { horizon: u64 },
+    /// The transaction is still running or was aborted, so there's no committed state to read at it.
+    NotCommitted,
+}
+
+impl AsOfError {
+    /// The error line sent back to the client.
+    pub fn message(&self) -> String {
+        match self {
+            AsOfError::BeforeGcHorizon { horizon } => format!("FAILED: versions before txid {} were garbage collected", horizon),
+            AsOfError::NotCommitted => String::from("FAILED: transaction is not committed"),
+        }
+    }
+}
+
+/// Value of `key` in the state as of the committed txid `as_of`, see [`version_visible_as_of`].
+///
+/// `gc_horizon` is the oldest txid garbage collection kept every version for ([`crate::catalog::tables::Catalog::gc_horizon`]).
+/// Earlier points are refused rather than answered from a history with holes in it.
+pub fn select_as_of(node: Arc<RwLock<Node>>, key: u32, as_of: u64, gc_horizon: u64, transaction: Arc<RwLock<Transaction>>) -> Result<Option<String>, AsOfError> {
+    let tx = transaction.read().unwrap();
+    check_as_of(as_of, gc_horizon, &tx)?;
+
+    Ok(with_versions(&node, key, |versions| {
+        versions.iter()
+            .rev()
+            .find(|version| version_visible_as_of(version, as_of, &tx))
+            .map(|version| version.value.clone())
+    }).flatten())
+}
+
+/// Every key in `range` with its value as of the committed txid `as_of`, in key order. See [`select_as_of`].
+///
+/// The transaction table stays read-locked during the walk, before any tree latch as `btree/latch.rs` requires, so
+/// commits wait for the scan while other reads don't.
+pub fn scan_as_of(node: Arc<RwLock<Node>>, range: RangeInclusive<u32>, as_of: u64, gc_horizon: u64, transaction: Arc<RwLock<Transaction>>) -> Result<Vec<(u32, String)>, AsOfError> {
+    let tx = transaction.read().unwrap();
+    check_as_of(as_of, gc_horizon, &tx)?;
+
+    let mut found = Vec::new();
+    Node::for_each_item(&node, &mut |item| {
+        if !range.contains(&item.key) {
+            return;
+        }
+        if let Some(version) = item.version.iter().rev().find(|version| version_visible_as_of(version, as_of, &tx)) {
+            found.push((item.key, version.value.clone()));
+        }
+    });
+    Ok(found)
+}
+
+fn check_as_of(as_of: u64, gc_horizon: u64, tx: &Transaction) -> Result<(), AsOfError> {
+    if as_of < gc_horizon {
+        return Err(AsOfError::BeforeGcHorizon { horizon: gc_horizon });
+    }
+    match tx.items.get(&as_of) {
+        Some(item) if item.status != TransactionStatus::Committed => Err(AsOfError::NotCommitted),
+        _ => Ok(()),
+    }
+}
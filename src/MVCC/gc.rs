@@ -1,50 +1,276 @@
-use std::sync::{Arc, RwLock};
-use crate::btree::node::Node;
+use std::cmp::Reverse;
+use std::fmt;
+use std::sync::{Arc, RwLock, TryLockError};
+use std::thread;
+use crate::btree::node::{closed_versions, Items, Node};
+use crate::catalog::index::Index;
+use crate::MVCC::versions::Version;
 
-pub fn remove_dead_version(node: Arc<RwLock<Node>>, oldest_active_txd: u32) {
-    let mut stack = Vec::new();
+/// How long versions that are no longer live stay around for `select_as_of` and friends before vacuum reclaims them.
+///
+/// Versions some active transaction can still see are never reclaimed, whatever the policy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetentionPolicy {
+    /// Never reclaim anything.
+    KeepAll,
+    /// Keep versions closed by the last N transactions.
+    Transactions(u64),
+    /// Keep versions closed in the last T seconds.
+    Seconds(u64),
+    /// Keep the last K versions of every key, whenever they were closed.
+    LastVersions(usize),
+}
 
-    retain_active_txd(Arc::clone(&node), oldest_active_txd);
+/// Used until `retention` sets a policy.
+pub const DEFAULT_RETENTION: RetentionPolicy = RetentionPolicy::Transactions(1000);
 
-    let node_read = node.read().unwrap_or_else(|e| e.into_inner());
-    let root_children = &node_read.children;
-    for child in root_children {
-        stack.push(Arc::clone(child));
+impl RetentionPolicy {
+    /// Parses the arguments of the `retention` command, e.g. `["transactions", "100"]`.
+    pub fn parse(args: &[&str]) -> Option<RetentionPolicy> {
+        let amount = |arg: Option<&&str>| arg.and_then(|arg| arg.parse::<u64>().ok()).filter(|amount| *amount > 0);
+        match (args.first().map(|arg| arg.to_lowercase()).as_deref(), args.len()) {
+            (Some("keep_all"), 1) => Some(RetentionPolicy::KeepAll),
+            (Some("transactions"), 2) => amount(args.get(1)).map(RetentionPolicy::Transactions),
+            (Some("seconds"), 2) => amount(args.get(1)).map(RetentionPolicy::Seconds),
+            (Some("versions"), 2) => amount(args.get(1)).map(|k| RetentionPolicy::LastVersions(k as usize)),
+            _ => None,
+        }
     }
-    drop(node_read);
+}
+
+/// The same form [`RetentionPolicy::parse`] reads, used in the catalog file and in `retention`'s output.
+impl fmt::Display for RetentionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetentionPolicy::KeepAll => write!(f, "keep_all"),
+            RetentionPolicy::Transactions(n) => write!(f, "transactions {}", n),
+            RetentionPolicy::Seconds(t) => write!(f, "seconds {}", t),
+            RetentionPolicy::LastVersions(k) => write!(f, "versions {}", k),
+        }
+    }
+}
 
-    while let Some(node) = stack.pop() {
+/// What a vacuum pass over a table reclaimed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    pub versions_reclaimed: usize,
+    /// Keys whose every version was reclaimed, removed from the tree altogether.
+    pub keys_removed: usize,
+}
 
-        retain_active_txd(Arc::clone(&node), oldest_active_txd);
+impl GcStats {
+    pub fn add(&mut self, other: GcStats) {
+        self.versions_reclaimed += other.versions_reclaimed;
+        self.keys_removed += other.keys_removed;
+    }
+}
 
-        let node_read = node.read().unwrap_or_else(|e| e.into_inner());
+/// A version is dead once a transaction older than `horizon` closed it: no transaction from `horizon` on can see it.
+/// Rolled back versions (`xmin == xmax`) are closed by their own writer and die the same way.
+pub fn is_dead(version: &Version, horizon: u64) -> bool {
+    version.xmax.is_some_and(|xmax| xmax < horizon)
+}
+
+/// Reclaims dead versions from every key of `node`, see [`is_dead`].
+///
+/// # Working:
+/// - With `keep_last` set, only dead versions beyond the newest `keep_last` live-or-dead ones of a key are reclaimed,
+///   rolled back versions don't count towards them. Keys always keep at least one version then.
+/// - Otherwise every dead version is reclaimed. A key left without versions can't stay in the tree, so the newest
+///   one is kept at first and all such keys are removed together by [`Node::rebuild`] afterwards.
+/// - Versions are pruned one node at a time under that node's write latch, so readers and writers elsewhere in the
+///   tree carry on. Only key removal needs the whole tree for itself, and only when there is something to remove.
+/// - Entries of the table's `indexes` that only reclaimed versions had go with them, see [`Index::remove`].
+pub fn vacuum_table(node: &Arc<RwLock<Node>>, horizon: u64, keep_last: Option<usize>, indexes: &[Arc<Index>]) -> GcStats {
+    let mut stats = GcStats::default();
+    let mut fully_dead = 0;
+    prune_nodes(node, &mut |item| {
+        let (reclaimed, dead) = prune_item(item, horizon, keep_last, indexes);
+        stats.versions_reclaimed += reclaimed;
+        fully_dead += dead as usize;
+    });
 
-        if !node_read.children.is_empty() {
-            let current_children = &node_read.children;
-            for child in current_children {
-                stack.push(Arc::clone(child));
+    if fully_dead > 0 {
+        let mut reclaimed = 0;
+        stats.keys_removed = Node::rebuild(node, |item| {
+            let dead = remove_if_dead(item, horizon, indexes);
+            if dead {
+                reclaimed += item.version.len();
             }
+            !dead
+        });
+        stats.versions_reclaimed += reclaimed;
+    }
+    stats
+}
+
+/// Whether every version of `item` is dead, in which case its index entries are dropped as [`Node::rebuild`] is about
+/// to remove it.
+pub fn remove_if_dead(item: &Items, horizon: u64, indexes: &[Arc<Index>]) -> bool {
+    let dead = item.version.iter().all(|version| is_dead(version, horizon));
+    if dead {
+        let values: Vec<String> = item.version.iter().map(|version| version.value.clone()).collect();
+        for index in indexes.iter() {
+            index.remove(item.key, &values, &[]);
         }
     }
+    dead
 }
 
-pub fn retain_active_txd(node: Arc<RwLock<Node>>, oldest_active_txd: u32) {
-    let mut current_write = node.write().unwrap_or_else(|e| {
-        eprintln!("Error: {:?}", e);
-        e.into_inner()
-    });
-    for i in &mut current_write.input {
-        i.version.retain(|f| {
-            match f.xmax {
-                Some(x_max) => {
-                    if x_max < oldest_active_txd {
-                        return false;
-                    }
-                    return true;
-                }
+/// [`prune`]s the versions of `item`, then drops the entries of `indexes` that only its reclaimed versions had.
+fn prune_item(item: &mut Items, horizon: u64, keep_last: Option<usize>, indexes: &[Arc<Index>]) -> (usize, bool) {
+    if indexes.is_empty() {
+        return prune(&mut item.version, horizon, keep_last);
+    }
+    let values: Vec<String> = item.version.iter().map(|version| version.value.clone()).collect();
+    let (reclaimed, dead) = prune(&mut item.version, horizon, keep_last);
+    if reclaimed > 0 {
+        for index in indexes.iter() {
+            index.remove(item.key, &values, &item.version);
+        }
+    }
+    (reclaimed, dead)
+}
+
+/// Removes the reclaimable versions of one key and returns how many went, and whether every version was dead.
+fn prune(versions: &mut Vec<Version>, horizon: u64, keep_last: Option<usize>) -> (usize, bool) {
+    let before = versions.len();
 
-                None => true,
+    match keep_last {
+        Some(keep_last) => {
+            let mut kept = 0;
+            let mut keep = vec![true; versions.len()];
+            for (i, version) in versions.iter().enumerate().rev() {
+                let rolled_back = version.xmax == Some(version.xmin);
+                if rolled_back {
+                    keep[i] = !is_dead(version, horizon);
+                    continue;
+                }
+                kept += 1;
+                keep[i] = kept <= keep_last || !is_dead(version, horizon);
+            }
+            let mut keep = keep.into_iter();
+            versions.retain(|_| keep.next().unwrap());
+            (before - versions.len(), false)
+        }
+        None => {
+            if versions.iter().all(|version| is_dead(version, horizon)) {
+                let newest = versions.pop();
+                versions.clear();
+                versions.extend(newest);
+                return (before - versions.len(), true);
             }
-        })
+            versions.retain(|version| !is_dead(version, horizon));
+            (before - versions.len(), false)
+        }
+    }
+}
+
+/// Visits every item of the tree with its node write-latched, one node at a time.
+///
+/// A node's children are read under its latch and visited once it's released. A split running in between can move
+/// items into a new sibling that isn't visited, they're simply left for the next pass. Latches are never held two at
+/// a time, so this can't deadlock with anything following the lock order in `btree/latch.rs`.
+fn prune_nodes(node: &Arc<RwLock<Node>>, f: &mut impl FnMut(&mut Items)) {
+    let children = {
+        let mut node_write = node.write().unwrap_or_else(|e| e.into_inner());
+        for item in node_write.input.iter_mut() {
+            f(item);
+        }
+        node_write.dead = closed_versions(&node_write.input);
+        node_write.children.clone()
+    };
+
+    for child in children.iter() {
+        prune_nodes(child, f);
+    }
+}
+
+/// Nodes looked at per node vacuumed by [`vacuum_step`], the ones with the most dead versions among them go first.
+pub const SCAN_FACTOR: usize = 4;
+
+/// What one [`vacuum_step`] did.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StepStats {
+    pub reclaimed: GcStats,
+    /// Keys left with only dead versions, kept in the tree until the table is rebuilt, see [`vacuum_table`].
+    pub fully_dead: usize,
+    /// Where the next step resumes, [`None`] once the end of the table was reached.
+    pub next_key: Option<u32>,
+}
+
+/// Vacuums at most `budget` nodes of `node`, looking only at nodes whose first key is `from_key` or above.
+///
+/// # Working:
+/// - Nodes are visited in order of their first key, read-latched one at a time, until `budget * SCAN_FACTOR` were
+///   seen. Of those, the `budget` with the highest [`Node::dead`] are pruned like [`vacuum_table`] does and the rest
+///   wait for the next pass over the table. Nodes without closed versions are never write-latched.
+/// - A node that's latched by someone else is skipped rather than waited for, the next best one taking its place, and
+///   the thread yields after every node, so foreground traffic always goes first. Skipped nodes are picked up on the
+///   next pass over the table.
+/// - Fully dead keys keep their newest version, removing them needs [`Node::rebuild`], which is left to the caller.
+/// - Index entries are pruned along with the versions, as in [`vacuum_table`].
+pub fn vacuum_step(node: &Arc<RwLock<Node>>, from_key: u32, horizon: u64, keep_last: Option<usize>, budget: usize, indexes: &[Arc<Index>]) -> StepStats {
+    let window = budget.max(1) * SCAN_FACTOR;
+    let mut candidates = Vec::with_capacity(window);
+    collect_window(node, from_key, window, &mut candidates);
+
+    let mut step = StepStats::default();
+    if candidates.len() == window {
+        step.next_key = candidates.last().and_then(|(_, first_key, _)| first_key.checked_add(1));
     }
-}
\ No newline at end of file
+
+    candidates.sort_by_key(|(_, _, dead)| Reverse(*dead));
+    let mut vacuumed = 0;
+    for (candidate, _, dead) in candidates.into_iter() {
+        if dead == 0 || vacuumed == budget {
+            break;
+        }
+        let mut node_write = match candidate.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => continue,
+        };
+        for item in node_write.input.iter_mut() {
+            let (reclaimed, dead) = prune_item(item, horizon, keep_last, indexes);
+            step.reclaimed.versions_reclaimed += reclaimed;
+            step.fully_dead += dead as usize;
+        }
+        node_write.dead = closed_versions(&node_write.input);
+        drop(node_write);
+        vacuumed += 1;
+        thread::yield_now();
+    }
+    step
+}
+
+/// Collects up to `window` nodes below `node` whose first key is `from_key` or above, with their first key and
+/// [`Node::dead`], in order of first key.
+///
+/// A node's first key sits between its first child's keys and the rest of its children's, so visiting the first child,
+/// then the node, then the other children yields that order. Latches are never held two at a time, like `prune_nodes`.
+fn collect_window(node: &Arc<RwLock<Node>>, from_key: u32, window: usize, out: &mut Vec<(Arc<RwLock<Node>>, u32, usize)>) {
+    let (first_key, dead, children, upper_bounds) = {
+        let node_read = node.read().unwrap_or_else(|e| e.into_inner());
+        let Some(first) = node_read.input.first() else { return };
+        // Child `i` only holds keys below `input[i]`, the last one is unbounded.
+        let upper_bounds: Vec<Option<u32>> = (0..node_read.children.len()).map(|i| node_read.input.get(i).map(|item| item.key)).collect();
+        (first.key, node_read.dead, node_read.children.clone(), upper_bounds)
+    };
+
+    let wanted = |i: usize| upper_bounds[i].is_none_or(|upper| upper > from_key);
+    if let Some(first_child) = children.first() && wanted(0) {
+        collect_window(first_child, from_key, window, out);
+    }
+    if out.len() < window && first_key >= from_key {
+        out.push((Arc::clone(node), first_key, dead));
+    }
+    for (i, child) in children.iter().enumerate().skip(1) {
+        if out.len() >= window {
+            return;
+        }
+        if wanted(i) {
+            collect_window(child, from_key, window, out);
+        }
+    }
+}
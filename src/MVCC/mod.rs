@@ -1,4 +1,5 @@
-pub(crate) mod versions;
+pub mod versions;
 pub mod gc;
-pub(crate) mod visibility;
-pub mod snapshot;
\ No newline at end of file
+pub mod visibility;
+pub mod snapshot;
+pub mod as_of;pub mod range;
@@ -0,0 +1,71 @@
+use std::ops::{ControlFlow, RangeInclusive};
+use std::sync::{Arc, RwLock};
+use crate::btree::node::{Items, Node};
+use crate::MVCC::visibility::version_visible;
+use crate::transactions::transactions::Transaction;
+
+/// What `aggregate` computes over the keys visible to a transaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    Count,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    pub fn parse(name: &str) -> Option<Aggregate> {
+        match name.to_lowercase().as_str() {
+            "count" => Some(Aggregate::Count),
+            "min" => Some(Aggregate::Min),
+            "max" => Some(Aggregate::Max),
+            _ => None,
+        }
+    }
+
+    /// The line sent back to the client for `result`, as returned by [`aggregate_in`].
+    pub fn message(&self, result: Option<u64>) -> String {
+        match (self, result) {
+            (Aggregate::Count, result) => format!("Count: {}", result.unwrap_or(0)),
+            (_, None) => String::from("Key not found"),
+            (Aggregate::Min, Some(key)) => format!("Min: {}", key),
+            (Aggregate::Max, Some(key)) => format!("Max: {}", key),
+        }
+    }
+}
+
+/// `aggregate` over the keys in `ranges` visible to `txd`: how many there are, or the smallest or largest one if there's
+/// any.
+///
+/// # Working:
+/// - The tree is walked in key order with [`Node::for_each_item_in`], which only visits the subtrees overlapping
+///   `ranges`. Visibility is decided per key like `select` does, see [`version_visible`], and no value is copied.
+/// - `min` stops at the first visible key. `count` and `max` walk every key in `ranges`.
+/// - The transaction table stays read-locked for the whole walk, so every key is judged against the same table. Until
+///   it's done, `begin`, `commit` and `abort` wait for it: the narrower `ranges` are, the shorter that is.
+pub fn aggregate_in(node: &Arc<RwLock<Node>>, ranges: &[RangeInclusive<u32>], aggregate: Aggregate, txd: u64, transaction: &Arc<RwLock<Transaction>>) -> Option<u64> {
+    let mut count = 0;
+    let mut last = None;
+    for_each_visible(node, ranges, txd, transaction, |key| {
+        count += 1;
+        last = Some(key);
+        if aggregate == Aggregate::Min { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    });
+    match aggregate {
+        Aggregate::Count => Some(count),
+        Aggregate::Min | Aggregate::Max => last.map(u64::from),
+    }
+}
+
+/// Hands every key in `ranges` visible to `txd` to `f` in key order until it breaks. The transaction table stays
+/// read-locked during the whole walk, taken before any tree latch as `btree/latch.rs` requires.
+fn for_each_visible(node: &Arc<RwLock<Node>>, ranges: &[RangeInclusive<u32>], txd: u64, transaction: &Arc<RwLock<Transaction>>, mut f: impl FnMut(u32) -> ControlFlow<()>) {
+    let tx = transaction.read().unwrap();
+    for range in ranges {
+        let flow = Node::for_each_item_in(node, range, &mut |item: &Items| {
+            if item.version.iter().any(|version| version_visible(version, txd, &tx)) { f(item.key) } else { ControlFlow::Continue(()) }
+        });
+        if flow.is_break() {
+            return;
+        }
+    }
+}
@@ -1,14 +1,7 @@
 use std::sync::{Arc, RwLock};
-use std::sync::atomic::Ordering;
-use crate::btree::node::Node;
-use crate::LAST_ACTIVE_TXD;
-pub fn snapshot(node: Arc<RwLock<Node>>, required_xmax: Option<u32>) -> Arc<RwLock<Node>> {
-    let xmax_threshold = if let Some(xmax) = required_xmax {
-        xmax
-    } else {
-        LAST_ACTIVE_TXD.load(Ordering::SeqCst) as u32
-    };
-
+use crate::btree::node::{closed_versions, Node};
+use crate::MVCC::versions::VersionStatus;
+pub fn snapshot(node: Arc<RwLock<Node>>, xmax_threshold: u64) -> Arc<RwLock<Node>> {
     {
         let mut node_guard = node.write().unwrap();
 
@@ -45,7 +38,7 @@ pub fn snapshot(node: Arc<RwLock<Node>>, required_xmax: Option<u32>) -> Arc<RwLo
 
     for child_arc in &node_guard.children {
         let clone = Arc::clone(child_arc);
-        snapshot(clone, Some(xmax_threshold));
+        snapshot(clone, xmax_threshold);
     }
 
     drop(node_guard);
@@ -53,3 +46,26 @@ pub fn snapshot(node: Arc<RwLock<Node>>, required_xmax: Option<u32>) -> Arc<RwLo
     node
 }
 
+
+/// Aborts `txids` in a table loaded from a checkpoint image, for transactions that were still in progress when it was
+/// taken. Like an abort, the versions they wrote are closed by their own txid and the versions they closed reopened.
+pub fn roll_back(node: &Arc<RwLock<Node>>, txids: &[u64]) {
+    if txids.is_empty() {
+        return;
+    }
+    let mut node_guard = node.write().unwrap();
+    for input in node_guard.input.iter_mut() {
+        for version in input.version.iter_mut() {
+            if txids.contains(&version.xmin) {
+                version.version_status = VersionStatus::Abort;
+                version.xmax = Some(version.xmin);
+            } else if version.xmax.is_some_and(|xmax| txids.contains(&xmax)) {
+                version.xmax = None;
+            }
+        }
+    }
+    node_guard.dead = closed_versions(&node_guard.input);
+    for child in node_guard.children.iter() {
+        roll_back(child, txids);
+    }
+}
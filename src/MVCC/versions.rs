@@ -1,8 +1,8 @@
 #[derive(Debug, Clone, PartialEq, Ord, PartialOrd, Eq, Hash)]
 pub struct Version {
     pub value: String,
-    pub xmin: u32,
-    pub xmax: Option<u32>,
+    pub xmin: u64,
+    pub xmax: Option<u64>,
     pub version_status: VersionStatus,
 }
 
@@ -1,152 +1,147 @@
-use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::sync::{Arc, RwLock};
 use crate::btree::node::{Items, Node};
 use crate::MVCC::versions::{Version, VersionStatus};
 use crate::transactions::transactions::*;
 
-pub fn select_key(node: Arc<RwLock<Node>>, k: u32, last_txd: u32, current_txd: u32, status: Arc<RwLock<Transaction>>) -> Option<String> {
-    // HAck: Looks inefficient, redo it later
-    let mut result = Vec::new();
-    match fetch_items_for_key(node, k) {
-        Some(items) => {
-            println!("{:#?} \n  {:?}", items, last_txd);
-            result = items.version;
-        }
-        None => {}
-    }
-    if result.is_empty() {
-        return None;
-    }
+/// Returns the value of `k` visible to `current_txd` under snapshot isolation, see [`version_visible`].
+///
+/// The version chain is walked in place under the leaf's read latch and only the selected value is cloned. The
+/// transaction table is read-locked before the tree, following the lock order in `btree/latch.rs`, so concurrent
+/// selects only ever take read locks and never wait on each other.
+pub fn select_key(node: Arc<RwLock<Node>>, k: u32, current_txd: u64, status: Arc<RwLock<Transaction>>) -> Option<String> {
     let status_read_guard = status.read().unwrap();
 
-    let mut selected_keys = Vec::new();
-    for i in 0..result.iter().len() {
-        let result_max = result[i].xmax;
-        let result_min = result[i].xmin;
-
-        if let Some(result_xmax_now) = result_max {
-            if result_min == result_xmax_now {
-                continue;
-            }
-        }
+    with_versions(&node, k, |versions| {
+        versions.iter()
+            .rev()
+            .find(|version| version_visible(version, current_txd, &status_read_guard))
+            .map(|version| version.value.clone())
+    }).flatten()
+}
 
-        let min_status = status_read_guard.items.get(&result[i].xmin);
+/// Snapshot isolation visibility of a single version to `current_txd`.
+///
+/// Another transaction's effects are part of `current_txd`'s snapshot only if it has a smaller txid, wasn't in
+/// [`TransactionItems::snapshot`] (still active when `current_txd` began) and has committed.
+///
+/// - `xmin`: written by `current_txd` itself (or one of its savepoints' sub-transactions), or by a transaction in its snapshot. A version's own
+///   [`VersionStatus::Commit`] is trusted first, the transaction table covers the window before [`commit_abort_handler`] runs.
+/// - `xmax`: still open, closed by a transaction outside the snapshot, or never closed by `current_txd` itself (its own
+///   update or delete). An `xmax` whose transaction is gone from the table belongs to a committed transaction, as aborts
+///   reopen every version they closed.
+///
+/// Versions with `xmin == xmax` were rolled back (aborted or deleted by their own writer) and are never visible.
+pub fn version_visible(version: &Version, current_txd: u64, transaction: &Transaction) -> bool {
+    if version.xmax == Some(version.xmin) {
+        return false;
+    }
 
-        let (mut visible_xmax, mut visible_xmin) = (false, false);
+    let visible_xmin = own(version.xmin, current_txd, transaction)
+        || (in_snapshot(version.xmin, current_txd, transaction) && (version.version_status == VersionStatus::Commit || is_committed(version.xmin, transaction)));
 
-        match result_max {
-            Some(xmax) => {
-                if xmax >= last_txd {
-                    visible_xmax = true;
-                    // visible -- xmax >= current_txd_id
-                }
+    let visible_xmax = match version.xmax {
+        None => true,
+        Some(xmax) if own(xmax, current_txd, transaction) => false,
+        Some(xmax) => !(in_snapshot(xmax, current_txd, transaction) && is_committed(xmax, transaction)),
+    };
 
-                let max_status = status_read_guard.items.get(&xmax);
+    visible_xmin && visible_xmax
+}
 
-                if let Some(max_temp_status) = max_status {
-                    if let TransactionStatus::Active = max_temp_status.status {
-                        visible_xmax = true;
-                        // visible -- xmax == ACTIVE
-                    }
-                }
-            }
+/// Visibility of a single version in the state as of txid `as_of`: the effects of every committed transaction with a
+/// txid up to and including `as_of`, and nothing else.
+///
+/// Unlike [`version_visible`] no snapshot is involved. A transaction that was still running when `as_of` began but
+/// committed since counts as part of that state, the same way it would for a transaction beginning right after it.
+pub fn version_visible_as_of(version: &Version, as_of: u64, transaction: &Transaction) -> bool {
+    if version.xmax == Some(version.xmin) {
+        return false;
+    }
 
-            None => {
-                visible_xmax = true;
-                // visible -- xmax == None
+    let committed = |txd: u64| txd <= as_of && is_committed(txd, transaction);
+    let visible_xmin = version.xmin <= as_of && (version.version_status == VersionStatus::Commit || is_committed(version.xmin, transaction));
+    visible_xmin && !version.xmax.is_some_and(committed)
+}
 
-                if result_min == current_txd {
-                    return Some(result[i].value.clone());
-                }
-            }
-        }
+/// Snapshot isolation's first-updater-wins rule: `current_txd` may not write `key` if a transaction outside its
+/// snapshot has already committed a write (insert, update or delete) to it. Uncommitted writers are caught earlier by
+/// [`modified_key_check`].
+pub fn serialization_conflict(node: Arc<RwLock<Node>>, key: u32, current_txd: u64, transaction: Arc<RwLock<Transaction>>) -> bool {
+    let tx = transaction.read().unwrap();
+    has_serialization_conflict(&node, key, current_txd, &tx)
+}
 
-        if result_min == current_txd {
-            visible_xmin = true;
-            // visible -- min == current_txd_id
-        } else if result_min < current_txd {
+/// [`serialization_conflict`] for callers already holding the transaction table.
+pub fn has_serialization_conflict(node: &Arc<RwLock<Node>>, key: u32, current_txd: u64, tx: &Transaction) -> bool {
+    let concurrent_commit = |txd: u64| txd != current_txd && is_committed(txd, tx) && !in_snapshot(txd, current_txd, tx);
 
-            match min_status {
-                Some(min_temp_status) => {
-                    if matches!(min_temp_status.status, TransactionStatus::Committed) {
-                        visible_xmin = true;
-                    }
-                }
-                None => {
-                    println!("SHOULD BE HERE");
-                    visible_xmin = true;
-                }
+    with_versions(node, key, |versions| {
+        versions.iter().any(|version| {
+            if version.xmax == Some(version.xmin) {
+                return false;
             }
-        }
+            let committed_xmin = version.version_status == VersionStatus::Commit || is_committed(version.xmin, tx);
+            (committed_xmin && concurrent_commit(version.xmin)) || version.xmax.is_some_and(concurrent_commit)
+        })
+    }).unwrap_or(false)
+}
 
-        println!("Visible XMAX: {:?} ... Visible XMIN: {:?}", visible_xmax, visible_xmin);
+/// Whether `txd` is `current_txd` or the sub-transaction of one of its savepoints.
+fn own(txd: u64, current_txd: u64, transaction: &Transaction) -> bool {
+    txd == current_txd || transaction.items.get(&txd).is_some_and(|item| item.parent == Some(current_txd))
+}
 
-        if visible_xmax && visible_xmin {
-            selected_keys.push(result[i].value.clone());
-        }
-    }
+fn in_snapshot(txd: u64, current_txd: u64, transaction: &Transaction) -> bool {
+    let excluded = match transaction.items.get(&current_txd) {
+        Some(current) => current.snapshot.contains(&txd),
+        None => false,
+    };
+    txd < current_txd && !excluded
+}
 
-    if !selected_keys.is_empty() {
-        return Some(selected_keys.last().unwrap().clone());
+/// Finished transactions are dropped from the table when their client begins a new one, so a missing entry means committed.
+fn is_committed(txd: u64, transaction: &Transaction) -> bool {
+    match transaction.items.get(&txd) {
+        Some(item) => item.status == TransactionStatus::Committed,
+        None => true,
     }
-
-    None
 }
 
 /// Searches selected key from a pre-defined B-Tree. If found, returns [`Option::Some(Items)`].
-/// Iterative method was chosen rather than recursive as:
-/// - The stack based function holds one lock at a time, preventing deadlocks.
-/// - Prevents stack overflow and poor stability.
-/// - Easier integration of concurrency + persistence.
 ///
 /// # Working:
-/// - Pushes the pre-defined B-Tree into `stack: Vec<Arc<Mutex<Node>>>`.
-/// - Picks the last `Arc<Mutex<Node>>` from `stack`.
-/// - Using iteration, if any key from the picked node matches the selected key, the [`Items`] is returned.
-///     - [`Items`] is decently cheap to clone as its 32 bytes in size.
-/// - Pushes the selected node via key range comparison to `stack` and repeats till the key is found.
-/// - Returns a [`None`] if  the key isn't found.
+/// - Descends with [`Node::with_key`], which read-latches the child before releasing its parent (latch crabbing), so
+///   a concurrent split can't move the key out of the path being followed.
+/// - If the key is stored in the node it lands on, the [`Items`] is cloned out and returned.
+/// - Returns a [`None`] if the key isn't found.
 ///
 /// # Condition:
-/// - Every key is sorted by ascending as [`Node::sort_everything`] is invoked before invoking [`Node::fetch_versions_for_key`].
-/// - Nodes are locked depth first, left to right.
+/// - Readers never block each other, only a writer latching the same node does.
 /// - [`std::sync::PoisonError`] is a plausible error, handled temporarily by [`Result::unwrap_or_else`] assuming no corruption has occurred.
-/// - [`Rc<RefCell<T>>`] isn't preferred because they're not for concurrent access across multiple threads.
 /// - Root, Branch and Internal nodes, all of them will provide a valid result.
 ///
 /// # Examples
-/// ```rust
+/// ```ignore
 /// // Assume you have a B-tree with a key 1 on rank 2 with value "Woof".
 ///
-/// let result = Node::key_position(new_node.clone(), required_key);
-/// assert_eq!(result, Some(Items { key: 1, value: String::from("Woof"), rank: 2 }));
+/// let result = fetch_items_for_key(new_node.clone(), 1);
+/// assert_eq!(result.unwrap().key, 1);
 /// ```
 ///
-/// ```rust
+/// ```ignore
 /// // Assume you have a B-Tree without the entered key.
 ///
-/// let result = Node::key_position(new_node.clone(), required_key);
+/// let result = fetch_items_for_key(new_node.clone(), required_key);
 /// assert_eq!(result, None);
 /// ```
-///
-/// # TODO + WARNING:
-/// - THE SYSTEM CURRENTLY ISN'T CONCURRENT BUT IS CONCURRENCY IS THE NEXT FEATURE TO BE ADDED AFTER WRITE AHEAD LOGIN. PLEASE FORGIVE ME.
-/// - CASES WITH READER/WRITER COLLISION WILL BE HANDLED WITH REPLACEMENT OF MUTEX WITH RWLOCK, DEPENDING UPON NEED.
 fn fetch_items_for_key(node: Arc<RwLock<Node>>, key: u32) -> Option<Items> {
-    let mut stack = Vec::new();
-    stack.push(node);
-
-    while let Some(self_node) = stack.pop() {
-        let current = self_node.read().unwrap_or_else(|e| e.into_inner());
-
-        for i in 0..current.input.len() {
-            if current.input[i].key == key {
-                return Some(current.input[i].clone())
-            }
-        }
+    Node::with_key(&node, key, |node, position| position.ok().map(|i| node.input[i].clone()))
+}
 
-        push_to_stack(key, &mut stack, current);
-    }
-    None
+/// Runs `f` on the version chain of `key` while its node is read-latched, without cloning the chain.
+/// Returns [`None`] if the key isn't in the tree.
+pub fn with_versions<T>(node: &Arc<RwLock<Node>>, key: u32, f: impl FnOnce(&[Version]) -> T) -> Option<T> {
+    Node::with_key(node, key, |node, position| position.ok().map(|i| f(&node.input[i].version)))
 }
 
 pub fn fetch_version_vec_for_key(node: Arc<RwLock<Node>>, key:u32) -> Option<Vec<Version>> {
@@ -161,97 +156,89 @@ pub fn fetch_version_vec_for_key(node: Arc<RwLock<Node>>, key:u32) -> Option<Vec
      }
 }
 
-fn fetch_nodes_for_key(node: Arc<RwLock<Node>>, key: u32) -> (Option<Arc<RwLock<Node>>>, Option<usize>) {
-    let mut stack = Vec::new();
-    stack.push(node);
-
-    while let Some(self_node) = stack.pop() {
-        let current = self_node.read().unwrap_or_else(|e| e.into_inner());
-
-        for i in 0..current.input.len() {
-            if current.input[i].key == key {
-                drop(current);
-                return (Some(self_node), Some(i));
-            }
-        }
-
-        push_to_stack(key, &mut stack, current);
-    }
-    (None, None)
-}
-
-pub fn commit_abort_handler(node: Arc<RwLock<Node>>, key: u32, commit: bool ) {
-    match fetch_nodes_for_key(node, key) {
-        (Some(node), Some(i)) => {
+/// Resolves the versions of `key` written or closed by `txd` once the transaction commits or aborts.
+/// Only the node holding `key` is write-latched, see [`Node::with_key_mut`]. The versions closed for good are added to
+/// its [`Node::dead`].
+pub fn commit_abort_handler(node: Arc<RwLock<Node>>, key: u32, txd: u64, commit: bool ) {
+    Node::with_key_mut(&node, key, |node, position| match position {
+        Ok(i) => {
+            let versions = &mut node.input[i].version;
             if commit {
-                modify_committed_version(node, i);
+                modify_committed_version(versions, txd);
             } else {
-                modify_aborted_version(node, i);
+                modify_aborted_version(versions, txd);
             }
+            // What `txd` closed for good: the versions it replaced or deleted, or on abort its own.
+            node.dead += versions.iter()
+                .filter(|version| version.xmax == Some(txd) && (commit || version.xmin == txd))
+                .count();
         }
-        (None, None) => {
-            println!("No keys were modified in current transaction.");
-        }
-
-        _ => {},
-    }
+        Err(_) => println!("No keys were modified in current transaction."),
+    });
 }
 
-fn modify_committed_version(node:Arc<RwLock<Node>>, key_position: usize) {
-    let mut modified_version_index = Vec::new();
-
-    {
-        let ver_read_guard = &node.read().unwrap().input[key_position].version;
-        let ver_len = ver_read_guard.len();
-
-        for i in 0..ver_len {
-            if ver_read_guard[i].version_status == VersionStatus::Active {
-                modified_version_index.push(i);
-            }
+fn modify_committed_version(versions: &mut [Version], txd: u64) {
+    for version in versions.iter_mut() {
+        if version.version_status == VersionStatus::Active && version.xmin == txd {
+            version.version_status = VersionStatus::Commit;
         }
     }
+}
 
-    {
-        let ver_write_guard = &mut node.write().unwrap().input[key_position].version;
-
-        for i in modified_version_index {
-            ver_write_guard[i].version_status = VersionStatus::Commit;
+/// Rolls back `txd`'s writes on a key: its own versions become [`VersionStatus::Abort`] with `xmax = xmin`, and the
+/// versions it closed (updated or deleted) are reopened. Versions closed by other transactions are left untouched.
+fn modify_aborted_version(versions: &mut [Version], txd: u64) {
+    for version in versions.iter_mut() {
+        if version.version_status == VersionStatus::Active && version.xmin == txd {
+            version.version_status = VersionStatus::Abort;
+            version.xmax = Some(version.xmin);
+        } else if version.xmax == Some(txd) && version.xmin != txd {
+            version.xmax = None;
         }
     }
 }
 
-fn modify_aborted_version(node: Arc<RwLock<Node>>, key_position: usize) {
-    let ver_len ;
-    let mut modified_version_index = Vec::new();
-    {
-        let ver_read_guard = &node.read().unwrap().input[key_position].version;
-        ver_len = ver_read_guard.len();
-        for i in 0..ver_len {
-            if ver_read_guard[i].version_status == VersionStatus::Active {
-                modified_version_index.push(i);
+/// Hands the versions of `key` written or closed by the sub-transactions `subtxds` over to `into`, the enclosing
+/// savepoint's sub-transaction or the transaction itself, when a savepoint is released or the transaction ends.
+/// A version written and then replaced inside `into` ends up like one rewritten in place would: rolled back.
+pub fn merge_subtransactions(node: &Arc<RwLock<Node>>, key: u32, subtxds: &[u64], into: u64) {
+    Node::with_key_mut(node, key, |node, position| {
+        let Ok(i) = position else { return };
+        for version in node.input[i].version.iter_mut() {
+            if subtxds.contains(&version.xmin) {
+                version.xmin = into;
+            }
+            if version.xmax.is_some_and(|xmax| subtxds.contains(&xmax)) {
+                version.xmax = Some(into);
+            }
+            if version.xmin == into && version.xmax == Some(into) && version.version_status == VersionStatus::Active {
+                version.version_status = VersionStatus::Abort;
             }
         }
+    });
+}
 
-    }
-    let ver_write_guard = &mut node.write().unwrap().input[key_position].version;
-    {
-        for i in modified_version_index.iter().rev() {
-            ver_write_guard[*i].version_status = VersionStatus::Abort;
-            let xmin_aborted_versions = ver_write_guard[*i].xmin;
-            ver_write_guard[*i].xmax = Some(xmin_aborted_versions);
-        }
-    }
-
-    {
-        for i in (0..ver_len).rev() {
-            if ver_write_guard[i].version_status == VersionStatus::Commit {
-                ver_write_guard[i].xmax = None;
+/// `rollback to` for `key`: undoes the writes of the sub-transactions `subtxds` the way an abort undoes a
+/// transaction's, see [`modify_aborted_version`]. The versions rolled back are added to the node's [`Node::dead`].
+pub fn roll_back_subtransactions(node: &Arc<RwLock<Node>>, key: u32, subtxds: &[u64]) {
+    Node::with_key_mut(node, key, |node, position| {
+        let Ok(i) = position else { return };
+        let mut rolled_back = 0;
+        for version in node.input[i].version.iter_mut() {
+            if version.version_status == VersionStatus::Active && subtxds.contains(&version.xmin) {
+                version.version_status = VersionStatus::Abort;
+                version.xmax = Some(version.xmin);
+                rolled_back += 1;
+            } else if version.xmax.is_some_and(|xmax| subtxds.contains(&xmax)) && !subtxds.contains(&version.xmin) {
+                version.xmax = None;
             }
         }
-    }
+        node.dead += rolled_back;
+    });
 }
 
-pub fn modified_key_check(active_txd: Vec<u32>, new_key: u32, txd_of_key: u32, transaction: Arc<RwLock<Transaction>>) -> bool {
+/// Whether another active transaction already wrote `new_key` in `table` (first-writer-wins).
+pub fn modified_key_check(active_txd: Vec<u64>, table: &str, new_key: u32, txd_of_key: u64, transaction: Arc<RwLock<Transaction>>) -> bool {
     for i in active_txd {
         if txd_of_key == i { continue; } // Allows updating a key in the same txd of its first modification
 
@@ -259,8 +246,8 @@ pub fn modified_key_check(active_txd: Vec<u32>, new_key: u32, txd_of_key: u32, t
             Some(item) => {
                 let x = &item.modified_keys;
 
-                for j in x.iter() {
-                    if *j == new_key { return true; }
+                for (j_table, j) in x.iter() {
+                    if *j == new_key && j_table == table { return true; }
                 }
             }
             None => {  }
@@ -269,18 +256,12 @@ pub fn modified_key_check(active_txd: Vec<u32>, new_key: u32, txd_of_key: u32, t
     false
 }
 
-fn push_to_stack(key: u32, stack: &mut Vec<Arc<RwLock<Node>>>, current: RwLockReadGuard<Node>)-> () {
-    if !current.children.is_empty() {
-        if key < current.input[0].key {
-            stack.push(Arc::clone(&current.children[0]));
-        } else if key > current.input[current.input.len() - 1].key {
-            stack.push(Arc::clone(&current.children[current.children.len() - 1]));
-        } else {
-            for i in 0..current.input.len() - 1 {
-                if key > current.input[i].key && key < current.input[i + 1].key {
-                    stack.push(Arc::clone(&current.children[i + 1]));
-                }
-            }
-        }
-    }
-}
\ No newline at end of file
+/// [`modified_key_check`] for callers already holding the transaction table: whether an active transaction other than
+/// `current_txd` already wrote `key` in `table`.
+pub fn key_claimed(tx: &Transaction, table: &str, key: u32, current_txd: u64) -> bool {
+    tx.items.iter().any(|(txd, item)| {
+        *txd != current_txd
+            && item.status == TransactionStatus::Active
+            && item.modified_keys.iter().any(|(t, k)| *k == key && t == table)
+    })
+}
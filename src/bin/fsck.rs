@@ -0,0 +1,42 @@
+use std::env;
+use std::process::ExitCode;
+
+use ASMT::NODE_SIZE;
+use ASMT::btree::node::Node;
+
+// Usage: fsck <serialized_file_path> [node_size]
+//
+// Loads the checkpoint image the manifest points to (falling back like the server does) and prints the integrity
+// report as JSON lines. Exits with 0 when the tree is clean, 1 on violations and 2 when the image can't be loaded.
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: {} <serialized_file_path> [node_size]", args[0]);
+        return ExitCode::from(2);
+    }
+
+    let node_size = match args.get(2) {
+        Some(size) => match size.parse::<usize>() {
+            Ok(size) if size >= 4 => size,
+            _ => {
+                eprintln!("Invalid node size: {}", size);
+                return ExitCode::from(2);
+            }
+        },
+        None => 4,
+    };
+    NODE_SIZE.set(node_size).expect("Failed to set size");
+
+    let node = match Node::deserialize(&args[1]) {
+        Ok(node) => node,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", args[1], e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let report = Node::check(node);
+    println!("{}", report.to_json_lines());
+
+    if report.is_clean() { ExitCode::SUCCESS } else { ExitCode::from(1) }
+}
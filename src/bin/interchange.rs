@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::process::ExitCode;
+
+use ASMT::NODE_SIZE;
+use ASMT::btree::node::Node;
+use ASMT::catalog::tables::{valid_table_name, Catalog};
+use ASMT::engine::services::Services;
+use ASMT::storage::interchange::{export, read_records, records_to_items, Format};
+use ASMT::transactions::transactions::Transaction;
+
+// Usage: interchange export <serialized_file_path> <table> <jsonl|csv> [history]
+//        interchange import <serialized_file_path> <table> <input_file> <jsonl|csv>
+//
+// Works on the checkpoint images directly, with the server stopped. `export` streams a table to stdout, every key with
+// its committed value, or every committed version with `history`. `import` fills an empty (or new) table from a file
+// written by `export` and stores the catalog again. Exits with 0 on success, 1 when the data is rejected and 2 on
+// usage or I/O errors.
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    NODE_SIZE.set(4).expect("Failed to set size");
+
+    match args.get(1).map(String::as_str) {
+        Some("export") if args.len() == 5 || (args.len() == 6 && args[5] == "history") => {
+            let Some(format) = Format::parse(&args[4]) else { return usage(&args[0]) };
+            run_export(&args[2], &args[3], format, args.len() == 6)
+        }
+        Some("import") if args.len() == 6 => {
+            let Some(format) = Format::parse(&args[5]) else { return usage(&args[0]) };
+            run_import(&args[2], &args[3], &args[4], format)
+        }
+        _ => usage(&args[0]),
+    }
+}
+
+fn usage(program: &str) -> ExitCode {
+    eprintln!("Usage: {} export <serialized_file_path> <table> <jsonl|csv> [history]", program);
+    eprintln!("       {} import <serialized_file_path> <table> <input_file> <jsonl|csv>", program);
+    ExitCode::from(2)
+}
+
+fn run_export(serialized_file_path: &str, table: &str, format: Format, history: bool) -> ExitCode {
+    let catalog = match Catalog::load(serialized_file_path, &Services::default()) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", serialized_file_path, e);
+            return ExitCode::from(2);
+        }
+    };
+    let Some(node) = catalog.table(table) else {
+        eprintln!("Table not found: {}", table);
+        return ExitCode::from(1);
+    };
+
+    // No transaction is running, so reading as the highest txid sees every committed version.
+    let transaction = Transaction { items: HashMap::new(), ip_txd: HashMap::new() };
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    match export(&node, &mut out, format, history, u64::MAX, &transaction).and_then(|count| out.flush().map(|_| count)) {
+        Ok(count) => {
+            eprintln!("Exported {} records from {}", count, table);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Export failed: {}", e);
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run_import(serialized_file_path: &str, table: &str, input_file: &str, format: Format) -> ExitCode {
+    if !valid_table_name(table) {
+        eprintln!("Invalid table name: {}", table);
+        return ExitCode::from(1);
+    }
+
+    // The epoch, fence and txid reservation are read with the catalog and stored back unchanged.
+    let services = Services::default();
+    let mut catalog = match Catalog::load(serialized_file_path, &services) {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", serialized_file_path, e);
+            return ExitCode::from(2);
+        }
+    };
+    let records = match File::open(input_file).and_then(|input| read_records(BufReader::new(input), format)) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", input_file, e);
+            return ExitCode::from(1);
+        }
+    };
+
+    catalog.create_table(table);
+    let node = catalog.table(table).unwrap();
+    if !node.read().unwrap().input.is_empty() {
+        eprintln!("Table {} is not empty", table);
+        return ExitCode::from(1);
+    }
+
+    // Plain values are stamped with a txid above every one already stored, so they read as the newest committed write.
+    let mut highest_txd = 0;
+    for table_node in catalog.tables.values() {
+        Node::for_each_item(table_node, &mut |item| {
+            for version in item.version.iter() {
+                highest_txd = highest_txd.max(version.xmin).max(version.xmax.unwrap_or(0));
+            }
+        });
+    }
+
+    let count = records.len();
+    *node.write().unwrap() = Node::bulk_load_items(records_to_items(records, highest_txd + 1), 1.0);
+    for index in catalog.indexes_on(table) {
+        index.build(&node);
+    }
+
+    match catalog.store(serialized_file_path, &services) {
+        Ok(_) => {
+            eprintln!("Imported {} records into {}", count, table);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Failed to store {}: {}", serialized_file_path, e);
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// bulk_load, bulk_load_items, rebuild, build_level, node_sizes
+
+use std::sync::{Arc, RwLock};
+use crate::btree::node::{Items, Node};
+use crate::MVCC::versions::{Version, VersionStatus};
+use crate::NODE_SIZE;
+
+/// Fill factor of the leaves [`Node::rebuild`] writes, leaving some room so the next inserts don't split right away.
+pub const REBUILD_FILL_FACTOR: f64 = 0.75;
+
+impl Node {
+    /// Builds a packed, balanced tree bottom-up from `(key, value)` pairs sorted by ascending key, without going
+    /// through [`Node::insert`]. Duplicate keys must already be removed.
+    ///
+    /// Every key gets a single [`VersionStatus::Commit`] version written by `txd`.
+    ///
+    /// # Working:
+    /// - The leaf level is cut into nodes of about `fill_factor * NODE_SIZE` keys each, with one key between every two
+    ///   leaves set aside as the separator going up into their parent.
+    /// - The separators then form the next level up, cut the same way around the nodes just built, until the
+    ///   remaining keys fit in a single root.
+    /// - Keys are spread evenly over the nodes of a level so that no node, including the last one, ever falls under
+    ///   `NODE_SIZE/2` keys, which [`Node::check`] would report as an underflow.
+    ///
+    /// `fill_factor` is clamped to `(0, 1]`, a low fill factor leaves room for later inserts before leaves start splitting.
+    ///
+    /// Panics if static `NODE_SIZE` is uninitialized.
+    pub fn bulk_load(pairs: impl IntoIterator<Item = (u32, String)>, fill_factor: f64, txd: u64) -> Node {
+        let items: Vec<Items> = pairs.into_iter()
+            .map(|(key, value)| Items { key, rank: 1, version: vec![Version { value, xmin: txd, xmax: None, version_status: VersionStatus::Commit }] })
+            .collect();
+        Node::bulk_load_items(items, fill_factor)
+    }
+
+    /// [`Node::bulk_load`] for items that already carry their versions, e.g. an imported version history.
+    /// `items` must be sorted by ascending key without duplicates.
+    pub fn bulk_load_items(items: Vec<Items>, fill_factor: f64) -> Node {
+        let node_size = *NODE_SIZE.get().unwrap();
+        let fill = (fill_factor.clamp(f64::MIN_POSITIVE, 1.0) * node_size as f64).round() as usize;
+        let target = fill.clamp((node_size / 2).max(1), node_size);
+
+        let mut level = (items, Vec::new());
+        loop {
+            let (items, children) = level;
+            if items.len() <= node_size {
+                let mut root = Node::with_items(items, 1, children);
+                Node::rank_correction(&mut root);
+                return root;
+            }
+            level = build_level(items, children, target, node_size);
+        }
+    }
+}
+
+impl Node {
+    /// Rebuilds the tree rooted at `root` in place with only the items `keep` returns true for, and returns how many
+    /// were dropped. Nothing is rebuilt if every item is kept.
+    ///
+    /// # Working:
+    /// - The root is write-latched for the whole rebuild, so nobody new enters the tree.
+    /// - Every other node is then write-latched top-down while its items are collected. Each latch waits for whoever
+    ///   is still inside that subtree, and once a subtree was collected nobody can be left in it, so the collected items
+    ///   are final.
+    /// - The kept items are bulk-loaded at [`REBUILD_FILL_FACTOR`] and the new tree replaces the root's contents, which
+    ///   keeps the `Arc` everyone holds to the table valid.
+    pub fn rebuild(root: &Arc<RwLock<Node>>, keep: impl FnMut(&mut Items) -> bool) -> usize {
+        let mut keep = keep;
+        let mut root_write = root.write().unwrap_or_else(|e| e.into_inner());
+
+        let mut items = Vec::new();
+        collect_exclusive(&root_write, &mut items);
+        let before = items.len();
+        items.retain_mut(|item| keep(item));
+
+        let dropped = before - items.len();
+        if dropped > 0 {
+            *root_write = Node::bulk_load_items(items, REBUILD_FILL_FACTOR);
+        }
+        dropped
+    }
+}
+
+/// Clones every item below `node` in key order, write-latching each child while its subtree is collected.
+fn collect_exclusive(node: &Node, items: &mut Vec<Items>) {
+    for (i, item) in node.input.iter().enumerate() {
+        if let Some(child) = node.children.get(i) {
+            collect_exclusive(&child.write().unwrap_or_else(|e| e.into_inner()), items);
+        }
+        items.push(item.clone());
+    }
+    if let Some(last) = node.children.get(node.input.len()) {
+        collect_exclusive(&last.write().unwrap_or_else(|e| e.into_inner()), items);
+    }
+}
+
+/// Cuts one level into nodes, returning the separators and nodes that make up the level above it.
+/// `children` is empty for the leaf level and holds `items.len() + 1` nodes otherwise.
+fn build_level(items: Vec<Items>, children: Vec<Arc<RwLock<Node>>>, target: usize, node_size: usize) -> (Vec<Items>, Vec<Arc<RwLock<Node>>>) {
+    let sizes = node_sizes(items.len(), target, node_size);
+    let leaf_level = children.is_empty();
+
+    let mut items = items.into_iter();
+    let mut children = children.into_iter();
+    let mut separators = Vec::with_capacity(sizes.len() - 1);
+    let mut nodes = Vec::with_capacity(sizes.len());
+
+    for (i, size) in sizes.iter().enumerate() {
+        let input: Vec<Items> = items.by_ref().take(*size).collect();
+        let node_children: Vec<Arc<RwLock<Node>>> = if leaf_level { Vec::new() } else { children.by_ref().take(size + 1).collect() };
+        nodes.push(Arc::new(RwLock::new(Node::with_items(input, 1, node_children))));
+
+        if i + 1 < sizes.len() {
+            separators.push(items.next().unwrap());
+        }
+    }
+    (separators, nodes)
+}
+
+/// Number of keys in each node when `n` keys are cut into nodes of about `target` keys, one key between every two
+/// nodes going up as a separator. The node count is chosen so that every node ends up with between `node_size / 2`
+/// and `node_size` keys, and the keys are spread evenly across them.
+fn node_sizes(n: usize, target: usize, node_size: usize) -> Vec<usize> {
+    let min_keys = (node_size / 2).max(1);
+    let most_nodes = (n + 1) / (min_keys + 1);
+    let nodes = (n + 1).div_ceil(target + 1).min(most_nodes).max(1);
+
+    let keys = n + 1 - nodes;
+    let (base, extra) = (keys / nodes, keys % nodes);
+    (0..nodes).map(|i| if i < extra { base + 1 } else { base }).collect()
+}
@@ -0,0 +1,258 @@
+// check, CheckReport, Violation
+
+use std::sync::{Arc, RwLock};
+use crate::btree::node::Node;
+use crate::MVCC::versions::{Version, VersionStatus};
+use crate::NODE_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationKind {
+    KeyOrder,
+    SeparatorBounds,
+    ChildCount,
+    LeafDepth,
+    Rank,
+    FanOut,
+    Underflow,
+    EmptyVersionChain,
+    XminAfterXmax,
+    OverlappingVersions,
+    MultipleActiveVersions,
+}
+
+impl ViolationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ViolationKind::KeyOrder => "key_order",
+            ViolationKind::SeparatorBounds => "separator_bounds",
+            ViolationKind::ChildCount => "child_count",
+            ViolationKind::LeafDepth => "leaf_depth",
+            ViolationKind::Rank => "rank",
+            ViolationKind::FanOut => "fan_out",
+            ViolationKind::Underflow => "underflow",
+            ViolationKind::EmptyVersionChain => "empty_version_chain",
+            ViolationKind::XminAfterXmax => "xmin_after_xmax",
+            ViolationKind::OverlappingVersions => "overlapping_versions",
+            ViolationKind::MultipleActiveVersions => "multiple_active_versions",
+        }
+    }
+}
+
+/// A single broken invariant.
+/// `path` is the list of child indices from the root to the offending node, so `[]` is the root and `[2, 0]` is the
+/// first child of the root's third child.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    pub path: Vec<usize>,
+    pub key: Option<u32>,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CheckReport {
+    pub nodes_checked: usize,
+    pub keys_checked: usize,
+    pub versions_checked: usize,
+    pub violations: Vec<Violation>,
+}
+
+impl CheckReport {
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty()
+    }
+
+    /// One JSON object per violation followed by a summary object, e.g.
+    /// ```text
+    /// {"kind":"separator_bounds","path":[1],"key":12,"detail":"key 12 outside (5, 9)"}
+    /// {"summary":true,"nodes":4,"keys":11,"versions":13,"violations":1}
+    /// ```
+    pub fn to_json_lines(&self) -> String {
+        let mut lines = Vec::new();
+        for violation in &self.violations {
+            let path: Vec<String> = violation.path.iter().map(|p| p.to_string()).collect();
+            let key = match violation.key {
+                Some(k) => k.to_string(),
+                None => "null".to_string(),
+            };
+            lines.push(format!(
+                "{{\"kind\":\"{}\",\"path\":[{}],\"key\":{},\"detail\":\"{}\"}}",
+                violation.kind.as_str(),
+                path.join(","),
+                key,
+                escape_json(&violation.detail)
+            ));
+        }
+        lines.push(format!(
+            "{{\"summary\":true,\"nodes\":{},\"keys\":{},\"versions\":{},\"violations\":{}}}",
+            self.nodes_checked,
+            self.keys_checked,
+            self.versions_checked,
+            self.violations.len()
+        ));
+        lines.join("\n")
+    }
+
+    fn report(&mut self, kind: ViolationKind, path: &[usize], key: Option<u32>, detail: String) {
+        self.violations.push(Violation { kind, path: path.to_vec(), key, detail });
+    }
+}
+
+impl Node {
+    /// Validates the structural B-Tree invariants and the per-key MVCC invariants without modifying anything.
+    ///
+    /// Unlike [`Node::validate_after_mutation`], nothing is repaired, every violation is reported as a [`Violation`].
+    ///
+    /// # Structural checks:
+    /// - Keys inside a node are strictly ascending.
+    /// - Every key of a child lies between the parent's separators surrounding it.
+    /// - Internal nodes have `children.len() == input.len() + 1`.
+    /// - Every leaf sits at the same depth.
+    /// - `Node.rank` and `Items.rank` equal the node's depth (root is rank 1).
+    /// - No node holds more than `NODE_SIZE` keys, and no non-root node holds fewer than `NODE_SIZE/2`.
+    ///
+    /// # MVCC checks:
+    /// - Every key has at least one version.
+    /// - `xmin <= xmax` for every closed version.
+    /// - The `[xmin, xmax)` lifetimes of non-aborted versions don't overlap, i.e. at most one version is visible at any txid.
+    /// - At most one version per key is still [`VersionStatus::Active`].
+    ///
+    /// Read locks are taken one node at a time, top-down. A tree under concurrent mutation can therefore produce
+    /// transient violations, run it on a quiescent tree for an authoritative report.
+    pub fn check(node: Arc<RwLock<Node>>) -> CheckReport {
+        let mut report = CheckReport::default();
+        let mut leaf_depth = None;
+        check_node(node, &mut Vec::new(), None, None, &mut leaf_depth, &mut report);
+        report
+    }
+}
+
+fn check_node(node: Arc<RwLock<Node>>, path: &mut Vec<usize>, lower: Option<u32>, upper: Option<u32>, leaf_depth: &mut Option<usize>, report: &mut CheckReport) {
+    let node_read = node.read().unwrap_or_else(|e| e.into_inner());
+    let depth = path.len() + 1;
+    let node_size = *NODE_SIZE.get().unwrap();
+
+    report.nodes_checked += 1;
+
+    if node_read.rank as usize != depth {
+        report.report(ViolationKind::Rank, path, None, format!("node rank {} at depth {}", node_read.rank, depth));
+    }
+
+    if node_read.input.len() > node_size {
+        report.report(ViolationKind::FanOut, path, None, format!("{} keys exceed NODE_SIZE {}", node_read.input.len(), node_size));
+    }
+    if node_read.children.len() > node_size + 1 {
+        report.report(ViolationKind::FanOut, path, None, format!("{} children exceed NODE_SIZE + 1", node_read.children.len()));
+    }
+    if depth > 1 && node_read.input.len() < node_size / 2 {
+        report.report(ViolationKind::Underflow, path, None, format!("{} keys below minimum {}", node_read.input.len(), node_size / 2));
+    }
+
+    for (i, item) in node_read.input.iter().enumerate() {
+        report.keys_checked += 1;
+
+        if i > 0 && node_read.input[i - 1].key >= item.key {
+            report.report(ViolationKind::KeyOrder, path, Some(item.key), format!("key {} follows key {}", item.key, node_read.input[i - 1].key));
+        }
+
+        let below_lower = lower.is_some_and(|l| item.key <= l);
+        let above_upper = upper.is_some_and(|u| item.key >= u);
+        if below_lower || above_upper {
+            report.report(ViolationKind::SeparatorBounds, path, Some(item.key), format!("key {} outside ({}, {})", item.key, bound_str(lower.map(u64::from)), bound_str(upper.map(u64::from))));
+        }
+
+        if item.rank != node_read.rank {
+            report.report(ViolationKind::Rank, path, Some(item.key), format!("item rank {} in node of rank {}", item.rank, node_read.rank));
+        }
+
+        check_versions(item.key, &item.version, path, report);
+    }
+
+    if node_read.children.is_empty() {
+        match leaf_depth {
+            Some(expected) if *expected != depth => {
+                report.report(ViolationKind::LeafDepth, path, None, format!("leaf at depth {}, expected {}", depth, expected));
+            }
+            Some(_) => {}
+            None => *leaf_depth = Some(depth),
+        }
+        return;
+    }
+
+    if node_read.children.len() != node_read.input.len() + 1 {
+        report.report(ViolationKind::ChildCount, path, None, format!("{} children for {} keys", node_read.children.len(), node_read.input.len()));
+    }
+
+    let children = node_read.children.clone();
+    let separators: Vec<u32> = node_read.input.iter().map(|item| item.key).collect();
+    drop(node_read);
+
+    for (i, child) in children.into_iter().enumerate() {
+        let child_lower = if i == 0 { lower } else { separators.get(i - 1).or(separators.last()).copied() };
+        let child_upper = separators.get(i).copied().or(upper);
+
+        path.push(i);
+        check_node(child, path, child_lower, child_upper, leaf_depth, report);
+        path.pop();
+    }
+}
+
+fn check_versions(key: u32, versions: &[Version], path: &[usize], report: &mut CheckReport) {
+    report.versions_checked += versions.len();
+
+    if versions.is_empty() {
+        report.report(ViolationKind::EmptyVersionChain, path, Some(key), format!("key {} has no versions", key));
+        return;
+    }
+
+    let active = versions.iter().filter(|v| v.version_status == VersionStatus::Active).count();
+    if active > 1 {
+        report.report(ViolationKind::MultipleActiveVersions, path, Some(key), format!("key {} has {} active versions", key, active));
+    }
+
+    let mut lifetimes = Vec::new();
+    for version in versions {
+        if let Some(xmax) = version.xmax {
+            if version.xmin > xmax {
+                report.report(ViolationKind::XminAfterXmax, path, Some(key), format!("xmin {} > xmax {}", version.xmin, xmax));
+            }
+            // Aborted and deleted versions are closed by their own txid and are never visible.
+            if version.xmin == xmax {
+                continue;
+            }
+        }
+        if version.version_status != VersionStatus::Abort {
+            lifetimes.push((version.xmin, version.xmax.unwrap_or(u64::MAX)));
+        }
+    }
+
+    lifetimes.sort();
+    for pair in lifetimes.windows(2) {
+        if pair[0].1 > pair[1].0 {
+            report.report(ViolationKind::OverlappingVersions, path, Some(key), format!("[{}, {}) overlaps [{}, {})", pair[0].0, bound_str(Some(pair[0].1)), pair[1].0, bound_str(Some(pair[1].1))));
+        }
+    }
+}
+
+fn bound_str(bound: Option<u64>) -> String {
+    match bound {
+        Some(u64::MAX) | None => "∞".to_string(),
+        Some(b) => b.to_string(),
+    }
+}
+
+fn escape_json(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,131 @@
+// with_key, with_key_mut, for_each_item, for_each_item_in
+
+use std::ops::{ControlFlow, RangeInclusive};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use crate::btree::node::{Items, Node};
+
+// Lock order, followed by every function touching the tree:
+// - The vacuum state (`engine/vacuum.rs`), then `all_addr`, then `txd_count`, then the `Catalog`, then the `Transaction`
+//   table, then tree latches, then the change log (`engine/cdc.rs`), then the WAL file, then the WAL shipper
+//   (`replication/shipper.rs`). A tree latch is never held while acquiring the `Transaction` table or the `Catalog`.
+// - Tree latches are acquired top-down (parent before child) and left to right between siblings, never the other way.
+// - Readers crab with read latches: the child is latched before the parent is released, so a split (which needs the
+//   parent's write latch) can never move a key out from under a reader between two levels.
+// - Writers crab with read latches too and only write-latch the node they modify. A split write-latches the parent and
+//   then the child being split, which keeps it ordered with everyone else.
+// - Secondary index entries are locked last and never held while acquiring anything else.
+// - Structure modifications that can reach the root (a split propagating up, `validate_after_mutation`) write-latch the
+//   root first and hold the path they modify, so at most one of them runs at a time.
+// - `Node::rebuild` holds the root's write latch throughout and write-latches every other node top-down, waiting out
+//   whoever is still inside the tree before replacing it.
+
+impl Node {
+    /// Position of `key` in `input`: `Ok(i)` if it's stored in this node, otherwise `Err(i)` with `i` being both the
+    /// sorted insertion point and the index of the child whose range covers `key`.
+    pub fn locate(&self, key: u32) -> Result<usize, usize> {
+        self.input.binary_search_by_key(&key, |item| item.key)
+    }
+
+    /// Read-latch coupled descent from `root` towards `key`.
+    ///
+    /// `f` is called with the node holding `key` (`Ok(i)`), or the leaf where it would be inserted (`Err(i)`), while
+    /// that node is read-latched. Readers never block each other and at most two latches (parent and child) are held.
+    pub fn with_key<T>(root: &Arc<RwLock<Node>>, key: u32, f: impl FnOnce(&Node, Result<usize, usize>) -> T) -> T {
+        let guard = root.read().unwrap_or_else(|e| e.into_inner());
+        descend(guard, key, f)
+    }
+
+    /// Like [`Node::with_key`], but the node handed to `f` is write-latched.
+    ///
+    /// # Working:
+    /// - The path is crabbed down with read latches, so concurrent readers are never blocked by inner nodes.
+    /// - Once the target is found its read latch is swapped for a write latch while the parent's read latch is still
+    ///   held. Splitting a node requires its parent's write latch, so the target can't lose `key` during the swap.
+    /// - The root has no parent to pin it. If it was split during the swap the descent simply restarts.
+    pub fn with_key_mut<T, F>(root: &Arc<RwLock<Node>>, key: u32, f: F) -> T
+    where
+        F: FnOnce(&mut Node, Result<usize, usize>) -> T,
+    {
+        let mut f = f;
+        loop {
+            let guard = root.read().unwrap_or_else(|e| e.into_inner());
+            match descend_mut(root, guard, None, key, f) {
+                Ok(result) => return result,
+                Err(returned) => f = returned,
+            }
+        }
+    }
+
+    /// Visits every item in key order. Each node stays read-latched while its subtree is visited, so the latches
+    /// held are always the path from the root, taken top-down.
+    pub fn for_each_item(node: &Arc<RwLock<Node>>, f: &mut impl FnMut(&Items)) {
+        let guard = node.read().unwrap_or_else(|e| e.into_inner());
+        for (i, item) in guard.input.iter().enumerate() {
+            if let Some(child) = guard.children.get(i) {
+                Node::for_each_item(child, f);
+            }
+            f(item);
+        }
+        if let Some(last) = guard.children.get(guard.input.len()) {
+            Node::for_each_item(last, f);
+        }
+    }
+
+    /// [`Node::for_each_item`] for the items with a key in `range` only, skipping the subtrees outside of it. The walk
+    /// stops as soon as `f` breaks, which is returned.
+    pub fn for_each_item_in(node: &Arc<RwLock<Node>>, range: &RangeInclusive<u32>, f: &mut impl FnMut(&Items) -> ControlFlow<()>) -> ControlFlow<()> {
+        let guard = node.read().unwrap_or_else(|e| e.into_inner());
+        for (i, item) in guard.input.iter().enumerate() {
+            if item.key > *range.start() && let Some(child) = guard.children.get(i) {
+                Node::for_each_item_in(child, range, f)?;
+            }
+            if item.key > *range.end() {
+                return ControlFlow::Continue(());
+            }
+            if range.contains(&item.key) {
+                f(item)?;
+            }
+        }
+        if guard.input.last().is_none_or(|item| item.key < *range.end()) && let Some(last) = guard.children.get(guard.input.len()) {
+            Node::for_each_item_in(last, range, f)?;
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+fn descend<T>(guard: RwLockReadGuard<Node>, key: u32, f: impl FnOnce(&Node, Result<usize, usize>) -> T) -> T {
+    let position = guard.locate(key);
+    let child = match position {
+        Err(i) if !guard.children.is_empty() => Arc::clone(&guard.children[i]),
+        _ => return f(&guard, position),
+    };
+
+    let child_guard = child.read().unwrap_or_else(|e| e.into_inner());
+    drop(guard);
+    descend(child_guard, key, f)
+}
+
+/// Returns `f` back if the target was restructured between releasing its read latch and acquiring its write latch.
+fn descend_mut<T, F>(node: &Arc<RwLock<Node>>, guard: RwLockReadGuard<Node>, parent: Option<RwLockReadGuard<Node>>, key: u32, f: F) -> Result<T, F>
+where
+    F: FnOnce(&mut Node, Result<usize, usize>) -> T,
+{
+    let child = match guard.locate(key) {
+        Err(i) if !guard.children.is_empty() => Arc::clone(&guard.children[i]),
+        _ => {
+            drop(guard);
+            let mut write_guard = node.write().unwrap_or_else(|e| e.into_inner());
+            drop(parent);
+
+            let position = write_guard.locate(key);
+            if position.is_err() && !write_guard.children.is_empty() {
+                return Err(f);
+            }
+            return Ok(f(&mut write_guard, position));
+        }
+    };
+
+    drop(parent);
+    let child_guard = child.read().unwrap_or_else(|e| e.into_inner());
+    descend_mut(&child, child_guard, Some(guard), key, f)
+}
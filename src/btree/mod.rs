@@ -4,4 +4,7 @@ pub mod sort;
 pub mod ops;
 pub mod validation;
 pub mod scan;
-pub mod repair;
\ No newline at end of file
+pub mod repair;
+pub mod check;
+pub mod latch;
+pub mod bulk;
\ No newline at end of file
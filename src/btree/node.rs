@@ -13,6 +13,9 @@ pub struct Node {
     pub input: Vec<Items>,
     pub rank: u32,
     pub children: Vec<Arc<RwLock<Node>>>,
+    /// Versions in `input` closed by a finished transaction, i.e. what vacuum may reclaim here once the horizon passes.
+    /// Only a hint for picking nodes worth vacuuming: bumped as transactions finish, recounted by vacuum and splits.
+    pub dead: usize,
 }
 
 impl Node {
@@ -33,7 +36,19 @@ impl Node {
             input: Vec::new(),
             rank: 1,
             children: Vec::new(),
+            dead: 0,
         }));
         instance
     }
+
+    /// A node holding `input`, with [`Node::dead`] counted from it.
+    pub fn with_items(input: Vec<Items>, rank: u32, children: Vec<Arc<RwLock<Node>>>) -> Node {
+        let dead = closed_versions(&input);
+        Node { input, rank, children, dead }
+    }
+}
+
+/// Versions in `input` that are closed, the exact value of [`Node::dead`].
+pub fn closed_versions(input: &[Items]) -> usize {
+    input.iter().map(|item| item.version.iter().filter(|version| version.xmax.is_some()).count()).sum()
 }
\ No newline at end of file
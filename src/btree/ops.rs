@@ -2,130 +2,179 @@ use std::io;
 use std::sync::{Arc, RwLock};
 use crate::btree::node::{Items, Node};
 use crate::MVCC::versions::{Version, VersionStatus};
+use crate::NODE_SIZE;
 
 impl Node {
-    pub fn insert(self_node: Arc<RwLock<Node>>, k: u32, v: String, txn: u32) -> io::Result<()> {
-        {
-            let ver = Version { value: v.clone(), xmin: txn, xmax: None, version_status: VersionStatus::Active };
-            match Node::find_and_update_key_version(Arc::clone(&self_node), k, Some(v), txn, false) {
-                Some(_) => {
-                    println!("Key already exists");
-                    return Ok(());
-                }
-                None => {
-                    let version = vec![ver.clone()];
-                    Node::add_new_keys(Arc::clone(&self_node), Items { key: k, rank: 1, version }, );
-                }
+    /// Inserts `k` with a new [`VersionStatus::Active`] version written by `txn`.
+    ///
+    /// # Working:
+    /// - Optimistic pass: [`Node::with_key_mut`] crabs down with read latches and write-latches only the target. If `k`
+    ///   already exists it's handled in place, otherwise the item goes into its leaf if the leaf has room.
+    /// - Pessimistic pass, only when the leaf is full: the root is write-latched and the path to the leaf is held down
+    ///   to it by [`Node::insert_with_splits`], splitting overflowing nodes on the way back up.
+    /// - A key that still has an open version is refused with [`io::ErrorKind::AlreadyExists`] and left untouched.
+    pub fn insert(self_node: Arc<RwLock<Node>>, k: u32, v: String, txn: u64) -> io::Result<()> {
+        let node_size = *NODE_SIZE.get().unwrap();
+        let inserted = Node::with_key_mut(&self_node, k, |node, position| match position {
+            Ok(i) => Some(insert_existing(&mut node.input[i].version, v.clone(), txn)),
+            Err(i) if node.input.len() < node_size => {
+                let version = vec![Version { value: v.clone(), xmin: txn, xmax: None, version_status: VersionStatus::Active }];
+                let rank = node.rank;
+                node.input.insert(i, Items { key: k, rank, version });
+                Some(true)
             }
-        }
+            Err(_) => None,
+        });
 
-        Node::validate_after_mutation(self_node);
+        let inserted = match inserted {
+            Some(inserted) => inserted,
+            None => {
+                let mut root_write = self_node.write().unwrap_or_else(|e| e.into_inner());
+                let inserted = Node::insert_with_splits(&mut root_write, k, v, txn);
+                if root_write.input.len() > node_size {
+                    // A root an unfinished `upsert_batch` left over capacity may take more than one level.
+                    while root_write.input.len() > node_size {
+                        Node::split_root(&mut root_write);
+                        let left = Node::split_child_to_fit(&mut root_write, 0);
+                        Node::split_child_to_fit(&mut root_write, left);
+                    }
+                    Node::rank_correction(&mut root_write);
+                }
+                inserted
+            }
+        };
 
+        if !inserted {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "Key already exists"));
+        }
         Ok(())
     }
 
-    /// # THIS IS A TEMPORARY HACK SOLUTION. IT'LL STAY THERE TILL I ADD AN ACTUAL THREAD SAFE FUNCTION.
-    /// ## DO NOT TAKE THIS SERIOUSLY.
-    /// ### :(
-    pub fn find_and_update_key_version(node: Arc<RwLock<Node>>, key: u32, v: Option<String>, txn: u32, delete: bool) -> Option<()> {
-        let mut write_guard = {
-            let w1 = node.write();
-            w1.unwrap_or_else(|poisoned| poisoned.into_inner())
-        };
-        for i in 0..write_guard.input.len() {
-            if write_guard.input[i].key == key {
-                let ver_count = write_guard.input[i].version.len();
-
-                if delete {
-                    let last_xmin = {
-                        let x = &write_guard.input[i].version;
-                        x[ver_count - 1].xmin
-                    };
-
-                    write_guard.input[i].version[ver_count - 1].xmax = Option::from(last_xmin);
-                } else {
-                    write_guard.input[i].version[ver_count - 1].xmax = Option::from(txn);
-                }
+    /// Writes a new version of `key` on behalf of `txn`, or deletes it when `delete` is set.
+    ///
+    /// - If the newest version is `txn`'s own uncommitted write, it's rewritten in place (or rolled back on delete)
+    ///   instead of stacking a second [`VersionStatus::Active`] version for the same transaction.
+    /// - Otherwise the newest version is closed with `xmax = txn` and, unless deleting, a new Active version is pushed.
+    ///   Versions that are already closed keep their `xmax`, so a deleted or superseded version is never reopened.
+    ///
+    /// Only the node holding `key` is write-latched, see [`Node::with_key_mut`].
+    ///
+    /// Returns [`None`] if the key doesn't exist, or if a delete found nothing live to delete.
+    pub fn find_and_update_key_version(node: Arc<RwLock<Node>>, key: u32, v: Option<String>, txn: u64, delete: bool) -> Option<()> {
+        Node::with_key_mut(&node, key, |node, position| match position {
+            Ok(i) => update_versions(&mut node.input[i].version, v, txn, delete),
+            Err(_) => None,
+        })
+    }
 
-                if ver_count >= 2 {
-                    write_guard.input[i].version[ver_count - 2].xmax = None;
-                }
+    /// Writes every `(key, value)` pair on behalf of `txn`, updating keys that exist and inserting the ones that don't,
+    /// then rebalances once for the whole batch.
+    ///
+    /// New keys go straight into their leaf whatever its size, so a leaf may grow well past `NODE_SIZE` until
+    /// [`Node::validate_after_mutation`] splits it as many times as needed. Readers stay correct meanwhile, an
+    /// overfull leaf is still sorted, and a concurrent [`Node::insert`] into it splits it until it fits.
+    pub fn upsert_batch(self_node: Arc<RwLock<Node>>, pairs: Vec<(u32, String)>, txn: u64) {
+        let node_size = *NODE_SIZE.get().unwrap();
+        let mut overflowed = false;
 
-                if let Some(value) = v {
-                    let ver = Version {
-                        value,
-                        xmin: txn,
-                        xmax: None,
-                        version_status: VersionStatus::Active,
-                    };
-                    write_guard.input[i].version.push(ver);
+        for (k, v) in pairs {
+            Node::with_key_mut(&self_node, k, |node, position| match position {
+                Ok(i) => {
+                    update_versions(&mut node.input[i].version, Some(v), txn, false);
                 }
-                return Some(());
-            }
-        }
-        drop(write_guard);
-        let read_guard = node.read().unwrap_or_else(|poisoned| poisoned.into_inner());
-        if !read_guard.children.is_empty() {
-            if key < read_guard.input[0].key {
-                let guard = Arc::clone(&read_guard.children[0]);
-                drop(read_guard);
-                return Node::find_and_update_key_version(guard, key, v, txn, delete);
-            } else if key > read_guard.input[read_guard.input.len() - 1].key {
-                let guard = Arc::clone(&read_guard.children.last().unwrap());
-                drop(read_guard);
-                return Node::find_and_update_key_version(guard, key, v, txn, delete);
-            } else {
-                for i in 0..read_guard.input.len() - 1 {
-                    if key > read_guard.input[i].key && key < read_guard.input[i + 1].key {
-                        let guard = Arc::clone(&read_guard.children[i + 1]);
-                        drop(read_guard);
-                        return Node::find_and_update_key_version(guard, key, v, txn, delete);
-                    }
+                Err(i) => {
+                    let version = vec![Version { value: v, xmin: txn, xmax: None, version_status: VersionStatus::Active }];
+                    let rank = node.rank;
+                    node.input.insert(i, Items { key: k, rank, version });
+                    overflowed |= node.input.len() > node_size;
                 }
-            }
+            });
+        }
+
+        if overflowed {
+            Node::validate_after_mutation(self_node);
         }
-        None
     }
 
+    /// Pessimistic insert below an already write-latched `self_node`. Every node on the path to the leaf stays
+    /// write-latched until the recursion unwinds, and a child left with more than `NODE_SIZE` keys is split with
+    /// [`Node::split_child_to_fit`] before its parent is released, so a leaf an [`Node::upsert_batch`] filled past
+    /// `NODE_SIZE` fits again too. The root itself is left for [`Node::insert`] to split.
+    /// Returns false if `k` was refused, see [`insert_existing`].
+    fn insert_with_splits(self_node: &mut Node, k: u32, v: String, txn: u64) -> bool {
+        let i = match self_node.locate(k) {
+            // Inserted by someone else between the optimistic and the pessimistic pass.
+            Ok(i) => return insert_existing(&mut self_node.input[i].version, v, txn),
+            Err(i) => i,
+        };
 
-    fn add_new_keys(self_node: Arc<RwLock<Node>>, mut x: Items) {
-        let self_instance = &mut self_node.write().unwrap();
-        if self_instance.children.is_empty() {
-            self_instance.input.push(x.clone());
-        } else {
-            if x.key < self_instance.input[0].key {
-                if !self_instance.children.is_empty() {
-                    Node::add_new_keys(Arc::clone(&self_instance.children[0]), x);
-                } else {
-                    x.rank = self_instance.input[0].rank;
-                    self_instance.input.push(x);
-                }
-            } else if x.key > self_instance.input[self_instance.input.len() - 1].key {
-                if !self_instance.children.is_empty() {
-                    Node::add_new_keys(
-                        Arc::clone(&self_instance.children[self_instance.children.len() - 1]),
-                        x,
-                    );
-                } else {
-                    x.rank = self_instance.input[0].rank;
-                    self_instance.input.push(x);
-                }
-            } else {
-                for i in 0..self_instance.input.len() - 1 {
-                    if x.key > self_instance.input[i].key && x.key < self_instance.input[i + 1].key
-                    {
-                        if !self_instance.children.is_empty() {
-                            Node::add_new_keys(
-                                Arc::clone(&self_instance.children[i + 1]),
-                                x.clone(),
-                            );
-                        } else {
-                            x.rank = self_instance.input[0].rank;
-                            self_instance.input.push(x.clone());
-                        }
-                    }
-                }
+        if self_node.children.is_empty() {
+            let version = vec![Version { value: v, xmin: txn, xmax: None, version_status: VersionStatus::Active }];
+            self_node.input.insert(i, Items { key: k, rank: self_node.rank, version });
+            return true;
+        }
+
+        let (inserted, overflowing) = {
+            let mut child_write = self_node.children[i].write().unwrap_or_else(|e| e.into_inner());
+            let inserted = Node::insert_with_splits(&mut child_write, k, v, txn);
+            (inserted, child_write.input.len() > *NODE_SIZE.get().unwrap())
+        };
+        if overflowing {
+            Node::split_child_to_fit(self_node, i);
+        }
+        inserted
+    }
+}
+
+/// [`Node::insert`] on a key that's already in the tree: refused while a version is still open, otherwise (every version
+/// was closed by an abort or a delete) the key is free to be inserted again. Returns whether it was inserted.
+fn insert_existing(versions: &mut Vec<Version>, v: String, txn: u64) -> bool {
+    if versions.iter().any(|ver| ver.xmax.is_none()) {
+        return false;
+    }
+    update_versions(versions, Some(v), txn, false);
+    true
+}
+
+fn update_versions(versions: &mut Vec<Version>, v: Option<String>, txn: u64, delete: bool) -> Option<()> {
+    // Rolled back versions (`xmin == xmax`) are skipped, the newest live one is what gets closed.
+    let newest_live = versions.iter().rposition(|ver| ver.xmax != Some(ver.xmin));
+
+    let last = match newest_live {
+        Some(position) => &mut versions[position],
+        None if delete => return None,
+        None => {
+            if let Some(value) = v {
+                versions.push(Version { value, xmin: txn, xmax: None, version_status: VersionStatus::Active });
             }
+            return Some(());
         }
+    };
+
+    if last.version_status == VersionStatus::Active && last.xmin == txn && last.xmax.is_none() {
+        if delete {
+            last.version_status = VersionStatus::Abort;
+            last.xmax = Some(txn);
+        } else if let Some(value) = v {
+            last.value = value;
+        }
+        return Some(());
+    }
+
+    match last.xmax {
+        None => last.xmax = Some(txn),
+        Some(_) if delete => return None,
+        Some(_) => {}
+    }
+
+    if let Some(value) = v {
+        let ver = Version {
+            value,
+            xmin: txn,
+            xmax: None,
+            version_status: VersionStatus::Active,
+        };
+        versions.push(ver);
     }
-}
\ No newline at end of file
+    Some(())
+}
@@ -22,14 +22,12 @@ impl Node {
         self_node = Node::sort_main_nodes(self_node);
         self_node = Node::sort_children_nodes(self_node);
 
-        let self_read = self_node.write().unwrap();
-        let children = self_read.children.clone();
+        let children = self_node.read().unwrap().children.clone();
 
         for child in children {
             Node::sort_everything(child);
         }
 
-        drop(self_read);
         self_node
     }
 }
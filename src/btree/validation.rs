@@ -2,28 +2,17 @@ use std::sync::{Arc, RwLock};
 use crate::btree::node::Node;
 
 impl Node {
-    pub fn validate_after_mutation(mut node: Arc<RwLock<Node>>) {
-        node = Node::overflow_check(node);
-        node = Node::min_size_check(node);
-        node = Node::sort_main_nodes(node);
-        node = Node::sort_children_nodes(node);
-
-        node = Node::child_overflow(node);
-
-        node = Node::min_size_check(node);
-
-        node = Node::overflow_check(node);
-
-        node = Node::min_size_check(node);
-        node = Node::sort_main_nodes(node);
-        node = Node::child_overflow(node);
-        node = Node::rank_correction(node);
-        node = Node::sort_everything(node);
-
-        node = Node::overflow_check(node);
-        node = Node::min_size_check(node);
-        node = Node::child_overflow(node);
-        node = Node::rank_correction(node);
-        Node::sort_everything(node);
+    /// Restores the B-Tree invariants after keys were added to the leaves: overflowing nodes are split bottom-up by
+    /// [`Node::overflow_check`] and ranks are recomputed top-down in case the root grew a level.
+    ///
+    /// Keys only ever enter the tree sorted into their leaf and are never physically removed (MVCC deletes close a
+    /// version instead), so overflow is the only invariant a mutation can break.
+    ///
+    /// The root stays write-latched for the whole pass, see `btree/latch.rs` for the lock order.
+    pub fn validate_after_mutation(node: Arc<RwLock<Node>>) {
+        let mut root_write = node.write().unwrap_or_else(|e| e.into_inner());
+        if Node::overflow_check(&mut root_write) {
+            Node::rank_correction(&mut root_write);
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,89 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock};
+use crate::btree::node::Node;
+use crate::MVCC::versions::Version;
+use crate::MVCC::visibility::select_key;
+use crate::transactions::transactions::Transaction;
+
+/// A secondary index from a value (or one comma-separated field of it) to the primary keys of a table.
+///
+/// Entries aren't versioned. Every write adds the entry for the value it wrote once the version is in the tree, and
+/// entries only go when vacuum reclaims the last version of their key that has them, see [`Index::remove`]. A value a
+/// transaction overwrote with its own next write never became a version, so its entry stays. So the index may point at keys whose visible value has since changed, was deleted or was never committed. [`lookup`]
+/// rechecks each candidate against the version visible to the reading transaction, which makes the index follow the
+/// same MVCC rules as `select` and keeps it in step with the transaction that wrote the row without any extra undo.
+#[derive(Debug)]
+pub struct Index {
+    pub table: String,
+    /// 0-based comma-separated field of the value that gets indexed, the whole value if [`None`].
+    pub field: Option<usize>,
+    pub entries: RwLock<BTreeMap<String, BTreeSet<u32>>>,
+}
+
+impl Index {
+    pub fn new(table: &str, field: Option<usize>) -> Index {
+        Index { table: table.to_string(), field, entries: RwLock::new(BTreeMap::new()) }
+    }
+
+    /// The part of `value` this index is keyed by. [`None`] if the value doesn't have the indexed field.
+    pub fn extract<'a>(&self, value: &'a str) -> Option<&'a str> {
+        match self.field {
+            Some(field) => value.split(',').nth(field),
+            None => Some(value),
+        }
+    }
+
+    pub fn add(&self, key: u32, value: &str) {
+        if let Some(indexed) = self.extract(value) {
+            self.entries.write().unwrap().entry(indexed.to_string()).or_default().insert(key);
+        }
+    }
+
+    /// Drops `key` from the entries of the `reclaimed` values that none of its `remaining` versions has, and drops
+    /// entries left without keys. Called by vacuum under the write latch of the node holding `key`; writers add their
+    /// entry after the version is written, so an entry a running write still needs always has a remaining version.
+    pub fn remove(&self, key: u32, reclaimed: &[String], remaining: &[Version]) {
+        let mut entries = self.entries.write().unwrap();
+        for value in reclaimed.iter() {
+            let Some(indexed) = self.extract(value) else { continue };
+            if remaining.iter().any(|version| self.extract(&version.value) == Some(indexed)) {
+                continue;
+            }
+            if let Some(keys) = entries.get_mut(indexed) {
+                keys.remove(&key);
+                if keys.is_empty() {
+                    entries.remove(indexed);
+                }
+            }
+        }
+    }
+
+    /// Indexes every version of every key in `node`, used when the index is created on a table that already has rows.
+    pub fn build(&self, node: &Arc<RwLock<Node>>) {
+        Node::for_each_item(node, &mut |item| {
+            for version in item.version.iter() {
+                self.add(item.key, &version.value);
+            }
+        });
+    }
+
+    fn candidates(&self, indexed: &str) -> Vec<u32> {
+        match self.entries.read().unwrap().get(indexed) {
+            Some(keys) => keys.iter().copied().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Keys of `node` whose value visible to `current_txd` has `indexed` as its indexed part, with that value.
+/// Every candidate from the index is rechecked with [`select_key`], see [`Index`].
+pub fn lookup(index: &Index, node: Arc<RwLock<Node>>, indexed: &str, current_txd: u64, transaction: Arc<RwLock<Transaction>>) -> Vec<(u32, String)> {
+    let mut found = Vec::new();
+    for key in index.candidates(indexed) {
+        if let Some(value) = select_key(Arc::clone(&node), key, current_txd, Arc::clone(&transaction))
+            && index.extract(&value) == Some(indexed) {
+            found.push((key, value));
+        }
+    }
+    found
+}
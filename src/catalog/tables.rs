@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::btree::node::Node;
+use crate::catalog::index::Index;
+use crate::engine::services::Services;
+use crate::replication::role::Role;
+use crate::MVCC::gc::{RetentionPolicy, DEFAULT_RETENTION};
+use crate::MVCC::snapshot::roll_back;
+use crate::storage::manifest::{sync_parent_dir, tmp_path_for, Manifest};
+use crate::storage::ser::serialize;
+
+/// The table every client starts on. It lives at the serialized file path itself, so images written before tables
+/// existed load as this table.
+pub const DEFAULT_TABLE: &str = "default";
+
+/// Named tables, each its own B-Tree, sharing one WAL and one [`crate::transactions::transactions::Transaction`] table
+/// so a transaction can touch several tables atomically. What the engine runs next to them are its
+/// [`Services`].
+#[derive(Debug)]
+pub struct Catalog {
+    pub tables: HashMap<String, Arc<RwLock<Node>>>,
+    /// The table selected with `use` by each client. Clients that never ran `use` are on [`DEFAULT_TABLE`].
+    pub ip_table: HashMap<SocketAddr, String>,
+    /// Secondary indexes by name. Only their definitions are persisted, entries are rebuilt from the table on load.
+    pub indexes: HashMap<String, Arc<Index>>,
+    /// Oldest txid garbage collection kept every version for, reads as of an earlier txid are refused. Raised by each
+    /// vacuum pass and persisted with the catalog.
+    pub gc_horizon: AtomicU64,
+    /// What vacuum may reclaim, set with `retention` and persisted with the catalog.
+    pub retention: RetentionPolicy,
+}
+
+impl Catalog {
+    pub fn new(default_table: Arc<RwLock<Node>>) -> Catalog {
+        let mut tables = HashMap::new();
+        tables.insert(String::from(DEFAULT_TABLE), default_table);
+        Catalog { tables, ip_table: HashMap::new(), indexes: HashMap::new(), gc_horizon: AtomicU64::new(0), retention: DEFAULT_RETENTION }
+    }
+
+    /// Name of the table `addr` is using. Falls back to [`DEFAULT_TABLE`] if it was dropped meanwhile.
+    pub fn current_table(&self, addr: &SocketAddr) -> String {
+        match self.ip_table.get(addr) {
+            Some(name) if self.tables.contains_key(name) => name.clone(),
+            _ => String::from(DEFAULT_TABLE),
+        }
+    }
+
+    pub fn table(&self, name: &str) -> Option<Arc<RwLock<Node>>> {
+        self.tables.get(name).map(Arc::clone)
+    }
+
+    /// Returns false if the table already exists.
+    pub fn create_table(&mut self, name: &str) -> bool {
+        if self.tables.contains_key(name) {
+            return false;
+        }
+        self.tables.insert(name.to_string(), Node::new());
+        true
+    }
+
+    /// Returns false if the table doesn't exist or is [`DEFAULT_TABLE`], which can't be dropped. Clients using the
+    /// dropped table are moved back to [`DEFAULT_TABLE`] and its indexes are dropped with it. Its images are deleted by
+    /// the next [`Catalog::store`], once the catalog file no longer lists it.
+    pub fn drop_table(&mut self, name: &str) -> bool {
+        if name == DEFAULT_TABLE || self.tables.remove(name).is_none() {
+            return false;
+        }
+        self.ip_table.retain(|_, table| table != name);
+        self.indexes.retain(|_, index| index.table != name);
+        true
+    }
+
+    /// Creates index `name` on `table` and fills it from the rows already there.
+    /// Returns false if the index already exists or the table doesn't.
+    pub fn create_index(&mut self, name: &str, table: &str, field: Option<usize>) -> bool {
+        let node = match self.tables.get(table) {
+            Some(node) if !self.indexes.contains_key(name) => node,
+            _ => return false,
+        };
+        let index = Index::new(table, field);
+        index.build(node);
+        self.indexes.insert(name.to_string(), Arc::new(index));
+        true
+    }
+
+    /// Returns false if the index doesn't exist.
+    pub fn drop_index(&mut self, name: &str) -> bool {
+        self.indexes.remove(name).is_some()
+    }
+
+    pub fn index(&self, name: &str) -> Option<Arc<Index>> {
+        self.indexes.get(name).map(Arc::clone)
+    }
+
+    /// Every index that has to be maintained when `table` is written to.
+    pub fn indexes_on(&self, table: &str) -> Vec<Arc<Index>> {
+        self.indexes.values().filter(|index| index.table == table).map(Arc::clone).collect()
+    }
+
+    /// Returns false if the table doesn't exist.
+    pub fn use_table(&mut self, addr: SocketAddr, name: &str) -> bool {
+        if !self.tables.contains_key(name) {
+            return false;
+        }
+        self.ip_table.insert(addr, name.to_string());
+        true
+    }
+
+    /// Where a table's checkpoint images go: [`DEFAULT_TABLE`] at `serialized_file_path`, every other table at
+    /// `<serialized_file_path>.table.<name>`, each with its own manifest.
+    pub fn table_path(serialized_file_path: &str, name: &str) -> String {
+        if name == DEFAULT_TABLE {
+            serialized_file_path.to_string()
+        } else {
+            format!("{}.table.{}", serialized_file_path, name)
+        }
+    }
+
+    /// The catalog file, stored next to the images at `<serialized_file_path>.catalog`. It lists one table name per
+    /// line, followed by one `index <name> <table> <field>` line per index, `-` standing for the whole value, a
+    /// `gc_horizon <txid>` line, a `retention <policy>` line, an `epoch <n>` line, for a fenced primary a
+    /// `fenced <newer epoch>` line, a `txid <reserved txid>` line and an `in_progress <txid>...` line listing the
+    /// transactions the images caught in progress.
+    pub fn path_for(serialized_file_path: &str) -> PathBuf {
+        PathBuf::from(format!("{}.catalog", serialized_file_path))
+    }
+
+    /// Loads every table listed in the catalog file and rebuilds its indexes. Without one, only [`DEFAULT_TABLE`] is
+    /// loaded. The epoch, fence and txid reservation it persists are restored into `services`.
+    ///
+    /// A table whose images can't be loaded fails the whole load. Starting it empty instead would have the next
+    /// checkpoints overwrite its last good image with the empty tree. Only a fresh install, without a catalog file or
+    /// any image, starts empty.
+    ///
+    /// The writes of transactions the images caught in progress are rolled back, nothing after the checkpoint will
+    /// ever commit them.
+    pub fn load(serialized_file_path: &str, services: &Services) -> io::Result<Catalog> {
+        let (contents, fresh) = match fs::read_to_string(Catalog::path_for(serialized_file_path)) {
+            Ok(contents) => (contents, false),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (format!("{}\n", DEFAULT_TABLE), true),
+            Err(e) => return Err(e),
+        };
+
+        let mut names = Vec::new();
+        let mut index_lines = Vec::new();
+        let mut gc_horizon = 0;
+        let mut retention = None;
+        let mut epoch = None;
+        let mut reserved = None;
+        let mut fenced = None;
+        let mut in_progress = Vec::new();
+        for line in contents.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+                ["index", name, table, field] => index_lines.push((name.to_string(), table.to_string(), field.to_string())),
+                ["retention", policy @ ..] => retention = Some(RetentionPolicy::parse(policy).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed retention policy: {:?}", line)))?),
+                ["epoch", number] => epoch = Some(number.parse::<u64>().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed epoch: {:?}", number)))?),
+                ["fenced", number] => fenced = Some(number.parse::<u64>().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed fence: {:?}", number)))?),
+                ["txid", txid] => reserved = Some(txid.parse::<u64>().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed txid: {:?}", txid)))?),
+                ["in_progress", txids @ ..] => in_progress = txids.iter().map(|txid| txid.parse::<u64>()).collect::<Result<Vec<u64>, _>>().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed in progress txids: {:?}", line)))?,
+                ["gc_horizon", horizon] => gc_horizon = horizon.parse::<u64>().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed gc horizon: {:?}", horizon)))?,
+                [name] => names.push(name.to_string()),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Malformed catalog line: {:?}", line))),
+            }
+        }
+
+        let mut catalog = Catalog::new(Node::new());
+        catalog.gc_horizon = AtomicU64::new(gc_horizon);
+        catalog.retention = retention.unwrap_or(DEFAULT_RETENTION);
+        if let Some(epoch) = epoch {
+            services.replication.set_epoch(epoch);
+        }
+        if let Some(newer) = fenced {
+            services.replication.set_role(Role::Fenced(newer));
+        }
+        if let Some(txid) = reserved {
+            services.txids.reserve(txid);
+        }
+        for name in names {
+            let table_path = Catalog::table_path(serialized_file_path, &name);
+            let table = match Node::deserialize(&table_path) {
+                Ok(node) => node,
+                Err(e) if fresh && e.kind() == io::ErrorKind::NotFound && !Manifest::path_for(&table_path).exists() => Node::new(),
+                Err(e) => return Err(io::Error::new(e.kind(), format!("Failed to load table {}: {}", name, e))),
+            };
+            roll_back(&table, &in_progress);
+            catalog.tables.insert(name, table);
+        }
+
+        for (name, table, field) in index_lines {
+            let field = match field.as_str() {
+                "-" => None,
+                field => Some(field.parse::<usize>().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed index field: {:?}", field)))?),
+            };
+            if !catalog.create_index(&name, &table, field) {
+                println!("Skipping index {} on missing table {}", name, table);
+            }
+        }
+        Ok(catalog)
+    }
+
+    /// Serializes every table and then atomically replaces the catalog file, so a crash in between leaves the previous
+    /// list pointing at images that are all still valid. The images of tables it no longer lists are deleted last.
+    pub fn store(&self, serialized_file_path: &str, services: &Services) -> io::Result<()> {
+        self.store_with(serialized_file_path, services, &[])
+    }
+
+    /// [`Catalog::store`] for a checkpoint that caught the transactions `in_progress`, which [`Catalog::load`] rolls
+    /// back. Nothing may write to the tables meanwhile.
+    pub fn store_with(&self, serialized_file_path: &str, services: &Services, in_progress: &[u64]) -> io::Result<()> {
+        let mut names: Vec<&String> = self.tables.keys().collect();
+        names.sort();
+
+        for name in names.iter() {
+            serialize(Arc::clone(&self.tables[name.as_str()]), &Catalog::table_path(serialized_file_path, name))?;
+        }
+
+        let mut contents = String::new();
+        for name in names {
+            contents.push_str(name);
+            contents.push('\n');
+        }
+
+        let mut index_names: Vec<&String> = self.indexes.keys().collect();
+        index_names.sort();
+        for name in index_names {
+            let index = &self.indexes[name.as_str()];
+            let field = index.field.map_or(String::from("-"), |field| field.to_string());
+            contents.push_str(&format!("index {} {} {}\n", name, index.table, field));
+        }
+
+        contents.push_str(&format!("gc_horizon {}\n", self.gc_horizon.load(Ordering::SeqCst)));
+        contents.push_str(&format!("retention {}\n", self.retention));
+        contents.push_str(&format!("epoch {}\n", services.replication.epoch()));
+        if let Role::Fenced(newer) = services.replication.role() {
+            contents.push_str(&format!("fenced {}\n", newer));
+        }
+        contents.push_str(&format!("txid {}\n", services.txids.reserved()));
+        if !in_progress.is_empty() {
+            let txids: Vec<String> = in_progress.iter().map(|txid| txid.to_string()).collect();
+            contents.push_str(&format!("in_progress {}\n", txids.join(" ")));
+        }
+
+        let catalog_path = Catalog::path_for(serialized_file_path);
+        let tmp_path = tmp_path_for(&catalog_path);
+        {
+            let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &catalog_path)?;
+        sync_parent_dir(&catalog_path)?;
+        self.remove_dropped_images(serialized_file_path)
+    }
+
+    /// Deletes the `<serialized_file_path>.table.<name>` images, manifests and leftover temp files of every table the
+    /// catalog doesn't hold. Only called once the catalog file listing them is gone.
+    fn remove_dropped_images(&self, serialized_file_path: &str) -> io::Result<()> {
+        let path = Path::new(serialized_file_path);
+        let Some(file_name) = path.file_name() else { return Ok(()) };
+        let prefix = format!("{}.table.", file_name.to_string_lossy());
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Some(table) = name.strip_prefix(&prefix).and_then(|rest| rest.split('.').next()) else { continue };
+            if !self.tables.contains_key(table) {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Table names end up in file names, so only ASCII letters, digits and `_` are allowed.
+pub fn valid_table_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
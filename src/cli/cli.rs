@@ -1,19 +1,70 @@
+use std::collections::HashMap;
 use std::io::Write;
 use std::fs::File;
 use std::io;
-use std::net::{SocketAddr, TcpStream};
-use std::sync::{Arc, RwLock};
+use std::net::TcpStream;
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
 use std::sync::atomic::Ordering;
+use std::time::Duration;
 use crate::btree::node::Node;
-use crate::CHECKPOINT_COUNTER;
+use crate::catalog::tables::{valid_table_name, Catalog};
+use crate::catalog::index::lookup;
+use crate::transactions::batch::batch_write;
+use crate::transactions::conditional::{conditional_write, Condition, ConditionalWrite, Target};
+use crate::{CHECKPOINT_COUNTER, WAL_SHIPPER};
 use crate::cli::parser::parse_string;
 use crate::MVCC::snapshot::snapshot;
-use crate::transactions::transactions::{Transaction, TransactionItems, TransactionStatus};
-use crate::transactions::manager::get_all_active_transaction;
-use crate::storage::wal::writer::flush_to_wal;
-use crate::MVCC::visibility::{select_key, modified_key_check, fetch_version_vec_for_key, commit_abort_handler};
+use crate::MVCC::as_of::{scan_as_of, select_as_of};
+use crate::MVCC::range::{aggregate_in, Aggregate};
+use crate::MVCC::gc::RetentionPolicy;
+use crate::engine::checkpoint::checkpoint;
+use crate::engine::cdc::{capture_changes, stream_changes, ChangeLog};
+use crate::engine::services::Services;
+use crate::engine::vacuum::{vacuum, vacuum_tick, TICK_NODES};
+use crate::engine::watch::{KeyPattern, WaitOutcome, Watches};
+use crate::replication::failover::{fence_remote, promote};
+use crate::replication::role::{allowed_read_only, Role};
+use crate::replication::shipper::serve_follower;
+use crate::transactions::savepoint::{end_savepoints, has_savepoint, release, rollback_to, savepoint, write_txd, SavepointStatus};
+use crate::transactions::locks::{lock_for_write, LockManager, LockMode, LockOutcome};
+use crate::transactions::autocommit::{autocommit, read_only, Autocommit};
+use crate::transactions::prepared::{can_prepare, prepare, resolvable, PreparedStatus};
+use crate::transactions::timeout::active_txd;
+use crate::transactions::transactions::{Transaction, TransactionStatus};
+use crate::transactions::manager::{get_all_active_transaction, read_view, start_transaction};
+use crate::storage::wal::writer::{flush_to_wal, log_to_wal};
+use crate::storage::external_sort::{sorted_pairs, RUN_SIZE};
+use crate::storage::interchange::{export, read_records, records_to_items, Format};
+use crate::MVCC::visibility::{select_key, modified_key_check, serialization_conflict, fetch_version_vec_for_key, commit_abort_handler};
+
+/// Runs a command line sent by a client. Followers and fenced primaries refuse the commands that write, see
+/// [`allowed_read_only`].
+pub fn cli(cli_input: String, txd_count: Arc<RwLock<u64>>, current_transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, catalog: Arc<RwLock<Catalog>>, stream: Option<&TcpStream>, services: Arc<Services>) -> io::Result<u8> {
+    let role = services.replication.role();
+    if role != Role::Primary {
+        let words: Vec<&str> = cli_input.split_whitespace().collect();
+        if let Some(command) = words.first() && words.len() > 1 && !allowed_read_only(&command.to_lowercase(), words.len() - 2) {
+            let message = match role {
+                Role::Fenced(epoch) => format!("FAILED: fenced by epoch {}, this server is no longer the primary", epoch),
+                _ => String::from("FAILED: read-only follower, send writes to the primary"),
+            };
+            match stream {
+                Some(s) => {
+                    let mut s = s;
+                    writeln!(s, "{}", message)?;
+                }
+                None => println!("{}", message),
+            }
+            return Ok(1);
+        }
+    }
 
-pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, new_node: Arc<RwLock<Node>>, stream: Option<&TcpStream>, all_addr: Arc<RwLock<Vec<SocketAddr>>> ) -> io::Result<u8> {
+    execute(cli_input, txd_count, current_transaction, file, catalog, stream, services)
+}
+
+/// Runs a command line without checking the role of the server, which is how a follower applies what its primary
+/// shipped.
+pub(crate) fn execute(cli_input: String, txd_count: Arc<RwLock<u64>>, current_transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, catalog: Arc<RwLock<Catalog>>, stream: Option<&TcpStream>, services: Arc<Services>) -> io::Result<u8> {
     println!("{:?}", cli_input);
 
     let log_message = |message: &str|{
@@ -32,74 +83,116 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
         Ok((addr, args_string)) => {
             let args: Vec<&str> = args_string.iter().map(|s| s.as_str()).collect();
 
-            let mut all_addr_write = all_addr.write().unwrap();
+            let mut all_addr_write = services.all_addr.write().unwrap();
             all_addr_write.push(addr);
             drop(all_addr_write);
 
             if args.is_empty() { return Ok(1); }
+
+            // A statement sent without a transaction runs in one of its own, see `autocommit`.
+            let command = args[0].to_lowercase();
+            if let Some(mode) = autocommit(&command) && active_txd(&current_transaction.read().unwrap(), &addr).is_none() {
+                let run = |line: String| execute(line, Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&catalog), stream, Arc::clone(&services));
+                run(format!("{} {}", mode.begin(), addr))?;
+                let Some(txd) = active_txd(&current_transaction.read().unwrap(), &addr) else { return Ok(1) };
+                let status = run(args.join(" "));
+                let wrote = current_transaction.read().unwrap().items.get(&txd).is_some_and(|item| !item.modified_keys.is_empty());
+                let end = if mode == Autocommit::ReadWrite && !wrote { "abort" } else { "commit" };
+                run(format!("{} {}", end, addr))?;
+                return status;
+            }
+            // Read-only transactions run what a read-only server would.
+            if read_only(&current_transaction.read().unwrap(), &addr) && !allowed_read_only(&command, args.len().saturating_sub(2)) {
+                log_message("FAILED: read only transaction");
+                return Ok(1);
+            }
+
+            // Every key command runs against the table this client selected with `use`.
+            let (table, new_node, indexes) = {
+                let catalog_read = catalog.read().unwrap();
+                let table = catalog_read.current_table(&addr);
+                let node = catalog_read.table(&table).unwrap();
+                let indexes = catalog_read.indexes_on(&table);
+                (table, node, indexes)
+            };
+            let (changes, watches, txids, locks) = (Arc::clone(&services.changes), Arc::clone(&services.watches), Arc::clone(&services.txids), Arc::clone(&services.locks));
+            // Writes wait for their row lock like `lock` does, and a transaction picked as a deadlock victim is aborted.
+            let lock_write = |key: u32| -> io::Result<LockOutcome> {
+                let outcome = lock_for_write(&locks, &current_transaction, &addr, &table, key, services.timeouts().lock_wait);
+                if outcome == LockOutcome::Deadlock {
+                    execute(format!("abort {}", addr), Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&catalog), None, Arc::clone(&services))?;
+                }
+                Ok(outcome)
+            };
+
             match args[0].to_lowercase().as_str() {
+                // begin [read only]
                 "begin" => {
-                    if args.len() != 2 {
-                        log_message("Invalid argument");
+                    let read_only = match &args[1..args.len() - 1] {
+                        [] => false,
+                        [read, only] if read.eq_ignore_ascii_case("read") && only.eq_ignore_ascii_case("only") => true,
+                        _ => {
+                            log_message("Invalid argument");
 
-                        return Ok(1);
-                    }
+                            return Ok(1);
+                        }
+                    };
 
-                    flush_to_wal(Arc::clone(&file), args)?;
+                    // A read-only transaction has nothing to replay, none of it is logged, not even a txid reservation.
+                    if !read_only {
+                        flush_to_wal(Arc::clone(&file), args)?;
+                    }
                     let mut mut_txd_count = txd_count.write().unwrap();
-                    *mut_txd_count += 1;
+                    let txd = if read_only { txids.allocate_unreserved(&mut mut_txd_count)? } else { txids.allocate(&mut mut_txd_count, &file)? };
 
                     {
                         let mut tx = current_transaction.write().unwrap();
 
-                        match tx.ip_txd.get(&addr) {
-                            Some(&x) => {
-                                let item = tx.items.get(&x);
-                                if let Some(item) = item {
-                                    if item.status == TransactionStatus::Committed || item.status == TransactionStatus::Aborted {
-                                        tx.ip_txd.insert(addr, *mut_txd_count);
-                                        tx.items.insert(*mut_txd_count, TransactionItems {status: TransactionStatus::Active, socket_addr: addr, last_txd: x, modified_keys: Vec::new() });
-                                        tx.items.remove(&x);
-                                    } else {
-                                        *mut_txd_count -= 1;
-                                        log_message("Previous transaction is still active. Close it to start a new one.");
-                                    }
-                                }
-                            }
-                            None => {
-                                tx.ip_txd.insert(addr, *mut_txd_count);
-                                tx.items.insert(*mut_txd_count, TransactionItems {status: TransactionStatus::Active, socket_addr: addr, last_txd: 0, modified_keys: Vec::new() });
-
-                            }
+                        if !start_transaction(&mut tx, txd, addr) {
+                            *mut_txd_count -= 1;
+                            log_message("Previous transaction is still active. Close it to start a new one.");
+                        } else if let Some(item) = tx.items.get_mut(&txd) {
+                            item.read_only = read_only;
                         }
                     }
                 }
 
+                // commit prepared <gid>, rollback prepared <gid>
+                "commit" | "rollback" if args.len() == 4 && args[1].eq_ignore_ascii_case("prepared") => {
+                    let tables = catalog.read().unwrap().tables.clone();
+                    let tx = current_transaction.write().unwrap();
+                    let x = match resolvable(&tx, &addr, args[2]) {
+                        Ok(x) => x,
+                        Err(status) => {
+                            log_message(&status.message());
+                            return Ok(1);
+                        }
+                    };
+                    flush_to_wal(Arc::clone(&file), args.clone())?;
+                    let commit = args[0].eq_ignore_ascii_case("commit");
+                    end_transaction(tx, x, commit, &tables, &changes, &watches, &locks)?;
+                    log_message(&PreparedStatus::Done.message());
+                }
+
                 "commit" => {
                     if args.len() != 2 {
                         log_message("Invalid argument");
                         return Ok(1);
                     }
 
-                    flush_to_wal(Arc::clone(&file), args)?;
+                    if !read_only(&current_transaction.read().unwrap(), &addr) {
+                        flush_to_wal(Arc::clone(&file), args)?;
+                    }
 
                     {
-                        let mut tx = current_transaction.write().unwrap();
+                        let tables = catalog.read().unwrap().tables.clone();
+                        let tx = current_transaction.write().unwrap();
 
                         match tx.ip_txd.get(&addr) {
                             Some(&x) => {
-                                let item = &mut tx.items.get(&x);
-                                let mut modified_key_vec = Vec::new();
-                                if let Some(item) = item {
+                                if let Some(item) = tx.items.get(&x) {
                                     if item.status == TransactionStatus::Active {
-                                        if let Some(items) = tx.items.get_mut(&x) {
-                                            items.status = TransactionStatus::Committed;
-                                            modified_key_vec = items.modified_keys.clone();
-                                        }
-                                        drop(tx);
-                                        for j in modified_key_vec.iter() {
-                                            commit_abort_handler(Arc::clone(&new_node), *j, true);
-                                        }
+                                        end_transaction(tx, x, true, &tables, &changes, &watches, &locks)?;
                                     } else {
                                         println!("Active transaction not found. Commit failed.");
                                         return Ok(1);
@@ -121,25 +214,19 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
                         log_message("Invalid argument");
                         return Ok(1);
                     }
-                    flush_to_wal(Arc::clone(&file), args)?;
+                    if !read_only(&current_transaction.read().unwrap(), &addr) {
+                        flush_to_wal(Arc::clone(&file), args)?;
+                    }
 
                     {
-                        let mut tx = current_transaction.write().unwrap();
+                        let tables = catalog.read().unwrap().tables.clone();
+                        let tx = current_transaction.write().unwrap();
 
                         match tx.ip_txd.get(&addr) {
                             Some(&x) => {
-                                let item = tx.items.get(&x);
-                                let mut modified_key_vec = Vec::new();
-                                if let Some(item) = item {
+                                if let Some(item) = tx.items.get(&x) {
                                     if item.status == TransactionStatus::Active {
-                                        if let Some(items) = tx.items.get_mut(&x) {
-                                            items.status = TransactionStatus::Aborted;
-                                            modified_key_vec = items.modified_keys.clone();
-                                        }
-                                        drop(tx);
-                                        for j in modified_key_vec.iter() {
-                                            commit_abort_handler(Arc::clone(&new_node), *j, false);
-                                        }
+                                        end_transaction(tx, x, false, &tables, &changes, &watches, &locks)?;
                                         println!("B");
 
                                     } else {
@@ -156,32 +243,145 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
                     }
                 }
 
-                "insert" => {
-                    if args.len() != 4 {
+                // prepare <gid>
+                "prepare" => {
+                    if args.len() != 3 {
                         log_message("Invalid argument");
                         return Ok(1);
                     }
 
+                    let tables = catalog.read().unwrap().tables.clone();
+                    let mut tx = current_transaction.write().unwrap();
+                    let status = can_prepare(&tx, &addr, args[1]);
+                    if status != PreparedStatus::Done {
+                        log_message(&status.message());
+                        return Ok(1);
+                    }
+                    // Logged with the transaction table held, so no other client prepares the same gid meanwhile.
+                    // The txid goes first, for recovery to prepare the transaction again under it.
+                    if let Some(x) = active_txd(&tx, &addr) {
+                        log_to_wal(Arc::clone(&file), vec!["prepared_txid", args[1], x.to_string().as_str()])?;
+                    }
                     flush_to_wal(Arc::clone(&file), args.clone())?;
+                    if let Some(x) = active_txd(&tx, &addr) {
+                        end_savepoints(&mut tx, x, |table| tables.get(table).cloned());
+                    }
+                    log_message(&prepare(&mut tx, &addr, args[1]).message());
+                }
+
+                // savepoint <name>, rollback to <name>, release <name>
+                "savepoint" | "rollback" | "release" => {
+                    let command = args[0].to_lowercase();
+                    let name = match (command.as_str(), &args[1..args.len() - 1]) {
+                        ("savepoint" | "release", [name]) => *name,
+                        ("rollback", [to, name]) if to.eq_ignore_ascii_case("to") => *name,
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    };
+                    flush_to_wal(Arc::clone(&file), args.clone())?;
+
+                    let mut txd_count_write = txd_count.write().unwrap();
+                    let tables = catalog.read().unwrap().tables.clone();
+                    let table = |table: &str| tables.get(table).cloned();
+                    let mut tx = current_transaction.write().unwrap();
+                    let status = match (command.as_str(), has_savepoint(&tx, &addr, name)) {
+                        ("release", _) => release(&mut tx, &addr, name, table),
+                        (_, SavepointStatus::NoTransaction) => SavepointStatus::NoTransaction,
+                        ("rollback", SavepointStatus::NotFound(name)) => SavepointStatus::NotFound(name),
+                        ("savepoint", _) => savepoint(&mut tx, &addr, name, txids.allocate(&mut txd_count_write, &file)?, &locks),
+                        _ => rollback_to(&mut tx, &addr, name, txids.allocate(&mut txd_count_write, &file)?, table, &locks),
+                    };
+                    log_message(&status.message());
+                }
+
+                // lock <key> [shared|exclusive], select_for_update <key>
+                "lock" | "select_for_update" => {
+                    let command = args[0].to_lowercase();
+                    let (key, mode) = match (command.as_str(), &args[1..args.len() - 1]) {
+                        ("lock" | "select_for_update", [key]) => (key.parse::<u32>().ok(), Some(LockMode::Exclusive)),
+                        ("lock", [key, mode]) => (key.parse::<u32>().ok(), LockMode::parse(mode)),
+                        _ => (None, None),
+                    };
+                    let (Some(key), Some(mode)) = (key, mode) else {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    };
+                    let Some(txd) = active_txd(&current_transaction.read().unwrap(), &addr) else {
+                        log_message("FAILED: active transaction not found");
+                        return Ok(1);
+                    };
+
+                    let lock_wait = services.timeouts().lock_wait;
+                    let outcome = locks.acquire(txd, &table, key, mode, lock_wait);
+                    if outcome == LockOutcome::Deadlock {
+                        execute(format!("abort {}", addr), Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&catalog), None, Arc::clone(&services))?;
+                    }
+
+                    match outcome {
+                        // Locking doesn't move the snapshot, a write committed while waiting can't be built on.
+                        LockOutcome::Granted if command == "select_for_update" => {
+                            if serialization_conflict(Arc::clone(&new_node), key, txd, Arc::clone(&current_transaction)) {
+                                log_message(format!("FAILED: key {} modified by a concurrent transaction", key).as_str());
+                            } else {
+                                match select_key(Arc::clone(&new_node), key, txd, Arc::clone(&current_transaction)) {
+                                    Some(value) => log_message(format!("Value: {:?}", value).as_str()),
+                                    None => log_message("Key not found"),
+                                }
+                            }
+                        }
+                        outcome => log_message(outcome.message(txd).as_str()),
+                    }
+                }
+
+                "insert" => {
+                    if args.len() != 4 {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
 
                     let key = args[1].parse::<u32>().expect("Invalid argument");
                     let value = args[2].parse::<String>().expect("Invalid argument");
 
-                    let active_txd_vec = get_all_active_transaction(Arc::clone(&current_transaction), all_addr);
+                    // A prepared transaction no longer belongs to its client.
+                    let Some(txd) = current_transaction.read().unwrap().ip_txd.get(&addr).copied() else {
+                        println!("Active transaction not found. Insert failed.");
+                        return Ok(1);
+                    };
+                    let held = locks.held(txd);
+                    let outcome = lock_write(key)?;
+                    if outcome != LockOutcome::Granted {
+                        log_message(outcome.message(txd).as_str());
+                        return Ok(1);
+                    }
 
-                    let txd = current_transaction.read().unwrap().ip_txd.get(&addr).unwrap().clone();
-                    let y = modified_key_check(active_txd_vec, key, txd, Arc::clone(&current_transaction) );
+                    let active_txd_vec = get_all_active_transaction(Arc::clone(&current_transaction), Arc::clone(&services.all_addr));
+                    let y = modified_key_check(active_txd_vec, &table, key, txd, Arc::clone(&current_transaction) )
+                        || serialization_conflict(Arc::clone(&new_node), key, txd, Arc::clone(&current_transaction));
 
                     if y {
-                        println!("The key you're trying to insert has already been updated by another client.")
+                        locks.release_from(txd, held);
+                        log_message("The key you're trying to insert has already been updated or locked by another client.")
                     } else {
                         let mut tx = current_transaction.write().unwrap();
 
                         match tx.ip_txd.get(&addr) {
                             Some(&x) => {
-                                let _ = Node::insert(Arc::clone(&new_node), key, value, x);
+                                // A refused insert wrote nothing, so it neither claims the key, keeps the lock it
+                                // just took nor gets indexed.
+                                if let Err(e) = Node::insert(Arc::clone(&new_node), key, value.clone(), write_txd(&tx, x)) {
+                                    locks.release_from(x, held);
+                                    log_message(&e.to_string());
+                                    return Ok(1);
+                                }
+                                for index in indexes.iter() {
+                                    index.add(key, &value);
+                                }
+                                // Like `update`, only what was written is logged, so replaying the WAL does the same.
+                                flush_to_wal(Arc::clone(&file), args.clone())?;
                                 if let Some(item) = tx.items.get_mut(&x) {
-                                    item.modified_keys.push(key);
+                                    item.modified_keys.push((table.clone(), key));
                                 }
                             }
                             None => {
@@ -202,25 +402,45 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
                     let key = args[1].parse::<u32>().expect("Invalid argument");
                     let value = args[2].parse::<String>().expect("Invalid argument");
 
-                    let active_txd_vec = get_all_active_transaction(Arc::clone(&current_transaction), all_addr);
+                    let Some(txd) = current_transaction.read().unwrap().ip_txd.get(&addr).copied() else {
+                        println!("Active transaction not found. Update failed.");
+                        return Ok(1);
+                    };
+                    let held = locks.held(txd);
+                    let outcome = lock_write(key)?;
+                    if outcome != LockOutcome::Granted {
+                        log_message(outcome.message(txd).as_str());
+                        return Ok(1);
+                    }
 
-                    let txd = current_transaction.read().unwrap().ip_txd.get(&addr).unwrap().clone();
-                    let y = modified_key_check(active_txd_vec, key, txd, Arc::clone(&current_transaction) );
+                    let active_txd_vec = get_all_active_transaction(Arc::clone(&current_transaction), Arc::clone(&services.all_addr));
+                    let y = modified_key_check(active_txd_vec, &table, key, txd, Arc::clone(&current_transaction) )
+                        || serialization_conflict(Arc::clone(&new_node), key, txd, Arc::clone(&current_transaction));
 
                     if y {
-                        println!("The key you're trying to update has already been updated by another client.")
+                        locks.release_from(txd, held);
+                        log_message("The key you're trying to update has already been updated or locked by another client.")
                     } else {
                         let mut tx = current_transaction.write().unwrap();
 
                         match tx.ip_txd.get(&addr) {
                             Some(&x) => {
-                                match Node::find_and_update_key_version(Arc::clone(&new_node), key, Some(value), x, false) {
-                                    Some(_) => flush_to_wal(Arc::clone(&file), args)?,
-                                    None => log_message("Key not found"),
-                                }
-
-                                if let Some(item) = tx.items.get_mut(&x) {
-                                    item.modified_keys.push(key);
+                                // Like a refused insert, updating a missing key wrote nothing: it neither claims the
+                                // key nor keeps the lock it just took.
+                                match Node::find_and_update_key_version(Arc::clone(&new_node), key, Some(value.clone()), write_txd(&tx, x), false) {
+                                    Some(_) => {
+                                        for index in indexes.iter() {
+                                            index.add(key, &value);
+                                        }
+                                        flush_to_wal(Arc::clone(&file), args)?;
+                                        if let Some(item) = tx.items.get_mut(&x) {
+                                            item.modified_keys.push((table.clone(), key));
+                                        }
+                                    }
+                                    None => {
+                                        locks.release_from(x, held);
+                                        log_message("Key not found");
+                                    }
                                 }
                             }
                             None => {
@@ -239,23 +459,663 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
 
                     let key = args[1].parse::<u32>().expect("Invalid argument");
 
-                    {
-                        let tx = current_transaction.read().unwrap();
+                    let Some(txd) = current_transaction.read().unwrap().ip_txd.get(&addr).copied() else {
+                        println!("Active transaction not found. Delete failed.");
+                        return Ok(1);
+                    };
+                    let held = locks.held(txd);
+                    let outcome = lock_write(key)?;
+                    if outcome != LockOutcome::Granted {
+                        log_message(outcome.message(txd).as_str());
+                        return Ok(1);
+                    }
+
+                    let active_txd_vec = get_all_active_transaction(Arc::clone(&current_transaction), Arc::clone(&services.all_addr));
+                    let y = modified_key_check(active_txd_vec, &table, key, txd, Arc::clone(&current_transaction) )
+                        || serialization_conflict(Arc::clone(&new_node), key, txd, Arc::clone(&current_transaction));
+
+                    if y {
+                        locks.release_from(txd, held);
+                        log_message("The key you're trying to delete has already been updated or locked by another client.")
+                    } else {
+                        let mut tx = current_transaction.write().unwrap();
 
                         match tx.ip_txd.get(&addr) {
-                            Some(x) => {
-                                match Node::find_and_update_key_version(Arc::clone(&new_node), key, None, *x, true) {
+                            Some(&x) => {
+                                match Node::find_and_update_key_version(Arc::clone(&new_node), key, None, write_txd(&tx, x), true) {
                                     Some(_) => {
                                         flush_to_wal(Arc::clone(&file), args.clone())?;
+                                        if let Some(item) = tx.items.get_mut(&x) {
+                                            item.modified_keys.push((table.clone(), key));
+                                        }
+                                    }
+                                    None => {
+                                        locks.release_from(x, held);
+                                        log_message("Key not found");
                                     }
-                                    None => log_message("Key not found"),
                                 }
                             }
                             None => {
-                                println!("Active transaction not found. Update failed.");
+                                println!("Active transaction not found. Delete failed.");
+                                return Ok(1);
+                            }
+                        }
+                    }
+                }
+
+                // upsert <key> <value>, mset <key> <value> [<key> <value> ...], mdel <key> [<key> ...]
+                "upsert" | "mset" | "mdel" => {
+                    let command = args[0].to_lowercase();
+                    let operands = &args[1..args.len() - 1];
+                    let valid_len = match command.as_str() {
+                        "upsert" => operands.len() == 2,
+                        "mset" => !operands.is_empty() && operands.len().is_multiple_of(2),
+                        _ => !operands.is_empty(),
+                    };
+                    if !valid_len {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
+
+                    let step = if command == "mdel" { 1 } else { 2 };
+                    let mut writes = Vec::new();
+                    for operand in operands.chunks(step) {
+                        match operand[0].parse::<u32>() {
+                            Ok(key) => writes.push((key, operand.get(1).map(|value| value.to_string()))),
+                            Err(_) => {
+                                log_message("Invalid argument");
+                                return Ok(1);
+                            }
+                        }
+                    }
+
+                    let target = Target { table: &table, node: Arc::clone(&new_node), indexes: &indexes, changes: &changes, watches: &watches, txids: &txids, locks: &locks };
+                    let status = batch_write(target, writes, addr, Arc::clone(&current_transaction), Arc::clone(&file), args.clone())?;
+                    log_message(status.message().as_str());
+                    CHECKPOINT_COUNTER.fetch_add(1, Ordering::SeqCst);
+                }
+
+                "mget" => {
+                    if args.len() < 3 {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
+
+                    let mut keys = Vec::new();
+                    for operand in &args[1..args.len() - 1] {
+                        match operand.parse::<u32>() {
+                            Ok(key) => keys.push(key),
+                            Err(_) => {
+                                log_message("Invalid argument");
+                                return Ok(1);
+                            }
+                        }
+                    }
+
+                    let txd = current_transaction.read().unwrap().ip_txd.get(&addr).copied();
+                    match txd {
+                        Some(x) => {
+                            for key in keys {
+                                match select_key(Arc::clone(&new_node), key, x, Arc::clone(&current_transaction)) {
+                                    Some(value) => log_message(format!("Key: {} Value: {:?}", key, value).as_str()),
+                                    None => log_message(format!("Key: {} not found", key).as_str()),
+                                }
+                            }
+                        }
+                        None => log_message("Active transaction not found"),
+                    }
+                }
+
+                "cas" | "insert_if_absent" | "update_if_version" | "delete_if" => {
+                    let command = args[0].to_lowercase();
+                    let expected_len = if command == "cas" || command == "update_if_version" { 5 } else { 4 };
+                    if args.len() != expected_len {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
+
+                    let key = match args[1].parse::<u32>() {
+                        Ok(key) => key,
+                        Err(_) => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    };
+                    let (condition, value) = match command.as_str() {
+                        "cas" => (Condition::Equals(args[2].to_string()), Some(args[3].to_string())),
+                        "insert_if_absent" => (Condition::Absent, Some(args[2].to_string())),
+                        "delete_if" => (Condition::Equals(args[2].to_string()), None),
+                        _ => match args[2].parse::<u64>() {
+                            Ok(xmin) => (Condition::Version(xmin), Some(args[3].to_string())),
+                            Err(_) => {
+                                log_message("Invalid argument");
+                                return Ok(1);
+                            }
+                        },
+                    };
+
+                    let target = Target { table: &table, node: Arc::clone(&new_node), indexes: &indexes, changes: &changes, watches: &watches, txids: &txids, locks: &locks };
+                    let write = ConditionalWrite { key, condition, value };
+                    let status = conditional_write(target, write, addr, Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), args.clone())?;
+                    log_message(status.as_str());
+                    CHECKPOINT_COUNTER.fetch_add(1, Ordering::SeqCst);
+                }
+
+                "create" => {
+                    if args.len() < 4 || !valid_table_name(args[2]) {
+                        log_message("Invalid argument. Names may only contain letters, digits and '_'");
+                        return Ok(1);
+                    }
+
+                    match args[1].to_lowercase().as_str() {
+                        "table" if args.len() == 4 => {
+                            if catalog.write().unwrap().create_table(args[2]) {
+                                flush_to_wal(Arc::clone(&file), args)?;
+                            } else {
+                                log_message("Table already exists");
+                            }
+                        }
+
+                        // create index <name> on <table> [field]
+                        "index" if (args.len() == 6 || args.len() == 7) && args[3].to_lowercase() == "on" => {
+                            let field = if args.len() == 7 {
+                                match args[5].parse::<usize>() {
+                                    Ok(field) => Some(field),
+                                    Err(_) => {
+                                        log_message("Invalid argument");
+                                        return Ok(1);
+                                    }
+                                }
+                            } else {
+                                None
+                            };
+
+                            if catalog.write().unwrap().create_index(args[2], args[4], field) {
+                                flush_to_wal(Arc::clone(&file), args)?;
+                            } else {
+                                log_message("Index already exists or table not found");
+                            }
+                        }
+
+                        _ => log_message("Invalid argument"),
+                    }
+                }
+
+                "drop" => {
+                    if args.len() != 4 {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
+
+                    match args[1].to_lowercase().as_str() {
+                        "table" => {}
+                        "index" => {
+                            if catalog.write().unwrap().drop_index(args[2]) {
+                                flush_to_wal(Arc::clone(&file), args)?;
+                            } else {
+                                log_message("Index not found");
+                            }
+                            return Ok(1);
+                        }
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    }
+
+                    let mut catalog_write = catalog.write().unwrap();
+                    let in_use = current_transaction.read().unwrap().items.values()
+                        .filter(|item| item.status == TransactionStatus::Active)
+                        .any(|item| item.modified_keys.iter().any(|(t, _)| t == args[2]));
+
+                    if in_use {
+                        log_message("Table has uncommitted writes. Drop failed.");
+                    } else if catalog_write.drop_table(args[2]) {
+                        drop(catalog_write);
+                        flush_to_wal(Arc::clone(&file), args)?;
+                    } else {
+                        log_message("Table not found or can't be dropped");
+                    }
+                }
+
+                "use" => {
+                    if args.len() != 3 {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
+
+                    if catalog.write().unwrap().use_table(addr, args[1]) {
+                        flush_to_wal(Arc::clone(&file), args)?;
+                    } else {
+                        log_message("Table not found");
+                    }
+                }
+
+                // load <file> [fill_factor]
+                "load" => {
+                    if args.len() != 3 && args.len() != 4 {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
+
+                    let fill_factor = if args.len() == 4 {
+                        match args[2].parse::<f64>() {
+                            Ok(fill) if fill > 0.0 && fill <= 1.0 => fill,
+                            _ => {
+                                log_message("Invalid argument. The fill factor must be in (0, 1]");
+                                return Ok(1);
+                            }
+                        }
+                    } else {
+                        1.0
+                    };
+
+                    let pairs = match sorted_pairs(args[1], &std::env::temp_dir(), RUN_SIZE) {
+                        Ok(pairs) => pairs,
+                        Err(e) => {
+                            log_message(format!("Load failed: {}", e).as_str());
+                            return Ok(1);
+                        }
+                    };
+
+                    let mut txd_count_write = txd_count.write().unwrap();
+                    let tx_write = current_transaction.write().unwrap();
+                    let mut root_write = new_node.write().unwrap();
+                    if !root_write.input.is_empty() {
+                        log_message("Table is not empty. Load failed.");
+                        return Ok(1);
+                    }
+
+                    let txd = txids.allocate(&mut txd_count_write, &file)?;
+                    // The pairs are streamed out of the merge straight into the new tree, which only replaces the
+                    // empty one if every line could be read.
+                    let mut failed = None;
+                    let mut loaded = 0;
+                    let pairs = pairs.map_while(|pair| pair.map_err(|e| failed = Some(e)).ok()).inspect(|_| loaded += 1);
+                    let tree = Node::bulk_load(pairs, fill_factor, txd);
+                    if let Some(e) = failed {
+                        log_message(format!("Load failed: {}", e).as_str());
+                        return Ok(1);
+                    }
+                    *root_write = tree;
+                    drop(root_write);
+                    for index in indexes.iter() {
+                        index.build(&new_node);
+                    }
+                    drop(tx_write);
+                    drop(txd_count_write);
+
+                    flush_to_wal(Arc::clone(&file), args)?;
+                    log_message(format!("Loaded {} keys into {}", loaded, table).as_str());
+
+                    // The WAL only names the file, so the loaded rows are made durable by an image of their own
+                    // before the client hears back. Without a place for images the checkpoint thread is asked instead.
+                    match services.checkpoints.as_ref() {
+                        Some(paths) => checkpoint(Arc::clone(&catalog), Arc::clone(&services), Arc::clone(&current_transaction), &paths.image, &paths.wal, Arc::clone(&file)),
+                        None => return Ok(3),
+                    }
+                }
+
+                // export <file> <jsonl|csv> [history]
+                "export" => {
+                    let history = args.len() == 5 && args[3].to_lowercase() == "history";
+                    let format = match Format::parse(args.get(2).copied().unwrap_or_default()) {
+                        Some(format) if args.len() == 4 || history => format,
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    };
+
+                    // Reads inside the client's transaction if it has one, otherwise at the latest committed state.
+                    let (txd, view) = {
+                        let txd_count_read = txd_count.read().unwrap();
+                        let tx = current_transaction.read().unwrap();
+                        let active = tx.ip_txd.get(&addr).copied()
+                            .filter(|txd| tx.items.get(txd).is_some_and(|item| item.status == TransactionStatus::Active));
+                        read_view(&tx, active, *txd_count_read + 1)
+                    };
+
+                    let exported = File::create(args[1]).and_then(|out| {
+                        let mut out = io::BufWriter::new(out);
+                        let count = export(&new_node, &mut out, format, history, txd, &view)?;
+                        out.flush()?;
+                        Ok(count)
+                    });
+                    match exported {
+                        Ok(count) => log_message(format!("Exported {} records from {}", count, table).as_str()),
+                        Err(e) => log_message(format!("Export failed: {}", e).as_str()),
+                    }
+                }
+
+                // import <file> <jsonl|csv>
+                "import" => {
+                    let format = match Format::parse(args.get(2).copied().unwrap_or_default()) {
+                        Some(format) if args.len() == 4 => format,
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    };
+
+                    let records = match File::open(args[1]).and_then(|input| read_records(io::BufReader::new(input), format)) {
+                        Ok(records) => records,
+                        Err(e) => {
+                            log_message(format!("Import failed: {}", e).as_str());
+                            return Ok(1);
+                        }
+                    };
+
+                    let with_history = records.iter().filter(|record| record.history.is_some()).count();
+                    if with_history == 0 {
+                        // Plain values are written as the `mset` they amount to inside the client's transaction, and
+                        // logged as that `mset` too: replay and followers never need the file.
+                        let writes: Vec<(u32, Option<String>)> = records_to_items(records, 0).into_iter()
+                            .map(|mut item| (item.key, item.version.pop().map(|version| version.value)))
+                            .collect();
+                        let unloggable = writes.iter()
+                            .find(|(_, value)| value.as_ref().is_none_or(|value| value.is_empty() || value.contains(char::is_whitespace)));
+                        if let Some((key, _)) = unloggable {
+                            log_message(format!("Import failed: the value of key {} is empty or holds whitespace", key).as_str());
+                            return Ok(1);
+                        }
+                        let operands: Vec<String> = writes.iter()
+                            .flat_map(|(key, value)| [key.to_string(), value.clone().unwrap_or_default()])
+                            .collect();
+                        let mut mset = vec!["mset"];
+                        mset.extend(operands.iter().map(String::as_str));
+                        mset.push(args[args.len() - 1]);
+
+                        let target = Target { table: &table, node: Arc::clone(&new_node), indexes: &indexes, changes: &changes, watches: &watches, txids: &txids, locks: &locks };
+                        let status = batch_write(target, writes, addr, Arc::clone(&current_transaction), Arc::clone(&file), mset)?;
+                        log_message(status.message().as_str());
+                        CHECKPOINT_COUNTER.fetch_add(1, Ordering::SeqCst);
+                        return Ok(1);
+                    }
+                    if with_history != records.len() {
+                        log_message("Import failed: records mix values with and without version history");
+                        return Ok(1);
+                    }
+
+                    // A version history replaces the whole table, like `load`, and keeps the original txids.
+                    let highest_txd = records.iter()
+                        .filter_map(|record| record.history.as_ref())
+                        .map(|history| history.xmax.unwrap_or(0).max(history.xmin))
+                        .max()
+                        .unwrap_or(0);
+
+                    let mut txd_count_write = txd_count.write().unwrap();
+                    let tx_write = current_transaction.write().unwrap();
+                    let mut root_write = new_node.write().unwrap();
+                    if !root_write.input.is_empty() {
+                        log_message("Table is not empty. Import failed.");
+                        return Ok(1);
+                    }
+
+                    *txd_count_write = (*txd_count_write).max(highest_txd);
+                    let count = records.len();
+                    *root_write = Node::bulk_load_items(records_to_items(records, 0), 1.0);
+                    drop(root_write);
+                    drop(tx_write);
+                    drop(txd_count_write);
+
+                    for index in indexes.iter() {
+                        index.build(&new_node);
+                    }
+
+                    flush_to_wal(Arc::clone(&file), args)?;
+                    log_message(format!("Imported {} versions into {}", count, table).as_str());
+
+                    // Like `load`, the record only names the file: the imported history is in an image before the
+                    // client hears back, so recovery never reads the file again.
+                    match services.checkpoints.as_ref() {
+                        Some(paths) => checkpoint(Arc::clone(&catalog), Arc::clone(&services), Arc::clone(&current_transaction), &paths.image, &paths.wal, Arc::clone(&file)),
+                        None => return Ok(3),
+                    }
+                }
+
+                // retention [keep_all | transactions <n> | seconds <t> | versions <k>]
+                "retention" => {
+                    if args.len() == 2 {
+                        let policy = catalog.read().unwrap().retention;
+                        log_message(format!("Retention: {}", policy).as_str());
+                        return Ok(1);
+                    }
+
+                    match RetentionPolicy::parse(&args[1..args.len() - 1]) {
+                        Some(policy) => {
+                            catalog.write().unwrap().retention = policy;
+                            flush_to_wal(Arc::clone(&file), args)?;
+                            log_message(format!("Retention: {}", policy).as_str());
+                        }
+                        None => log_message("Invalid argument"),
+                    }
+                }
+
+                // timeout [idle|total <seconds>], for the current transaction
+                "timeout" => {
+                    let timeouts = services.timeouts();
+                    let mut tx = current_transaction.write().unwrap();
+                    let Some(item) = active_txd(&tx, &addr).and_then(|txd| tx.items.get_mut(&txd)) else {
+                        if args.len() == 2 {
+                            log_message(format!("Idle timeout: {}s, maximum transaction age: {}s", timeouts.idle.as_secs_f64(), timeouts.max_age.as_secs_f64()).as_str());
+                        } else {
+                            log_message("Active transaction not found.");
+                        }
+                        return Ok(1);
+                    };
+
+                    match &args[1..args.len() - 1] {
+                        [] => {}
+                        [kind, seconds] => {
+                            let Some(timeout) = seconds.parse::<f64>().ok().and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()).filter(|timeout| !timeout.is_zero()) else {
+                                log_message("Invalid argument");
+                                return Ok(1);
+                            };
+                            match *kind {
+                                "idle" => item.lifetime.idle = Some(timeout),
+                                "total" => item.lifetime.total = Some(timeout),
+                                _ => {
+                                    log_message("Invalid argument");
+                                    return Ok(1);
+                                }
+                            }
+                        }
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    }
+                    log_message(format!("Idle timeout: {}s, total timeout: {}s", item.lifetime.idle_timeout(timeouts).as_secs_f64(), item.lifetime.total_timeout(timeouts).as_secs_f64()).as_str());
+                }
+
+                "vacuum" => {
+                    let stats = match &args[1..args.len() - 1] {
+                        [] => vacuum(Arc::clone(&catalog), Arc::clone(&services), Arc::clone(&txd_count), Arc::clone(&current_transaction)),
+                        ["step"] => vacuum_tick(Arc::clone(&catalog), Arc::clone(&services), Arc::clone(&txd_count), Arc::clone(&current_transaction), TICK_NODES),
+                        ["step", nodes] => match nodes.parse::<usize>() {
+                            Ok(nodes) if nodes > 0 => vacuum_tick(Arc::clone(&catalog), Arc::clone(&services), Arc::clone(&txd_count), Arc::clone(&current_transaction), nodes),
+                            _ => {
+                                log_message("Invalid argument");
+                                return Ok(1);
+                            }
+                        },
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    };
+
+                    match stats {
+                        Some(stats) => {
+                            let state = Arc::clone(&services.vacuum);
+                            let state = state.lock().unwrap_or_else(|e| e.into_inner());
+                            log_message(format!("Reclaimed {} versions, removed {} keys (horizon {})", stats.versions_reclaimed, stats.keys_removed, state.last_horizon).as_str());
+                            log_message(format!("Since startup: {} runs, {} steps, {} versions, {} keys", state.runs, state.ticks, state.totals.versions_reclaimed, state.totals.keys_removed).as_str());
+                        }
+                        None => log_message("Retention is keep_all, nothing to reclaim"),
+                    }
+                }
+
+                // subscribe [from_lsn]
+                "subscribe" => {
+                    let from_lsn = match &args[1..args.len() - 1] {
+                        [] => None,
+                        [lsn] => match lsn.parse::<u64>() {
+                            Ok(lsn) => Some(lsn),
+                            Err(_) => {
+                                log_message("Invalid argument");
+                                return Ok(1);
+                            }
+                        },
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    };
+                    let Some(stream) = stream else {
+                        log_message("subscribe needs a TCP connection");
+                        return Ok(1);
+                    };
+
+                    let mut subscription = match changes.subscribe(from_lsn) {
+                        Ok(subscription) => subscription,
+                        Err(e) => {
+                            log_message(e.message().as_str());
+                            return Ok(1);
+                        }
+                    };
+                    log_message(format!("Subscribed from {}", subscription.position()).as_str());
+
+                    // The connection belongs to the subscription from now on and is closed when it ends.
+                    stream_changes(&mut subscription, stream)?;
+                    return Ok(2);
+                }
+
+                // watch <key | from-to>, wait <key | from-to> <timeout_ms>
+                "watch" | "wait" => {
+                    let command = args[0].to_lowercase();
+                    let expected_len = if command == "wait" { 4 } else { 3 };
+                    let pattern = KeyPattern::parse(args.get(1).copied().unwrap_or_default());
+                    let timeout = match command.as_str() {
+                        "wait" => args.get(2).and_then(|ms| ms.parse::<u64>().ok()).map(Duration::from_millis),
+                        _ => None,
+                    };
+                    let valid = args.len() == expected_len && (command == "watch" || timeout.is_some());
+                    let pattern = match pattern {
+                        Some(pattern) if valid => pattern,
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    };
+
+                    // Registered before blocking, so any commit from here on is seen.
+                    let watch = watches.register(&table, pattern);
+                    let outcome = match stream {
+                        Some(stream) => watch.wait_connected(timeout, stream)?,
+                        None => watch.wait(timeout).map_or(WaitOutcome::TimedOut, WaitOutcome::Changed),
+                    };
+                    match outcome {
+                        WaitOutcome::Changed(notification) => log_message(notification.message().as_str()),
+                        WaitOutcome::TimedOut => log_message("TIMEOUT"),
+                        WaitOutcome::Disconnected => return Ok(2),
+                    }
+                }
+
+                // replicate [epoch], sent by a follower. The connection then carries the WAL stream until it
+                // disconnects.
+                "replicate" => {
+                    let epoch = match &args[1..args.len() - 1] {
+                        [] => 0,
+                        [epoch] => match epoch.parse::<u64>() {
+                            Ok(epoch) => epoch,
+                            Err(_) => {
+                                log_message("Invalid argument");
                                 return Ok(1);
                             }
+                        },
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
                         }
+                    };
+                    let Some(stream) = stream else {
+                        log_message("replicate needs a TCP connection");
+                        return Ok(1);
+                    };
+
+                    // A follower from a newer epoch means another server was promoted meanwhile.
+                    let replication = Arc::clone(&services.replication);
+                    if epoch > replication.epoch() {
+                        replication.fence(epoch);
+                        log_message(format!("FAILED: epoch {} is newer than this server's epoch {}", epoch, replication.epoch()).as_str());
+                        return Ok(3);
+                    }
+
+                    serve_follower(&WAL_SHIPPER, stream, &txd_count, &current_transaction, &catalog, &services)?;
+                    return Ok(2);
+                }
+
+                // Stops following, resumes allocating txids and starts taking writes as the primary of a new epoch.
+                "promote" => {
+                    if args.len() != 2 {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
+
+                    let Some(promotion) = promote(&txd_count, &current_transaction, &catalog, &services) else {
+                        log_message("FAILED: only a follower can be promoted");
+                        return Ok(1);
+                    };
+                    log_message(format!("Promoted to primary at epoch {}, next txid {}", promotion.epoch, promotion.next_txid).as_str());
+                    match fence_remote(&promotion.old_primary, promotion.epoch) {
+                        Ok(answer) => log_message(format!("Old primary {}: {}", promotion.old_primary, answer).as_str()),
+                        Err(e) => log_message(format!("Old primary {} not reached ({}), it's fenced once it sees epoch {}", promotion.old_primary, e, promotion.epoch).as_str()),
+                    }
+                    // Checkpointed right away, so the new epoch survives a restart.
+                    return Ok(3);
+                }
+
+                // fence <epoch>, sent by a promoted follower to its old primary.
+                "fence" => {
+                    let epoch = match args.get(1).and_then(|epoch| epoch.parse::<u64>().ok()) {
+                        Some(epoch) if args.len() == 3 => epoch,
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    };
+
+                    let replication = Arc::clone(&services.replication);
+                    if !replication.fence(epoch) {
+                        log_message(format!("Not fenced, this server is no primary of an epoch before {}", epoch).as_str());
+                        return Ok(1);
+                    }
+                    log_message(format!("Fenced by epoch {}", epoch).as_str());
+                    // Checkpointed right away, so the fence survives a restart.
+                    return Ok(3);
+                }
+
+                "replication" => {
+                    if args.len() != 2 {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
+
+                    let replication = Arc::clone(&services.replication);
+                    match replication.role() {
+                        Role::Primary => {
+                            log_message(format!("Role: primary at epoch {}, next record {}", replication.epoch(), WAL_SHIPPER.next_seq()).as_str());
+                            for lag in WAL_SHIPPER.lag() {
+                                log_message(lag.message().as_str());
+                            }
+                        }
+                        Role::Follower(primary) => {
+                            let connection = if replication.connected() { "connected" } else { "disconnected" };
+                            log_message(format!("Role: follower of {} ({}) at epoch {}, applied {}", primary, connection, replication.epoch(), replication.applied()).as_str());
+                        }
+                        Role::Fenced(newer) => log_message(format!("Role: fenced primary of epoch {}, superseded by epoch {}", replication.epoch(), newer).as_str()),
                     }
                 }
 
@@ -277,14 +1137,15 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
                     let key = args[1].parse::<u32>().expect("Invalid argument");
 
                     {
-                        let tx = current_transaction.read().unwrap();
+                        // Copied out so the table isn't read-locked twice, `select_key` takes its own read lock.
+                        let txd = current_transaction.read().unwrap().ip_txd.get(&addr).copied();
 
-                        match tx.ip_txd.get(&addr) {
-                            Some(&x) => {
+                        match txd {
+                            Some(x) => {
                                 // let last_txd = tx.items.get(&x).unwrap().last_txd;
 
                                 let messages ;
-                                match select_key(Arc::clone(&new_node), key, *txd_count.read().unwrap(), x, Arc::clone(&current_transaction)) {
+                                match select_key(Arc::clone(&new_node), key, x, Arc::clone(&current_transaction)) {
                                     Some(value) => {
                                         messages = format!("Value: {:?}", value)
                                     },
@@ -296,11 +1157,111 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
                                 log_message(messages.as_str());
 
                             }
-                            None => {}
+                            None => log_message("Active transaction not found"),
                         }
                     }
                 }
 
+                // select_as_of <key> <txid>, scan_as_of <txid> [<from> <to>]
+                "select_as_of" | "scan_as_of" => {
+                    let command = args[0].to_lowercase();
+                    let operands = &args[1..args.len() - 1];
+                    let (as_of, keys) = match operands.split_at_checked(if command == "select_as_of" { 1 } else { 0 }) {
+                        Some((keys, [as_of, rest @ ..])) => (as_of.parse::<u64>(), keys.iter().chain(rest).map(|key| key.parse::<u32>()).collect::<Result<Vec<u32>, _>>()),
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    };
+                    let (as_of, keys) = match (as_of, keys) {
+                        (Ok(as_of), Ok(keys)) if command == "select_as_of" && keys.len() == 1 => (as_of, keys),
+                        (Ok(as_of), Ok(keys)) if command == "scan_as_of" && (keys.is_empty() || keys.len() == 2) => (as_of, keys),
+                        _ => {
+                            log_message("Invalid argument");
+                            return Ok(1);
+                        }
+                    };
+
+                    if as_of > *txd_count.read().unwrap() {
+                        log_message("FAILED: txid not allocated yet");
+                        return Ok(1);
+                    }
+                    let gc_horizon = catalog.read().unwrap().gc_horizon.load(Ordering::SeqCst);
+
+                    if command == "select_as_of" {
+                        match select_as_of(Arc::clone(&new_node), keys[0], as_of, gc_horizon, Arc::clone(&current_transaction)) {
+                            Ok(Some(value)) => log_message(format!("Value: {:?}", value).as_str()),
+                            Ok(None) => log_message("Key not found"),
+                            Err(e) => log_message(e.message().as_str()),
+                        }
+                    } else {
+                        let range = if keys.len() == 2 { keys[0]..=keys[1] } else { 0..=u32::MAX };
+                        match scan_as_of(Arc::clone(&new_node), range, as_of, gc_horizon, Arc::clone(&current_transaction)) {
+                            Ok(found) => {
+                                if found.is_empty() {
+                                    log_message("Key not found");
+                                }
+                                for (key, value) in found {
+                                    log_message(format!("Key: {} Value: {:?}", key, value).as_str());
+                                }
+                            }
+                            Err(e) => log_message(e.message().as_str()),
+                        }
+                    }
+                }
+
+                "select_by" => {
+                    if args.len() != 4 {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
+
+                    let index = match catalog.read().unwrap().index(args[1]) {
+                        Some(index) => index,
+                        None => {
+                            log_message("Index not found");
+                            return Ok(1);
+                        }
+                    };
+                    let txd = current_transaction.read().unwrap().ip_txd.get(&addr).copied();
+                    let node = catalog.read().unwrap().table(&index.table);
+
+                    if let (Some(x), Some(node)) = (txd, node) {
+                        let found = lookup(&index, node, args[2], x, Arc::clone(&current_transaction));
+                        if found.is_empty() {
+                            log_message("Key not found");
+                        }
+                        for (key, value) in found {
+                            log_message(format!("Key: {} Value: {:?}", key, value).as_str());
+                        }
+                    }
+                }
+
+                // count <from> <to>, aggregate <count|min|max> [<from> <to>]
+                // No `prefix` until keys can be byte strings, see `KeyPattern`.
+                "count" | "aggregate" => {
+                    let range = |from: &str, to: &str| match (from.parse::<u32>(), to.parse::<u32>()) {
+                        (Ok(from), Ok(to)) => Some(vec![from..=to]),
+                        _ => None,
+                    };
+                    let query = match (args[0].to_lowercase().as_str(), &args[1..args.len() - 1]) {
+                        ("count", [from, to]) => range(from, to).map(|ranges| (Aggregate::Count, ranges)),
+                        ("aggregate", [name]) => Aggregate::parse(name).map(|aggregate| (aggregate, vec![0..=u32::MAX])),
+                        ("aggregate", [name, from, to]) => Aggregate::parse(name).zip(range(from, to)),
+                        _ => None,
+                    };
+                    let Some((aggregate, ranges)) = query else {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    };
+                    let Some(txd) = current_transaction.read().unwrap().ip_txd.get(&addr).copied() else {
+                        log_message("Active transaction not found");
+                        return Ok(1);
+                    };
+
+                    log_message(&aggregate.message(aggregate_in(&new_node, &ranges, aggregate, txd, &current_transaction)));
+                }
+
                 "dump" => {
                     if args.len() != 3 {
                         log_message("Invalid argument");
@@ -349,7 +1310,7 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
 
                         match tx.ip_txd.get(&addr) {
                             Some(&x) => {
-                                let tree_node = snapshot(duplicate_node, Some(x));
+                                let tree_node = snapshot(duplicate_node, x);
                                 println!("{:?}", tree_node.read().unwrap().print_tree());
                             }
                             None => {}
@@ -369,7 +1330,7 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
 
                         match tx.ip_txd.get(&addr) {
                             Some(&x) => {
-                                let tree_node = snapshot(duplicate_node, Some(x));
+                                let tree_node = snapshot(duplicate_node, x);
                                 println!("{:?}", tree_node.read().unwrap().print_stats());
                             }
                             None => {}
@@ -377,6 +1338,16 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
                     }
                 }
 
+                "check" => {
+                    if args.len() != 2 {
+                        log_message("Invalid argument");
+                        return Ok(1);
+                    }
+
+                    let report = Node::check(Arc::clone(&new_node));
+                    log_message(report.to_json_lines().as_str());
+                }
+
                 "help" => {
                     if args.len() != 2 {
                         log_message("Invalid argument");
@@ -390,10 +1361,52 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
                  dump <key>            - Get all the values for the key\n
                  delete <key>          - Delete a key\n
                  begin                 - Start a cycle\n
+                 begin read only       - Start a cycle that only reads, without logging it\n
                  commit                - Push a new version of the key\n
                  abort                 - Abort the current cycle\n
+                 prepare <gid>         - Prepare the current transaction to be committed or rolled back later, from any client\n
+                 commit prepared <gid> - Commit a prepared transaction\n
+                 rollback prepared <gid> - Roll back a prepared transaction\n
+                 savepoint <name>      - Mark a point in the current transaction to roll back to\n
+                 rollback to <name>    - Undo what the current transaction wrote since the savepoint\n
+                 release <name>        - Forget the savepoint, keeping what was written since\n
+                 lock <key> [shared|exclusive] - Lock a key until the current transaction ends, waiting for other holders\n
+                 select_for_update <key> - Lock a key exclusively and get its visible value\n
                  tree                  - Show B-Tree in ASCII art form\n
                  stats                 - Show B-Tree Stats\n
+                 check                 - Report B-Tree and MVCC invariant violations\n
+                 upsert <key> <value>  - Update a key, inserting it if it doesn't exist\n
+                 mset <key> <value> ...               - Upsert several keys at once\n
+                 mget <key> ...                       - Get the visible values for several keys\n
+                 mdel <key> ...                       - Delete several keys at once\n
+                 cas <key> <expected> <new>           - Update only if the visible value is <expected>\n
+                 insert_if_absent <key> <value>       - Insert only if no value is visible\n
+                 update_if_version <key> <xmin> <new> - Update only if the visible version was written by txid <xmin>\n
+                 delete_if <key> <expected>           - Delete only if the visible value is <expected>\n
+                 create table <name>   - Create a new table\n
+                 drop table <name>     - Drop a table and all its keys\n
+                 use <name>            - Run the following commands against a table\n
+                 create index <name> on <table> [field] - Index a table by value, or by a comma-separated field of it\n
+                 drop index <name>     - Drop an index\n
+                 select_by <index> <value> - Get the visible keys and values matching an indexed value\n
+                 count <from> <to>     - Count the visible keys in [from, to]\n
+                 aggregate <count|min|max> [<from> <to>] - Count the visible keys, or find the smallest or largest one\n
+                 select_as_of <key> <txid> - Get the value the key had once txid <txid> committed\n
+                 scan_as_of <txid> [<from> <to>] - List the keys and values as of txid <txid>, optionally only keys in [from, to]\n
+                 load <file> [fill_factor] - Bulk load '<key> <value>' lines into an empty table, then checkpoint\n
+                 export <file> <jsonl|csv> [history] - Write the visible values, or every version, of the table to a file\n
+                 import <file> <jsonl|csv> - Upsert exported values in a transaction, or restore an exported history into an empty table, then checkpoint\n
+                 retention [keep_all | transactions <n> | seconds <t> | versions <k>] - Show or set how long old versions are kept\n
+                 timeout [idle|total <seconds>] - Show or set how long the current transaction may stay idle or run\n
+                 vacuum                - Reclaim old versions now and report what was reclaimed\n
+                 vacuum step [nodes]   - Vacuum the next few nodes, like the background vacuum does\n
+                 subscribe [from_lsn]  - Stream committed changes as JSON lines, from a position or from now on\n
+                 watch <key|from-to>   - Block until another transaction commits the key, or a key in [from, to], and print its new value\n
+                 wait <key|from-to> <ms> - Like watch, printing TIMEOUT if nothing changes in time\n
+                 replication           - Show the role of this server and how far behind its followers are\n
+                 replicate [epoch]     - Follow this server, sent by followers to get its WAL\n
+                 promote               - Turn this follower into the primary of a new epoch and fence the old primary\n
+                 fence <epoch>         - Stop taking writes because a newer epoch was promoted\n
                  help                  - List out all the commands\n
                  exit                  - Exit the program"
                     };
@@ -430,4 +1443,29 @@ pub fn cli(cli_input: String, txd_count: Arc<RwLock<u32>>, current_transaction:
 
 
     Ok(0)
-}
\ No newline at end of file
+}
+
+/// Commits or aborts `txd`, an active transaction, releasing the transaction table `tx` once it's marked: merges its
+/// savepoints, publishes what it committed, resolves the versions it wrote and releases its row locks.
+fn end_transaction(mut tx: RwLockWriteGuard<Transaction>, txd: u64, commit: bool, tables: &HashMap<String, Arc<RwLock<Node>>>, changes: &ChangeLog, watches: &Watches, locks: &LockManager) -> io::Result<()> {
+    end_savepoints(&mut tx, txd, |table| tables.get(table).cloned());
+    let mut modified_key_vec = Vec::new();
+    if let Some(items) = tx.items.get_mut(&txd) {
+        items.status = if commit { TransactionStatus::Committed } else { TransactionStatus::Aborted };
+        modified_key_vec = items.modified_keys.clone();
+    }
+    // Published before the transaction table is released, so the change stream follows commit order.
+    let committed = if commit { capture_changes(|table| tables.get(table).cloned(), txd, &modified_key_vec) } else { Vec::new() };
+    let published = changes.publish(txd, &committed);
+    drop(tx);
+    for (table, key) in modified_key_vec.iter() {
+        if let Some(node) = tables.get(table) {
+            commit_abort_handler(Arc::clone(node), *key, txd, commit);
+        }
+    }
+    if commit {
+        watches.notify(txd, &committed);
+    }
+    locks.release_all(txd);
+    published
+}
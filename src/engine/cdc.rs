@@ -0,0 +1,333 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use crate::btree::node::Node;
+use crate::MVCC::visibility::with_versions;
+use crate::storage::interchange::{json_string, parse_json_object};
+use crate::storage::manifest::{sync_parent_dir, tmp_path_for};
+
+/// How often a blocked `subscribe`, `watch` or `wait` connection checks whether its client is still there.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Events kept for subscribers to resume from, older ones are dropped and can't be subscribed from anymore.
+pub const RETAINED_EVENTS: usize = 100_000;
+
+/// One committed change to one key. The events of a transaction are contiguous and share `commit` and `txid`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    /// Position in the change stream, one per event. What subscribers resume from.
+    pub lsn: u64,
+    /// Commit order of the transaction that made the change.
+    pub commit: u64,
+    pub txid: u64,
+    pub table: String,
+    pub key: u32,
+    /// Value before the transaction, [`None`] for an insert.
+    pub old: Option<String>,
+    /// Value after the transaction, [`None`] for a delete.
+    pub new: Option<String>,
+}
+
+impl ChangeEvent {
+    /// One JSON object per event, the format sent to `subscribe` clients and kept in the change log file.
+    pub fn to_json(&self) -> String {
+        let value = |value: &Option<String>| value.as_deref().map_or(String::from("null"), json_string);
+        format!("{{\"lsn\":{},\"commit\":{},\"txid\":{},\"table\":{},\"key\":{},\"old\":{},\"new\":{}}}",
+            self.lsn, self.commit, self.txid, json_string(&self.table), self.key, value(&self.old), value(&self.new))
+    }
+
+    pub fn from_json(line: &str) -> Option<ChangeEvent> {
+        let fields = parse_json_object(line)?;
+        let field = |name: &str| fields.iter().find(|(field, _)| field == name).map(|(_, value)| value.clone());
+        Some(ChangeEvent {
+            lsn: field("lsn")??.parse().ok()?,
+            commit: field("commit")??.parse().ok()?,
+            txid: field("txid")??.parse().ok()?,
+            table: field("table")??,
+            key: field("key")??.parse().ok()?,
+            old: field("old")?,
+            new: field("new")?,
+        })
+    }
+}
+
+/// A change of a transaction that's about to be published, see [`capture_changes`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub table: String,
+    pub key: u32,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CdcError {
+    /// The position was dropped from the log, `oldest` is the first one still there.
+    Truncated { oldest: u64 },
+    /// The position hasn't been written yet, `next` is the next one that will be.
+    Ahead { next: u64 },
+}
+
+impl CdcError {
+    pub fn message(&self) -> String {
+        match self {
+            CdcError::Truncated { oldest } => format!("FAILED: position no longer retained, oldest is {}", oldest),
+            CdcError::Ahead { next } => format!("FAILED: position not written yet, next is {}", next),
+        }
+    }
+}
+
+/// The ordered stream of committed changes, fed by every commit and read through [`Subscription`]s.
+#[derive(Debug, Default)]
+pub struct ChangeLog {
+    state: Mutex<LogState>,
+    appended: Condvar,
+}
+
+#[derive(Debug)]
+struct LogState {
+    events: VecDeque<ChangeEvent>,
+    next_lsn: u64,
+    next_commit: u64,
+    /// Where events are appended, if the log is durable.
+    file: Option<File>,
+}
+
+impl Default for LogState {
+    fn default() -> LogState {
+        LogState { events: VecDeque::new(), next_lsn: 1, next_commit: 1, file: None }
+    }
+}
+
+impl ChangeLog {
+    /// Opens the change log kept in `path`, creating it if needed, and carries on numbering after its last event.
+    /// Only the last [`RETAINED_EVENTS`] are kept, the file is rewritten with them if it had more.
+    pub fn open(path: &str) -> io::Result<ChangeLog> {
+        let mut state = LogState::default();
+        let mut dropped = false;
+        match File::open(path) {
+            Ok(existing) => {
+                for (number, line) in BufReader::new(existing).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let event = ChangeEvent::from_json(&line).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, format!("Malformed change log entry at line {}", number + 1))
+                    })?;
+                    state.next_lsn = event.lsn + 1;
+                    state.next_commit = event.commit + 1;
+                    state.events.push_back(event);
+                    if state.events.len() > RETAINED_EVENTS {
+                        state.events.pop_front();
+                        dropped = true;
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        if dropped {
+            let tmp = tmp_path_for(Path::new(path));
+            {
+                let mut out = File::create(&tmp)?;
+                for event in state.events.iter() {
+                    writeln!(out, "{}", event.to_json())?;
+                }
+                out.sync_all()?;
+            }
+            fs::rename(&tmp, path)?;
+            sync_parent_dir(Path::new(path))?;
+        }
+        state.file = Some(OpenOptions::new().append(true).create(true).open(path)?);
+        Ok(ChangeLog { state: Mutex::new(state), appended: Condvar::new() })
+    }
+
+    /// Appends the changes of committed transaction `txid` as one commit, waking up subscribers.
+    ///
+    /// Callers hold the `Transaction` table's write lock from marking `txid` committed until this returns, so commits
+    /// are published in the order they happen. Transactions without changes don't take a commit number.
+    pub fn publish(&self, txid: u64, changes: &[Change]) -> io::Result<()> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let commit = state.next_commit;
+        state.next_commit += 1;
+
+        let mut lines = String::new();
+        for change in changes {
+            let event = ChangeEvent { lsn: state.next_lsn, commit, txid, table: change.table.clone(), key: change.key, old: change.old.clone(), new: change.new.clone() };
+            state.next_lsn += 1;
+            lines.push_str(&event.to_json());
+            lines.push('\n');
+            state.events.push_back(event);
+        }
+        while state.events.len() > RETAINED_EVENTS {
+            state.events.pop_front();
+        }
+
+        let written = match state.file.as_mut() {
+            Some(file) => file.write_all(lines.as_bytes()).and_then(|_| file.sync_data()),
+            None => Ok(()),
+        };
+        drop(state);
+        self.appended.notify_all();
+        written
+    }
+
+    /// The position the next event will get.
+    pub fn next_lsn(&self) -> u64 {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).next_lsn
+    }
+
+    /// Starts reading the stream at `from_lsn`, or at the next event written if [`None`].
+    pub fn subscribe(self: &Arc<Self>, from_lsn: Option<u64>) -> Result<Subscription, CdcError> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let next_lsn = match from_lsn {
+            None => state.next_lsn,
+            Some(lsn) => {
+                state.check(lsn)?;
+                lsn
+            }
+        };
+        Ok(Subscription { log: Arc::clone(self), next_lsn })
+    }
+}
+
+impl LogState {
+    fn oldest(&self) -> u64 {
+        self.events.front().map_or(self.next_lsn, |event| event.lsn)
+    }
+
+    fn check(&self, lsn: u64) -> Result<(), CdcError> {
+        if lsn < self.oldest() {
+            return Err(CdcError::Truncated { oldest: self.oldest() });
+        }
+        if lsn > self.next_lsn {
+            return Err(CdcError::Ahead { next: self.next_lsn });
+        }
+        Ok(())
+    }
+
+    fn get(&self, lsn: u64) -> Option<&ChangeEvent> {
+        self.events.get(usize::try_from(lsn.checked_sub(self.oldest())?).ok()?)
+    }
+}
+
+/// A reader of the change stream, handing out events in order from its position on. Iterating blocks until the next
+/// event is committed, and ends if the reader fell so far behind that its position was dropped.
+#[derive(Debug)]
+pub struct Subscription {
+    log: Arc<ChangeLog>,
+    next_lsn: u64,
+}
+
+impl Subscription {
+    /// The position of the next event, what to subscribe from to resume after the last one received.
+    pub fn position(&self) -> u64 {
+        self.next_lsn
+    }
+
+    /// The next event, waiting at most `timeout` for it to be committed.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<Option<ChangeEvent>, CdcError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.log.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            state.check(self.next_lsn)?;
+            if let Some(event) = state.get(self.next_lsn) {
+                self.next_lsn += 1;
+                return Ok(Some(event.clone()));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            state = self.log.appended.wait_timeout(state, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = ChangeEvent;
+
+    fn next(&mut self) -> Option<ChangeEvent> {
+        let mut state = self.log.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            state.check(self.next_lsn).ok()?;
+            if let Some(event) = state.get(self.next_lsn) {
+                self.next_lsn += 1;
+                return Some(event.clone());
+            }
+            state = self.log.appended.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+}
+
+/// Reads what `txid` changed on each of its `modified_keys`, in the order they were first modified, before it's
+/// published. Keys it inserted and deleted again, leaving nothing behind, are skipped.
+///
+/// `txid` must still hold its keys, i.e. be active or just marked committed with the `Transaction` table still
+/// write-locked: its versions can't change under the read then.
+pub fn capture_changes(table: impl Fn(&str) -> Option<Arc<RwLock<Node>>>, txid: u64, modified_keys: &[(String, u32)]) -> Vec<Change> {
+    let mut changes: Vec<Change> = Vec::new();
+    for (name, key) in modified_keys.iter() {
+        if changes.iter().any(|change| change.key == *key && change.table == *name) {
+            continue;
+        }
+        let Some(node) = table(name) else { continue };
+        let change = with_versions(&node, *key, |versions| {
+            let old = versions.iter().find(|version| version.xmax == Some(txid) && version.xmin != txid);
+            let new = versions.iter().rev().find(|version| version.xmin == txid && version.xmax.is_none());
+            (old.map(|version| version.value.clone()), new.map(|version| version.value.clone()))
+        });
+        if let Some((old, new)) = change && (old.is_some() || new.is_some()) {
+            changes.push(Change { table: name.clone(), key: *key, old, new });
+        }
+    }
+    changes
+}
+
+/// Sends every event of `subscription` to `stream` as a JSON line as it's committed, until the client disconnects or
+/// falls behind what's retained. Used by `subscribe`, which gives the connection over to it.
+pub fn stream_changes(subscription: &mut Subscription, stream: &TcpStream) -> io::Result<()> {
+    let mut stream = stream;
+    loop {
+        match subscription.next_timeout(POLL_INTERVAL) {
+            Ok(Some(event)) => {
+                if writeln!(stream, "{}", event.to_json()).is_err() {
+                    return Ok(());
+                }
+            }
+            Ok(None) => {
+                if disconnected(stream)? {
+                    return Ok(());
+                }
+            }
+            Err(e) => {
+                writeln!(stream, "{}", e.message())?;
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Whether the other end closed `stream`, checked without blocking or consuming anything it sent.
+pub(crate) fn disconnected(stream: &TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let peeked = stream.peek(&mut [0u8; 1]);
+    stream.set_nonblocking(false)?;
+    match peeked {
+        Ok(0) => Ok(true),
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+        Err(_) => Ok(true),
+    }
+}
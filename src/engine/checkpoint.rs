@@ -1,35 +1,36 @@
 use std::fs::File;
-use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 use std::sync::atomic::Ordering;
-use crate::btree::node::Node;
+use crate::catalog::tables::Catalog;
 use crate::CHECKPOINT_COUNTER;
-use crate::MVCC::gc::remove_dead_version;
-use crate::MVCC::snapshot::snapshot;
+use crate::engine::services::Services;
 use crate::storage::io::empty_file;
-use crate::storage::ser::serialize;
 use crate::storage::wal::reader::get_uncommitted_transactions;
-use crate::storage::wal::writer::flush_to_wal;
-use crate::transactions::manager::get_all_active_transaction;
+use crate::storage::wal::writer::carry_over_to_wal;
+use crate::transactions::manager::get_active_txd_snapshot;
 use crate::transactions::transactions::Transaction;
 
-pub fn checkpoint(node: Arc<RwLock<Node>>, serialized_file_path: &str, wal_file_path: &str, file: Arc<RwLock<File>>, all_addr: Arc<RwLock<Vec<SocketAddr>>>, transaction: Arc<RwLock<Transaction>> ) {
-    let all_active_txd =  get_all_active_transaction(transaction, all_addr);
-    
-    if all_active_txd.len() == 0 { 
-        println!("The given transaction has no active transaction. That's odd. HMM");
-    } else {
-        let x = all_active_txd.first().unwrap();
-        remove_dead_version(Arc::clone(&node), *x);
-    }
-    
-    let mut cloned_node = node.clone();
+/// The catalog image [`checkpoint`] writes and the WAL it truncates.
+#[derive(Debug, Clone)]
+pub struct CheckpointPaths {
+    pub image: String,
+    pub wal: String,
+}
 
-    cloned_node = snapshot(cloned_node, None);
+/// Checkpoints every table in the catalog, see [`Catalog::store_with`]. Reclaiming old versions is left to
+/// [`crate::engine::vacuum`], which runs on its own schedule.
+///
+/// The `Transaction` table is held while the images are written, so no transaction writes, begins, commits or aborts
+/// meanwhile. The ones in progress are recorded with the images and rolled back when they're loaded.
+pub fn checkpoint(catalog: Arc<RwLock<Catalog>>, services: Arc<Services>, transaction: Arc<RwLock<Transaction>>, serialized_file_path: &str, wal_file_path: &str, file: Arc<RwLock<File>>) {
+    let catalog_read = catalog.read().unwrap();
 
-    match serialize(Arc::clone(&cloned_node),serialized_file_path) {
-        Ok(_) => {}
-        Err(e) =>  println!("Serialization failed: {}", e),
+    {
+        let tx = transaction.read().unwrap();
+        match catalog_read.store_with(serialized_file_path, &services, &get_active_txd_snapshot(&tx)) {
+            Ok(_) => {}
+            Err(e) =>  println!("Serialization failed: {}", e),
+        }
     }
 
     match get_uncommitted_transactions(wal_file_path) {
@@ -37,7 +38,7 @@ pub fn checkpoint(node: Arc<RwLock<Node>>, serialized_file_path: &str, wal_file_
             Ok(_) => {
                 for strs in uncommitted_strings.iter() {
                     let uncommitted_args = strs.split(" ").collect::<Vec<&str>>();
-                    match flush_to_wal(Arc::clone(&file), uncommitted_args) {
+                    match carry_over_to_wal(Arc::clone(&file), uncommitted_args) {
                         Ok(_) => {}
                         Err(e) => println!("Flushing to WAL failed: {}", e),
                     }
@@ -48,7 +49,9 @@ pub fn checkpoint(node: Arc<RwLock<Node>>, serialized_file_path: &str, wal_file_
         Err(e) => println!("Can't fetch uncommitted transactions: {}", e),
     }
 
-    println!("{:?}", cloned_node.read().unwrap().print_tree());
+    for (name, node) in catalog_read.tables.iter() {
+        println!("{}: {:?}", name, node.read().unwrap().print_tree());
+    }
 
     CHECKPOINT_COUNTER.store(0, Ordering::Relaxed);
 }
@@ -1,2 +1,6 @@
 pub mod checkpoint;
-pub mod stream_processor;
\ No newline at end of file
+pub mod stream_processor;
+pub mod vacuum;
+pub mod cdc;
+pub mod watch;
+pub mod services;
@@ -0,0 +1,58 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use crate::engine::cdc::ChangeLog;
+use crate::engine::checkpoint::CheckpointPaths;
+use crate::engine::vacuum::VacuumState;
+use crate::engine::watch::Watches;
+use crate::replication::role::Replication;
+use crate::transactions::locks::LockManager;
+use crate::transactions::timeout::TransactionTimeouts;
+use crate::transactions::txid::TxidReservation;
+
+/// What the engine runs next to the tables of the [`Catalog`](crate::catalog::tables::Catalog), shared by every
+/// connection. Each service locks itself: the vacuum state, `all_addr` and the change log have their place in the lock
+/// order of `btree/latch.rs`, the others are never held while acquiring anything else.
+#[derive(Debug, Default)]
+pub struct Services {
+    /// Every client that ever sent a command.
+    pub all_addr: Arc<RwLock<Vec<SocketAddr>>>,
+    pub vacuum: Arc<Mutex<VacuumState>>,
+    /// Committed changes for `subscribe`. Kept in memory only unless replaced with a [`ChangeLog::open`]ed one.
+    pub changes: Arc<ChangeLog>,
+    /// Clients blocked in `watch` or `wait`.
+    pub watches: Arc<Watches>,
+    /// Whether this server is a primary or follows one. Its epoch and fence are persisted with the catalog.
+    pub replication: Arc<Replication>,
+    /// How far txids are reserved on disk, persisted with the catalog.
+    pub txids: Arc<TxidReservation>,
+    /// How long transactions may stay idle or run, set when the server starts.
+    pub timeouts: RwLock<TransactionTimeouts>,
+    /// Row locks of the running transactions.
+    pub locks: Arc<LockManager>,
+    /// Where checkpoints go, set when the server starts. Commands that need a checkpoint before they return, like
+    /// `load`, take it here, and otherwise ask the checkpoint thread for one.
+    pub checkpoints: Option<CheckpointPaths>,
+}
+
+impl Services {
+    pub fn timeouts(&self) -> TransactionTimeouts {
+        *self.timeouts.read().unwrap()
+    }
+
+    /// The same services as seen while the WAL is replayed on startup: lock waits end at once, and nothing is
+    /// checkpointed, the WAL being rewritten isn't in place yet.
+    pub fn for_replay(&self) -> Services {
+        Services {
+            all_addr: Arc::clone(&self.all_addr),
+            vacuum: Arc::clone(&self.vacuum),
+            changes: Arc::clone(&self.changes),
+            watches: Arc::clone(&self.watches),
+            replication: Arc::clone(&self.replication),
+            txids: Arc::clone(&self.txids),
+            timeouts: RwLock::new(TransactionTimeouts { lock_wait: Duration::ZERO, ..self.timeouts() }),
+            locks: Arc::clone(&self.locks),
+            checkpoints: None,
+        }
+    }
+}
@@ -1,67 +1,125 @@
 use std::fs::File;
 use std::{fs, io};
 use std::io::{BufRead, BufReader, Write};
-use std::net::{SocketAddr, TcpStream};
+use std::net::TcpStream;
 use std::sync::{Arc, RwLock};
 use std::sync::atomic::Ordering;
 use std::sync::mpsc::Sender;
-use crate::btree::node::Node;
+use std::time::Instant;
+use crate::catalog::tables::Catalog;
 use crate::CHECKPOINT_COUNTER;
 use crate::cli::cli::cli;
+use crate::engine::services::Services;
+use crate::transactions::timeout::{active_txd, expired, touch, TIMEOUT_POLL};
 use crate::transactions::transactions::Transaction;
 
-pub fn process_tcp_stream(mut stream: TcpStream, wal_file_path: &str, txd_count: Arc<RwLock<u32>>, current_transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, new_node: Arc<RwLock<Node>>, all_addr: Arc<RwLock<Vec<SocketAddr>>>, tx: Sender<i32>) -> io::Result<()> {
+/// Serves one client connection until the client exits or disconnects.
+///
+/// # Working:
+/// - While waiting for the next command, the connection wakes up every [`TIMEOUT_POLL`] to check the client's
+///   transaction against its timeouts, and checks again before running each command. A transaction that was idle or
+///   ran for too long is aborted and the connection closed after a line saying why.
+/// - However the connection ends, a transaction the client left open is aborted, so it doesn't hold back vacuum or
+///   keep other transactions off the keys it wrote.
+pub fn process_tcp_stream(mut stream: TcpStream, wal_file_path: &str, txd_count: Arc<RwLock<u64>>, current_transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, catalog: Arc<RwLock<Catalog>>, services: Arc<Services>, tx: Sender<i32>) -> io::Result<()> {
     // In session project.
     // println!("Enter 'Help' for available commands & 'exit' to quit.");
 
-    let mut reader = BufReader::new(stream.try_clone()?);
-    let mut buffer = String::new();
-
-    loop {
-        buffer.clear();
-
-        match reader.read_line(&mut buffer) {
-            Ok(0) => {
-                println!("Client {} disconnected", stream.peer_addr()?);
-                break;
+    let addr = stream.peer_addr()?;
+    let abort_open_transaction = || {
+        if active_txd(&current_transaction.read().unwrap(), &addr).is_some() {
+            let abort = format!("abort {}", addr);
+            if let Err(e) = cli(abort, Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&catalog), None, Arc::clone(&services)) {
+                println!("Aborting the transaction of {} failed: {}", addr, e);
             }
+        }
+    };
+    let expired_transaction = || {
+        let timeouts = services.timeouts();
+        let tx = current_transaction.read().unwrap();
+        let txd = active_txd(&tx, &addr)?;
+        expired(&tx.items[&txd], timeouts, Instant::now()).map(|expiry| expiry.message(txd))
+    };
 
-            Ok(_) => {
-                let command = buffer.trim().to_string();
+    let served = (|| -> io::Result<()> {
+        stream.set_read_timeout(Some(TIMEOUT_POLL))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut buffer = String::new();
+
+        loop {
+            match reader.read_line(&mut buffer) {
+                Ok(0) => {
+                    println!("Client {} disconnected", addr);
+                    break;
+                }
 
-                let addr = stream.peer_addr()?;
-                let command = format!("{} {}", command, addr);
+                Ok(_) => {
+                    let command = buffer.trim().to_string();
+                    buffer.clear();
 
-                match cli(command, Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&new_node), Some(&stream), Arc::clone(&all_addr)) {
-                    Ok(1) => continue,
-                    Ok(2) => break,
-                    Ok(3) => {
-                        println!(":HI");
-                        CHECKPOINT_COUNTER.store(100, Ordering::Relaxed);
+                    if let Some(message) = expired_transaction() {
+                        abort_open_transaction();
+                        writeln!(stream, "{}", message)?;
+                        break;
                     }
-                    Ok(_) => {}
-                    Err(e) => println!("Error: {}", e),
-                }
 
-                let metadata = fs::metadata(wal_file_path)?;
-                let size = metadata.len();
+                    let command = format!("{} {}", command, addr);
+
+                    match cli(command, Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&catalog), Some(&stream), Arc::clone(&services)) {
+                        Ok(1) => {
+                            touch(&current_transaction, &addr);
+                            continue;
+                        }
+                        Ok(2) => break,
+                        Ok(3) => {
+                            println!(":HI");
+                            CHECKPOINT_COUNTER.store(100, Ordering::Relaxed);
+                        }
+                        Ok(_) => {}
+                        Err(e) => println!("Error: {}", e),
+                    }
+                    touch(&current_transaction, &addr);
 
-                if CHECKPOINT_COUNTER.load(Ordering::Relaxed) >= 100 || size >= 1024 {
-                    tx.send(1).unwrap();
-                    println!("Maximum WAL file size exceeded.");
-                    CHECKPOINT_COUNTER.store(0, Ordering::Relaxed);
+                    if request_checkpoint_if_due(wal_file_path, &tx)? {
+                        return Ok(());
+                    }
 
-                    return Ok(());
+                    stream.write_all(b"\n")?;
                 }
 
-                stream.write_all(b"\n")?;
-            }
+                // Nothing arrived for a while. What was read of a line so far stays in `buffer`.
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    if let Some(message) = expired_transaction() {
+                        abort_open_transaction();
+                        writeln!(stream, "{}", message)?;
+                        break;
+                    }
+                }
 
-            Err(e) => {
-                println!("Error reading from {}: {}", stream.peer_addr()?, e);
-                break;
+                Err(e) => {
+                    println!("Error reading from {}: {}", addr, e);
+                    break;
+                }
             }
         }
+        Ok(())
+    })();
+
+    abort_open_transaction();
+    served
+}
+
+/// Asks the checkpoint thread behind `tx` for a checkpoint once enough was written since the last one or the WAL grew
+/// too big. Returns whether it did.
+pub fn request_checkpoint_if_due(wal_file_path: &str, tx: &Sender<i32>) -> io::Result<bool> {
+    let metadata = fs::metadata(wal_file_path)?;
+    let size = metadata.len();
+
+    if CHECKPOINT_COUNTER.load(Ordering::Relaxed) >= 100 || size >= 1024 {
+        tx.send(1).unwrap();
+        println!("Maximum WAL file size exceeded.");
+        CHECKPOINT_COUNTER.store(0, Ordering::Relaxed);
+        return Ok(true);
     }
-    Ok(())
+    Ok(false)
 }
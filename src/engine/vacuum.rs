@@ -0,0 +1,212 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::Ordering;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::catalog::index::Index;
+use crate::catalog::tables::Catalog;
+use crate::btree::node::Node;
+use crate::engine::services::Services;
+use crate::MVCC::gc::{remove_if_dead, vacuum_step, vacuum_table, GcStats, RetentionPolicy};
+use crate::transactions::transactions::{Transaction, TransactionStatus};
+
+/// Bookkeeping shared by every vacuum pass, whether run by the background thread or the `vacuum` command.
+#[derive(Debug, Default)]
+pub struct VacuumState {
+    /// `(when, next txid)` taken at each pass, mapping [`RetentionPolicy::Seconds`] to a txid horizon. Only samples
+    /// still needed for that are kept.
+    samples: VecDeque<(Instant, u64)>,
+    /// Where the next [`vacuum_tick`] resumes.
+    cursor: Cursor,
+    /// Full passes, by the `vacuum` command.
+    pub runs: u64,
+    pub ticks: u64,
+    /// Everything reclaimed since startup.
+    pub totals: GcStats,
+    pub last_horizon: u64,
+}
+
+/// The table an incremental vacuum is going through and how far it got. A `table` that's [`None`] or was dropped is
+/// replaced by the next one on the following tick.
+#[derive(Debug, Default)]
+struct Cursor {
+    table: Option<String>,
+    key: u32,
+    /// Fully dead keys seen in `table` so far this sweep, removed once it's done.
+    fully_dead: usize,
+}
+
+/// Nodes a [`vacuum_tick`] vacuums at most, unless told otherwise.
+pub const TICK_NODES: usize = 32;
+
+/// Samples closer together than this are skipped, bounding how many [`RetentionPolicy::Seconds`] keeps.
+const SAMPLE_SPACING: Duration = Duration::from_secs(1);
+
+/// Starts the background vacuum thread, running a [`vacuum_tick`] of `budget` nodes every `interval`, independently
+/// of checkpoints.
+pub fn spawn_vacuum(catalog: Arc<RwLock<Catalog>>, services: Arc<Services>, txd_count: Arc<RwLock<u64>>, transaction: Arc<RwLock<Transaction>>, interval: Duration, budget: usize) -> JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        if let Some(stats) = vacuum_tick(Arc::clone(&catalog), Arc::clone(&services), Arc::clone(&txd_count), Arc::clone(&transaction), budget)
+            && stats != GcStats::default()
+        {
+            println!("Vacuum reclaimed {} versions and removed {} keys", stats.versions_reclaimed, stats.keys_removed);
+        }
+    })
+}
+
+/// Runs one vacuum pass over every table under the catalog's [`RetentionPolicy`] and returns what it reclaimed, or
+/// [`None`] if the policy keeps everything.
+///
+/// # Working:
+/// - The horizon never passes the oldest transaction that's active, or aborted with its rollback possibly still
+///   running, nor the oldest one in any of their snapshots, so nothing a transaction can see or restore is reclaimed. With nothing running it's the next txid,
+///   letting vacuum reclaim everything the policy allows even on an idle server.
+/// - The policy then holds the horizon further back: by N txids, or to the txid that was next T seconds ago.
+///   [`RetentionPolicy::LastVersions`] keeps the horizon and limits what goes per key instead.
+/// - [`Catalog::gc_horizon`] is raised before anything is reclaimed, so `select_as_of` refuses a point before versions
+///   it needs go missing, never after.
+/// - Passes are serialized by the [`VacuumState`] lock, which is taken before any other lock, see `btree/latch.rs`.
+pub fn vacuum(catalog: Arc<RwLock<Catalog>>, services: Arc<Services>, txd_count: Arc<RwLock<u64>>, transaction: Arc<RwLock<Transaction>>) -> Option<GcStats> {
+    let policy = catalog.read().unwrap().retention;
+    if policy == RetentionPolicy::KeepAll {
+        return None;
+    }
+
+    let mut state = services.vacuum.lock().unwrap_or_else(|e| e.into_inner());
+    let (horizon, keep_last) = horizon(policy, &mut state, &catalog, &txd_count, &transaction);
+    let tables: Vec<_> = {
+        let catalog_read = catalog.read().unwrap();
+        catalog_read.tables.iter().map(|(name, table)| (Arc::clone(table), catalog_read.indexes_on(name))).collect()
+    };
+
+    let mut stats = GcStats::default();
+    for (table, indexes) in tables.iter() {
+        stats.add(vacuum_table(table, horizon, keep_last, indexes));
+    }
+
+    state.runs += 1;
+    state.totals.add(stats);
+    Some(stats)
+}
+
+/// Runs one bounded step of incremental vacuum: at most `budget` nodes of one table, resuming where the last tick
+/// stopped, see [`vacuum_step`]. Returns [`None`] if the policy keeps everything.
+///
+/// # Working:
+/// - Tables are swept one after another in name order, each from its first key to its last, wrapping around to the
+///   first table once the last is done. A table dropped mid-sweep is simply moved past.
+/// - Within a table, nodes with the most dead versions go first, and nodes busy with foreground traffic are skipped.
+/// - Fully dead keys are counted as the sweep goes, and the table is rebuilt once at the end of its sweep if there
+///   were any, rather than on every tick.
+/// - The horizon is worked out afresh every tick exactly like [`vacuum`] does, and shares its lock.
+pub fn vacuum_tick(catalog: Arc<RwLock<Catalog>>, services: Arc<Services>, txd_count: Arc<RwLock<u64>>, transaction: Arc<RwLock<Transaction>>, budget: usize) -> Option<GcStats> {
+    let policy = catalog.read().unwrap().retention;
+    if policy == RetentionPolicy::KeepAll {
+        return None;
+    }
+
+    let mut state = services.vacuum.lock().unwrap_or_else(|e| e.into_inner());
+    let (horizon, keep_last) = horizon(policy, &mut state, &catalog, &txd_count, &transaction);
+
+    let (table, indexes) = {
+        let catalog_read = catalog.read().unwrap();
+        let current = state.cursor.table.as_ref().and_then(|name| catalog_read.tables.get(name));
+        let table = match current {
+            Some(table) => Arc::clone(table),
+            None => {
+                let Some(next) = next_table(&catalog_read.tables, state.cursor.table.as_deref()) else { return Some(GcStats::default()) };
+                state.cursor = Cursor { key: 0, fully_dead: 0, table: Some(next) };
+                Arc::clone(&catalog_read.tables[state.cursor.table.as_ref().unwrap()])
+            }
+        };
+        (table, catalog_read.indexes_on(state.cursor.table.as_ref().unwrap()))
+    };
+
+    let step = vacuum_step(&table, state.cursor.key, horizon, keep_last, budget, &indexes);
+    let mut stats = step.reclaimed;
+    state.cursor.fully_dead += step.fully_dead;
+
+    match step.next_key {
+        Some(key) => state.cursor.key = key,
+        None => {
+            if state.cursor.fully_dead > 0 {
+                stats.add(remove_dead_keys(&table, horizon, &indexes));
+            }
+            let next = next_table(&catalog.read().unwrap().tables, state.cursor.table.as_deref());
+            state.cursor = Cursor { key: 0, fully_dead: 0, table: next };
+        }
+    }
+
+    state.ticks += 1;
+    state.totals.add(stats);
+    Some(stats)
+}
+
+/// The table swept after `after`, wrapping around to the first one, or the first one if `after` is [`None`].
+fn next_table(tables: &HashMap<String, Arc<RwLock<Node>>>, after: Option<&str>) -> Option<String> {
+    tables.keys()
+        .filter(|name| after.is_none_or(|after| name.as_str() > after))
+        .min()
+        .or_else(|| tables.keys().min())
+        .cloned()
+}
+
+/// Removes the keys of `table` left with only dead versions, counting every one of their versions as reclaimed.
+fn remove_dead_keys(table: &Arc<RwLock<Node>>, horizon: u64, indexes: &[Arc<Index>]) -> GcStats {
+    let mut versions_reclaimed = 0;
+    let keys_removed = Node::rebuild(table, |item| {
+        let dead = remove_if_dead(item, horizon, indexes);
+        if dead {
+            versions_reclaimed += item.version.len();
+        }
+        !dead
+    });
+    GcStats { versions_reclaimed, keys_removed }
+}
+
+/// Works out the horizon for a pass under `policy`, recording it in `state`, and raises [`Catalog::gc_horizon`] to
+/// match. Returns it with the number of versions [`RetentionPolicy::LastVersions`] keeps per key.
+fn horizon(policy: RetentionPolicy, state: &mut VacuumState, catalog: &Arc<RwLock<Catalog>>, txd_count: &Arc<RwLock<u64>>, transaction: &Arc<RwLock<Transaction>>) -> (u64, Option<usize>) {
+    let next_txd = *txd_count.read().unwrap() + 1;
+    let oldest_running = {
+        let tx = transaction.read().unwrap();
+        // A running transaction still sees what the transactions in its snapshot closed, however long ago they
+        // committed, so those count as running too.
+        tx.items.iter()
+            .filter(|(_, item)| item.status != TransactionStatus::Committed)
+            .flat_map(|(txd, item)| item.snapshot.iter().copied().chain([*txd]))
+            .min()
+            .unwrap_or(next_txd)
+            .min(next_txd)
+    };
+
+    let now = Instant::now();
+    if state.samples.back().is_none_or(|(at, _)| now.duration_since(*at) >= SAMPLE_SPACING) {
+        state.samples.push_back((now, next_txd));
+    }
+    let (horizon, keep_last) = match policy {
+        RetentionPolicy::KeepAll => unreachable!(),
+        RetentionPolicy::Transactions(n) => (next_txd.saturating_sub(n).min(oldest_running), None),
+        RetentionPolicy::Seconds(t) => (sampled_horizon(&mut state.samples, now, Duration::from_secs(t)).min(oldest_running), None),
+        RetentionPolicy::LastVersions(k) => (oldest_running, Some(k)),
+    };
+
+    // Everything closed before `horizon` is already invisible as of `horizon - 1`, so that point stays readable.
+    catalog.read().unwrap().gc_horizon.fetch_max(horizon.saturating_sub(1), Ordering::SeqCst);
+    state.last_horizon = horizon;
+    (horizon, keep_last)
+}
+
+/// The next txid as of `retention` ago, from the newest sample at least that old. Older samples are dropped, they'll
+/// never be needed again. 0, reclaiming nothing, until the server has been sampled for that long.
+fn sampled_horizon(samples: &mut VecDeque<(Instant, u64)>, now: Instant, retention: Duration) -> u64 {
+    let Some(cutoff) = now.checked_sub(retention) else { return 0 };
+    while samples.get(1).is_some_and(|(at, _)| *at <= cutoff) {
+        samples.pop_front();
+    }
+    match samples.front() {
+        Some((at, txd)) if *at <= cutoff => *txd,
+        _ => 0,
+    }
+}
@@ -0,0 +1,161 @@
+use std::io;
+use std::net::TcpStream;
+use std::ops::RangeInclusive;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use crate::engine::cdc::{disconnected, Change, POLL_INTERVAL};
+
+/// The keys a `watch` or `wait` is interested in.
+///
+/// Keys are `u32`, so a group of keys is a range of them. There's no key prefix until keys can be byte strings: a
+/// prefix of a number's decimal form groups unrelated keys (`1*` would be 1, 10-19, 100-199...). Range queries
+/// (`count`, `aggregate`) leave prefixes out for the same reason.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyPattern {
+    Key(u32),
+    /// The keys in `[from, to]`, written `<from>-<to>`.
+    Range(RangeInclusive<u32>),
+}
+
+impl KeyPattern {
+    pub fn parse(pattern: &str) -> Option<KeyPattern> {
+        match pattern.split_once('-') {
+            Some((from, to)) => match (from.parse::<u32>(), to.parse::<u32>()) {
+                (Ok(from), Ok(to)) if from <= to => Some(KeyPattern::Range(from..=to)),
+                _ => None,
+            },
+            None => pattern.parse::<u32>().ok().map(KeyPattern::Key),
+        }
+    }
+
+    pub fn matches(&self, key: u32) -> bool {
+        match self {
+            KeyPattern::Key(watched) => *watched == key,
+            KeyPattern::Range(range) => range.contains(&key),
+        }
+    }
+}
+
+/// A committed change to a watched key, handed to its watchers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub txid: u64,
+    pub key: u32,
+    /// The committed value, [`None`] if the key was deleted.
+    pub value: Option<String>,
+}
+
+impl Notification {
+    /// The line sent back to the client.
+    pub fn message(&self) -> String {
+        match &self.value {
+            Some(value) => format!("{} {}", self.key, value),
+            None => format!("{} deleted", self.key),
+        }
+    }
+}
+
+/// How a [`Watch::wait_connected`] ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WaitOutcome {
+    Changed(Notification),
+    TimedOut,
+    /// The client went away while waiting.
+    Disconnected,
+}
+
+/// Connections blocked in `watch` or `wait`, woken up by the commits touching their keys.
+#[derive(Debug, Default)]
+pub struct Watches {
+    waiting: Mutex<Vec<Waiter>>,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    table: String,
+    pattern: KeyPattern,
+    sender: Sender<Notification>,
+}
+
+impl Watches {
+    /// Starts watching `pattern` in `table`. Only commits from now on are seen, until the [`Watch`] is dropped.
+    pub fn register(self: &Arc<Self>, table: &str, pattern: KeyPattern) -> Watch {
+        let (sender, receiver) = mpsc::channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.waiting.lock().unwrap_or_else(|e| e.into_inner()).push(Waiter { id, table: table.to_string(), pattern, sender });
+        Watch { watches: Arc::clone(self), id, receiver }
+    }
+
+    /// Hands the changes committed by `txid` to everyone watching them. Called once the commit is resolved in the
+    /// tree, so a woken up client reads what it was told about.
+    pub fn notify(&self, txid: u64, changes: &[Change]) {
+        let waiting = self.waiting.lock().unwrap_or_else(|e| e.into_inner());
+        for waiter in waiting.iter() {
+            let matching = changes.iter().filter(|change| change.table == waiter.table && waiter.pattern.matches(change.key));
+            for change in matching {
+                // A waiter whose watch is being dropped no longer listens, that's fine.
+                let _ = waiter.sender.send(Notification { txid, key: change.key, value: change.new.clone() });
+            }
+        }
+    }
+
+    /// Number of registered watches.
+    pub fn len(&self) -> usize {
+        self.waiting.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A registered interest in some keys, see [`Watches::register`]. Unregistered when dropped.
+#[derive(Debug)]
+pub struct Watch {
+    watches: Arc<Watches>,
+    id: u64,
+    receiver: Receiver<Notification>,
+}
+
+impl Watch {
+    /// Blocks until a watched key changes, or at most `timeout`.
+    pub fn wait(&self, timeout: Option<Duration>) -> Option<Notification> {
+        match timeout {
+            Some(timeout) => self.receiver.recv_timeout(timeout).ok(),
+            None => self.receiver.recv().ok(),
+        }
+    }
+
+    /// [`Watch::wait`] for a client on `stream`, giving up early if it disconnects meanwhile.
+    pub fn wait_connected(&self, timeout: Option<Duration>, stream: &TcpStream) -> io::Result<WaitOutcome> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let slice = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()).min(POLL_INTERVAL),
+                None => POLL_INTERVAL,
+            };
+            match self.receiver.recv_timeout(slice) {
+                Ok(notification) => return Ok(WaitOutcome::Changed(notification)),
+                Err(RecvTimeoutError::Timeout) => {}
+                // The sender lives in the registry as long as this watch does.
+                Err(RecvTimeoutError::Disconnected) => unreachable!(),
+            }
+            if disconnected(stream)? {
+                return Ok(WaitOutcome::Disconnected);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(WaitOutcome::TimedOut);
+            }
+        }
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.watches.waiting.lock().unwrap_or_else(|e| e.into_inner()).retain(|waiter| waiter.id != self.id);
+    }
+}
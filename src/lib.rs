@@ -1,5 +1,6 @@
 use std::sync::atomic::AtomicUsize;
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
+use crate::replication::shipper::Shipper;
 
 pub mod MVCC;
 pub mod btree;
@@ -7,9 +8,12 @@ pub mod storage;
 pub mod transactions;
 pub mod cli;
 pub mod engine;
+pub mod catalog;
+pub mod replication;
 mod transaction_process_tree_fix;
 
 // temp
 pub static NODE_SIZE: OnceCell<usize> = OnceCell::new();
-pub static LAST_ACTIVE_TXD: AtomicUsize = AtomicUsize::new(100);
 pub static CHECKPOINT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// Every record flushed to the WAL, numbered for the followers replicating this server.
+pub static WAL_SHIPPER: Lazy<Shipper> = Lazy::new(Shipper::default);
@@ -3,62 +3,138 @@ use std::fs::{ OpenOptions};
 use std::sync::{mpsc, Arc, RwLock };
 use std::{io, thread};
 use std::net::{TcpListener};
+use std::time::Duration;
 
 use ASMT::{NODE_SIZE};
-use ASMT::engine::checkpoint::checkpoint;
+use ASMT::engine::cdc::ChangeLog;
+use ASMT::engine::checkpoint::{checkpoint, CheckpointPaths};
+use ASMT::engine::services::Services;
+use ASMT::engine::vacuum::{spawn_vacuum, TICK_NODES};
 use ASMT::engine::stream_processor::process_tcp_stream;
+use ASMT::replication::follower::{spawn_follower, Applier};
+use ASMT::replication::failover::highest_txid;
+use ASMT::replication::role::Role;
 use ASMT::storage::io::is_file_empty;
-use ASMT::btree::node::Node;
+use ASMT::catalog::tables::Catalog;
+use ASMT::transactions::timeout::TransactionTimeouts;
 use ASMT::transactions::transactions::Transaction;
-use ASMT::storage::wal::recovery::initialize_from_wal;
+use ASMT::storage::wal::recovery::{initialize_from_wal, recover_from_wal};
+
+/// How often the background vacuum takes a step of incremental vacuum.
+const VACUUM_TICK_MILLIS: u64 = 200;
+
+const DEFAULT_DATA_DIR: &str = "/home/_merinh/RustroverProjects/ASMT_V0.2";
+const DEFAULT_PORT: u16 = 8080;
+
+/// `ASMT [--data <dir>] [--port <port>] [--follow <host:port>] [--idle-timeout <seconds>] [--max-transaction-age <seconds>]
+/// [--lock-timeout <seconds>]`. The checkpoint images, the WAL and the change log are kept in the data directory. With
+/// `--follow` the server replicates the primary at that address and only serves reads. Transactions idle or running for
+/// longer than the timeouts are aborted, row locks are waited for up to the lock timeout.
+struct Options {
+    data_dir: String,
+    port: u16,
+    follow: Option<String>,
+    timeouts: TransactionTimeouts,
+}
+
+fn parse_options(args: impl Iterator<Item = String>) -> io::Result<Options> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let seconds = |value: &str| value.parse::<f64>().ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .filter(|timeout| !timeout.is_zero())
+        .ok_or_else(|| invalid(format!("Invalid timeout: {}", value)));
+    let mut options = Options { data_dir: String::from(DEFAULT_DATA_DIR), port: DEFAULT_PORT, follow: None, timeouts: TransactionTimeouts::default() };
+    let mut args = args;
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or_else(|| invalid(format!("Missing value for {}", flag)))?;
+        match flag.as_str() {
+            "--data" => options.data_dir = value,
+            "--port" => options.port = value.parse().map_err(|_| invalid(format!("Invalid port: {}", value)))?,
+            "--follow" => options.follow = Some(value),
+            "--idle-timeout" => options.timeouts.idle = seconds(&value)?,
+            "--max-transaction-age" => options.timeouts.max_age = seconds(&value)?,
+            "--lock-timeout" => options.timeouts.lock_wait = seconds(&value)?,
+            _ => return Err(invalid(format!("Unknown option: {}", flag))),
+        }
+    }
+    Ok(options)
+}
 
 fn main() -> io::Result<()> {
     NODE_SIZE.set(4).expect("Failed to set size");
-    let serialized_file_path = "/home/_merinh/RustroverProjects/ASMT_V0.2/example.txt";
-    let wal_file_path = "/home/_merinh/RustroverProjects/ASMT_V0.2/WAL.txt";
-    let mut new_node = Node::new();
+    let options = parse_options(std::env::args().skip(1))?;
+    let serialized_file_path = format!("{}/example.txt", options.data_dir);
+    let wal_file_path = format!("{}/WAL.txt", options.data_dir);
+    let cdc_file_path = format!("{}/CDC.txt", options.data_dir);
 
     let current_transaction = Arc::new(RwLock::new(Transaction { items: HashMap::new(), ip_txd: HashMap::new() }));
-    let all_address = Arc::new(RwLock::new(Vec::new()));
+    let services = Services {
+        changes: Arc::new(ChangeLog::open(&cdc_file_path)?),
+        timeouts: RwLock::new(options.timeouts),
+        checkpoints: Some(CheckpointPaths { image: serialized_file_path.clone(), wal: wal_file_path.clone() }),
+        ..Services::default()
+    };
 
-    match Node::deserialize(serialized_file_path) {
-        Ok(node) =>  new_node = node,
-        Err(e) => println!("{:?}", e),
+    // A table that can't be loaded keeps the server from starting, its last good image is left as it is.
+    let catalog = Catalog::load(&serialized_file_path, &services)?;
+    for (name, node) in catalog.tables.iter() {
+        println!("{}: {:?}", name, node.read().unwrap().print_tree());
+    }
+    if let Some(primary) = options.follow {
+        services.replication.set_role(Role::Follower(primary));
     }
+    let catalog = Arc::new(RwLock::new(catalog));
+    let services = Arc::new(services);
 
     let file = Arc::new(RwLock::new(OpenOptions::new()
         .append(true)
         .create(true)
-        .open(wal_file_path)?));
+        .open(&wal_file_path)?));
 
-    let cloned_node = Arc::clone(&new_node);
+    let cloned_catalog = Arc::clone(&catalog);
     let cloned_file = Arc::clone(&file);
-    let cloned_addr = Arc::clone(&all_address);
+    let cloned_services = Arc::clone(&services);
     let cloned_transaction = Arc::clone(&current_transaction);
 
-    let txd_count = Arc::new(RwLock::new(0));
+    // Carries on after every txid reserved before the restart, or written to an image older than reservations.
+    let reserved = services.txids.recover(&wal_file_path)?;
+    let txd_count = Arc::new(RwLock::new(reserved.max(highest_txid(&catalog.read().unwrap(), &current_transaction.read().unwrap()))));
+
+    let prepared = recover_from_wal(&wal_file_path, Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&catalog), Arc::clone(&services))?;
+    if prepared > 0 {
+        println!("Recovered {} prepared transactions", prepared);
+    }
 
-    // if !is_file_empty(wal_file_path) { initialize_from_wal(wal_file_path, Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&new_node), Arc::clone(&all_address)); }
+    // if !is_file_empty(&wal_file_path) { initialize_from_wal(&wal_file_path, Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&catalog), Arc::clone(&services)); }
+
+    spawn_vacuum(Arc::clone(&catalog), Arc::clone(&services), Arc::clone(&txd_count), Arc::clone(&current_transaction), Duration::from_millis(VACUUM_TICK_MILLIS), TICK_NODES);
 
     let (tx, rx) = mpsc::channel();
+    let checkpoint_wal_file_path = wal_file_path.clone();
     let t1 = thread::spawn(move || {
         while let Ok(_) = rx.recv() {
-            checkpoint(Arc::clone(&cloned_node), serialized_file_path, wal_file_path, Arc::clone(&cloned_file), Arc::clone(&cloned_addr), Arc::clone(&cloned_transaction));
+            checkpoint(Arc::clone(&cloned_catalog), Arc::clone(&cloned_services), Arc::clone(&cloned_transaction), &serialized_file_path, &checkpoint_wal_file_path, Arc::clone(&cloned_file));
         }
     });
 
-    let listener: TcpListener = TcpListener::bind("127.0.0.1:8080")?;
-    println!("Server listening on port 8080");
+    if services.replication.is_follower() {
+        let applier = Applier::new(Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&catalog), Arc::clone(&services));
+        spawn_follower(applier, wal_file_path.clone(), tx.clone());
+    }
+
+    let listener: TcpListener = TcpListener::bind(("127.0.0.1", options.port))?;
+    println!("Server listening on port {}", options.port);
     for stream in listener.incoming() {
-        let cloned_node = Arc::clone(&new_node);
+        let cloned_catalog = Arc::clone(&catalog);
         let cloned_file = Arc::clone(&file);
         let cloned_transaction = Arc::clone(&current_transaction);
         let cloned_txd_count = Arc::clone(&txd_count);
-        let cloned_all_addr = Arc::clone(&all_address);
+        let cloned_services = Arc::clone(&services);
         let tx_clone = tx.clone();
+        let wal_file_path = wal_file_path.clone();
         match stream {
             Ok(stream) => {
-                thread::spawn(move || process_tcp_stream(stream, wal_file_path, cloned_txd_count, cloned_transaction, cloned_file, cloned_node, cloned_all_addr, tx_clone));
+                thread::spawn(move || process_tcp_stream(stream, &wal_file_path, cloned_txd_count, cloned_transaction, cloned_file, cloned_catalog, cloned_services, tx_clone));
             }
             Err(e) => println!("Error: {}", e),
         }
@@ -0,0 +1,170 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use crate::btree::node::Node;
+use crate::catalog::tables::Catalog;
+use crate::engine::services::Services;
+use crate::MVCC::gc::RetentionPolicy;
+use crate::replication::shipper::{ShipError, Shipper};
+use crate::storage::interchange::{read_records, records_for, write_record, Format, Record};
+use crate::storage::wal::reader::open_records;
+use crate::transactions::manager::read_view;
+use crate::transactions::transactions::{Transaction, TransactionStatus};
+
+/// Everything a follower needs before it can apply shipped records: the committed state of the primary at one point
+/// of its WAL and the records of the transactions that were still open there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bootstrap {
+    /// Position of the first record shipped after the bootstrap.
+    pub seq: u64,
+    /// The epoch of the primary, which the follower takes over.
+    pub epoch: u64,
+    /// The latest committed value of every key, per table.
+    pub tables: Vec<(String, Vec<Record>)>,
+    /// Index name, table and indexed field.
+    pub indexes: Vec<(String, String, Option<usize>)>,
+    pub retention: RetentionPolicy,
+    /// The table each client selected with `use`.
+    pub tables_in_use: Vec<(SocketAddr, String)>,
+    /// The records of the open and prepared transactions so far, in WAL order. Their commits (or aborts) come later.
+    pub pending: Vec<String>,
+}
+
+impl Bootstrap {
+    /// Captures the state of the primary as of the next record to be shipped.
+    ///
+    /// # Working:
+    /// - `txd_count`, the `Catalog` and the `Transaction` table are held throughout, in lock order. No transaction can
+    ///   begin, commit or abort meanwhile and the tables can't be created or dropped.
+    /// - Writes flush their record before or after applying it, under the `Transaction` table's lock or not at all.
+    ///   A record before the bootstrap position can therefore belong to a transaction that's still active, or to one
+    ///   that flushed its commit and waits to be marked committed. Both are open: active in the `Transaction` table,
+    ///   or without a commit or abort after their last `begin` among the shipped records.
+    /// - The records of every open transaction from its `begin` on are handed over, its writes are left out of the
+    ///   tables since they aren't committed yet.
+    /// - Everything else is in the tables, read at the latest committed state like `export` does.
+    pub fn capture(shipper: &Shipper, txd_count: &Arc<RwLock<u64>>, transaction: &Arc<RwLock<Transaction>>, catalog: &Arc<RwLock<Catalog>>, services: &Arc<Services>) -> Result<Bootstrap, ShipError> {
+        let txd_count_read = txd_count.read().unwrap();
+        let catalog_read = catalog.read().unwrap();
+        let tx = transaction.write().unwrap();
+
+        let seq = shipper.next_seq();
+        let pending = open_transactions(&shipper.retained_before(seq), &tx)?;
+
+        let (txd, view) = read_view(&tx, None, *txd_count_read + 1);
+        let mut names: Vec<&String> = catalog_read.tables.keys().collect();
+        names.sort();
+        let tables = names.into_iter().map(|name| {
+            let mut records = Vec::new();
+            Node::for_each_item(&catalog_read.tables[name], &mut |item| records.extend(records_for(item, false, txd, &view)));
+            (name.clone(), records)
+        }).collect();
+
+        let mut indexes: Vec<(String, String, Option<usize>)> = catalog_read.indexes.iter()
+            .map(|(name, index)| (name.clone(), index.table.clone(), index.field))
+            .collect();
+        indexes.sort();
+        let mut tables_in_use: Vec<(SocketAddr, String)> = catalog_read.ip_table.iter().map(|(addr, table)| (*addr, table.clone())).collect();
+        tables_in_use.sort();
+
+        Ok(Bootstrap { seq, epoch: services.replication.epoch(), tables, indexes, retention: catalog_read.retention, tables_in_use, pending })
+    }
+
+    /// Sends the bootstrap as lines: `bootstrap <seq> <epoch>`, then `table <name>` followed by its records as JSON
+    /// lines, `index <name> <table> <field>` (`-` for the whole value), `retention <policy>`, `use <addr> <table>`,
+    /// `pending <record>` and finally `ready`.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "bootstrap {} {}", self.seq, self.epoch)?;
+        for (name, records) in self.tables.iter() {
+            writeln!(out, "table {}", name)?;
+            for record in records {
+                write_record(out, Format::Jsonl, record)?;
+            }
+        }
+        for (name, table, field) in self.indexes.iter() {
+            let field = field.map_or(String::from("-"), |field| field.to_string());
+            writeln!(out, "index {} {} {}", name, table, field)?;
+        }
+        writeln!(out, "retention {}", self.retention)?;
+        for (addr, table) in self.tables_in_use.iter() {
+            writeln!(out, "use {} {}", addr, table)?;
+        }
+        for record in self.pending.iter() {
+            writeln!(out, "pending {}", record)?;
+        }
+        writeln!(out, "ready")
+    }
+
+    /// Reads a bootstrap written by [`Bootstrap::write_to`]. A `FAILED` line from the primary is returned as an error.
+    pub fn read_from(input: &mut impl BufRead) -> io::Result<Bootstrap> {
+        let malformed = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed bootstrap line: {:?}", line));
+        let mut line = String::new();
+        let mut next_line = |line: &mut String| -> io::Result<()> {
+            line.clear();
+            if input.read_line(line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Primary closed the connection during bootstrap"));
+            }
+            let trimmed = line.trim_end_matches(['\r', '\n']).len();
+            line.truncate(trimmed);
+            Ok(())
+        };
+
+        next_line(&mut line)?;
+        if line.starts_with("FAILED") {
+            return Err(io::Error::other(line));
+        }
+        let (seq, epoch) = match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            ["bootstrap", seq, epoch] => (seq.parse::<u64>().map_err(|_| malformed(&line))?, epoch.parse::<u64>().map_err(|_| malformed(&line))?),
+            _ => return Err(malformed(&line)),
+        };
+        let mut bootstrap = Bootstrap { seq, epoch, tables: Vec::new(), indexes: Vec::new(), retention: RetentionPolicy::KeepAll, tables_in_use: Vec::new(), pending: Vec::new() };
+
+        loop {
+            next_line(&mut line)?;
+            if line == "ready" {
+                return Ok(bootstrap);
+            }
+            if line.starts_with('{') {
+                let (_, records) = bootstrap.tables.last_mut().ok_or_else(|| malformed(&line))?;
+                records.extend(read_records(line.as_bytes(), Format::Jsonl)?);
+                continue;
+            }
+            if let Some(record) = line.strip_prefix("pending ") {
+                bootstrap.pending.push(record.to_string());
+                continue;
+            }
+
+            match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+                ["table", name] => bootstrap.tables.push((name.to_string(), Vec::new())),
+                ["index", name, table, field] => {
+                    let field = match *field {
+                        "-" => None,
+                        field => Some(field.parse::<usize>().map_err(|_| malformed(&line))?),
+                    };
+                    bootstrap.indexes.push((name.to_string(), table.to_string(), field));
+                }
+                ["retention", policy @ ..] => bootstrap.retention = RetentionPolicy::parse(policy).ok_or_else(|| malformed(&line))?,
+                ["use", addr, table] => bootstrap.tables_in_use.push((addr.parse::<SocketAddr>().map_err(|_| malformed(&line))?, table.to_string())),
+                _ => return Err(malformed(&line)),
+            }
+        }
+    }
+}
+
+/// The records of the transactions still open or prepared after `records`, see [`Bootstrap::capture`].
+fn open_transactions(records: &[String], tx: &Transaction) -> Result<Vec<String>, ShipError> {
+    let open = open_records(records);
+    for item in tx.items.values().filter(|item| item.status == TransactionStatus::Active) {
+        let began = match &item.prepared {
+            Some(gid) => open.prepared.contains_key(gid),
+            None => open.by_addr.contains_key(&item.socket_addr),
+        };
+        if !began {
+            return Err(ShipError::BeganBeforeRetained(item.socket_addr));
+        }
+    }
+
+    let positions: BTreeSet<usize> = open.by_addr.values().chain(open.prepared.values()).flatten().copied().collect();
+    Ok(positions.into_iter().map(|position| records[position].clone()).collect())
+}
@@ -0,0 +1,61 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use crate::btree::node::Node;
+use crate::catalog::tables::Catalog;
+use crate::engine::services::Services;
+use crate::transactions::transactions::Transaction;
+
+/// How long `promote` tries to reach the old primary to fence it.
+pub const FENCE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What `promote` did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Promotion {
+    pub epoch: u64,
+    /// The txid the next transaction will get.
+    pub next_txid: u64,
+    pub old_primary: String,
+}
+
+/// Promotes this follower to primary, see [`crate::replication::role::Replication::promote`]. [`None`] if it isn't
+/// a follower.
+///
+/// Once nothing is applied anymore, txids carry on after the highest one in the tables or the transaction table, so a
+/// txid never names two transactions even if this server restarted since it last allocated one.
+pub fn promote(txd_count: &Arc<RwLock<u64>>, transaction: &Arc<RwLock<Transaction>>, catalog: &Arc<RwLock<Catalog>>, services: &Arc<Services>) -> Option<Promotion> {
+    let replication = Arc::clone(&services.replication);
+    let old_primary = replication.promote()?;
+
+    let mut txd_count_write = txd_count.write().unwrap();
+    let catalog_read = catalog.read().unwrap();
+    let tx = transaction.read().unwrap();
+    *txd_count_write = (*txd_count_write).max(highest_txid(&catalog_read, &tx));
+    Some(Promotion { epoch: replication.epoch(), next_txid: *txd_count_write + 1, old_primary })
+}
+
+/// The highest txid that wrote or deleted a version in any table, or is in the transaction table.
+pub fn highest_txid(catalog: &Catalog, transaction: &Transaction) -> u64 {
+    let mut highest = transaction.items.keys().copied().max().unwrap_or(0);
+    for node in catalog.tables.values() {
+        Node::for_each_item(node, &mut |item| {
+            for version in item.version.iter() {
+                highest = highest.max(version.xmin).max(version.xmax.unwrap_or(0));
+            }
+        });
+    }
+    highest
+}
+
+/// Tells the server at `addr` that `epoch` was promoted, returning its answer.
+pub fn fence_remote(addr: &str, epoch: u64) -> io::Result<String> {
+    let target = addr.to_socket_addrs()?.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown address: {}", addr)))?;
+    let mut stream = TcpStream::connect_timeout(&target, FENCE_TIMEOUT)?;
+    stream.set_read_timeout(Some(FENCE_TIMEOUT))?;
+    writeln!(stream, "fence {}", epoch)?;
+
+    let mut answer = String::new();
+    BufReader::new(&stream).read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::btree::node::Node;
+use crate::catalog::tables::Catalog;
+use crate::cli::cli::execute;
+use crate::engine::services::Services;
+use crate::engine::stream_processor::request_checkpoint_if_due;
+use crate::replication::bootstrap::Bootstrap;
+use crate::replication::role::Role;
+use crate::replication::shipper::record_parts;
+use crate::transactions::prepared::prepared_record;
+use crate::storage::interchange::records_to_items;
+use crate::transactions::transactions::{Transaction, TransactionStatus};
+
+/// How long a follower waits before connecting to its primary again after losing it.
+pub const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What [`Applier::apply`] did with a record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Applied {
+    /// Kept until its transaction commits or aborts.
+    Buffered,
+    /// Applied, on its own or with the rest of the transaction it commits.
+    Replayed,
+    /// Part of an aborted transaction, or something the primary refused.
+    Dropped,
+    /// Reads a file only the primary has (`load`, `import`). The follower has to bootstrap again to get its effect.
+    Resync,
+}
+
+/// Applies the records shipped by a primary to this server.
+///
+/// # Working:
+/// - Records are buffered per client from its `begin` on. On `commit` the whole transaction is replayed through
+///   `cli`, so it runs against this server's tree and transaction table as if its client had sent it here. On
+///   `abort` it's dropped without ever touching the tree.
+/// - Transactions are thus applied one at a time in commit order. Whatever the primary let them write, they can't
+///   conflict with each other here, and readers on the follower only ever see committed transactions.
+/// - Catalog commands outside a transaction (`create`, `drop`, `use`, `retention`) are replayed as they come.
+/// - A `prepare` replays its transaction like a commit does, leaving it prepared here as well. The `commit prepared` or
+///   `rollback prepared` resolving it is replayed as it comes.
+#[derive(Debug)]
+pub struct Applier {
+    txd_count: Arc<RwLock<u64>>,
+    transaction: Arc<RwLock<Transaction>>,
+    file: Arc<RwLock<File>>,
+    catalog: Arc<RwLock<Catalog>>,
+    services: Arc<Services>,
+    open: HashMap<SocketAddr, Vec<String>>,
+}
+
+impl Applier {
+    pub fn new(txd_count: Arc<RwLock<u64>>, transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, catalog: Arc<RwLock<Catalog>>, services: Arc<Services>) -> Applier {
+        Applier { txd_count, transaction, file, catalog, services, open: HashMap::new() }
+    }
+
+    /// Replaces this server's tables and catalog with the ones of `bootstrap`, then picks up its open transactions.
+    ///
+    /// The committed values are loaded like `load` does, as written by one new txid. Tables the primary doesn't have
+    /// are dropped, indexes are recreated from their definitions.
+    pub fn install(&mut self, bootstrap: Bootstrap) -> io::Result<Applied> {
+        self.open.clear();
+        {
+            let mut txd_count_write = self.txd_count.write().unwrap();
+            let mut catalog_write = self.catalog.write().unwrap();
+            let mut tx_write = self.transaction.write().unwrap();
+
+            let txd = self.services.txids.allocate(&mut txd_count_write, &self.file)?;
+            let stale: Vec<String> = catalog_write.tables.keys()
+                .filter(|name| !bootstrap.tables.iter().any(|(table, _)| table == *name))
+                .cloned()
+                .collect();
+            for name in stale {
+                catalog_write.drop_table(&name);
+            }
+            catalog_write.indexes.clear();
+            // The tables they wrote are replaced, they're prepared again from the bootstrap's pending records.
+            let prepared: Vec<u64> = tx_write.items.iter()
+                .filter(|(_, item)| item.prepared.is_some() && item.status == TransactionStatus::Active)
+                .map(|(txd, _)| *txd)
+                .collect();
+            for txd in prepared {
+                tx_write.items.remove(&txd);
+                self.services.locks.release_all(txd);
+            }
+
+            for (name, records) in bootstrap.tables {
+                catalog_write.create_table(&name);
+                if let Some(node) = catalog_write.table(&name) {
+                    *node.write().unwrap() = Node::bulk_load_items(records_to_items(records, txd), 1.0);
+                }
+            }
+            for (name, table, field) in bootstrap.indexes {
+                catalog_write.create_index(&name, &table, field);
+            }
+            catalog_write.retention = bootstrap.retention;
+            catalog_write.ip_table.clear();
+            for (addr, table) in bootstrap.tables_in_use {
+                catalog_write.use_table(addr, &table);
+            }
+        }
+
+        let mut installed = Applied::Replayed;
+        for record in bootstrap.pending {
+            if self.apply(&record)? == Applied::Resync {
+                installed = Applied::Resync;
+            }
+        }
+        Ok(installed)
+    }
+
+    pub fn apply(&mut self, record: &str) -> io::Result<Applied> {
+        let Some((command, addr)) = record_parts(record) else { return Ok(Applied::Dropped) };
+
+        if let Some(lines) = self.open.get_mut(&addr) {
+            lines.push(record.to_string());
+            return match command.as_str() {
+                "commit" => {
+                    let lines = self.open.remove(&addr).unwrap_or_default();
+                    self.replay(&lines)
+                }
+                "abort" => {
+                    self.open.remove(&addr);
+                    Ok(Applied::Dropped)
+                }
+                // Prepared here too, so a promoted follower can still resolve it.
+                "prepare" => {
+                    let lines = self.open.remove(&addr).unwrap_or_default();
+                    self.replay(&lines)
+                }
+                _ => Ok(Applied::Buffered),
+            };
+        }
+
+        match command.as_str() {
+            "begin" => {
+                self.open.insert(addr, vec![record.to_string()]);
+                Ok(Applied::Buffered)
+            }
+            "create" | "drop" | "use" | "retention" => self.replay(&[record.to_string()]),
+            "commit" | "rollback" if prepared_record(record).is_some() => self.replay(&[record.to_string()]),
+            "load" | "import" => Ok(Applied::Resync),
+            // Writes outside a transaction were refused by the primary, a commit or abort without one did nothing.
+            _ => Ok(Applied::Dropped),
+        }
+    }
+
+    fn replay(&self, lines: &[String]) -> io::Result<Applied> {
+        let reads_file = |line: &String| record_parts(line).is_some_and(|(command, _)| command == "load" || command == "import");
+        if lines.iter().any(reads_file) {
+            return Ok(Applied::Resync);
+        }
+
+        for line in lines {
+            execute(line.clone(), Arc::clone(&self.txd_count), Arc::clone(&self.transaction), Arc::clone(&self.file), Arc::clone(&self.catalog), None, Arc::clone(&self.services))?;
+        }
+        Ok(Applied::Replayed)
+    }
+}
+
+/// Follows `primary` over one connection: bootstraps from it, then applies every record it ships and acknowledges
+/// them. Returns `Ok(true)` if it stopped because a new bootstrap is needed, `Ok(false)` if the primary went away.
+pub fn follow(primary: &str, applier: &mut Applier, wal_file_path: &str, checkpoints: &Sender<i32>) -> io::Result<bool> {
+    let replication = Arc::clone(&applier.services.replication);
+    let stream = TcpStream::connect(primary)?;
+    let mut acks = stream.try_clone()?;
+    // A primary older than our epoch learns it was replaced and fences itself.
+    writeln!(acks, "replicate {}", replication.epoch())?;
+    replication.set_connection(Some(stream.try_clone()?));
+
+    let mut reader = BufReader::new(stream);
+    let bootstrap = Bootstrap::read_from(&mut reader)?;
+    let from_seq = bootstrap.seq;
+    {
+        let _applying = replication.applying();
+        if !replication.is_follower() {
+            return Ok(false);
+        }
+        replication.set_epoch(bootstrap.epoch);
+        if applier.install(bootstrap)? == Applied::Resync {
+            return Ok(true);
+        }
+    }
+    replication.set_applied(from_seq - 1);
+    replication.set_connected(true);
+    println!("Following {} from record {}", primary, from_seq);
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(false);
+        }
+        let text = line.trim_end();
+        if text.starts_with("FAILED") {
+            println!("{}", text);
+            return Ok(true);
+        }
+        let (seq, record) = text.split_once(' ')
+            .and_then(|(seq, record)| Some((seq.parse::<u64>().ok()?, record)))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed shipped record: {:?}", text)))?;
+
+        let _applying = replication.applying();
+        if !replication.is_follower() {
+            return Ok(false);
+        }
+        if applier.apply(record)? == Applied::Resync {
+            return Ok(true);
+        }
+        replication.set_applied(seq);
+        // Acknowledged once caught up with what arrived, not once per record.
+        if reader.buffer().is_empty() {
+            writeln!(acks, "ack {}", seq)?;
+        }
+        request_checkpoint_if_due(wal_file_path, checkpoints)?;
+    }
+}
+
+/// Follows the primary named by this server's [`Role`] in the background, reconnecting and bootstrapping again
+/// whenever it has to, for as long as the server is a follower. Ends once it's promoted.
+pub fn spawn_follower(applier: Applier, wal_file_path: String, checkpoints: Sender<i32>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut applier = applier;
+        let replication = Arc::clone(&applier.services.replication);
+        while let Role::Follower(primary) = replication.role() {
+            let resync = match follow(&primary, &mut applier, &wal_file_path, &checkpoints) {
+                Ok(resync) => resync,
+                Err(e) => {
+                    println!("Replication from {} failed: {}", primary, e);
+                    false
+                }
+            };
+            replication.set_connected(false);
+            replication.set_connection(None);
+            if !resync {
+                thread::sleep(RECONNECT_INTERVAL);
+            }
+        }
+    })
+}
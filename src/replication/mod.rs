@@ -0,0 +1,5 @@
+pub mod role;
+pub mod shipper;
+pub mod bootstrap;
+pub mod follower;
+pub mod failover;
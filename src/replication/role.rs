@@ -0,0 +1,146 @@
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Mutex, MutexGuard, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// What a server does in a replicated setup.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Role {
+    /// Takes writes and ships its WAL to whoever runs `replicate`.
+    Primary,
+    /// Applies what the primary at this address ships and only serves reads to its own clients.
+    Follower(String),
+    /// A former primary that learned a newer epoch was promoted elsewhere. Only serves reads until it's restarted as a
+    /// follower of the new primary.
+    Fenced(u64),
+}
+
+/// The replication state of a server, shared through the [`crate::catalog::tables::Catalog`].
+///
+/// Every promotion starts a new epoch, one higher than any the promoted follower knew of. A primary that hears of an
+/// epoch newer than its own, from `fence` or from a follower connecting with `replicate`, is fenced. The epoch and the
+/// fence are persisted with the catalog so a restart doesn't lift them.
+#[derive(Debug)]
+pub struct Replication {
+    role: RwLock<Role>,
+    epoch: AtomicU64,
+    /// Last record applied from the primary, while following.
+    applied: AtomicU64,
+    /// Whether the follower is currently connected to its primary.
+    connected: AtomicBool,
+    /// The follower's connection to its primary, shut down to stop following.
+    connection: Mutex<Option<TcpStream>>,
+    /// Held by the follower while it applies a record, so a promotion can wait for it to finish.
+    applying: Mutex<()>,
+}
+
+impl Default for Replication {
+    fn default() -> Replication {
+        Replication {
+            role: RwLock::new(Role::Primary),
+            epoch: AtomicU64::new(1),
+            applied: AtomicU64::new(0),
+            connected: AtomicBool::new(false),
+            connection: Mutex::new(None),
+            applying: Mutex::new(()),
+        }
+    }
+}
+
+impl Replication {
+    pub fn role(&self) -> Role {
+        self.role.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set_role(&self, role: Role) {
+        *self.role.write().unwrap_or_else(|e| e.into_inner()) = role;
+    }
+
+    pub fn is_follower(&self) -> bool {
+        matches!(*self.role.read().unwrap_or_else(|e| e.into_inner()), Role::Follower(_))
+    }
+
+    /// Only a primary that hasn't been fenced lets its clients write.
+    pub fn accepts_writes(&self) -> bool {
+        *self.role.read().unwrap_or_else(|e| e.into_inner()) == Role::Primary
+    }
+
+    /// The epoch this server leads, or follows its primary in.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    pub fn set_epoch(&self, epoch: u64) {
+        self.epoch.store(epoch, Ordering::Release);
+    }
+
+    pub fn applied(&self) -> u64 {
+        self.applied.load(Ordering::Acquire)
+    }
+
+    pub fn set_applied(&self, seq: u64) {
+        self.applied.store(seq, Ordering::Release);
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Release);
+    }
+
+    /// Fences this server if it's a primary and `epoch` is newer than its own. Returns whether it was fenced.
+    pub fn fence(&self, epoch: u64) -> bool {
+        let mut role = self.role.write().unwrap_or_else(|e| e.into_inner());
+        if *role != Role::Primary || epoch <= self.epoch() {
+            return false;
+        }
+        *role = Role::Fenced(epoch);
+        true
+    }
+
+    /// Turns a follower into the primary of a new epoch, returning the address of the primary it followed.
+    ///
+    /// The connection to the old primary is shut down and the record being applied, if any, is waited for, so nothing
+    /// shipped by the old primary is applied once this returns. Transactions it hadn't committed yet are dropped.
+    pub fn promote(&self) -> Option<String> {
+        let old_primary = {
+            let mut role = self.role.write().unwrap_or_else(|e| e.into_inner());
+            let Role::Follower(primary) = role.clone() else { return None };
+            *role = Role::Primary;
+            self.epoch.fetch_add(1, Ordering::AcqRel);
+            primary
+        };
+
+        if let Some(connection) = self.connection.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            let _ = connection.shutdown(Shutdown::Both);
+        }
+        drop(self.applying());
+        self.set_connected(false);
+        Some(old_primary)
+    }
+
+    pub(crate) fn set_connection(&self, connection: Option<TcpStream>) {
+        *self.connection.lock().unwrap_or_else(|e| e.into_inner()) = connection;
+    }
+
+    /// Held while applying a record. The follower checks it's still following once it holds it.
+    pub(crate) fn applying(&self) -> MutexGuard<'_, ()> {
+        self.applying.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Whether a server that doesn't accept writes (a follower or a fenced primary) lets its clients run `command`. Writes
+/// only arrive through replication, everything that just reads (including transactions to read in) is served locally.
+pub fn allowed_read_only(command: &str, operands: usize) -> bool {
+    match command {
+        "begin" | "abort" | "select" | "mget" | "select_by" | "count" | "aggregate" | "select_as_of" | "scan_as_of" | "dump"
+        | "tree" | "stats" | "check" | "export" | "use" | "vacuum" | "checkpoint" | "subscribe" | "watch" | "wait"
+        | "replicate" | "replication" | "promote" | "fence" | "timeout" | "help" | "exit" => true,
+        // Showing the retention policy is fine, changing it isn't.
+        "retention" => operands == 0,
+        // A client's own read-only transaction can commit, a prepared one can't.
+        "commit" => operands == 0,
+        _ => false,
+    }
+}
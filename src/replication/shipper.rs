@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::catalog::tables::Catalog;
+use crate::engine::services::Services;
+use crate::engine::cdc::POLL_INTERVAL;
+use crate::replication::bootstrap::Bootstrap;
+use crate::transactions::transactions::Transaction;
+
+/// Records kept for followers to catch up from, older ones are dropped and a follower needing them bootstraps again.
+pub const RETAINED_RECORDS: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShipError {
+    /// The position was dropped from the shipping log, `oldest` is the first one still there.
+    Truncated { oldest: u64 },
+    /// The open transaction of this client began before the oldest retained record, so it can't be handed over.
+    BeganBeforeRetained(SocketAddr),
+}
+
+impl ShipError {
+    pub fn message(&self) -> String {
+        match self {
+            ShipError::Truncated { oldest } => format!("FAILED: record no longer retained, oldest is {}", oldest),
+            ShipError::BeganBeforeRetained(addr) => format!("FAILED: transaction of {} began before the oldest retained record", addr),
+        }
+    }
+}
+
+/// How far behind a connected follower is, as shown by `replication`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FollowerLag {
+    pub addr: SocketAddr,
+    /// Last record sent to the follower.
+    pub sent: u64,
+    /// Last record the follower reported applied.
+    pub applied: u64,
+    /// Records shipped but not applied yet.
+    pub records: u64,
+    /// How long ago the oldest record not applied yet was shipped, zero when caught up.
+    pub behind: Duration,
+}
+
+impl FollowerLag {
+    pub fn message(&self) -> String {
+        format!("Follower {}: sent {}, applied {}, {} records behind ({} ms)", self.addr, self.sent, self.applied, self.records, self.behind.as_millis())
+    }
+}
+
+/// The WAL records of this server in the order they were flushed, numbered from 1, for followers to apply. Fed by
+/// [`crate::storage::wal::writer::flush_to_wal`] through [`crate::WAL_SHIPPER`].
+#[derive(Debug, Default)]
+pub struct Shipper {
+    state: Mutex<ShipperState>,
+    shipped: Condvar,
+}
+
+#[derive(Debug)]
+struct ShipperState {
+    records: VecDeque<Shipped>,
+    next_seq: u64,
+    followers: Vec<Follower>,
+    next_follower: u64,
+}
+
+impl Default for ShipperState {
+    fn default() -> ShipperState {
+        ShipperState { records: VecDeque::new(), next_seq: 1, followers: Vec::new(), next_follower: 0 }
+    }
+}
+
+#[derive(Debug)]
+struct Shipped {
+    seq: u64,
+    record: String,
+    at: Instant,
+}
+
+#[derive(Debug)]
+struct Follower {
+    id: u64,
+    addr: SocketAddr,
+    sent: u64,
+    applied: u64,
+}
+
+impl Shipper {
+    /// Appends a record, waking up the followers waiting for it. Called with the WAL file still locked, so records
+    /// are numbered in WAL order.
+    pub fn ship(&self, record: String) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        state.records.push_back(Shipped { seq, record, at: Instant::now() });
+        while state.records.len() > RETAINED_RECORDS {
+            state.records.pop_front();
+        }
+        drop(state);
+        self.shipped.notify_all();
+    }
+
+    /// The position the next record will get.
+    pub fn next_seq(&self) -> u64 {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).next_seq
+    }
+
+    /// Every retained record before `seq`, oldest first.
+    pub fn retained_before(&self, seq: u64) -> Vec<String> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.records.iter().take_while(|shipped| shipped.seq < seq).map(|shipped| shipped.record.clone()).collect()
+    }
+
+    /// The records from `from_seq` on, waiting at most `timeout` for the first one to be shipped. Empty if none was.
+    pub fn read_from(&self, from_seq: u64, timeout: Duration) -> Result<Vec<(u64, String)>, ShipError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if from_seq < state.oldest() {
+                return Err(ShipError::Truncated { oldest: state.oldest() });
+            }
+            if from_seq < state.next_seq {
+                let skip = usize::try_from(from_seq - state.oldest()).unwrap_or(usize::MAX);
+                return Ok(state.records.iter().skip(skip).map(|shipped| (shipped.seq, shipped.record.clone())).collect());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(Vec::new());
+            }
+            state = self.shipped.wait_timeout(state, deadline - now).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    /// Starts tracking the lag of a follower that bootstrapped up to `seq`, returning its id.
+    pub fn attach(&self, addr: SocketAddr, seq: u64) -> u64 {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let id = state.next_follower;
+        state.next_follower += 1;
+        let position = seq.saturating_sub(1);
+        state.followers.push(Follower { id, addr, sent: position, applied: position });
+        id
+    }
+
+    pub fn detach(&self, id: u64) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).followers.retain(|follower| follower.id != id);
+    }
+
+    pub fn sent(&self, id: u64, seq: u64) {
+        self.update(id, |follower| follower.sent = follower.sent.max(seq));
+    }
+
+    pub fn acknowledge(&self, id: u64, seq: u64) {
+        self.update(id, |follower| follower.applied = follower.applied.max(seq));
+    }
+
+    fn update(&self, id: u64, f: impl FnOnce(&mut Follower)) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(follower) = state.followers.iter_mut().find(|follower| follower.id == id) {
+            f(follower);
+        }
+    }
+
+    /// The lag of every connected follower, in the order they connected.
+    pub fn lag(&self) -> Vec<FollowerLag> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        state.followers.iter().map(|follower| {
+            let behind = state.records.iter()
+                .find(|shipped| shipped.seq > follower.applied)
+                .map_or(Duration::ZERO, |shipped| now.duration_since(shipped.at));
+            FollowerLag { addr: follower.addr, sent: follower.sent, applied: follower.applied, records: (state.next_seq - 1).saturating_sub(follower.applied), behind }
+        }).collect()
+    }
+}
+
+impl ShipperState {
+    fn oldest(&self) -> u64 {
+        self.records.front().map_or(self.next_seq, |shipped| shipped.seq)
+    }
+}
+
+/// Serves a follower that sent `replicate` on `stream`, which is given over to it until the follower disconnects.
+///
+/// # Working:
+/// - A [`Bootstrap`] is captured and sent first: the committed tables, the catalog around them and the records of
+///   the transactions still open at that point.
+/// - Then every record shipped from the bootstrap position on is sent as a `<seq> <record>` line as soon as it's
+///   flushed, including the ones of transactions that later abort. The follower only applies a transaction once it
+///   sees its commit.
+/// - The follower answers with `ack <seq>` lines, read on a second thread, which is what the lag is computed from.
+pub fn serve_follower(shipper: &Shipper, stream: &TcpStream, txd_count: &Arc<RwLock<u64>>, transaction: &Arc<RwLock<Transaction>>, catalog: &Arc<RwLock<Catalog>>, services: &Arc<Services>) -> io::Result<()> {
+    let bootstrap = match Bootstrap::capture(shipper, txd_count, transaction, catalog, services) {
+        Ok(bootstrap) => bootstrap,
+        Err(e) => {
+            let mut stream = stream;
+            writeln!(stream, "{}", e.message())?;
+            return Ok(());
+        }
+    };
+    let mut out = io::BufWriter::new(stream);
+    bootstrap.write_to(&mut out)?;
+    out.flush()?;
+    drop(out);
+
+    let id = shipper.attach(stream.peer_addr()?, bootstrap.seq);
+    // Acks may be far apart, the connection's poll for transaction timeouts mustn't end the ack reader.
+    stream.set_read_timeout(None)?;
+    let acks = stream.try_clone()?;
+    let closed = AtomicBool::new(false);
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            for line in BufReader::new(acks).lines() {
+                let Ok(line) = line else { break };
+                if let Some(seq) = line.strip_prefix("ack ").and_then(|seq| seq.trim().parse::<u64>().ok()) {
+                    shipper.acknowledge(id, seq);
+                }
+            }
+            closed.store(true, Ordering::Release);
+        });
+
+        let shipped = ship_records(shipper, stream, id, bootstrap.seq, &closed);
+        // Unblocks the ack reader if the follower is still there.
+        let _ = stream.shutdown(Shutdown::Both);
+        shipped
+    });
+    shipper.detach(id);
+    result
+}
+
+/// The lowercased command of a record and the client it was run for, which is always its last argument.
+pub(crate) fn record_parts(record: &str) -> Option<(String, SocketAddr)> {
+    let command = record.split_whitespace().next()?.to_lowercase();
+    let addr = record.split_whitespace().last()?.parse::<SocketAddr>().ok()?;
+    Some((command, addr))
+}
+
+fn ship_records(shipper: &Shipper, stream: &TcpStream, id: u64, from_seq: u64, closed: &AtomicBool) -> io::Result<()> {
+    let mut stream = stream;
+    let mut next_seq = from_seq;
+    while !closed.load(Ordering::Acquire) {
+        let records = match shipper.read_from(next_seq, POLL_INTERVAL) {
+            Ok(records) => records,
+            Err(e) => {
+                writeln!(stream, "{}", e.message())?;
+                return Ok(());
+            }
+        };
+        let Some((last, _)) = records.last() else { continue };
+        let last = *last;
+
+        let mut lines = String::new();
+        for (seq, record) in records {
+            lines.push_str(&format!("{} {}\n", seq, record));
+        }
+        if stream.write_all(lines.as_bytes()).is_err() {
+            return Ok(());
+        }
+        shipper.sent(id, last);
+        next_seq = last + 1;
+    }
+    Ok(())
+}
@@ -0,0 +1,175 @@
+// fn read_node(lines) -> Node
+
+use std::io;
+use std::io::BufRead;
+use std::sync::{Arc, RwLock};
+use crate::btree::node::{Items, Node};
+use crate::MVCC::versions::{Version, VersionStatus};
+
+/// Reads a checkpoint image written by [`crate::storage::ser::serialize`] back into a [`Node`].
+///
+/// The image is a pre-order dump of the tree, one field group per line:
+/// ```text
+/// [0]                      header
+/// [rank]                   hex
+/// [key count]              hex
+/// [key][version count]     decimal, once per key
+/// [xmin][xmax or -][len]   decimal, once per version
+/// ['v', 'a', 'l']          Debug output of the value's chars
+/// [child count]            hex, followed by every child
+/// ```
+///
+/// Unlike the line-by-line regex matching it replaces, every malformed or truncated line is reported as
+/// [`io::ErrorKind::InvalidData`] instead of panicking, so the caller can fall back to an older image.
+pub fn read_image<R: BufRead>(reader: R) -> io::Result<Node> {
+    let mut lines = ImageLines { lines: reader.lines(), line_no: 0 };
+    let header = lines.next_fields()?;
+    if header != ["0"] {
+        return Err(lines.invalid("Missing image header"));
+    }
+    read_node(&mut lines)
+}
+
+struct ImageLines<L> {
+    lines: L,
+    line_no: usize,
+}
+
+impl<L: Iterator<Item = io::Result<String>>> ImageLines<L> {
+    fn next_line(&mut self) -> io::Result<String> {
+        self.line_no += 1;
+        match self.lines.next() {
+            Some(line) => line,
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("Image truncated at line {}", self.line_no))),
+        }
+    }
+
+    /// Splits `[a][b][c]` into `["a", "b", "c"]`.
+    fn next_fields(&mut self) -> io::Result<Vec<String>> {
+        let line = self.next_line()?;
+        let trimmed = line.trim();
+        if !trimmed.starts_with('[') || !trimmed.ends_with(']') {
+            return Err(self.invalid("Expected bracketed fields"));
+        }
+        Ok(trimmed[1..trimmed.len() - 1].split("][").map(|s| s.to_string()).collect())
+    }
+
+    fn invalid(&self, message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} (line {})", message, self.line_no))
+    }
+
+    fn number(&self, field: &str, radix: u32) -> io::Result<u32> {
+        u32::from_str_radix(field, radix).map_err(|_| self.invalid(&format!("Invalid number {:?}", field)))
+    }
+
+    fn txid(&self, field: &str) -> io::Result<u64> {
+        field.parse::<u64>().map_err(|_| self.invalid(&format!("Invalid txid {:?}", field)))
+    }
+}
+
+fn read_node<L: Iterator<Item = io::Result<String>>>(lines: &mut ImageLines<L>) -> io::Result<Node> {
+    let rank = single_field(lines, 16)?;
+    let key_count = single_field(lines, 16)?;
+
+    let mut input = Vec::with_capacity(key_count as usize);
+    for _ in 0..key_count {
+        let fields = lines.next_fields()?;
+        if fields.len() != 2 {
+            return Err(lines.invalid("Expected [key][version count]"));
+        }
+        let key = lines.number(&fields[0], 10)?;
+        let version_count = lines.number(&fields[1], 10)?;
+
+        let mut version = Vec::with_capacity(version_count as usize);
+        for _ in 0..version_count {
+            let fields = lines.next_fields()?;
+            if fields.len() != 3 {
+                return Err(lines.invalid("Expected [xmin][xmax][length]"));
+            }
+            let xmin = lines.txid(&fields[0])?;
+            let xmax = match fields[1].as_str() {
+                "-" => None,
+                x => Some(lines.txid(x)?),
+            };
+            let value_len = lines.number(&fields[2], 10)? as usize;
+
+            let value_line = lines.next_line()?;
+            let value = parse_char_list(&value_line).ok_or_else(|| lines.invalid("Invalid value"))?;
+            if value.len() != value_len {
+                return Err(lines.invalid("Value length mismatch"));
+            }
+
+            // Statuses aren't part of the image. Aborted versions are the only ones closed by their own txid.
+            let version_status = if xmax == Some(xmin) { VersionStatus::Abort } else { VersionStatus::Commit };
+            version.push(Version { value, xmin, xmax, version_status });
+        }
+        input.push(Items { key, rank, version });
+    }
+
+    let child_count = single_field(lines, 16)?;
+    if child_count != 0 && child_count as usize != input.len() + 1 {
+        return Err(lines.invalid("Child count doesn't match key count"));
+    }
+
+    let mut children = Vec::with_capacity(child_count as usize);
+    for _ in 0..child_count {
+        children.push(Arc::new(RwLock::new(read_node(lines)?)));
+    }
+
+    Ok(Node::with_items(input, rank, children))
+}
+
+fn single_field<L: Iterator<Item = io::Result<String>>>(lines: &mut ImageLines<L>, radix: u32) -> io::Result<u32> {
+    let fields = lines.next_fields()?;
+    if fields.len() != 1 {
+        return Err(lines.invalid("Expected a single field"));
+    }
+    lines.number(&fields[0], radix)
+}
+
+/// Parses the `{:?}` output of a `Vec<char>`, e.g. `['a', '\'', '\n']`.
+fn parse_char_list(line: &str) -> Option<String> {
+    let inner = line.trim().strip_prefix('[')?.strip_suffix(']')?;
+    let mut chars = inner.chars().peekable();
+    let mut value = String::new();
+
+    while chars.peek().is_some() {
+        if chars.next()? != '\'' {
+            return None;
+        }
+        let c = match chars.next()? {
+            '\\' => match chars.next()? {
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                'u' => {
+                    if chars.next()? != '{' { return None; }
+                    let mut hex = String::new();
+                    loop {
+                        match chars.next()? {
+                            '}' => break,
+                            h => hex.push(h),
+                        }
+                    }
+                    char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                }
+                other => other,
+            },
+            other => other,
+        };
+        if chars.next()? != '\'' {
+            return None;
+        }
+        value.push(c);
+
+        match chars.next() {
+            Some(',') => {
+                if chars.next()? != ' ' { return None; }
+            }
+            None => break,
+            Some(_) => return None,
+        }
+    }
+    Some(value)
+}
@@ -1,41 +1,46 @@
+use std::fs::File;
 use std::io;
+use std::io::BufReader;
 use std::sync::{Arc, RwLock};
 use crate::btree::node::Node;
 
-pub mod tree_nodes;
-pub mod num_or_str;
-pub mod raw;
-pub mod parse;
-pub mod reconstruct;
+pub mod image;
 
 use crate::storage::io::is_file_empty;
+use crate::storage::manifest::Manifest;
 
 impl Node {
+    /// Loads the tree from the image the manifest points to.
+    /// If the current image fails its length/checksum verification or can't be parsed, the previous image is loaded
+    /// instead.
+    /// Without a manifest (nothing checkpointed yet), `serialized_file_path` itself is read as a legacy image.
     pub fn deserialize(serialized_file_path: &str) -> io::Result<Arc<RwLock<Node>>> {
+        let manifest = match Manifest::load(serialized_file_path)? {
+            Some(manifest) => manifest,
+            None => return Node::deserialize_image(serialized_file_path),
+        };
+
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "Manifest doesn't point to any checkpoint image");
+        for entry in manifest.candidates() {
+            let image_path = entry.resolve(serialized_file_path);
+            match entry.verify(serialized_file_path).and_then(|_| Node::deserialize_image(&image_path.to_string_lossy())) {
+                Ok(node) => return Ok(node),
+                Err(e) => {
+                    eprintln!("Skipping checkpoint image {}: {}", entry.file_name, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    fn deserialize_image(serialized_file_path: &str) -> io::Result<Arc<RwLock<Node>>> {
         if is_file_empty(serialized_file_path) {
             return Ok(Node::new());
         }
 
-        let required_node = match raw::get_serialized_file_data(serialized_file_path) {
-
-            Ok (vec) => {
-                let mut key_version_node = parse::get_key_version_node(vec);
-
-                let required_node = key_version_node[0].clone();
-                let hierarchical_node_instance = parse::get_hierarchical_node(required_node, &mut key_version_node);
-
-                let mut constructed_node = reconstruct::get_node(hierarchical_node_instance);
-                constructed_node = reconstruct::deduplicate_children_recursive(constructed_node);
-
-                let constructed_node = Arc::new(RwLock::new(constructed_node));
-                println!("{:?}", constructed_node.read().unwrap().print_tree());
-
-                constructed_node
-            }
-            Err(e) => {
-                return Err(e);
-            }
-        };
-        Ok(required_node)
+        let file = File::open(serialized_file_path)?;
+        let constructed_node = image::read_image(BufReader::new(file))?;
+        Ok(Arc::new(RwLock::new(constructed_node)))
     }
 }
@@ -0,0 +1,172 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+
+/// Pairs held in memory at once by `load` while sorting, each sorted batch is spilled to its own run file.
+pub const RUN_SIZE: usize = 100_000;
+
+/// Reads `<key> <value>` lines from `input_path` and streams the pairs sorted by key with duplicates removed, the
+/// last line for a key winning like a later write would.
+///
+/// # Working:
+/// - A first pass only checks whether the file is already sorted. If it is, the pairs are read straight through.
+/// - Otherwise the file is cut into runs of `run_size` pairs, each sorted in memory and spilled to
+///   `<tmp_dir>/<file name>.<pid>.run<N>`, and the runs are merged with a k-way heap merge as the pairs are pulled.
+/// - Only one line per run is held in memory at a time, whatever the size of the file. The run files are removed once
+///   the [`SortedPairs`] is dropped.
+///
+/// Blank lines are skipped, anything else that isn't a `u32` key followed by a value is an InvalidData error.
+pub fn sorted_pairs(input_path: &str, tmp_dir: &Path, run_size: usize) -> io::Result<SortedPairs> {
+    if is_sorted(input_path)? {
+        let pairs: Box<dyn Iterator<Item = io::Result<(u32, String)>>> = Box::new(read_pairs(input_path)?);
+        return Ok(SortedPairs { pairs, pending: None, runs: Vec::new() });
+    }
+
+    let runs = spill_runs(input_path, tmp_dir, run_size.max(1))?;
+    let merge = match RunMerge::open(&runs) {
+        Ok(merge) => merge,
+        Err(e) => {
+            remove_runs(&runs);
+            return Err(e);
+        }
+    };
+    Ok(SortedPairs { pairs: Box::new(merge), pending: None, runs })
+}
+
+/// The pairs of [`sorted_pairs`] in ascending key order, each key once.
+pub struct SortedPairs {
+    /// Sorted, but with every line of a duplicated key still there.
+    pairs: Box<dyn Iterator<Item = io::Result<(u32, String)>>>,
+    /// The last pair read, held back until a pair with another key shows up.
+    pending: Option<(u32, String)>,
+    runs: Vec<PathBuf>,
+}
+
+impl Iterator for SortedPairs {
+    type Item = io::Result<(u32, String)>;
+
+    fn next(&mut self) -> Option<io::Result<(u32, String)>> {
+        loop {
+            match self.pairs.next() {
+                Some(Ok((key, value))) => match self.pending.as_mut() {
+                    Some(pending) if pending.0 == key => pending.1 = value,
+                    _ => {
+                        if let Some(pair) = self.pending.replace((key, value)) {
+                            return Some(Ok(pair));
+                        }
+                    }
+                },
+                Some(Err(e)) => return Some(Err(e)),
+                None => return self.pending.take().map(Ok),
+            }
+        }
+    }
+}
+
+impl Drop for SortedPairs {
+    fn drop(&mut self) {
+        remove_runs(&self.runs);
+    }
+}
+
+fn remove_runs(runs: &[PathBuf]) {
+    for run in runs.iter() {
+        let _ = fs::remove_file(run);
+    }
+}
+
+fn read_pairs(input_path: &str) -> io::Result<impl Iterator<Item = io::Result<(u32, String)>> + use<>> {
+    let reader = BufReader::new(File::open(input_path)?);
+    Ok(reader.lines().filter_map(|line| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(parse_pair(&line)),
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+fn parse_pair(line: &str) -> io::Result<(u32, String)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Malformed bulk load line: {:?}", line));
+    let (key, value) = line.trim().split_once(char::is_whitespace).ok_or_else(invalid)?;
+    let key = key.parse::<u32>().map_err(|_| invalid())?;
+    Ok((key, value.trim().to_string()))
+}
+
+fn is_sorted(input_path: &str) -> io::Result<bool> {
+    let mut last = None;
+    for pair in read_pairs(input_path)? {
+        let (key, _) = pair?;
+        if last.is_some_and(|last| key < last) {
+            return Ok(false);
+        }
+        last = Some(key);
+    }
+    Ok(true)
+}
+
+/// Run files hold `<key> <value>` lines sorted by key. The sort is stable, so equal keys keep their input order.
+fn spill_runs(input_path: &str, tmp_dir: &Path, run_size: usize) -> io::Result<Vec<PathBuf>> {
+    let file_name = Path::new(input_path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut runs = Vec::new();
+    let mut batch = Vec::with_capacity(run_size);
+
+    let mut pairs = read_pairs(input_path)?.peekable();
+    while pairs.peek().is_some() {
+        batch.clear();
+        for pair in pairs.by_ref().take(run_size) {
+            batch.push(pair?);
+        }
+        batch.sort_by_key(|(key, _)| *key);
+
+        let run_path = tmp_dir.join(format!("{}.{}.run{}", file_name, std::process::id(), runs.len()));
+        let mut writer = BufWriter::new(File::create(&run_path)?);
+        for (key, value) in batch.iter() {
+            writeln!(writer, "{} {}", key, value)?;
+        }
+        writer.flush()?;
+        runs.push(run_path);
+    }
+    Ok(runs)
+}
+
+/// Merges sorted run files. Heap entries are ordered by key, then run number, so for equal keys the later run (later
+/// in the input) comes out last and overwrites the earlier ones.
+struct RunMerge {
+    readers: Vec<Lines<BufReader<File>>>,
+    heap: BinaryHeap<Reverse<(u32, usize, String)>>,
+}
+
+impl RunMerge {
+    fn open(runs: &[PathBuf]) -> io::Result<RunMerge> {
+        let mut merge = RunMerge { readers: Vec::new(), heap: BinaryHeap::new() };
+        for run in runs.iter() {
+            merge.readers.push(BufReader::new(File::open(run)?).lines());
+        }
+        for run in 0..merge.readers.len() {
+            merge.refill(run)?;
+        }
+        Ok(merge)
+    }
+
+    /// Pushes the next line of `run` onto the heap, if it has one.
+    fn refill(&mut self, run: usize) -> io::Result<()> {
+        if let Some(line) = self.readers[run].next() {
+            let (key, value) = parse_pair(&line?)?;
+            self.heap.push(Reverse((key, run, value)));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for RunMerge {
+    type Item = io::Result<(u32, String)>;
+
+    fn next(&mut self) -> Option<io::Result<(u32, String)>> {
+        let Reverse((key, run, value)) = self.heap.pop()?;
+        if let Err(e) = self.refill(run) {
+            return Some(Err(e));
+        }
+        Some(Ok((key, value)))
+    }
+}
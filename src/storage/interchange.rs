@@ -0,0 +1,420 @@
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, RwLock};
+use crate::btree::node::{Items, Node};
+use crate::MVCC::versions::{Version, VersionStatus};
+use crate::MVCC::visibility::version_visible;
+use crate::transactions::transactions::Transaction;
+
+/// Text formats `export` writes and `import` reads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// One JSON object per line: `{"key":1,"value":"a"}`, with `"xmin"`, `"xmax"` and `"version_status"` added for
+    /// version history.
+    Jsonl,
+    /// A `key,value` (or `key,value,xmin,xmax,version_status`) header, then one record per row. Fields are quoted
+    /// RFC 4180 style when needed and an empty `xmax` means the version is still open.
+    Csv,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "jsonl" | "json" => Some(Format::Jsonl),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// Version metadata carried by a record when exporting or importing full history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    pub xmin: u64,
+    pub xmax: Option<u64>,
+    pub version_status: VersionStatus,
+}
+
+/// A single exported row: the visible value of a key, or one version of it when [`History`] is present.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub key: u32,
+    pub value: String,
+    pub history: Option<History>,
+}
+
+/// Streams `node` to `out` as seen by `current_txd`, returning the number of records written.
+///
+/// # Working:
+/// - Without `history` every key with a visible version gets one record holding that version's value.
+/// - With `history` every version whose creation is visible to `current_txd` gets a record, oldest first. Its `xmax` is
+///   only kept if the transaction that closed it is visible as well, so later writes never leak into the export.
+///   Rolled back versions are skipped and versions committed before `commit_abort_handler` caught up are reported as
+///   [`VersionStatus::Commit`].
+/// - Keys are visited with [`Node::for_each_item`] under read latches. `transaction` must therefore not be the locked
+///   transaction table itself, see `btree/latch.rs`, pass a [`read_view`](crate::transactions::manager::read_view)
+///   of it instead. Visibility to one transaction never changes once it began, so the whole export reads one snapshot.
+pub fn export(node: &Arc<RwLock<Node>>, out: &mut impl Write, format: Format, history: bool, current_txd: u64, transaction: &Transaction) -> io::Result<usize> {
+    if format == Format::Csv {
+        let header = if history { "key,value,xmin,xmax,version_status" } else { "key,value" };
+        writeln!(out, "{}", header)?;
+    }
+
+    let mut written = 0;
+    let mut result = Ok(());
+    Node::for_each_item(node, &mut |item| {
+        if result.is_err() {
+            return;
+        }
+
+        for record in records_for(item, history, current_txd, transaction) {
+            result = write_record(out, format, &record);
+            if result.is_err() {
+                return;
+            }
+            written += 1;
+        }
+    });
+    result.map(|_| written)
+}
+
+pub(crate) fn records_for(item: &Items, history: bool, current_txd: u64, transaction: &Transaction) -> Vec<Record> {
+    if !history {
+        return item.version.iter().rev()
+            .find(|version| version_visible(version, current_txd, transaction))
+            .map(|version| Record { key: item.key, value: version.value.clone(), history: None })
+            .into_iter()
+            .collect();
+    }
+
+    let mut records = Vec::new();
+    for version in item.version.iter() {
+        let opened = Version { value: String::new(), xmin: version.xmin, xmax: None, version_status: version.version_status.clone() };
+        if !version_visible(&opened, current_txd, transaction) || version.xmax == Some(version.xmin) {
+            continue;
+        }
+
+        // Created in the snapshot but not visible means it was closed in the snapshot too.
+        let xmax = if version_visible(version, current_txd, transaction) { None } else { version.xmax };
+        // Everything in the snapshot is committed, apart from `current_txd`'s own writes.
+        let version_status = if version.xmin == current_txd { version.version_status.clone() } else { VersionStatus::Commit };
+        records.push(Record { key: item.key, value: version.value.clone(), history: Some(History { xmin: version.xmin, xmax, version_status }) });
+    }
+    records
+}
+
+pub fn write_record(out: &mut impl Write, format: Format, record: &Record) -> io::Result<()> {
+    match format {
+        Format::Jsonl => {
+            write!(out, "{{\"key\":{},\"value\":{}", record.key, json_string(&record.value))?;
+            if let Some(history) = &record.history {
+                let xmax = history.xmax.map(|xmax| xmax.to_string()).unwrap_or_else(|| String::from("null"));
+                write!(out, ",\"xmin\":{},\"xmax\":{},\"version_status\":\"{}\"", history.xmin, xmax, status_name(&history.version_status))?;
+            }
+            writeln!(out, "}}")
+        }
+        Format::Csv => {
+            write!(out, "{},{}", record.key, csv_field(&record.value))?;
+            if let Some(history) = &record.history {
+                let xmax = history.xmax.map(|xmax| xmax.to_string()).unwrap_or_default();
+                write!(out, ",{},{},{}", history.xmin, xmax, status_name(&history.version_status))?;
+            }
+            writeln!(out)
+        }
+    }
+}
+
+/// Reads every record from `input`. Records carry [`History`] if the input has version fields, which must then be
+/// present on every record.
+pub fn read_records(input: impl BufRead, format: Format) -> io::Result<Vec<Record>> {
+    match format {
+        Format::Jsonl => {
+            let mut records = Vec::new();
+            for (number, line) in input.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let fields = parse_json_object(&line).ok_or_else(|| invalid(number + 1, "malformed JSON object"))?;
+                records.push(record_from_fields(&fields, number + 1)?);
+            }
+            Ok(records)
+        }
+        Format::Csv => {
+            let mut input = input;
+            let mut text = String::new();
+            input.read_to_string(&mut text)?;
+
+            let mut rows = parse_csv(&text).ok_or_else(|| invalid(0, "unterminated quoted field"))?.into_iter();
+            let header = match rows.next() {
+                Some(header) => header,
+                None => return Ok(Vec::new()),
+            };
+
+            let mut records = Vec::new();
+            for (number, row) in rows.enumerate() {
+                if row.len() == 1 && row[0].is_empty() {
+                    continue;
+                }
+                if row.len() != header.len() {
+                    return Err(invalid(number + 2, "wrong number of fields"));
+                }
+                let fields: Vec<(String, Option<String>)> = header.iter().cloned()
+                    .zip(row.into_iter().map(|field| if field.is_empty() { None } else { Some(field) }))
+                    .collect();
+                records.push(record_from_fields(&fields, number + 2)?);
+            }
+            Ok(records)
+        }
+    }
+}
+
+/// Groups history records into the items of a table, each key's versions ordered by `xmin`.
+/// Records without history become a single committed version written by `txd`, the last record for a key winning.
+pub fn records_to_items(records: Vec<Record>, txd: u64) -> Vec<Items> {
+    let mut records = records;
+    records.sort_by_key(|record| (record.key, record.history.as_ref().map(|history| history.xmin)));
+
+    let mut items: Vec<Items> = Vec::new();
+    for record in records {
+        let (version, replace) = match record.history {
+            Some(history) => (Version { value: record.value, xmin: history.xmin, xmax: history.xmax, version_status: history.version_status }, false),
+            None => (Version { value: record.value, xmin: txd, xmax: None, version_status: VersionStatus::Commit }, true),
+        };
+        match items.last_mut() {
+            Some(last) if last.key == record.key && replace => last.version = vec![version],
+            Some(last) if last.key == record.key => last.version.push(version),
+            _ => items.push(Items { key: record.key, rank: 1, version: vec![version] }),
+        }
+    }
+    items
+}
+
+fn record_from_fields(fields: &[(String, Option<String>)], line: usize) -> io::Result<Record> {
+    let field = |name: &str| fields.iter().find(|(field, _)| field == name).map(|(_, value)| value.clone());
+
+    let key = field("key").flatten().and_then(|key| key.parse::<u32>().ok()).ok_or_else(|| invalid(line, "missing or invalid key"))?;
+    let value = field("value").flatten().unwrap_or_default();
+    let history = match field("xmin") {
+        None => None,
+        Some(xmin) => {
+            let xmin = xmin.and_then(|xmin| xmin.parse::<u64>().ok()).ok_or_else(|| invalid(line, "invalid xmin"))?;
+            let xmax = match field("xmax").flatten() {
+                Some(xmax) => Some(xmax.parse::<u64>().map_err(|_| invalid(line, "invalid xmax"))?),
+                None => None,
+            };
+            let version_status = field("version_status").flatten().and_then(|status| parse_status(&status)).ok_or_else(|| invalid(line, "invalid version_status"))?;
+            Some(History { xmin, xmax, version_status })
+        }
+    };
+    Ok(Record { key, value, history })
+}
+
+fn invalid(line: usize, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Import failed at line {}: {}", line, reason))
+}
+
+fn status_name(status: &VersionStatus) -> &'static str {
+    match status {
+        VersionStatus::Active => "Active",
+        VersionStatus::Commit => "Commit",
+        VersionStatus::Abort => "Abort",
+        VersionStatus::Delete => "Delete",
+    }
+}
+
+fn parse_status(name: &str) -> Option<VersionStatus> {
+    match name {
+        "Active" => Some(VersionStatus::Active),
+        "Commit" => Some(VersionStatus::Commit),
+        "Abort" => Some(VersionStatus::Abort),
+        "Delete" => Some(VersionStatus::Delete),
+        _ => None,
+    }
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Parses a flat JSON object whose values are strings, non-negative integers or `null`, the only shapes
+/// [`write_record`] produces. Numbers are returned as their text, `null` as [`None`].
+pub(crate) fn parse_json_object(line: &str) -> Option<Vec<(String, Option<String>)>> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = Vec::new();
+
+    let skip_whitespace = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+    };
+
+    if chars.next()? != '{' {
+        return None;
+    }
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+        return chars.next().is_none().then_some(fields);
+    }
+
+    loop {
+        skip_whitespace(&mut chars);
+        if chars.next()? != '"' {
+            return None;
+        }
+        let name = parse_json_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_whitespace(&mut chars);
+
+        let value = match chars.peek()? {
+            '"' => {
+                chars.next();
+                Some(parse_json_string(&mut chars)?)
+            }
+            'n' => {
+                let null: String = chars.by_ref().take(4).collect();
+                if null != "null" {
+                    return None;
+                }
+                None
+            }
+            c if c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    number.push(*c);
+                    chars.next();
+                }
+                Some(number)
+            }
+            _ => return None,
+        };
+        fields.push((name, value));
+
+        skip_whitespace(&mut chars);
+        match chars.next()? {
+            ',' => continue,
+            '}' => break,
+            _ => return None,
+        }
+    }
+
+    skip_whitespace(&mut chars);
+    chars.next().is_none().then_some(fields)
+}
+
+/// Reads a JSON string body up to and including its closing quote.
+fn parse_json_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    let mut value = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                '"' => value.push('"'),
+                '\\' => value.push('\\'),
+                '/' => value.push('/'),
+                'b' => value.push('\u{8}'),
+                'f' => value.push('\u{c}'),
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                'u' => {
+                    let high = parse_hex4(chars)?;
+                    let code = if (0xD800..0xDC00).contains(&high) {
+                        if chars.next()? != '\\' || chars.next()? != 'u' {
+                            return None;
+                        }
+                        let low = parse_hex4(chars)?;
+                        if !(0xDC00..0xE000).contains(&low) {
+                            return None;
+                        }
+                        0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                    } else {
+                        high
+                    };
+                    value.push(char::from_u32(code)?);
+                }
+                _ => return None,
+            },
+            c => value.push(c),
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<u32> {
+    let hex: String = chars.by_ref().take(4).collect();
+    if hex.len() != 4 {
+        return None;
+    }
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+fn csv_field(value: &str) -> String {
+    let needs_quotes = value.is_empty() || value.contains([',', '"', '\n', '\r']) || value.trim() != value;
+    if needs_quotes {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Splits CSV text into rows of fields. Quoted fields may contain commas, newlines and doubled quotes. An empty
+/// quoted field (`""`) comes back as an empty string just like an empty unquoted one, so an exported empty value
+/// imports as empty.
+fn parse_csv(text: &str) -> Option<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                c => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+
+    if quoted {
+        return None;
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Some(rows)
+}
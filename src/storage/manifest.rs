@@ -0,0 +1,185 @@
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A single checkpoint image recorded in the manifest.
+/// `len` and `checksum` are taken while the image is being written, so a torn or truncated file is detected before parsing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageEntry {
+    pub file_name: String,
+    pub len: u64,
+    pub checksum: u64,
+}
+
+/// Points to the current checkpoint image and the one before it.
+///
+/// The manifest lives next to the serialized tree as `<serialized_file_path>.manifest` and every image is written as
+/// `<serialized_file_path>.<generation>`. Only the manifest decides which image is live, so swapping it with a rename
+/// is what makes a checkpoint visible.
+///
+/// ```text
+/// generation 4
+/// current example.txt.4 1873 11740638377446301185
+/// previous example.txt.3 1610 3190412387362416011
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    pub generation: u64,
+    pub current: Option<ImageEntry>,
+    pub previous: Option<ImageEntry>,
+}
+
+impl Manifest {
+    pub fn path_for(serialized_file_path: &str) -> PathBuf {
+        PathBuf::from(format!("{}.manifest", serialized_file_path))
+    }
+
+    /// Returns `Ok(None)` when no checkpoint was ever written through the manifest (fresh install or legacy image).
+    pub fn load(serialized_file_path: &str) -> io::Result<Option<Manifest>> {
+        let file = match File::open(Manifest::path_for(serialized_file_path)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut manifest = Manifest::default();
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["generation", generation] => manifest.generation = parse_field(generation)?,
+                ["current", name, len, checksum] => manifest.current = Some(ImageEntry::parse(name, len, checksum)?),
+                ["previous", name, len, checksum] => manifest.previous = Some(ImageEntry::parse(name, len, checksum)?),
+                [] => {}
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Malformed manifest line: {:?}", line))),
+            }
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Writes the manifest to a temp file, fsyncs it, renames it over the old one and fsyncs the directory.
+    pub fn store(&self, serialized_file_path: &str) -> io::Result<()> {
+        let mut contents = format!("generation {}\n", self.generation);
+        if let Some(current) = &self.current {
+            contents.push_str(&format!("current {} {} {}\n", current.file_name, current.len, current.checksum));
+        }
+        if let Some(previous) = &self.previous {
+            contents.push_str(&format!("previous {} {} {}\n", previous.file_name, previous.len, previous.checksum));
+        }
+
+        let manifest_path = Manifest::path_for(serialized_file_path);
+        let tmp_path = tmp_path_for(&manifest_path);
+        {
+            let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &manifest_path)?;
+        sync_parent_dir(&manifest_path)
+    }
+
+    /// Candidate images in the order they should be tried on load.
+    pub fn candidates(&self) -> Vec<&ImageEntry> {
+        self.current.iter().chain(self.previous.iter()).collect()
+    }
+}
+
+impl ImageEntry {
+    fn parse(name: &str, len: &str, checksum: &str) -> io::Result<ImageEntry> {
+        Ok(ImageEntry { file_name: name.to_string(), len: parse_field(len)?, checksum: parse_field(checksum)? })
+    }
+
+    /// Images are stored next to the manifest, so only the file name is kept and resolved against its directory.
+    pub fn resolve(&self, serialized_file_path: &str) -> PathBuf {
+        match Path::new(serialized_file_path).parent() {
+            Some(dir) => dir.join(&self.file_name),
+            None => PathBuf::from(&self.file_name),
+        }
+    }
+
+    /// Re-reads the image and compares its length and checksum with what was recorded when it was written.
+    pub fn verify(&self, serialized_file_path: &str) -> io::Result<()> {
+        let mut file = File::open(self.resolve(serialized_file_path))?;
+        let mut hasher = Checksum::new();
+        let mut buffer = [0u8; 8192];
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 { break; }
+            hasher.update(&buffer[..n]);
+        }
+
+        if hasher.len != self.len || hasher.finish() != self.checksum {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Checkpoint image {} is torn or corrupted", self.file_name)));
+        }
+        Ok(())
+    }
+}
+
+/// FNV-1a over every byte written to an image, along with the byte count.
+#[derive(Debug, Clone)]
+pub struct Checksum {
+    hash: u64,
+    pub len: u64,
+}
+
+impl Checksum {
+    pub fn new() -> Checksum {
+        Checksum { hash: 0xcbf29ce484222325, len: 0 }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash ^= *byte as u64;
+            self.hash = self.hash.wrapping_mul(0x100000001b3);
+        }
+        self.len += bytes.len() as u64;
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+impl Default for Checksum {
+    fn default() -> Self {
+        Checksum::new()
+    }
+}
+
+/// Forwards writes to the inner writer while checksumming exactly the bytes that were accepted.
+pub struct ChecksumWriter<W: Write> {
+    pub inner: W,
+    pub checksum: Checksum,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.checksum.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+pub fn tmp_path_for(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+/// A rename is only durable once the directory entry itself has been flushed.
+pub fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+fn parse_field<T: std::str::FromStr>(field: &str) -> io::Result<T> {
+    field.parse::<T>().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Malformed manifest field: {:?}", field)))
+}
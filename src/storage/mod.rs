@@ -1,4 +1,8 @@
 pub mod ser;
 pub mod deser;
 pub mod io;
-pub mod wal;
\ No newline at end of file
+pub mod wal;
+pub mod manifest;
+pub mod external_sort;
+
+pub mod interchange;
\ No newline at end of file
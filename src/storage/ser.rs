@@ -1,53 +1,98 @@
 pub use std::io::Write;
-use std::fs::{File, OpenOptions};
+use std::fs::{self, OpenOptions};
 use std::io;
+use std::io::BufWriter;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 use crate::btree::node::Node;
+use crate::storage::manifest::{sync_parent_dir, tmp_path_for, ChecksumWriter, ImageEntry, Manifest, Checksum};
 
+/// Writes a checkpoint image of `node` without ever touching the live image in place.
+///
+/// # Working:
+/// - The tree is written to `<serialized_file_path>.<generation>.tmp`, flushed and fsynced.
+/// - The temp file is renamed to `<serialized_file_path>.<generation>` and the directory is fsynced.
+/// - The manifest is swapped (same temp + rename + fsync dance) so that the new image becomes `current` and the
+///   image it replaces becomes `previous`.
+/// - The image that fell off the manifest is removed on a best-effort basis.
+///
+/// A crash at any point leaves the manifest pointing to a complete image, [`Node::deserialize`] never sees a half-written file.
 pub fn serialize(node: Arc<RwLock<Node>>, serialized_file_path: &str) -> io::Result<()> {
-    let mut file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(serialized_file_path)?;
-
-    writeln!(file, "[0]").expect("TODO: panic message");
-    serialization(node, &mut file);
+    let old_manifest = Manifest::load(serialized_file_path)?.unwrap_or_default();
+    let generation = old_manifest.generation + 1;
+
+    let image_path = format!("{}.{}", serialized_file_path, generation);
+    let image_path = Path::new(&image_path);
+    let tmp_path = tmp_path_for(image_path);
+
+    let checksum = {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+
+        let mut writer = ChecksumWriter { inner: BufWriter::new(file), checksum: Checksum::new() };
+        writeln!(writer, "[0]")?;
+        serialization(node, &mut writer)?;
+        writer.flush()?;
+
+        let file = writer.inner.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        writer.checksum
+    };
+
+    fs::rename(&tmp_path, image_path)?;
+    sync_parent_dir(image_path)?;
+
+    let file_name = image_path.file_name().unwrap().to_string_lossy().to_string();
+    let new_manifest = Manifest {
+        generation,
+        current: Some(ImageEntry { file_name, len: checksum.len, checksum: checksum.finish() }),
+        previous: old_manifest.current.clone(),
+    };
+    new_manifest.store(serialized_file_path)?;
+
+    if let Some(stale) = old_manifest.previous
+        && let Err(e) = fs::remove_file(stale.resolve(serialized_file_path)) {
+        println!("Failed to remove stale checkpoint image {}: {}", stale.file_name, e);
+    }
+
     Ok(())
 }
 
-pub fn serialization(node: Arc<RwLock<Node>>, file: &mut File) {
+pub fn serialization<W: Write>(node: Arc<RwLock<Node>>, file: &mut W) -> io::Result<()> {
     let node_instance = node.read().unwrap();
     let l = node_instance.input.len();
-    writeln!(file, "[{:X}]", node_instance.rank).expect("Error writing to file.");
-    writeln!(file, "[{:X}]", l).expect("panic message");
+    writeln!(file, "[{:X}]", node_instance.rank)?;
+    writeln!(file, "[{:X}]", l)?;
     for i in 0..l {
-        write!(file, "[{}]", node_instance.input[i].key).expect("panic message");
+        write!(file, "[{}]", node_instance.input[i].key)?;
         let version_len = node_instance.input[i].version.len();
-        writeln!(file, "[{}]", version_len).expect("panic message");
+        writeln!(file, "[{}]", version_len)?;
         for ver in &node_instance.input[i].version {
-            write!(file, "[{}]", ver.xmin).expect("panic message");
+            write!(file, "[{}]", ver.xmin)?;
             match ver.xmax {
                 Some(xm) => {
-                    write!(file, "[{}]", xm).expect("panic message");
+                    write!(file, "[{}]", xm)?;
                 }
                 None => {
-                    write!(file, "[-]").expect("panic message");
+                    write!(file, "[-]")?;
                 }
             }
             let value_len = ver.value.len();
-            writeln!(file, "[{}]", value_len).expect("panic message");
+            writeln!(file, "[{}]", value_len)?;
             let x: Vec<char> = ver.value.chars().collect();
-            write!(file, "{:?}", x).expect("panic message");
-            writeln!(file, "").expect("panic message");
+            write!(file, "{:?}", x)?;
+            writeln!(file)?;
         }
     }
-    writeln!(file, "[{:X}]", node_instance.children.len()).expect("panic message");
+    writeln!(file, "[{:X}]", node_instance.children.len())?;
     if !node_instance.children.is_empty() {
         for i in 0..node_instance.input.len() + 1 {
             let z = Arc::clone(&node_instance.children[i]);
-            serialization(z, file);
+            serialization(z, file)?;
         }
     }
+    Ok(())
 }
-    
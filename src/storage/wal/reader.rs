@@ -1,8 +1,14 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::io;
-use std::sync::atomic::Ordering;
-use crate::LAST_ACTIVE_TXD;
+use std::net::SocketAddr;
+use crate::replication::shipper::record_parts;
 use crate::storage::io::{empty_file, read_file};
+use crate::transactions::prepared::{prepared_record, prepared_txid_record, PreparedRecord};
+use crate::transactions::txid::reservation;
 
+/// Reads and truncates the WAL, returning what has to survive the truncation: the records of every transaction
+/// without a `commit` or `abort` after its last `begin`, of every prepared transaction not resolved yet, and the last
+/// txid reservation, in WAL order.
 pub fn get_uncommitted_transactions(wal_file_path: &str) -> io::Result<Vec<String>> {
     let mut uncommitted_strings = Vec::new();
 
@@ -15,22 +21,16 @@ pub fn get_uncommitted_transactions(wal_file_path: &str) -> io::Result<Vec<Strin
                 }
             }
 
-            let mut commit_count = 0;
-
-            for line in metadata.lines() {
-                let items = line.replace("\"", "");
-
+            let lines: Vec<String> = metadata.lines().map(|line| line.replace("\"", "")).collect();
+            for items in lines.iter() {
                 println!("{:?}", items);
-
-                if commit_count >= LAST_ACTIVE_TXD.load(Ordering::SeqCst) {
-                    uncommitted_strings.push(items.clone());
-                }
-                {
-                    if items.to_lowercase().contains("commit") {
-                        commit_count += 1;
-                    }
-                }
             }
+
+            let open = open_records(&lines);
+            let mut kept: BTreeSet<usize> = open.last_reservation.into_iter().collect();
+            kept.extend(open.by_addr.values().flatten());
+            kept.extend(open.prepared.values().flatten());
+            uncommitted_strings.extend(kept.into_iter().map(|position| lines[position].clone()));
         }
         Err(e) => {
             println!("File read error: {}", e);
@@ -39,3 +39,79 @@ pub fn get_uncommitted_transactions(wal_file_path: &str) -> io::Result<Vec<Strin
 
     Ok(uncommitted_strings)
 }
+
+/// The positions of the records still needed at the end of a run of WAL records, see [`open_records`].
+#[derive(Debug, Default)]
+pub(crate) struct OpenRecords {
+    /// The records of each client's open transaction, from its `begin` on.
+    pub by_addr: HashMap<SocketAddr, Vec<usize>>,
+    /// The records of each prepared transaction not resolved yet, by gid, from its `begin` to its `prepare`.
+    pub prepared: BTreeMap<String, Vec<usize>>,
+    /// The txid each of them had, from its `prepared_txid` record.
+    pub prepared_txids: HashMap<String, u64>,
+    pub last_reservation: Option<usize>,
+}
+
+/// Follows `records` in WAL order to find the transactions still open or prepared at their end.
+///
+/// # Working:
+/// - A client's `begin` opens its transaction and every later record of the client belongs to it, until its `commit`
+///   or `abort`. A `begin` while it's open was refused, the open one carries on.
+/// - The last `use` of the client before its `begin` is kept with the transaction, so replaying it writes to the same
+///   table.
+/// - `prepare` moves the open transaction's records under its gid, with the `prepared_txid` record logged right
+///   before it, where they stay until a `commit prepared` or `rollback prepared` from any client. Those records never belong to a transaction of their own client, which can't
+///   send them while one is open.
+pub(crate) fn open_records(records: &[String]) -> OpenRecords {
+    let mut open = OpenRecords::default();
+    let mut last_use: HashMap<SocketAddr, usize> = HashMap::new();
+    let mut txids: HashMap<String, (usize, u64)> = HashMap::new();
+    for (position, record) in records.iter().enumerate() {
+        if reservation(record).is_some() {
+            open.last_reservation = Some(position);
+        }
+        if let Some((gid, txid)) = prepared_txid_record(record) {
+            txids.insert(gid, (position, txid));
+            continue;
+        }
+        let Some((command, addr)) = record_parts(record) else { continue };
+        match prepared_record(record) {
+            Some(PreparedRecord::Prepare(gid)) => {
+                if let Some(mut positions) = open.by_addr.remove(&addr) {
+                    if let Some((txid_position, txid)) = txids.remove(&gid) {
+                        positions.push(txid_position);
+                        open.prepared_txids.insert(gid.clone(), txid);
+                    }
+                    positions.push(position);
+                    open.prepared.insert(gid, positions);
+                }
+                continue;
+            }
+            Some(PreparedRecord::Commit(gid) | PreparedRecord::Rollback(gid)) => {
+                open.prepared.remove(&gid);
+                open.prepared_txids.remove(&gid);
+                continue;
+            }
+            None => {}
+        }
+
+        match command.as_str() {
+            "begin" if !open.by_addr.contains_key(&addr) => {
+                let positions = last_use.get(&addr).map_or_else(|| vec![position], |&used| vec![used, position]);
+                open.by_addr.insert(addr, positions);
+            }
+            "commit" | "abort" => {
+                open.by_addr.remove(&addr);
+            }
+            _ => {
+                if command == "use" {
+                    last_use.insert(addr, position);
+                }
+                if let Some(positions) = open.by_addr.get_mut(&addr) {
+                    positions.push(position);
+                }
+            }
+        }
+    }
+    open
+}
@@ -1,12 +1,22 @@
-use std::fs::File;
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
 use std::sync::{Arc, RwLock};
-use crate::btree::node::Node;
-use crate::cli::cli::cli;
+use crate::catalog::tables::Catalog;
+use crate::cli::cli::{cli, execute};
+use crate::engine::services::Services;
+use crate::replication::shipper::record_parts;
 use crate::storage::io::{empty_file, read_file};
+use crate::storage::manifest::sync_parent_dir;
+use crate::storage::wal::reader::open_records;
+use crate::storage::wal::writer::{carry_over_to_wal, flush_to_wal};
+use crate::transactions::manager::start_transaction;
+use crate::transactions::prepared::prepared_txid_record;
 use crate::transactions::transactions::Transaction;
+use crate::transactions::txid::reservation;
 
-pub fn initialize_from_wal(wal_file_path: &str, txd_count: Arc<RwLock<u32>>, current_transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, new_node: Arc<RwLock<Node>>, all_addr: Arc<RwLock<Vec<SocketAddr>>>) {
+pub fn initialize_from_wal(wal_file_path: &str, txd_count: Arc<RwLock<u64>>, current_transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, catalog: Arc<RwLock<Catalog>>, services: Arc<Services>) {
     match read_file(wal_file_path) {
         Ok(value) => {
             let mut uncommitted_strings = Vec::new();
@@ -21,7 +31,7 @@ pub fn initialize_from_wal(wal_file_path: &str, txd_count: Arc<RwLock<u32>>, cur
 
                 if load_to_cli {
                     for vals in uncommitted_strings.iter() {
-                        match cli(vals.clone(), Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&new_node), None, Arc::clone(&all_addr)) {
+                        match cli(vals.clone(), Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&file), Arc::clone(&catalog), None, Arc::clone(&services)) {
                             Ok(_) => {}
                             Err(e) => println!("WAL recovery error: {}", e),
 
@@ -42,3 +52,76 @@ pub fn initialize_from_wal(wal_file_path: &str, txd_count: Arc<RwLock<u32>>, cur
     }
 
 }
+
+/// Replays, on startup, every WAL record the catalog image doesn't cover. Returns how many prepared transactions that
+/// brought back.
+///
+/// # Working:
+/// - The image holds what was committed up to the last checkpoint, with whatever was in progress then rolled back. The
+///   WAL holds the rest: the records that checkpoint carried over and everything logged since.
+/// - Those records are replayed in WAL order, so commits since the checkpoint are applied again and prepared
+///   transactions are prepared again, see [`open_records`]. Records of transactions still open and not prepared at
+///   the end of the WAL are dropped: the restart aborted them.
+/// - A prepared transaction is started under the txid of its `prepared_txid` record rather than a new one. Its gid is
+///   all a coordinator knows it by, but its versions and the snapshots listing it use the txid.
+/// - Records run with [`Services::for_replay`]: nothing waits for a row lock, whoever held it is replayed before it
+///   or never comes back, and nothing checkpoints over the WAL being replayed.
+/// - Replaying writes the records again, into `<wal>.tmp` after the last txid reservation. Only once every record
+///   went through is it fsynced and renamed over the WAL, which `file` is then reopened on, so a crash during recovery
+///   leaves the old WAL as it was.
+pub fn recover_from_wal(wal_file_path: &str, txd_count: Arc<RwLock<u64>>, current_transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, catalog: Arc<RwLock<Catalog>>, services: Arc<Services>) -> io::Result<usize> {
+    let records: Vec<String> = match fs::read_to_string(wal_file_path) {
+        Ok(contents) => contents.lines().map(|line| line.replace("\"", "")).collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    let open = open_records(&records);
+
+    // Reservations are carried once, the `prepared_txid` records are written again by the `prepare` they precede.
+    let mut skipped: HashSet<usize> = open.by_addr.values().flatten().copied().collect();
+    skipped.extend(records.iter().enumerate()
+        .filter(|(_, record)| reservation(record).is_some() || prepared_txid_record(record).is_some())
+        .map(|(position, _)| position));
+    let mut original_txids = HashMap::new();
+    for (gid, positions) in open.prepared.iter() {
+        let begin = positions.iter().find(|&&position| record_parts(&records[position]).is_some_and(|(command, _)| command == "begin"));
+        if let (Some(&begin), Some(&txid)) = (begin, open.prepared_txids.get(gid)) {
+            original_txids.insert(begin, txid);
+        }
+    }
+
+    let tmp_path = format!("{}.tmp", wal_file_path);
+    let tmp = Arc::new(RwLock::new(OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?));
+    if let Some(position) = open.last_reservation {
+        carry_over_to_wal(Arc::clone(&tmp), records[position].split(" ").collect())?;
+    }
+
+    let replay = Arc::new(services.for_replay());
+    for (position, record) in records.iter().enumerate() {
+        if skipped.contains(&position) || record.trim().is_empty() {
+            continue;
+        }
+        match original_txids.get(&position) {
+            Some(&txid) => begin_as(record, txid, &tmp, &current_transaction, &replay)?,
+            None => {
+                execute(record.clone(), Arc::clone(&txd_count), Arc::clone(&current_transaction), Arc::clone(&tmp), Arc::clone(&catalog), None, Arc::clone(&replay))?;
+            }
+        }
+    }
+
+    tmp.write().unwrap().sync_all()?;
+    fs::rename(&tmp_path, wal_file_path)?;
+    sync_parent_dir(Path::new(wal_file_path))?;
+    *file.write().unwrap() = OpenOptions::new().append(true).create(true).open(wal_file_path)?;
+    Ok(open.prepared.len())
+}
+
+/// Replays the `begin` `record` of a prepared transaction under its original `txid`. It's lower than any txid handed
+/// out since the restart, and no other transaction has it: the one that had it didn't survive the restart.
+fn begin_as(record: &str, txid: u64, file: &Arc<RwLock<File>>, current_transaction: &Arc<RwLock<Transaction>>, services: &Services) -> io::Result<()> {
+    let Some((_, addr)) = record_parts(record) else { return Ok(()) };
+    flush_to_wal(Arc::clone(file), record.split(" ").collect())?;
+    services.all_addr.write().unwrap().push(addr);
+    start_transaction(&mut current_transaction.write().unwrap(), txid, addr);
+    Ok(())
+}
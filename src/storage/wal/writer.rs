@@ -2,6 +2,7 @@ use std::io::Write;
 use std::fs::File;
 use std::io;
 use std::sync::{Arc, RwLock};
+use crate::WAL_SHIPPER;
 
 pub fn flush_to_wal(file: Arc<RwLock<File>>, args: Vec<&str>) -> io::Result<()> {
     let args = args.join(" ");
@@ -10,6 +11,22 @@ pub fn flush_to_wal(file: Arc<RwLock<File>>, args: Vec<&str>) -> io::Result<()>
 
     writeln!(file_instance, "{:?}", args).expect("TODO: panic message");
     file_instance.sync_all()?;
+    // Shipped while the file is still locked, so followers get the records in WAL order.
+    WAL_SHIPPER.ship(args);
 
     Ok(())
 }
+
+/// Writes a record that was already flushed (and shipped) once back to the WAL, like checkpoint does with the records
+/// of uncommitted transactions when it truncates the WAL. Followers don't get it a second time.
+pub fn carry_over_to_wal(file: Arc<RwLock<File>>, args: Vec<&str>) -> io::Result<()> {
+    log_to_wal(file, args)
+}
+
+/// Writes a record only this server needs, like a txid reservation. It isn't shipped to followers.
+pub fn log_to_wal(file: Arc<RwLock<File>>, args: Vec<&str>) -> io::Result<()> {
+    let mut file_instance = file.write().unwrap();
+
+    writeln!(file_instance, "{:?}", args.join(" "))?;
+    file_instance.sync_all()
+}
@@ -0,0 +1,46 @@
+use std::net::SocketAddr;
+use crate::transactions::timeout::active_txd;
+use crate::transactions::transactions::Transaction;
+
+/// How a statement sent without an active transaction runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Autocommit {
+    /// In a read-only transaction of its own, nothing of which is logged.
+    ReadOnly,
+    /// In a transaction of its own, committed if the statement wrote anything and aborted otherwise.
+    ReadWrite,
+}
+
+impl Autocommit {
+    /// The command beginning the statement's transaction.
+    pub fn begin(&self) -> &'static str {
+        match self {
+            Autocommit::ReadOnly => "begin read only",
+            Autocommit::ReadWrite => "begin",
+        }
+    }
+}
+
+/// How `command` runs when its client has no active transaction, if it runs in one of its own.
+///
+/// # Working:
+/// - Reads (`select`, `mget`, `select_by`, `count`, `aggregate`) see everything committed when they run, like a
+///   `begin read only` sent just before them.
+/// - Writes (`insert`, `update`, `delete`, `upsert`, `mset`, `mdel`) get the same `begin`, command, `commit` records in
+///   the WAL an explicit transaction would have written, so followers and recovery replay them unchanged. A write that
+///   was refused is aborted instead.
+/// - The conditional writes run in an implicit transaction of their own already, see
+///   [`conditional_write`](crate::transactions::conditional::conditional_write). `lock` and `select_for_update` still
+///   need an explicit transaction: a lock released right away protects nothing.
+pub fn autocommit(command: &str) -> Option<Autocommit> {
+    match command {
+        "select" | "mget" | "select_by" | "count" | "aggregate" => Some(Autocommit::ReadOnly),
+        "insert" | "update" | "delete" | "upsert" | "mset" | "mdel" => Some(Autocommit::ReadWrite),
+        _ => None,
+    }
+}
+
+/// Whether `addr`'s active transaction began with `begin read only`.
+pub fn read_only(transaction: &Transaction, addr: &SocketAddr) -> bool {
+    active_txd(transaction, addr).is_some_and(|txd| transaction.items[&txd].read_only)
+}
@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use crate::btree::node::Node;
+use crate::MVCC::visibility::{has_serialization_conflict, key_claimed};
+use crate::storage::wal::writer::flush_to_wal;
+use crate::transactions::conditional::Target;
+use crate::transactions::savepoint::write_txd;
+use crate::transactions::transactions::{Transaction, TransactionStatus};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchStatus {
+    /// Number of keys written or deleted.
+    Applied(usize),
+    /// The batch was rejected as a whole because this key was written by another transaction.
+    Conflict(u32),
+    NoTransaction,
+}
+
+impl BatchStatus {
+    /// The status line sent back to the client.
+    pub fn message(&self) -> String {
+        match self {
+            BatchStatus::Applied(count) => format!("OK: {} keys", count),
+            BatchStatus::Conflict(key) => format!("FAILED: key {} modified by another transaction", key),
+            BatchStatus::NoTransaction => String::from("FAILED: active transaction not found"),
+        }
+    }
+}
+
+/// Runs a batch of upserts (`Some` value) and deletes (`None`) inside `addr`'s active transaction.
+///
+/// # Working:
+/// - The transaction table stays write-locked for the whole batch. Every key is conflict-checked before anything is
+///   written, so the batch either applies completely or not at all.
+/// - Upserts go through [`Node::upsert_batch`], which rebalances the tree once for the batch.
+/// - `args` is logged as one WAL record once the batch applied, recovery replays it through `cli` as a whole inside
+///   the same transaction. It's the command line, or the `mset` an `import` amounts to.
+/// - Deletes of keys without a live version are skipped and not counted.
+pub fn batch_write(target: Target, writes: Vec<(u32, Option<String>)>, addr: SocketAddr, transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, args: Vec<&str>) -> io::Result<BatchStatus> {
+    let mut tx = transaction.write().unwrap();
+
+    let txd = match tx.ip_txd.get(&addr) {
+        Some(&txd) if tx.items.get(&txd).is_some_and(|item| item.status == TransactionStatus::Active) => txd,
+        _ => return Ok(BatchStatus::NoTransaction),
+    };
+
+    for (key, _) in writes.iter() {
+        if key_claimed(&tx, target.table, *key, txd) || has_serialization_conflict(&target.node, *key, txd, &tx) {
+            return Ok(BatchStatus::Conflict(*key));
+        }
+    }
+    let keys: Vec<u32> = writes.iter().map(|(key, _)| *key).collect();
+    if let Err(key) = target.locks.try_acquire_all(txd, target.table, &keys) {
+        return Ok(BatchStatus::Conflict(key));
+    }
+
+    let writer = write_txd(&tx, txd);
+    let mut modified = Vec::new();
+    let mut upserts = Vec::new();
+    for (key, value) in writes {
+        match value {
+            Some(value) => {
+                upserts.push((key, value));
+                modified.push(key);
+            }
+            None => {
+                if Node::find_and_update_key_version(Arc::clone(&target.node), key, None, writer, true).is_some() {
+                    modified.push(key);
+                }
+            }
+        }
+    }
+    // Entries go in once the versions are written, so vacuum never drops one a version still needs.
+    let indexed = if target.indexes.is_empty() { Vec::new() } else { upserts.clone() };
+    Node::upsert_batch(Arc::clone(&target.node), upserts, writer);
+    for index in target.indexes.iter() {
+        for (key, value) in indexed.iter() {
+            index.add(*key, value);
+        }
+    }
+
+    let count = modified.len();
+    if let Some(item) = tx.items.get_mut(&txd) {
+        item.modified_keys.extend(modified.into_iter().map(|key| (target.table.to_string(), key)));
+    }
+    flush_to_wal(file, args)?;
+
+    Ok(BatchStatus::Applied(count))
+}
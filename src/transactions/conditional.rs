@@ -0,0 +1,189 @@
+use std::fs::File;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use crate::btree::node::Node;
+use crate::catalog::index::Index;
+use crate::engine::cdc::{capture_changes, ChangeLog};
+use crate::engine::watch::Watches;
+use crate::MVCC::visibility::{commit_abort_handler, has_serialization_conflict, key_claimed, version_visible, with_versions};
+use crate::storage::wal::writer::flush_to_wal;
+use crate::transactions::locks::LockManager;
+use crate::transactions::manager::start_transaction;
+use crate::transactions::savepoint::write_txd;
+use crate::transactions::transactions::{Transaction, TransactionStatus};
+use crate::transactions::txid::TxidReservation;
+
+/// What the visible version of a key has to look like for a conditional write to go through.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// The visible value equals this one (`cas`, `delete_if`).
+    Equals(String),
+    /// No version is visible (`insert_if_absent`).
+    Absent,
+    /// The visible version was written by this txid (`update_if_version`).
+    Version(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConditionalStatus {
+    Applied,
+    /// The condition didn't hold for the visible version, nothing was written.
+    Mismatch,
+    /// Another transaction wrote the key concurrently, nothing was written.
+    Conflict,
+}
+
+impl ConditionalStatus {
+    /// The status line sent back to the client.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConditionalStatus::Applied => "OK",
+            ConditionalStatus::Mismatch => "FAILED: condition not met",
+            ConditionalStatus::Conflict => "FAILED: key modified by another transaction",
+        }
+    }
+}
+
+/// Writes `value` to `key` (or deletes it if [`None`]) if `condition` holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionalWrite {
+    pub key: u32,
+    pub condition: Condition,
+    pub value: Option<String>,
+}
+
+/// The table a conditional write goes to, with the indexes to maintain, where its commits are announced, where an
+/// implicit transaction's txid is reserved and the row locks its writes take.
+pub struct Target<'a> {
+    pub table: &'a str,
+    pub node: Arc<RwLock<Node>>,
+    pub indexes: &'a [Arc<Index>],
+    pub changes: &'a ChangeLog,
+    pub watches: &'a Watches,
+    pub txids: &'a TxidReservation,
+    pub locks: &'a LockManager,
+}
+
+/// Applies `write` only if its condition holds for the version visible to `addr`'s transaction, checking and writing
+/// as one atomic step.
+///
+/// # Working:
+/// - The transaction table stays write-locked from the conflict checks until the write is done. Every writer in `cli`
+///   mutates the tree while holding it, so no other write can slip in between the check and the write.
+/// - If `addr` has an active transaction the write joins it, like `update` or `delete` would.
+/// - Otherwise it runs in an implicit transaction that's committed right away on success and aborted on failure, so
+///   clients get optimistic concurrency without a `begin`/`commit` round trip. The WAL gets the same `begin`, command,
+///   `commit` lines an explicit transaction would have written, so recovery replays it unchanged. They're flushed
+///   before the transaction is marked committed, and only then is the commit published to the target's change log and
+///   watchers like any other. If the WAL can't be written the write is rolled back instead.
+/// - Only applied writes are logged, a failed condition has no effect to replay.
+pub fn conditional_write(target: Target, write: ConditionalWrite, addr: SocketAddr, txd_count: Arc<RwLock<u64>>, transaction: Arc<RwLock<Transaction>>, file: Arc<RwLock<File>>, args: Vec<&str>) -> io::Result<ConditionalStatus> {
+    let mut txd_count_write = txd_count.write().unwrap();
+    let mut tx = transaction.write().unwrap();
+
+    let explicit = tx.ip_txd.get(&addr).copied()
+        .filter(|txd| tx.items.get(txd).is_some_and(|item| item.status == TransactionStatus::Active));
+    let txd = match explicit {
+        Some(txd) => txd,
+        None => {
+            let txd = target.txids.allocate(&mut txd_count_write, &file)?;
+            start_transaction(&mut tx, txd, addr);
+            txd
+        }
+    };
+    drop(txd_count_write);
+
+    let key = write.key;
+    let status = apply_if(&target, write, txd, &mut tx);
+
+    // Logged before the implicit transaction commits, like an explicit `commit` is, so nothing is published or
+    // notified that recovery wouldn't replay.
+    let logged = match status {
+        ConditionalStatus::Applied => log_write(&file, explicit.is_none(), addr, args),
+        _ => Ok(()),
+    };
+
+    let mut published = Ok(());
+    if explicit.is_none() {
+        let item = tx.items.get_mut(&txd).unwrap();
+        let commit = status == ConditionalStatus::Applied && logged.is_ok();
+        item.status = if commit { TransactionStatus::Committed } else { TransactionStatus::Aborted };
+        if commit {
+            let modified_keys = item.modified_keys.clone();
+            let table = |name: &str| (name == target.table).then(|| Arc::clone(&target.node));
+            let committed = capture_changes(table, txd, &modified_keys);
+            commit_abort_handler(Arc::clone(&target.node), key, txd, true);
+            published = target.changes.publish(txd, &committed);
+            target.watches.notify(txd, &committed);
+        } else if status == ConditionalStatus::Applied {
+            // The write never made it to the WAL, so it's rolled back rather than committed.
+            commit_abort_handler(Arc::clone(&target.node), key, txd, false);
+        }
+        target.locks.release_all(txd);
+    }
+
+    logged?;
+    published?;
+    Ok(status)
+}
+
+/// Writes `args` to the WAL, wrapped in `begin`/`commit` lines when it runs in an implicit transaction.
+fn log_write(file: &Arc<RwLock<File>>, implicit: bool, addr: SocketAddr, args: Vec<&str>) -> io::Result<()> {
+    let addr = addr.to_string();
+    if implicit {
+        flush_to_wal(Arc::clone(file), vec!["begin", &addr])?;
+    }
+    flush_to_wal(Arc::clone(file), args)?;
+    if implicit {
+        flush_to_wal(Arc::clone(file), vec!["commit", &addr])?;
+    }
+    Ok(())
+}
+
+/// Checks and writes for [`conditional_write`]. A write that doesn't go through keeps no row lock it took and claims
+/// nothing, like a refused `insert`.
+fn apply_if(target: &Target, write: ConditionalWrite, txd: u64, tx: &mut Transaction) -> ConditionalStatus {
+    let ConditionalWrite { key, condition, value } = write;
+    let held = target.locks.held(txd);
+    if key_claimed(tx, target.table, key, txd) || has_serialization_conflict(&target.node, key, txd, tx) || target.locks.try_acquire_all(txd, target.table, &[key]).is_err() {
+        return ConditionalStatus::Conflict;
+    }
+
+    let visible = with_versions(&target.node, key, |versions| {
+        versions.iter().rev().find(|version| version_visible(version, txd, tx)).map(|version| (version.value.clone(), version.xmin))
+    }).flatten();
+
+    let holds = match (&condition, &visible) {
+        (Condition::Absent, None) => true,
+        (Condition::Equals(expected), Some((value, _))) => expected == value,
+        (Condition::Version(xmin), Some((_, visible_xmin))) => xmin == visible_xmin,
+        _ => false,
+    };
+    if !holds {
+        target.locks.release_from(txd, held);
+        return ConditionalStatus::Mismatch;
+    }
+
+    let writer = write_txd(tx, txd);
+    let written = match &value {
+        Some(value) => Node::find_and_update_key_version(Arc::clone(&target.node), key, Some(value.clone()), writer, false).is_some()
+            || Node::insert(Arc::clone(&target.node), key, value.clone(), writer).is_ok(),
+        None => Node::find_and_update_key_version(Arc::clone(&target.node), key, None, writer, true).is_some(),
+    };
+    // Refused by the tree, an open version of the key belongs to another transaction.
+    if !written {
+        target.locks.release_from(txd, held);
+        return ConditionalStatus::Conflict;
+    }
+    if let Some(value) = &value {
+        for index in target.indexes.iter() {
+            index.add(key, value);
+        }
+    }
+
+    if let Some(item) = tx.items.get_mut(&txd) {
+        item.modified_keys.push((target.table.to_string(), key));
+    }
+    ConditionalStatus::Applied
+}
@@ -0,0 +1,275 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use crate::transactions::timeout::active_txd;
+use crate::transactions::transactions::Transaction;
+
+/// Used until `--lock-timeout` sets another one.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+impl LockMode {
+    pub fn parse(mode: &str) -> Option<LockMode> {
+        match mode.to_lowercase().as_str() {
+            "shared" => Some(LockMode::Shared),
+            "exclusive" => Some(LockMode::Exclusive),
+            _ => None,
+        }
+    }
+
+    fn compatible(self, other: LockMode) -> bool {
+        self == LockMode::Shared && other == LockMode::Shared
+    }
+}
+
+/// How a [`LockManager::acquire`] ended.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockOutcome {
+    Granted,
+    /// Other transactions still held the key once the lock timeout ran out.
+    TimedOut,
+    /// Waiting would have closed a cycle in the waits-for graph and this transaction was picked to break it. The
+    /// caller aborts it.
+    Deadlock,
+}
+
+impl LockOutcome {
+    /// The status line sent back to the client, for transaction `txd`.
+    pub fn message(&self, txd: u64) -> String {
+        match self {
+            LockOutcome::Granted => String::from("OK"),
+            LockOutcome::TimedOut => String::from("FAILED: lock wait timed out"),
+            LockOutcome::Deadlock => format!("FAILED: deadlock detected, transaction {} aborted", txd),
+        }
+    }
+}
+
+/// Row locks taken by `lock` and `select_for_update`, and implicitly by every write, held until the transaction
+/// commits or aborts.
+///
+/// # Working:
+/// - A key can be locked shared by any number of transactions, or exclusively by one. Locks are taken for a
+///   transaction's own txid, never a savepoint's, and a transaction asking again for a lock it holds gets the
+///   stronger of both modes.
+/// - `lock`, `select_for_update` and single-key writes wait in a FIFO queue per key, see [`lock_for_write`], until the
+///   key is free for them or their timeout runs out. Transactions upgrading a lock they already hold don't queue
+///   behind others.
+/// - Batched and conditional writes check and write under the `Transaction` table, so they can't wait and only try
+///   their locks, see [`LockManager::try_acquire_all`]. A key held by someone else, or with waiters queued ahead, is a
+///   write conflict like a key another transaction already wrote, so they never overtake a waiter.
+/// - Before waiting, the waits-for graph (waiters to the holders and earlier waiters they're incompatible with) is
+///   searched for a cycle through the new waiter. The youngest transaction on the cycle, the one with the highest
+///   txid, is the victim: it stops waiting with [`LockOutcome::Deadlock`] and its connection aborts it, releasing its
+///   locks to the others.
+/// - The lock table is locked last, after the `Transaction` table, and never held while waiting.
+#[derive(Debug, Default)]
+pub struct LockManager {
+    table: Mutex<LockTable>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct LockTable {
+    rows: HashMap<(String, u32), RowLock>,
+    /// The rows each transaction holds, in the order it locked them.
+    held: HashMap<u64, Vec<(String, u32)>>,
+    /// Waiters picked to break a deadlock, not told yet.
+    victims: HashSet<u64>,
+}
+
+#[derive(Debug, Default)]
+struct RowLock {
+    holders: HashMap<u64, LockMode>,
+    queue: VecDeque<(u64, LockMode)>,
+}
+
+impl RowLock {
+    /// Whether `txd` may lock the row in `mode` now. Waiters ahead of it in `queue` count unless it already holds the
+    /// row.
+    fn grantable(&self, txd: u64, mode: LockMode) -> bool {
+        self.blockers(txd, mode).next().is_none()
+    }
+
+    /// The transactions `txd` would wait for to lock the row in `mode`.
+    fn blockers(&self, txd: u64, mode: LockMode) -> impl Iterator<Item = u64> + '_ {
+        let holding = self.holders.contains_key(&txd);
+        let holders = self.holders.iter()
+            .filter(move |(holder, held)| **holder != txd && !mode.compatible(**held))
+            .map(|(holder, _)| *holder);
+        let ahead = self.queue.iter()
+            .take_while(move |(waiter, _)| *waiter != txd)
+            .filter(move |(waiter, queued)| !holding && *waiter != txd && !mode.compatible(*queued))
+            .map(|(waiter, _)| *waiter);
+        holders.chain(ahead)
+    }
+}
+
+impl LockTable {
+    fn grant(&mut self, txd: u64, row: (String, u32), mode: LockMode) {
+        let lock = self.rows.entry(row.clone()).or_default();
+        match lock.holders.get(&txd) {
+            Some(LockMode::Exclusive) => {}
+            Some(LockMode::Shared) => {
+                lock.holders.insert(txd, mode);
+            }
+            None => {
+                lock.holders.insert(txd, mode);
+                self.held.entry(txd).or_default().push(row);
+            }
+        }
+    }
+
+    fn dequeue(&mut self, txd: u64, row: &(String, u32)) {
+        if let Some(lock) = self.rows.get_mut(row) {
+            lock.queue.retain(|(waiter, _)| *waiter != txd);
+            if lock.holders.is_empty() && lock.queue.is_empty() {
+                self.rows.remove(row);
+            }
+        }
+    }
+
+    /// The transactions on a cycle of the waits-for graph through `txd`, if there's one.
+    fn cycle(&self, txd: u64) -> Option<Vec<u64>> {
+        let mut waits_for: HashMap<u64, Vec<u64>> = HashMap::new();
+        for lock in self.rows.values() {
+            for (waiter, mode) in lock.queue.iter() {
+                waits_for.entry(*waiter).or_default().extend(lock.blockers(*waiter, *mode));
+            }
+        }
+
+        let mut path = vec![txd];
+        let mut visited = HashSet::from([txd]);
+        let mut next: Vec<std::slice::Iter<u64>> = vec![waits_for.get(&txd).map_or([].iter(), |edges| edges.iter())];
+        while let Some(edges) = next.last_mut() {
+            match edges.next() {
+                Some(&blocker) if blocker == txd => return Some(path),
+                Some(&blocker) if visited.insert(blocker) => {
+                    path.push(blocker);
+                    next.push(waits_for.get(&blocker).map_or([].iter(), |edges| edges.iter()));
+                }
+                Some(_) => {}
+                None => {
+                    next.pop();
+                    path.pop();
+                }
+            }
+        }
+        None
+    }
+}
+
+impl LockManager {
+    /// Locks `key` of `table` in `mode` for `txd`, waiting up to `timeout` for the transactions holding it.
+    pub fn acquire(&self, txd: u64, table: &str, key: u32, mode: LockMode, timeout: Duration) -> LockOutcome {
+        let row = (table.to_string(), key);
+        let deadline = Instant::now() + timeout;
+        let mut locks = self.table.lock().unwrap();
+
+        let lock = locks.rows.entry(row.clone()).or_default();
+        if lock.grantable(txd, mode) {
+            locks.grant(txd, row, mode);
+            return LockOutcome::Granted;
+        }
+        lock.queue.push_back((txd, mode));
+
+        if let Some(cycle) = locks.cycle(txd) {
+            let victim = *cycle.iter().max().unwrap();
+            if victim == txd {
+                locks.dequeue(txd, &row);
+                self.released.notify_all();
+                return LockOutcome::Deadlock;
+            }
+            locks.victims.insert(victim);
+            self.released.notify_all();
+        }
+
+        loop {
+            if locks.victims.remove(&txd) {
+                locks.dequeue(txd, &row);
+                self.released.notify_all();
+                return LockOutcome::Deadlock;
+            }
+            if locks.rows.get(&row).is_some_and(|lock| lock.grantable(txd, mode)) {
+                locks.dequeue(txd, &row);
+                locks.grant(txd, row, mode);
+                return LockOutcome::Granted;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                locks.dequeue(txd, &row);
+                self.released.notify_all();
+                return LockOutcome::TimedOut;
+            }
+            locks = self.released.wait_timeout(locks, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Locks every key of `table` in `keys` exclusively for `txd` if [`LockManager::acquire`] would grant each right
+    /// away, or none of them. Never waits. Returns the first key locked by someone else or waited for.
+    pub fn try_acquire_all(&self, txd: u64, table: &str, keys: &[u32]) -> Result<(), u32> {
+        let mut locks = self.table.lock().unwrap();
+        let taken = keys.iter().find(|key| {
+            locks.rows.get(&(table.to_string(), **key)).is_some_and(|lock| !lock.grantable(txd, LockMode::Exclusive))
+        });
+        if let Some(key) = taken {
+            return Err(*key);
+        }
+        for key in keys {
+            locks.grant(txd, (table.to_string(), *key), LockMode::Exclusive);
+        }
+        Ok(())
+    }
+
+    /// How many rows `txd` holds, which [`LockManager::release_from`] goes back to.
+    pub fn held(&self, txd: u64) -> usize {
+        self.table.lock().unwrap().held.get(&txd).map_or(0, Vec::len)
+    }
+
+    /// Releases the rows `txd` locked after it held `count` of them, for `rollback to`.
+    pub fn release_from(&self, txd: u64, count: usize) {
+        let mut locks = self.table.lock().unwrap();
+        let Some(held) = locks.held.get_mut(&txd) else { return };
+        let released: Vec<(String, u32)> = held.drain(count.min(held.len())..).collect();
+        if held.is_empty() {
+            locks.held.remove(&txd);
+        }
+        for row in released {
+            if let Some(lock) = locks.rows.get_mut(&row) {
+                lock.holders.remove(&txd);
+                if lock.holders.is_empty() && lock.queue.is_empty() {
+                    locks.rows.remove(&row);
+                }
+            }
+        }
+        self.released.notify_all();
+    }
+
+    /// Releases every row `txd` holds once it committed or aborted.
+    pub fn release_all(&self, txd: u64) {
+        self.release_from(txd, 0);
+    }
+
+    /// The mode `txd` holds `key` of `table` in, if it does.
+    pub fn mode(&self, txd: u64, table: &str, key: u32) -> Option<LockMode> {
+        self.table.lock().unwrap().rows.get(&(table.to_string(), key)).and_then(|lock| lock.holders.get(&txd).copied())
+    }
+}
+
+/// Takes the row lock a write of `key` in `table` needs for `addr`'s transaction, waiting up to `timeout` like
+/// [`LockManager::acquire`] does. A client writing outside an active transaction takes no lock, it would never be
+/// released.
+///
+/// The `Transaction` table is only read to find the transaction, never held while waiting, so the holders can end.
+pub fn lock_for_write(locks: &LockManager, transaction: &RwLock<Transaction>, addr: &SocketAddr, table: &str, key: u32, timeout: Duration) -> LockOutcome {
+    let txd = active_txd(&transaction.read().unwrap(), addr);
+    match txd {
+        Some(txd) => locks.acquire(txd, table, key, LockMode::Exclusive, timeout),
+        None => LockOutcome::Granted,
+    }
+}
@@ -1,27 +1,85 @@
+use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
-use crate::transactions::transactions::{Transaction, TransactionStatus};
+use crate::transactions::timeout::Lifetime;
+use crate::transactions::transactions::{Transaction, TransactionItems, TransactionStatus};
 
-pub fn get_all_active_transaction(current_transactions: Arc<RwLock<Transaction>>, all_addr: Arc<RwLock<Vec<SocketAddr>>>) -> Vec<u32> {
+pub fn get_all_active_transaction(current_transactions: Arc<RwLock<Transaction>>, all_addr: Arc<RwLock<Vec<SocketAddr>>>) -> Vec<u64> {
     let mut all_txd = Vec::new();
 
     {
         let tx = current_transactions.read().unwrap();
         let addr_read = all_addr.read().unwrap();
 
-        // takes all the active transaction
+        // takes all the active transaction, except read-only ones which never claim a key
         for i in addr_read.iter() {
             if let Some(x) =  tx.ip_txd.get(i) {
                 if let Some(t_items) = tx.items.get(x) {
-                    if t_items.status == TransactionStatus::Active {
+                    if t_items.status == TransactionStatus::Active && !t_items.read_only {
                         all_txd.push(*x);
                     }
                 }
             }
         }
+
+        // Prepared transactions have no client any more and still hold the keys they wrote.
+        all_txd.extend(tx.items.iter()
+            .filter(|(_, item)| item.prepared.is_some() && item.status == TransactionStatus::Active)
+            .map(|(txd, _)| *txd));
     }
 
     all_txd.sort();
     
     all_txd
 }
+
+/// Transactions that are active right now, taken when a new transaction begins to fix what its snapshot excludes.
+pub fn get_active_txd_snapshot(transaction: &Transaction) -> Vec<u64> {
+    let mut active: Vec<u64> = transaction.items.iter()
+        .filter(|(_, item)| item.status == TransactionStatus::Active)
+        .map(|(txd, _)| *txd)
+        .collect();
+    active.sort();
+    active
+}
+
+/// Registers `txd` as the new active transaction of `addr`, replacing its previous finished one.
+/// Returns false, registering nothing, if `addr` still has an active transaction.
+pub fn start_transaction(transaction: &mut Transaction, txd: u64, addr: SocketAddr) -> bool {
+    let snapshot = get_active_txd_snapshot(transaction);
+    let last_txd = match transaction.ip_txd.get(&addr) {
+        Some(&x) => {
+            if transaction.items.get(&x).is_some_and(|item| item.status == TransactionStatus::Active) {
+                return false;
+            }
+            transaction.items.remove(&x);
+            x
+        }
+        None => 0,
+    };
+
+    transaction.ip_txd.insert(addr, txd);
+    transaction.items.insert(txd, TransactionItems { status: TransactionStatus::Active, socket_addr: addr, last_txd, modified_keys: Vec::new(), snapshot, lifetime: Lifetime::new(), savepoints: Vec::new(), parent: None, prepared: None, read_only: false });
+    true
+}
+
+/// A detached copy of everything [`version_visible`](crate::MVCC::visibility::version_visible) reads from the
+/// transaction table, for long reads that walk the tree without holding the table.
+///
+/// Returns the txid to read as together with the copy: `txd` itself if given, otherwise `next_txd` registered with a
+/// snapshot of the transactions active right now, so it sees exactly what's committed at this point.
+pub fn read_view(transaction: &Transaction, txd: Option<u64>, next_txd: u64) -> (u64, Transaction) {
+    let mut items: HashMap<u64, TransactionItems> = transaction.items.iter()
+        .map(|(txd, item)| (*txd, TransactionItems { status: item.status.clone(), socket_addr: item.socket_addr, last_txd: item.last_txd, modified_keys: Vec::new(), snapshot: item.snapshot.clone(), lifetime: item.lifetime.clone(), savepoints: Vec::new(), parent: item.parent, prepared: item.prepared.clone(), read_only: item.read_only }))
+        .collect();
+
+    let txd = match txd {
+        Some(txd) => txd,
+        None => {
+            let socket_addr = SocketAddr::from(([0, 0, 0, 0], 0));
+            items.insert(next_txd, TransactionItems { status: TransactionStatus::Active, socket_addr, last_txd: 0, modified_keys: Vec::new(), snapshot: get_active_txd_snapshot(transaction), lifetime: Lifetime::new(), savepoints: Vec::new(), parent: None, prepared: None, read_only: false });
+            next_txd
+        }
+    };
+    (txd, Transaction { items, ip_txd: HashMap::new() })
+}
@@ -1,2 +1,10 @@
 pub mod transactions;
-pub mod manager;
\ No newline at end of file
+pub mod manager;
+pub mod conditional;
+pub mod batch;
+pub mod txid;
+pub mod timeout;
+pub mod savepoint;
+pub mod locks;
+pub mod prepared;
+pub mod autocommit;
@@ -0,0 +1,110 @@
+use std::net::SocketAddr;
+use crate::transactions::timeout::active_txd;
+use crate::transactions::transactions::{Transaction, TransactionStatus};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreparedStatus {
+    Done,
+    NoTransaction,
+    /// `commit prepared` and `rollback prepared` were sent by a client with a transaction of its own running.
+    InTransaction,
+    AlreadyPrepared(String),
+    NotFound(String),
+}
+
+impl PreparedStatus {
+    /// The status line sent back to the client.
+    pub fn message(&self) -> String {
+        match self {
+            PreparedStatus::Done => String::from("OK"),
+            PreparedStatus::NoTransaction => String::from("FAILED: active transaction not found"),
+            PreparedStatus::InTransaction => String::from("FAILED: can't resolve a prepared transaction inside a transaction"),
+            PreparedStatus::AlreadyPrepared(gid) => format!("FAILED: a transaction is already prepared as {}", gid),
+            PreparedStatus::NotFound(gid) => format!("FAILED: prepared transaction {} not found", gid),
+        }
+    }
+}
+
+/// What a WAL record does to prepared transactions.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreparedRecord {
+    Prepare(String),
+    Commit(String),
+    Rollback(String),
+}
+
+/// Reads a `prepare <gid>`, `commit prepared <gid>` or `rollback prepared <gid>` record, client address last.
+/// `commit` and `rollback` records of a client's own transaction aren't one.
+pub fn prepared_record(record: &str) -> Option<PreparedRecord> {
+    let words: Vec<&str> = record.split_whitespace().collect();
+    match words[..] {
+        [command, gid, _] if command.eq_ignore_ascii_case("prepare") => Some(PreparedRecord::Prepare(gid.to_string())),
+        [command, prepared, gid, _] if prepared.eq_ignore_ascii_case("prepared") => match command.to_lowercase().as_str() {
+            "commit" => Some(PreparedRecord::Commit(gid.to_string())),
+            "rollback" => Some(PreparedRecord::Rollback(gid.to_string())),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Reads a `prepared_txid <gid> <txid>` record, written by `prepare` right before its `prepare` record. It's only for
+/// this server's recovery and isn't shipped, like a txid reservation.
+pub fn prepared_txid_record(record: &str) -> Option<(String, u64)> {
+    match record.replace("\"", "").split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["prepared_txid", gid, txid] => txid.parse::<u64>().ok().map(|txid| (gid.to_string(), txid)),
+        _ => None,
+    }
+}
+
+/// The txid of the transaction prepared as `gid` and not resolved yet.
+pub fn prepared_txd(transaction: &Transaction, gid: &str) -> Option<u64> {
+    transaction.items.iter()
+        .find(|(_, item)| item.status == TransactionStatus::Active && item.prepared.as_deref() == Some(gid))
+        .map(|(txd, _)| *txd)
+}
+
+/// Whether `addr`'s transaction may be prepared as `gid`, checked before the `prepare` record is written.
+pub fn can_prepare(transaction: &Transaction, addr: &SocketAddr, gid: &str) -> PreparedStatus {
+    match active_txd(transaction, addr) {
+        None => PreparedStatus::NoTransaction,
+        Some(_) if prepared_txd(transaction, gid).is_some() => PreparedStatus::AlreadyPrepared(gid.to_string()),
+        Some(_) => PreparedStatus::Done,
+    }
+}
+
+/// Prepares `addr`'s active transaction as `gid`, for an external coordinator to commit or roll it back later with
+/// `commit prepared` or `rollback prepared`, from any connection.
+///
+/// # Working:
+/// - The `prepare` record is fsynced to the WAL before this runs, after a `prepared_txid` record with the
+///   transaction's txid. The transaction's records stay in the WAL, carried over by checkpoints, until the one
+///   resolving it. A restarted server replays them to prepare it again under the same txid, see
+///   [`crate::storage::wal::recovery::recover_from_wal`].
+/// - The transaction stays active: its versions stay invisible to others, snapshots taken meanwhile list it, vacuum
+///   keeps what it may still need and a checkpoint records it as in progress. It keeps its row locks and the keys it
+///   wrote, so other writers are refused them until it's resolved.
+/// - It's detached from its client, which may begin another transaction. Closing the connection or timing out no longer
+///   aborts it.
+/// - Savepoints are merged into the transaction first, the caller does that with
+///   [`end_savepoints`](crate::transactions::savepoint::end_savepoints).
+pub fn prepare(transaction: &mut Transaction, addr: &SocketAddr, gid: &str) -> PreparedStatus {
+    let status = can_prepare(transaction, addr, gid);
+    if status != PreparedStatus::Done {
+        return status;
+    }
+    let Some(txd) = transaction.ip_txd.remove(addr) else { return PreparedStatus::NoTransaction };
+    if let Some(item) = transaction.items.get_mut(&txd) {
+        item.prepared = Some(gid.to_string());
+    }
+    PreparedStatus::Done
+}
+
+/// The transaction `addr` may resolve as `gid`. Clients in a transaction can't, so the resolving record never ends
+/// up among the records of their own.
+pub fn resolvable(transaction: &Transaction, addr: &SocketAddr, gid: &str) -> Result<u64, PreparedStatus> {
+    if active_txd(transaction, addr).is_some() {
+        return Err(PreparedStatus::InTransaction);
+    }
+    prepared_txd(transaction, gid).ok_or_else(|| PreparedStatus::NotFound(gid.to_string()))
+}
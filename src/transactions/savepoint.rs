@@ -0,0 +1,155 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use crate::btree::node::Node;
+use crate::MVCC::visibility::{merge_subtransactions, roll_back_subtransactions};
+use crate::transactions::locks::LockManager;
+use crate::transactions::timeout::{active_txd, Lifetime};
+use crate::transactions::transactions::{Transaction, TransactionItems, TransactionStatus};
+
+/// A point inside a transaction that `rollback to` returns to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Savepoint {
+    pub name: String,
+    /// The sub-transaction writing since the savepoint was set, see [`write_txd`].
+    pub subtxd: u64,
+    /// How many of the transaction's `modified_keys` were written before it.
+    pub modified_keys: usize,
+    /// How many row locks the transaction held before it.
+    pub locks: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SavepointStatus {
+    Done,
+    NoTransaction,
+    NotFound(String),
+}
+
+impl SavepointStatus {
+    /// The status line sent back to the client.
+    pub fn message(&self) -> String {
+        match self {
+            SavepointStatus::Done => String::from("OK"),
+            SavepointStatus::NoTransaction => String::from("FAILED: active transaction not found"),
+            SavepointStatus::NotFound(name) => format!("FAILED: savepoint {} not found", name),
+        }
+    }
+}
+
+/// The txid `txd` writes its versions as: the sub-transaction of its innermost savepoint, or `txd` itself.
+pub fn write_txd(transaction: &Transaction, txd: u64) -> u64 {
+    transaction.items.get(&txd)
+        .and_then(|item| item.savepoints.last())
+        .map_or(txd, |savepoint| savepoint.subtxd)
+}
+
+/// Sets savepoint `name` in `addr`'s active transaction, writing from now on as the sub-transaction `subtxd`.
+///
+/// # Working:
+/// - Every savepoint gets a sub-transaction with a txid of its own, registered in the transaction table as active
+///   with its transaction as `parent`. The transaction's writes are versioned with the innermost one's txid, see
+///   [`write_txd`], and still recorded in the transaction's own `modified_keys`.
+/// - To the transaction itself the sub-transactions' versions are its own. To everyone else they're a transaction
+///   that is still running, which snapshots taken meanwhile list like any other.
+/// - [`rollback_to`] rolls back the writes of the savepoint's sub-transaction and of every later one, [`release`]
+///   and the end of the transaction merge them into the enclosing level, see [`end_savepoints`].
+/// - A name may be reused, the latest savepoint with it is the one found.
+pub fn savepoint(transaction: &mut Transaction, addr: &SocketAddr, name: &str, subtxd: u64, locks: &LockManager) -> SavepointStatus {
+    let Some(txd) = active_txd(transaction, addr) else { return SavepointStatus::NoTransaction };
+    let item = &transaction.items[&txd];
+    let sub = TransactionItems {
+        status: TransactionStatus::Active,
+        socket_addr: *addr,
+        last_txd: 0,
+        modified_keys: Vec::new(),
+        snapshot: item.snapshot.clone(),
+        lifetime: Lifetime::new(),
+        savepoints: Vec::new(),
+        parent: Some(txd),
+        prepared: None,
+        read_only: false,
+    };
+    let modified_keys = item.modified_keys.len();
+    transaction.items.insert(subtxd, sub);
+    if let Some(item) = transaction.items.get_mut(&txd) {
+        item.savepoints.push(Savepoint { name: name.to_string(), subtxd, modified_keys, locks: locks.held(txd) });
+    }
+    SavepointStatus::Done
+}
+
+/// Whether `addr`'s active transaction has a savepoint `name`, checked before a sub-transaction's txid is allocated.
+pub fn has_savepoint(transaction: &Transaction, addr: &SocketAddr, name: &str) -> SavepointStatus {
+    match active_txd(transaction, addr) {
+        None => SavepointStatus::NoTransaction,
+        Some(txd) if transaction.items[&txd].savepoints.iter().any(|savepoint| savepoint.name == name) => SavepointStatus::Done,
+        Some(_) => SavepointStatus::NotFound(name.to_string()),
+    }
+}
+
+/// Undoes everything `addr`'s transaction wrote since savepoint `name`, which stays set with the fresh sub-transaction
+/// `subtxd`. Savepoints set after it are gone.
+///
+/// # Working:
+/// - The versions of the savepoint's sub-transaction and of every later one are rolled back, see
+///   [`roll_back_subtransactions`]. They're dropped from the transaction table, their rolled back versions stay
+///   invisible to everyone without them.
+/// - Keys written since the savepoint are dropped from `modified_keys` and the row locks taken since are released, other
+///   transactions may write them again.
+pub fn rollback_to(transaction: &mut Transaction, addr: &SocketAddr, name: &str, subtxd: u64, table: impl Fn(&str) -> Option<Arc<RwLock<Node>>>, locks: &LockManager) -> SavepointStatus {
+    let Some(txd) = active_txd(transaction, addr) else { return SavepointStatus::NoTransaction };
+    let item = transaction.items.get_mut(&txd).unwrap();
+    let Some(position) = item.savepoints.iter().rposition(|savepoint| savepoint.name == name) else {
+        return SavepointStatus::NotFound(name.to_string());
+    };
+
+    let undone: Vec<Savepoint> = item.savepoints.drain(position..).collect();
+    let subtxds: Vec<u64> = undone.iter().map(|savepoint| savepoint.subtxd).collect();
+    let modified_keys = undone[0].modified_keys;
+    for (name, key) in item.modified_keys.iter().skip(modified_keys) {
+        if let Some(node) = table(name) {
+            roll_back_subtransactions(&node, *key, &subtxds);
+        }
+    }
+    item.modified_keys.truncate(modified_keys);
+    locks.release_from(txd, undone[0].locks);
+    for subtxd in subtxds.iter() {
+        transaction.items.remove(subtxd);
+    }
+
+    savepoint(transaction, addr, name, subtxd, locks)
+}
+
+/// Releases savepoint `name` of `addr`'s transaction and every later one, keeping what was written since. The writes
+/// then belong to the enclosing savepoint, or to the transaction itself, see [`merge_subtransactions`].
+pub fn release(transaction: &mut Transaction, addr: &SocketAddr, name: &str, table: impl Fn(&str) -> Option<Arc<RwLock<Node>>>) -> SavepointStatus {
+    let Some(txd) = active_txd(transaction, addr) else { return SavepointStatus::NoTransaction };
+    let Some(position) = transaction.items[&txd].savepoints.iter().rposition(|savepoint| savepoint.name == name) else {
+        return SavepointStatus::NotFound(name.to_string());
+    };
+    merge_from(transaction, txd, position, table);
+    SavepointStatus::Done
+}
+
+/// Merges every savepoint of `txd` into the transaction itself before it commits or aborts, so its versions all carry
+/// `txd` again and are resolved like those of a transaction without savepoints.
+pub fn end_savepoints(transaction: &mut Transaction, txd: u64, table: impl Fn(&str) -> Option<Arc<RwLock<Node>>>) {
+    if transaction.items.get(&txd).is_some_and(|item| !item.savepoints.is_empty()) {
+        merge_from(transaction, txd, 0, table);
+    }
+}
+
+/// Merges the savepoints of `txd` from `position` on into the one before it, or into `txd`.
+fn merge_from(transaction: &mut Transaction, txd: u64, position: usize, table: impl Fn(&str) -> Option<Arc<RwLock<Node>>>) {
+    let item = transaction.items.get_mut(&txd).unwrap();
+    let merged: Vec<Savepoint> = item.savepoints.drain(position..).collect();
+    let into = item.savepoints.last().map_or(txd, |savepoint| savepoint.subtxd);
+    let subtxds: Vec<u64> = merged.iter().map(|savepoint| savepoint.subtxd).collect();
+    for (name, key) in item.modified_keys.iter().skip(merged[0].modified_keys) {
+        if let Some(node) = table(name) {
+            merge_subtransactions(&node, *key, &subtxds, into);
+        }
+    }
+    for subtxd in subtxds.iter() {
+        transaction.items.remove(subtxd);
+    }
+}
@@ -0,0 +1,108 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use crate::transactions::locks::DEFAULT_LOCK_TIMEOUT;
+use crate::transactions::transactions::{Transaction, TransactionItems, TransactionStatus};
+
+/// Used until `--idle-timeout` sets another one.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// Used until `--max-transaction-age` sets another one.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(3600);
+/// How often a connection waiting for its client checks whether its transaction timed out.
+pub const TIMEOUT_POLL: Duration = Duration::from_millis(250);
+
+/// The limits a server puts on every transaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransactionTimeouts {
+    /// How long a transaction may go without a command from its client, unless it sets its own with `timeout idle`.
+    pub idle: Duration,
+    /// How long any transaction may run. `timeout total` can only shorten it.
+    pub max_age: Duration,
+    /// How long `lock`, `select_for_update` and single-key writes wait for a row lock.
+    pub lock_wait: Duration,
+}
+
+impl Default for TransactionTimeouts {
+    fn default() -> TransactionTimeouts {
+        TransactionTimeouts { idle: DEFAULT_IDLE_TIMEOUT, max_age: DEFAULT_MAX_AGE, lock_wait: DEFAULT_LOCK_TIMEOUT }
+    }
+}
+
+/// When a transaction began and was last used, with the timeouts its client set for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lifetime {
+    pub began: Instant,
+    pub last_active: Instant,
+    pub idle: Option<Duration>,
+    pub total: Option<Duration>,
+}
+
+impl Lifetime {
+    pub fn new() -> Lifetime {
+        let now = Instant::now();
+        Lifetime { began: now, last_active: now, idle: None, total: None }
+    }
+
+    /// The idle timeout that applies under the server's `timeouts`.
+    pub fn idle_timeout(&self, timeouts: TransactionTimeouts) -> Duration {
+        self.idle.unwrap_or(timeouts.idle)
+    }
+
+    /// The total timeout that applies, never more than the server's maximum age.
+    pub fn total_timeout(&self, timeouts: TransactionTimeouts) -> Duration {
+        self.total.map_or(timeouts.max_age, |total| total.min(timeouts.max_age))
+    }
+}
+
+impl Default for Lifetime {
+    fn default() -> Lifetime {
+        Lifetime::new()
+    }
+}
+
+/// Why a transaction was aborted by the server.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expiry {
+    Idle(Duration),
+    Age(Duration),
+}
+
+impl Expiry {
+    /// The line sent to the client before its connection is closed.
+    pub fn message(&self, txd: u64) -> String {
+        match self {
+            Expiry::Idle(timeout) => format!("FAILED: transaction {} aborted, idle for more than {}s", txd, timeout.as_secs_f64()),
+            Expiry::Age(timeout) => format!("FAILED: transaction {} aborted, running for more than {}s", txd, timeout.as_secs_f64()),
+        }
+    }
+}
+
+/// Whether `item` ran out of time as of `now`. Only active transactions do.
+pub fn expired(item: &TransactionItems, timeouts: TransactionTimeouts, now: Instant) -> Option<Expiry> {
+    if item.status != TransactionStatus::Active {
+        return None;
+    }
+    let total = item.lifetime.total_timeout(timeouts);
+    if now.duration_since(item.lifetime.began) >= total {
+        return Some(Expiry::Age(total));
+    }
+    let idle = item.lifetime.idle_timeout(timeouts);
+    if now.duration_since(item.lifetime.last_active) >= idle {
+        return Some(Expiry::Idle(idle));
+    }
+    None
+}
+
+/// The txid of `addr`'s transaction if it's still active.
+pub fn active_txd(transaction: &Transaction, addr: &SocketAddr) -> Option<u64> {
+    transaction.ip_txd.get(addr).copied()
+        .filter(|txd| transaction.items.get(txd).is_some_and(|item| item.status == TransactionStatus::Active))
+}
+
+/// Records that `addr` just used its transaction, if it has an active one.
+pub fn touch(transaction: &Arc<RwLock<Transaction>>, addr: &SocketAddr) {
+    let mut tx = transaction.write().unwrap();
+    if let Some(txd) = active_txd(&tx, addr) && let Some(item) = tx.items.get_mut(&txd) {
+        item.lifetime.last_active = Instant::now();
+    }
+}
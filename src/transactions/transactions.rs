@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use crate::transactions::savepoint::Savepoint;
+use crate::transactions::timeout::Lifetime;
 
 #[derive(Debug, Clone, PartialEq, Ord, PartialOrd, Eq, Hash)]
 pub enum TransactionStatus { Active, Committed, Aborted, }
@@ -7,11 +9,25 @@ pub enum TransactionStatus { Active, Committed, Aborted, }
 pub struct TransactionItems {
     pub status: TransactionStatus,
     pub socket_addr: SocketAddr,
-    pub last_txd: u32,
-    pub modified_keys: Vec<u32>,
+    pub last_txd: u64,
+    /// `(table, key)` pairs written by this transaction, resolved on commit or abort.
+    pub modified_keys: Vec<(String, u32)>,
+    /// Transactions still active when this one began. Their writes stay invisible to it even once they commit.
+    pub snapshot: Vec<u64>,
+    /// When it began and was last used, to time it out.
+    pub lifetime: Lifetime,
+    /// Savepoints set and not released yet, innermost last.
+    pub savepoints: Vec<Savepoint>,
+    /// For the sub-transaction of a savepoint, the transaction it belongs to.
+    pub parent: Option<u64>,
+    /// The gid it was prepared as, see [`prepare`](crate::transactions::prepared::prepare). It stays active until
+    /// `commit prepared` or `rollback prepared`.
+    pub prepared: Option<String>,
+    /// Began with `begin read only`: it can't write and its `begin`, `commit` and `abort` aren't logged.
+    pub read_only: bool,
 }
 #[derive(Debug)]
 pub struct Transaction {
-    pub items: HashMap<u32, TransactionItems>,
-    pub ip_txd: HashMap<SocketAddr, u32>,
+    pub items: HashMap<u64, TransactionItems>,
+    pub ip_txd: HashMap<SocketAddr, u64>,
 }
\ No newline at end of file
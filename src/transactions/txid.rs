@@ -0,0 +1,80 @@
+use std::fs::{self, File};
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::storage::wal::writer::log_to_wal;
+
+/// How many txids one `txid` WAL record reserves.
+pub const TXID_BLOCK: u64 = 1000;
+
+/// Keeps txids unique across restarts: a txid is reserved on disk before it's handed out, so a restarted server carries
+/// on after every txid it could have written anywhere (tables, change log, followers).
+///
+/// # Working:
+/// - Txids are 64 bits wide and only ever grow, so they don't wrap around: at a million transactions a second,
+///   `u64::MAX` is more than half a million years away. Running out is still an error rather than a silent wrap.
+/// - The reservation is the highest txid that may have been handed out. Going past it, [`TxidReservation::allocate`]
+///   first writes `txid <n>` to the WAL, reserving the next [`TXID_BLOCK`] txids with a single record.
+/// - A checkpoint stores the reservation in the catalog file and carries the last `txid` record over when it truncates
+///   the WAL. On startup [`TxidReservation::recover`] takes the highest of both.
+#[derive(Debug, Default)]
+pub struct TxidReservation {
+    reserved: AtomicU64,
+}
+
+impl TxidReservation {
+    pub fn reserved(&self) -> u64 {
+        self.reserved.load(Ordering::Acquire)
+    }
+
+    /// Raises the reservation to `txid`, never lowers it.
+    pub fn reserve(&self, txid: u64) {
+        self.reserved.fetch_max(txid, Ordering::AcqRel);
+    }
+
+    /// Hands out the txid after `*txd_count` and stores it there, reserving the next block in the WAL first if it's
+    /// past the reservation. Called with `txd_count` write-locked, which orders the reservations.
+    pub fn allocate(&self, txd_count: &mut u64, file: &Arc<RwLock<File>>) -> io::Result<u64> {
+        let txid = txd_count.checked_add(1).ok_or_else(|| io::Error::other("Transaction IDs exhausted"))?;
+        if txid > self.reserved() {
+            let reserved = txid.saturating_add(TXID_BLOCK - 1);
+            log_to_wal(Arc::clone(file), vec!["txid", reserved.to_string().as_str()])?;
+            self.reserve(reserved);
+        }
+        *txd_count = txid;
+        Ok(txid)
+    }
+
+    /// Hands out the txid after `*txd_count` like [`TxidReservation::allocate`], but never reserves it in the WAL. Only
+    /// for transactions that write nothing under their txid, like read-only ones, which a restart ends anyway: handing
+    /// it out again afterwards is harmless. The next reserved txid still comes after it.
+    pub fn allocate_unreserved(&self, txd_count: &mut u64) -> io::Result<u64> {
+        let txid = txd_count.checked_add(1).ok_or_else(|| io::Error::other("Transaction IDs exhausted"))?;
+        *txd_count = txid;
+        Ok(txid)
+    }
+
+    /// Raises the reservation to the highest `txid` record in the WAL at `wal_file_path` and returns it. A missing
+    /// WAL reserves nothing more.
+    pub fn recover(&self, wal_file_path: &str) -> io::Result<u64> {
+        let contents = match fs::read_to_string(wal_file_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        for line in contents.lines() {
+            if let Some(txid) = reservation(line) {
+                self.reserve(txid);
+            }
+        }
+        Ok(self.reserved())
+    }
+}
+
+/// The txid reserved by a `txid <n>` WAL line, as written by [`TxidReservation::allocate`].
+pub fn reservation(line: &str) -> Option<u64> {
+    match line.replace("\"", "").split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["txid", txid] => txid.parse::<u64>().ok(),
+        _ => None,
+    }
+}
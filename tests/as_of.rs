@@ -0,0 +1,91 @@
+mod common;
+
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use common::Server;
+use ASMT::catalog::tables::{Catalog, DEFAULT_TABLE};
+use ASMT::engine::services::Services;
+use ASMT::engine::vacuum::vacuum;
+use ASMT::MVCC::as_of::{scan_as_of, select_as_of, AsOfError};
+
+fn as_of(server: &Server, key: u32, txid: u64) -> Result<Option<String>, AsOfError> {
+    let horizon = server.catalog.read().unwrap().gc_horizon.load(Ordering::SeqCst);
+    select_as_of(server.table(DEFAULT_TABLE).unwrap(), key, txid, horizon, Arc::clone(&server.transaction))
+}
+
+/// txid 1 inserts keys 1..=3, txid 2 updates key 1, txid 3 deletes key 2.
+fn history(name: &str) -> Server {
+    let server = Server::new(name);
+    server.run(0, "begin");
+    for k in 1..=3 {
+        server.run(0, &format!("insert {} v{}", k, k));
+    }
+    server.run(0, "commit");
+    server.run(0, "begin");
+    server.run(0, "update 1 changed");
+    server.run(0, "commit");
+    server.run(0, "begin");
+    server.run(0, "delete 2");
+    server.run(0, "commit");
+    server
+}
+
+#[test]
+fn reads_see_the_state_as_of_each_committed_txid() {
+    let server = history("as-of-select");
+
+    assert_eq!(as_of(&server, 1, 1), Ok(Some(String::from("v1"))));
+    assert_eq!(as_of(&server, 1, 2), Ok(Some(String::from("changed"))));
+    assert_eq!(as_of(&server, 2, 2), Ok(Some(String::from("v2"))));
+    assert_eq!(as_of(&server, 2, 3), Ok(None));
+    assert_eq!(as_of(&server, 3, 0), Ok(None));
+
+    let scan = scan_as_of(server.table(DEFAULT_TABLE).unwrap(), 1..=2, 2, 0, Arc::clone(&server.transaction)).unwrap();
+    assert_eq!(scan, vec![(1, String::from("changed")), (2, String::from("v2"))]);
+    let scan = scan_as_of(server.table(DEFAULT_TABLE).unwrap(), 0..=u32::MAX, 3, 0, Arc::clone(&server.transaction)).unwrap();
+    assert_eq!(scan, vec![(1, String::from("changed")), (3, String::from("v3"))]);
+}
+
+#[test]
+fn uncommitted_txids_are_refused() {
+    let server = history("as-of-uncommitted");
+    server.run(1, "begin");
+    server.run(1, "update 3 pending");
+    assert_eq!(as_of(&server, 3, 4), Err(AsOfError::NotCommitted));
+    server.run(1, "abort");
+    assert_eq!(as_of(&server, 3, 4), Err(AsOfError::NotCommitted));
+
+    // Later uncommitted writes never show up at an earlier point.
+    server.run(1, "begin");
+    server.run(1, "update 3 pending");
+    assert_eq!(as_of(&server, 3, 3), Ok(Some(String::from("v3"))));
+}
+
+#[test]
+fn points_before_the_gc_horizon_are_refused_and_the_horizon_persists() {
+    let server = history("as-of-horizon");
+    server.run(1, "begin");
+    server.run(0, "retention transactions 1");
+    vacuum(Arc::clone(&server.catalog), Arc::clone(&server.services), Arc::clone(&server.txd_count), Arc::clone(&server.transaction)).unwrap();
+
+    // Vacuum reclaimed the versions txids 1 and 2 closed, txid 3 is the oldest point left intact.
+    assert_eq!(as_of(&server, 1, 2), Err(AsOfError::BeforeGcHorizon { horizon: 3 }));
+    assert_eq!(as_of(&server, 1, 3), Ok(Some(String::from("changed"))));
+    server.run(1, "commit");
+    assert_eq!(as_of(&server, 1, 4), Ok(Some(String::from("changed"))));
+
+    let image = server.dir.path("image");
+    server.catalog.read().unwrap().store(&image, &server.services).unwrap();
+    let restored = Catalog::load(&image, &Services::default()).unwrap();
+    assert_eq!(restored.gc_horizon.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn as_of_commands_reject_txids_not_allocated_yet() {
+    let server = history("as-of-cli");
+    assert_eq!(server.run(0, "select_as_of 1 2"), 0);
+    assert_eq!(server.run(0, "scan_as_of 2 0 10"), 0);
+    assert_eq!(server.run(0, "select_as_of 1 99"), 1);
+    assert_eq!(server.run(0, "scan_as_of x"), 1);
+}
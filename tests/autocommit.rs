@@ -0,0 +1,107 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::Server;
+use ASMT::catalog::tables::DEFAULT_TABLE;
+use ASMT::transactions::autocommit::read_only;
+use ASMT::transactions::manager::get_all_active_transaction;
+use ASMT::transactions::timeout::active_txd;
+
+fn in_transaction(server: &Server, client: usize) -> bool {
+    active_txd(&server.transaction.read().unwrap(), &Server::addr(client)).is_some()
+}
+
+#[test]
+fn single_writes_commit_on_their_own() {
+    let server = Server::new("autocommit-writes");
+    server.run(0, "insert 1 one");
+    server.run(0, "mset 2 two 3 three");
+    server.run(0, "update 1 uno");
+    server.run(0, "delete 3");
+    assert!(!in_transaction(&server, 0));
+
+    let addr = Server::addr(0);
+    let records: Vec<String> = server.wal().lines().filter(|line| !line.contains("txid")).take(3).map(String::from).collect();
+    assert_eq!(records, vec![format!("\"begin {}\"", addr), format!("\"insert 1 one {}\"", addr), format!("\"commit {}\"", addr)]);
+
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("uno")));
+    assert_eq!(server.select(1, DEFAULT_TABLE, 2), Some(String::from("two")));
+    assert_eq!(server.select(1, DEFAULT_TABLE, 3), None);
+}
+
+#[test]
+fn refused_single_writes_are_aborted() {
+    let server = Server::new("autocommit-refused");
+    server.services.timeouts.write().unwrap().lock_wait = Duration::from_millis(50);
+    server.run(0, "insert 1 one");
+    server.run(1, "begin");
+    server.run(1, "update 1 locked");
+
+    assert_eq!(server.reply(0, "update 1 refused"), "FAILED: lock wait timed out");
+    assert_eq!(server.wal().lines().last(), Some(format!("\"abort {}\"", Server::addr(0)).as_str()));
+    server.run(1, "abort");
+    assert_eq!(server.reply(2, "select 1"), "Value: \"one\"");
+}
+
+#[test]
+fn single_reads_see_the_latest_commit_without_logging() {
+    let server = Server::new("autocommit-reads");
+    server.run(0, "insert 1 one");
+    server.run(0, "insert 2 two");
+    let wal = server.wal();
+
+    assert_eq!(server.reply(1, "select 1"), "Value: \"one\"");
+    assert_eq!(server.reply(1, "select 9"), "Key not found");
+    assert_eq!(server.reply(1, "mget 2 9"), "Key: 2 Value: \"two\"\nKey: 9 not found");
+    assert!(!in_transaction(&server, 1));
+    assert_eq!(server.wal(), wal);
+
+    // A finished transaction of the client no longer decides what it reads.
+    server.run(1, "begin");
+    server.run(1, "commit");
+    server.run(0, "update 1 later");
+    assert_eq!(server.reply(1, "select 1"), "Value: \"later\"");
+}
+
+#[test]
+fn read_only_transactions_refuse_writes_and_are_not_logged() {
+    let server = Server::new("autocommit-read-only");
+    server.run(0, "insert 1 one");
+    let wal = server.wal();
+
+    server.run(1, "begin read only");
+    assert!(read_only(&server.transaction.read().unwrap(), &Server::addr(1)));
+    assert_eq!(server.reply(1, "insert 2 two"), "FAILED: read only transaction");
+    assert_eq!(server.reply(1, "savepoint s"), "FAILED: read only transaction");
+    assert_eq!(server.reply(1, "select 1"), "Value: \"one\"");
+
+    // It keeps its snapshot and never holds a key against writers.
+    assert!(get_all_active_transaction(Arc::clone(&server.transaction), Arc::clone(&server.services.all_addr)).is_empty());
+    server.run(0, "update 1 uno");
+    assert_eq!(server.reply(1, "select 1"), "Value: \"one\"");
+    server.run(1, "commit");
+    assert_eq!(server.wal().lines().filter(|line| line.contains(&Server::addr(1).to_string())).count(), 0);
+    assert!(server.wal().starts_with(&wal));
+
+    // Not even a txid reservation, a read-only transaction writes nothing under its txid.
+    let reserved = server.services.txids.reserved();
+    *server.txd_count.write().unwrap() = reserved;
+    let wal = server.wal();
+    server.run(1, "begin read only");
+    assert_eq!(*server.txd_count.read().unwrap(), reserved + 1);
+    assert_eq!(server.services.txids.reserved(), reserved);
+    server.run(1, "commit");
+    assert_eq!(server.wal(), wal);
+    server.run(2, "begin");
+    assert_eq!(*server.txd_count.read().unwrap(), reserved + 2);
+    assert!(server.services.txids.reserved() > reserved + 2);
+    server.run(2, "commit");
+
+    assert_eq!(server.reply(1, "begin read write"), "Invalid argument");
+    server.run(1, "begin read only");
+    server.run(1, "abort");
+    assert!(!server.wal().contains(&Server::addr(1).to_string()));
+}
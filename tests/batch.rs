@@ -0,0 +1,120 @@
+mod common;
+
+use std::sync::{Arc, RwLock};
+
+use common::Server;
+use ASMT::btree::node::{Items, Node};
+use ASMT::MVCC::versions::{Version, VersionStatus};
+use ASMT::catalog::tables::DEFAULT_TABLE;
+
+#[test]
+fn mset_loads_a_large_batch_and_rebalances_once() {
+    let server = Server::new("batch-mset");
+    let pairs: Vec<String> = (0..300).rev().map(|k| format!("{} v{}", k * 3, k)).collect();
+
+    server.run(0, "begin");
+    server.run(0, &format!("mset {}", pairs.join(" ")));
+    server.run(0, "commit");
+
+    let report = Node::check(server.table(DEFAULT_TABLE).unwrap());
+    assert!(report.is_clean(), "{}", report.to_json_lines());
+
+    server.run(1, "begin");
+    for k in 0..300 {
+        assert_eq!(server.select(1, DEFAULT_TABLE, k * 3), Some(format!("v{}", k)));
+    }
+    assert_eq!(server.wal().lines().filter(|line| line.contains("mset")).count(), 1);
+}
+
+#[test]
+fn upsert_inserts_or_updates() {
+    let server = Server::new("batch-upsert");
+    server.run(0, "begin");
+    server.run(0, "upsert 1 a");
+    server.run(0, "upsert 1 b");
+    server.run(0, "commit");
+
+    server.run(0, "begin");
+    assert_eq!(server.select(0, DEFAULT_TABLE, 1), Some(String::from("b")));
+    server.run(0, "upsert 1 c");
+    server.run(0, "commit");
+
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("c")));
+}
+
+#[test]
+fn batches_run_inside_the_callers_transaction() {
+    let server = Server::new("batch-txn");
+    server.run(0, "begin");
+    server.run(0, "mset 1 a 2 b 3 c");
+    server.run(0, "commit");
+
+    server.run(0, "begin");
+    server.run(0, "mdel 1 2 99");
+    server.run(0, "mset 3 z 4 d");
+    assert_eq!(server.select(0, DEFAULT_TABLE, 1), None);
+    assert_eq!(server.select(0, DEFAULT_TABLE, 3), Some(String::from("z")));
+    server.run(0, "abort");
+
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("a")));
+    assert_eq!(server.select(1, DEFAULT_TABLE, 2), Some(String::from("b")));
+    assert_eq!(server.select(1, DEFAULT_TABLE, 3), Some(String::from("c")));
+    assert_eq!(server.select(1, DEFAULT_TABLE, 4), None);
+
+    // Without a transaction nothing is written.
+    server.run(2, "mset 5 e");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 5), None);
+}
+
+#[test]
+fn a_conflicting_key_rejects_the_whole_batch() {
+    let server = Server::new("batch-conflict");
+    server.run(0, "begin");
+    server.run(0, "insert 2 mine");
+
+    server.run(1, "begin");
+    server.run(1, "mset 1 a 2 b 3 c");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), None);
+    assert_eq!(server.select(1, DEFAULT_TABLE, 3), None);
+    server.run(1, "commit");
+
+    let versions = ASMT::MVCC::visibility::fetch_version_vec_for_key(Arc::clone(&server.table(DEFAULT_TABLE).unwrap()), 1);
+    assert!(versions.is_none());
+}
+
+/// Fills the leaf holding `keys` past capacity, the way `upsert_batch` leaves it until it rebalances.
+fn overfill(root: &Arc<RwLock<Node>>, keys: impl Iterator<Item = u32>) {
+    for key in keys {
+        Node::with_key_mut(root, key, |node, position| {
+            let version = vec![Version { value: format!("v{}", key), xmin: 1, xmax: None, version_status: VersionStatus::Commit }];
+            node.input.insert(position.unwrap_err(), Items { key, rank: node.rank, version });
+        });
+    }
+}
+
+#[test]
+fn inserts_into_an_overfull_node_split_it_until_it_fits() {
+    common::init();
+
+    // A leaf below the root.
+    let root = Node::new();
+    for key in (0..40).map(|k| k * 100) {
+        Node::insert(Arc::clone(&root), key, format!("v{}", key), 1).unwrap();
+    }
+    overfill(&root, 1001..1030);
+    Node::insert(Arc::clone(&root), 1050, String::from("late"), 2).unwrap();
+    let report = Node::check(Arc::clone(&root));
+    assert!(report.is_clean(), "{}", report.to_json_lines());
+
+    // The root itself, still a leaf.
+    let root = Node::new();
+    overfill(&root, 0..30);
+    Node::insert(Arc::clone(&root), 100, String::from("late"), 2).unwrap();
+    let report = Node::check(Arc::clone(&root));
+    assert!(report.is_clean(), "{}", report.to_json_lines());
+    for key in (0..30).chain([100]) {
+        assert!(Node::with_key(&root, key, |_, position| position.is_ok()), "key {} lost", key);
+    }
+}
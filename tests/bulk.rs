@@ -0,0 +1,132 @@
+mod common;
+
+use std::fs;
+use std::io;
+use std::sync::{Arc, RwLock};
+
+use common::{Server, TempDir};
+use ASMT::btree::node::Node;
+use ASMT::catalog::tables::{Catalog, DEFAULT_TABLE};
+use ASMT::engine::services::Services;
+use ASMT::storage::external_sort::sorted_pairs;
+use ASMT::storage::ser::serialize;
+
+fn keys_in_order(root: &Arc<RwLock<Node>>) -> Vec<u32> {
+    let mut keys = Vec::new();
+    Node::for_each_item(root, &mut |item| keys.push(item.key));
+    keys
+}
+
+fn leaf_count(node: &Arc<RwLock<Node>>) -> usize {
+    let guard = node.read().unwrap();
+    if guard.children.is_empty() { 1 } else { guard.children.iter().map(leaf_count).sum() }
+}
+
+#[test]
+fn bulk_loaded_trees_are_balanced_at_every_size_and_fill_factor() {
+    common::init();
+    for fill_factor in [0.1, 0.5, 0.75, 1.0] {
+        for n in 0..400u32 {
+            let root = Arc::new(RwLock::new(Node::bulk_load((0..n).map(|k| (k * 2, format!("v{}", k))), fill_factor, 1)));
+
+            let report = Node::check(Arc::clone(&root));
+            assert!(report.is_clean(), "n {} fill {}: {}", n, fill_factor, report.to_json_lines());
+            assert_eq!(keys_in_order(&root), (0..n).map(|k| k * 2).collect::<Vec<_>>());
+        }
+    }
+}
+
+#[test]
+fn lower_fill_factor_leaves_room_in_the_leaves() {
+    common::init();
+    let full = Arc::new(RwLock::new(Node::bulk_load((0..1000).map(|k| (k, String::from("v"))), 1.0, 1)));
+    let half = Arc::new(RwLock::new(Node::bulk_load((0..1000).map(|k| (k, String::from("v"))), 0.5, 1)));
+    assert!(leaf_count(&half) > leaf_count(&full));
+
+    // Inserting into the gaps keeps the tree valid.
+    for k in 1000..1200 {
+        Node::insert(Arc::clone(&half), k, String::from("w"), 2).unwrap();
+    }
+    let report = Node::check(Arc::clone(&half));
+    assert!(report.is_clean(), "{}", report.to_json_lines());
+}
+
+#[test]
+fn unsorted_input_is_sorted_through_runs_and_the_last_duplicate_wins() {
+    let dir = TempDir::new("bulk-sort");
+    let input = dir.path("input.txt");
+    let mut lines: Vec<String> = (0..500).map(|k| format!("{} first{}", (k * 7919) % 500, k)).collect();
+    lines.push(String::new());
+    lines.push(String::from("42 last value"));
+    fs::write(&input, lines.join("\n")).unwrap();
+
+    let runs = || fs::read_dir(&dir.0).unwrap().filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().contains(".run")).count();
+    let mut stream = sorted_pairs(&input, &dir.0, 64).unwrap();
+    let first = stream.next().unwrap().unwrap();
+    // The runs are merged as the pairs are pulled, not up front.
+    assert_eq!(runs(), 8);
+
+    let pairs: Vec<(u32, String)> = std::iter::once(Ok(first)).chain(stream.by_ref()).collect::<io::Result<_>>().unwrap();
+    assert_eq!(pairs.iter().map(|(key, _)| *key).collect::<Vec<_>>(), (0..500).collect::<Vec<_>>());
+    assert_eq!(pairs[42].1, "last value");
+
+    // Run files are removed once the stream is dropped.
+    drop(stream);
+    assert_eq!(runs(), 0);
+}
+
+#[test]
+fn malformed_lines_are_rejected() {
+    let dir = TempDir::new("bulk-malformed");
+    let input = dir.path("input.txt");
+    fs::write(&input, "1 a\nnot_a_key b\n").unwrap();
+    assert!(sorted_pairs(&input, &dir.0, 64).is_err());
+}
+
+#[test]
+fn load_command_fills_an_empty_table_and_checkpoints_it() {
+    let server = Server::new("bulk-load");
+    let input = server.dir.path("input.txt");
+    let lines: Vec<String> = (0..300).rev().map(|k| format!("{} v{}", k, k)).collect();
+    fs::write(&input, lines.join("\n")).unwrap();
+
+    server.run(1, "begin");
+    assert_eq!(server.run(0, &format!("load {} 0.75", input)), 0);
+
+    // The rows are in the image before `load` returns, and the WAL no longer needs the input file.
+    assert!(!server.wal().lines().any(|line| line.starts_with("\"load")));
+    let image = Catalog::load(&server.dir.path("example.txt"), &Services::default()).unwrap();
+    assert_eq!(keys_in_order(&image.table(DEFAULT_TABLE).unwrap()), (0..300).collect::<Vec<_>>());
+
+    let report = Node::check(server.table(DEFAULT_TABLE).unwrap());
+    assert!(report.is_clean(), "{}", report.to_json_lines());
+
+    // Snapshot isolation: a transaction that began before the load doesn't see it, a new one does.
+    assert_eq!(server.select(1, DEFAULT_TABLE, 10), None);
+    server.run(0, "begin");
+    for k in 0..300 {
+        assert_eq!(server.select(0, DEFAULT_TABLE, k), Some(format!("v{}", k)));
+    }
+    server.run(0, "update 10 changed");
+    server.run(0, "commit");
+
+    // A second load would overwrite live keys.
+    assert_eq!(server.run(0, &format!("load {}", input)), 1);
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 10), Some(String::from("changed")));
+}
+
+#[test]
+fn bulk_loaded_tree_roundtrips_through_the_checkpoint_serializer() {
+    common::init();
+    let dir = TempDir::new("bulk-roundtrip");
+    let path = dir.path("image");
+    let root = Arc::new(RwLock::new(Node::bulk_load((0..500).map(|k| (k, format!("v{}", k))), 0.8, 1)));
+
+    serialize(Arc::clone(&root), &path).unwrap();
+    let restored = Node::deserialize(&path).unwrap();
+
+    let report = Node::check(Arc::clone(&restored));
+    assert!(report.is_clean(), "{}", report.to_json_lines());
+    assert_eq!(keys_in_order(&restored), (0..500).collect::<Vec<_>>());
+}
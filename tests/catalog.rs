@@ -0,0 +1,142 @@
+mod common;
+
+use common::Server;
+use ASMT::catalog::tables::{Catalog, DEFAULT_TABLE};
+use ASMT::engine::services::Services;
+
+#[test]
+fn tables_are_separate_keyspaces() {
+    let server = Server::new("catalog-keyspaces");
+    server.run(0, "create table users");
+    server.run(0, "begin");
+    server.run(0, "insert 1 alice");
+    server.run(0, "use users");
+    server.run(0, "insert 1 bob");
+    server.run(0, "commit");
+
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("alice")));
+    assert_eq!(server.select(1, "users", 1), Some(String::from("bob")));
+}
+
+#[test]
+fn a_transaction_spans_tables_atomically() {
+    let server = Server::new("catalog-atomic");
+    server.run(0, "create table a");
+    server.run(0, "create table b");
+
+    server.run(0, "begin");
+    server.run(0, "use a");
+    server.run(0, "insert 1 x");
+    server.run(0, "use b");
+    server.run(0, "insert 2 y");
+    server.run(0, "abort");
+
+    server.run(0, "begin");
+    assert_eq!(server.select(0, "a", 1), None);
+    assert_eq!(server.select(0, "b", 2), None);
+    server.run(0, "insert 2 z");
+    server.run(0, "use a");
+    server.run(0, "insert 1 w");
+    server.run(0, "commit");
+
+    server.run(1, "begin");
+    assert_eq!(server.select(1, "a", 1), Some(String::from("w")));
+    assert_eq!(server.select(1, "b", 2), Some(String::from("z")));
+}
+
+#[test]
+fn same_key_in_different_tables_does_not_conflict() {
+    let server = Server::new("catalog-conflicts");
+    server.run(0, "create table other");
+    server.run(1, "use other");
+
+    server.run(0, "begin");
+    server.run(1, "begin");
+    server.run(0, "insert 7 mine");
+    server.run(1, "insert 7 theirs");
+    server.run(0, "commit");
+    server.run(1, "commit");
+
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 7), Some(String::from("mine")));
+    assert_eq!(server.select(2, "other", 7), Some(String::from("theirs")));
+}
+
+#[test]
+fn drop_is_refused_while_a_transaction_wrote_to_the_table() {
+    let server = Server::new("catalog-drop");
+    server.run(0, "create table t");
+    server.run(0, "use t");
+    server.run(0, "begin");
+    server.run(0, "insert 1 x");
+
+    server.run(1, "drop table t");
+    assert!(server.table("t").is_some());
+    server.run(1, "drop table default");
+    assert!(server.table(DEFAULT_TABLE).is_some());
+
+    server.run(0, "commit");
+    server.run(1, "drop table t");
+    assert!(server.table("t").is_none());
+    assert!(server.wal().contains("drop table t"));
+}
+
+#[test]
+fn catalog_survives_a_checkpoint() {
+    let server = Server::new("catalog-store");
+    server.run(0, "create table t");
+    server.run(0, "use t");
+    server.run(0, "begin");
+    server.run(0, "insert 3 three");
+    server.run(0, "commit");
+
+    let path = server.dir.path("example.txt");
+    server.catalog.read().unwrap().store(&path, &server.services).unwrap();
+
+    let loaded = Catalog::load(&path, &Services::default()).unwrap();
+    let mut names: Vec<&String> = loaded.tables.keys().collect();
+    names.sort();
+    assert_eq!(names, vec![DEFAULT_TABLE, "t"]);
+    let versions = ASMT::MVCC::visibility::fetch_version_vec_for_key(loaded.table("t").unwrap(), 3).unwrap();
+    assert_eq!(versions[0].value, "three");
+}
+
+#[test]
+fn a_table_that_fails_to_load_fails_the_catalog() {
+    let server = Server::new("catalog-bad-table");
+    server.run(0, "create table t");
+    server.run(0, "insert 1 one");
+    let path = server.dir.path("example.txt");
+    server.catalog.read().unwrap().store(&path, &server.services).unwrap();
+
+    // Every image of `t` is corrupted, nothing is left to fall back to.
+    for entry in std::fs::read_dir(&server.dir.0).unwrap() {
+        let entry = entry.unwrap();
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("example.txt.table.t.") && !name.ends_with(".manifest") {
+            std::fs::write(entry.path(), "garbage").unwrap();
+        }
+    }
+    let error = Catalog::load(&path, &Services::default()).unwrap_err();
+    assert!(error.to_string().contains("table t"), "{}", error);
+}
+
+#[test]
+fn dropped_tables_leave_no_images_behind() {
+    let server = Server::new("catalog-drop-images");
+    server.run(0, "create table t");
+    server.run(0, "create table t2");
+    let path = server.dir.path("example.txt");
+    let images = |table: &str| std::fs::read_dir(&server.dir.0).unwrap()
+        .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(&format!("example.txt.table.{}.", table)))
+        .count();
+    server.catalog.read().unwrap().store(&path, &server.services).unwrap();
+    assert!(images("t") > 0);
+
+    server.run(0, "drop table t");
+    server.catalog.read().unwrap().store(&path, &server.services).unwrap();
+    assert_eq!(images("t"), 0);
+    assert!(images("t2") > 0);
+    assert_eq!(Catalog::load(&path, &Services::default()).unwrap().tables.len(), 2);
+}
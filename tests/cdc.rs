@@ -0,0 +1,172 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use common::{Server, TempDir};
+use ASMT::cli::cli::cli;
+use ASMT::engine::cdc::{CdcError, Change, ChangeEvent, ChangeLog, Subscription};
+
+fn subscribe(server: &Server, from_lsn: Option<u64>) -> Result<Subscription, CdcError> {
+    let changes = Arc::clone(&server.services.changes);
+    changes.subscribe(from_lsn)
+}
+
+fn drain(server: &Server, from_lsn: u64) -> Vec<ChangeEvent> {
+    let mut subscription = subscribe(server, Some(from_lsn)).unwrap();
+    let mut events = Vec::new();
+    while let Some(event) = subscription.next_timeout(Duration::ZERO).unwrap() {
+        events.push(event);
+    }
+    events
+}
+
+fn change(event: &ChangeEvent) -> (u64, u32, Option<&str>, Option<&str>) {
+    (event.txid, event.key, event.old.as_deref(), event.new.as_deref())
+}
+
+#[test]
+fn commits_are_streamed_with_old_and_new_values() {
+    let server = Server::new("cdc-values");
+    server.run(0, "begin");
+    server.run(0, "insert 1 a");
+    server.run(0, "insert 2 b");
+    server.run(0, "insert 3 gone");
+    server.run(0, "delete 3");
+    server.run(0, "commit");
+
+    server.run(0, "begin");
+    server.run(0, "update 1 a2");
+    server.run(0, "update 1 a3");
+    server.run(0, "delete 2");
+    server.run(0, "commit");
+
+    server.run(1, "begin");
+    server.run(1, "update 1 never");
+    server.run(1, "abort");
+
+    server.run(0, "insert_if_absent 4 d");
+    server.run(0, "begin");
+    server.run(0, "mset 5 e 6 f");
+    server.run(0, "commit");
+
+    let events = drain(&server, 1);
+    let changes: Vec<_> = events.iter().map(change).collect();
+    assert_eq!(changes, vec![
+        (1, 1, None, Some("a")),
+        (1, 2, None, Some("b")),
+        (2, 1, Some("a"), Some("a3")),
+        (2, 2, Some("b"), None),
+        (4, 4, None, Some("d")),
+        (5, 5, None, Some("e")),
+        (5, 6, None, Some("f")),
+    ]);
+    assert_eq!(events.iter().map(|event| event.lsn).collect::<Vec<_>>(), (1..=7).collect::<Vec<_>>());
+    assert_eq!(events.iter().map(|event| event.commit).collect::<Vec<_>>(), vec![1, 1, 2, 2, 3, 4, 4]);
+    assert!(events.iter().all(|event| event.table == "default"));
+}
+
+#[test]
+fn events_follow_commit_order_not_begin_order() {
+    let server = Server::new("cdc-order");
+    server.run(0, "begin");
+    server.run(1, "begin");
+    server.run(0, "insert 1 first_begun");
+    server.run(1, "insert 2 first_committed");
+    server.run(1, "commit");
+    server.run(0, "commit");
+
+    let events = drain(&server, 1);
+    assert_eq!(events.iter().map(|event| (event.txid, event.commit)).collect::<Vec<_>>(), vec![(2, 1), (1, 2)]);
+}
+
+#[test]
+fn subscriptions_resume_from_a_position_and_wait_for_commits() {
+    let server = Server::new("cdc-resume");
+    for k in 1..=3 {
+        server.run(0, "begin");
+        server.run(0, &format!("insert {} v{}", k, k));
+        server.run(0, "commit");
+    }
+
+    assert_eq!(drain(&server, 2).iter().map(|event| event.key).collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(subscribe(&server, Some(5)).unwrap_err(), CdcError::Ahead { next: 4 });
+    assert_eq!(subscribe(&server, Some(0)).unwrap_err(), CdcError::Truncated { oldest: 1 });
+
+    let mut live = subscribe(&server, None).unwrap();
+    assert_eq!(live.position(), 4);
+    let reader = thread::spawn(move || live.next().unwrap());
+    thread::sleep(Duration::from_millis(50));
+    server.run(0, "begin");
+    server.run(0, "update 2 changed");
+    server.run(0, "commit");
+
+    let event = reader.join().unwrap();
+    assert_eq!((event.lsn, change(&event)), (4, (4, 2, Some("v2"), Some("changed"))));
+}
+
+#[test]
+fn durable_log_keeps_positions_across_reopens() {
+    let dir = TempDir::new("cdc-durable");
+    let path = dir.path("CDC.txt");
+    let change = |key: u32, new: &str| Change { table: String::from("t\"1"), key, old: None, new: Some(String::from(new)) };
+
+    let log = ChangeLog::open(&path).unwrap();
+    log.publish(7, &[change(1, "line\nbreak"), change(2, "b")]).unwrap();
+    log.publish(8, &[]).unwrap();
+    drop(log);
+
+    let log = Arc::new(ChangeLog::open(&path).unwrap());
+    assert_eq!(log.next_lsn(), 3);
+    log.publish(9, &[change(3, "c")]).unwrap();
+
+    let events: Vec<ChangeEvent> = log.subscribe(Some(1)).unwrap().take(3).collect();
+    assert_eq!(events[0].new.as_deref(), Some("line\nbreak"));
+    assert_eq!(events[0].table, "t\"1");
+    assert_eq!(events.iter().map(|event| (event.lsn, event.commit, event.txid)).collect::<Vec<_>>(), vec![(1, 1, 7), (2, 1, 7), (3, 2, 9)]);
+}
+
+#[test]
+fn subscribe_streams_json_lines_over_tcp() {
+    let server = Arc::new(Server::new("cdc-tcp"));
+    server.run(0, "begin");
+    server.run(0, "insert 1 before");
+    server.run(0, "commit");
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (connection, peer) = listener.accept().unwrap();
+
+    let session = {
+        let server = Arc::clone(&server);
+        thread::spawn(move || {
+            let line = format!("subscribe 1 {}", peer);
+            cli(line, Arc::clone(&server.txd_count), Arc::clone(&server.transaction), Arc::clone(&server.file), Arc::clone(&server.catalog), Some(&connection), Arc::clone(&server.services)).unwrap()
+        })
+    };
+
+    let mut lines = BufReader::new(client.try_clone().unwrap()).lines();
+    assert_eq!(lines.next().unwrap().unwrap(), "Subscribed from 1");
+    let first = ChangeEvent::from_json(&lines.next().unwrap().unwrap()).unwrap();
+    assert_eq!(change(&first), (1, 1, None, Some("before")));
+
+    server.run(0, "begin");
+    server.run(0, "update 1 after");
+    server.run(0, "commit");
+    let second = ChangeEvent::from_json(&lines.next().unwrap().unwrap()).unwrap();
+    assert_eq!((second.lsn, change(&second)), (2, (2, 1, Some("before"), Some("after"))));
+
+    // Closing the connection ends the subscription, and the session with it.
+    let mut client = client;
+    client.flush().unwrap();
+    client.shutdown(std::net::Shutdown::Both).unwrap();
+    drop(lines);
+    assert_eq!(session.join().unwrap(), 2);
+
+    // Without a connection there's nothing to stream to.
+    assert_eq!(server.run(0, "subscribe"), 1);
+    assert_eq!(server.run(0, "subscribe x"), 1);
+}
@@ -0,0 +1,32 @@
+mod common;
+
+use std::fs;
+use std::sync::{Arc, RwLock};
+
+use common::TempDir;
+use ASMT::btree::node::Node;
+use ASMT::storage::manifest::{Checksum, Manifest};
+use ASMT::storage::ser::serialize;
+
+#[test]
+fn unparsable_current_image_falls_back_to_the_previous_one() {
+    common::init();
+    let dir = TempDir::new("checkpoint-unparsable");
+    let path = dir.path("image");
+    serialize(Arc::new(RwLock::new(Node::bulk_load((0..10).map(|k| (k, format!("v{}", k))), 0.8, 1))), &path).unwrap();
+    serialize(Arc::new(RwLock::new(Node::bulk_load((0..20).map(|k| (k, format!("v{}", k))), 0.8, 1))), &path).unwrap();
+
+    // The image matches what the manifest recorded, it just isn't one.
+    let mut manifest = Manifest::load(&path).unwrap().unwrap();
+    let current = manifest.current.as_mut().unwrap();
+    let garbage = b"[0]\n[not hex]\n";
+    fs::write(current.resolve(&path), garbage).unwrap();
+    let mut checksum = Checksum::new();
+    checksum.update(garbage);
+    current.len = checksum.len;
+    current.checksum = checksum.finish();
+    manifest.store(&path).unwrap();
+
+    let restored = Node::deserialize(&path).unwrap();
+    assert_eq!(Node::check(Arc::clone(&restored)).keys_checked, 10);
+}
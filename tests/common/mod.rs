@@ -0,0 +1,297 @@
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use ASMT::NODE_SIZE;
+use ASMT::btree::node::Node;
+use ASMT::catalog::tables::{Catalog, DEFAULT_TABLE};
+use ASMT::cli::cli::cli;
+use ASMT::engine::checkpoint::CheckpointPaths;
+use ASMT::engine::services::Services;
+use ASMT::MVCC::visibility::select_key;
+use ASMT::transactions::timeout::active_txd;
+use ASMT::transactions::transactions::Transaction;
+
+pub fn init() {
+    let _ = NODE_SIZE.set(4);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Begin(usize),
+    Write(usize, u32, String),
+    Insert(usize, u32, String),
+    Delete(usize, u32),
+    Select(usize, u32),
+    Commit(usize),
+    Abort(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Done,
+    Skipped,
+    Conflict,
+    Value(Option<String>),
+}
+
+/// Drives the engine through `cli`, one command line per operation, like a client of the server does. Each client is
+/// identified by a fake socket address, exactly like a connection is on the server.
+pub struct Engine {
+    pub server: Server,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let server = Server::new(&format!("model-{}", RUNS.fetch_add(1, Ordering::Relaxed)));
+        // The model rejects a write to a key another transaction holds right away, instead of waiting for it.
+        server.services.timeouts.write().unwrap().lock_wait = Duration::ZERO;
+        Engine { server }
+    }
+
+    /// `Write` is an `upsert`, `Insert` an `insert` and `Delete` a `delete`. The outcome is read off the reply.
+    pub fn apply(&mut self, op: &Op) -> Outcome {
+        let (client, command) = match op {
+            Op::Begin(c) => (*c, String::from("begin")),
+            Op::Write(c, k, v) => (*c, format!("upsert {} {}", k, v)),
+            Op::Insert(c, k, v) => (*c, format!("insert {} {}", k, v)),
+            Op::Delete(c, k) => (*c, format!("delete {}", k)),
+            Op::Select(c, k) => (*c, format!("select {}", k)),
+            Op::Commit(c) => (*c, String::from("commit")),
+            Op::Abort(c) => (*c, String::from("abort")),
+        };
+        // Without a transaction a statement would run in one of its own, see `autocommit`, which the model leaves out.
+        if self.server.active_txd(client).is_some() == matches!(op, Op::Begin(_)) {
+            return Outcome::Skipped;
+        }
+
+        let reply = self.server.reply(client, &command);
+        match op {
+            Op::Select(..) => Outcome::Value(reply.strip_prefix("Value: ").map(|value| value.trim_matches('"').to_string())),
+            _ if reply.starts_with("FAILED") || reply.contains("already been updated or locked") => Outcome::Conflict,
+            _ => Outcome::Done,
+        }
+    }
+}
+
+/// Reference model of snapshot isolation: every transaction reads the committed state as of its `begin` plus its own
+/// writes. A write is rejected if another active transaction already claimed the key (first-writer-wins, like
+/// `modified_key_check`) or if a transaction that committed after this one began changed it (first-updater-wins, like
+/// `serialization_conflict`).
+#[derive(Debug, Default)]
+pub struct Model {
+    pub committed: BTreeMap<u32, String>,
+    /// Commit sequence number of the last committed change per key.
+    pub changed_at: BTreeMap<u32, u64>,
+    pub commit_seq: u64,
+    pub txns: BTreeMap<usize, ModelTxn>,
+}
+
+#[derive(Debug)]
+pub struct ModelTxn {
+    pub began_at: u64,
+    pub snapshot: BTreeMap<u32, String>,
+    pub writes: BTreeMap<u32, Option<String>>,
+    pub touched: BTreeSet<u32>,
+}
+
+impl Model {
+    pub fn apply(&mut self, op: &Op) -> Outcome {
+        match op {
+            Op::Begin(c) => {
+                if self.txns.contains_key(c) {
+                    return Outcome::Skipped;
+                }
+                let txn = ModelTxn { began_at: self.commit_seq, snapshot: self.committed.clone(), writes: BTreeMap::new(), touched: BTreeSet::new() };
+                self.txns.insert(*c, txn);
+                Outcome::Done
+            }
+            Op::Write(c, k, _) | Op::Insert(c, k, _) | Op::Delete(c, k) => {
+                let began_at = match self.txns.get(c) {
+                    Some(txn) => txn.began_at,
+                    None => return Outcome::Skipped,
+                };
+                let claimed_elsewhere = self.txns.iter().any(|(other, txn)| other != c && txn.touched.contains(k));
+                let changed_since = self.changed_at.get(k).is_some_and(|seq| *seq > began_at);
+                if claimed_elsewhere || changed_since {
+                    return Outcome::Conflict;
+                }
+
+                // Like `cli`, neither an insert of an existing key nor a delete of a missing key claims it.
+                let txn = self.txns.get_mut(c).unwrap();
+                let visible = txn.visible(*k);
+                match op {
+                    Op::Write(_, _, v) => {
+                        txn.writes.insert(*k, Some(v.clone()));
+                        txn.touched.insert(*k);
+                    }
+                    Op::Insert(_, _, v) if visible.is_none() => {
+                        txn.writes.insert(*k, Some(v.clone()));
+                        txn.touched.insert(*k);
+                    }
+                    Op::Delete(..) if visible.is_some() => {
+                        // Deleting its own insert rolls the version back, leaving nothing for others to conflict with.
+                        if txn.snapshot.contains_key(k) {
+                            txn.writes.insert(*k, None);
+                        } else {
+                            txn.writes.remove(k);
+                        }
+                        txn.touched.insert(*k);
+                    }
+                    _ => {}
+                }
+                Outcome::Done
+            }
+            Op::Select(c, k) => match self.txns.get(c) {
+                Some(txn) => Outcome::Value(txn.visible(*k)),
+                None => Outcome::Skipped,
+            },
+            Op::Commit(c) => match self.txns.remove(c) {
+                Some(txn) => {
+                    self.commit_seq += 1;
+                    for (k, v) in txn.writes {
+                        match v {
+                            Some(v) => self.committed.insert(k, v),
+                            None => self.committed.remove(&k),
+                        };
+                        self.changed_at.insert(k, self.commit_seq);
+                    }
+                    Outcome::Done
+                }
+                None => Outcome::Skipped,
+            },
+            Op::Abort(c) => match self.txns.remove(c) {
+                Some(_) => Outcome::Done,
+                None => Outcome::Skipped,
+            },
+        }
+    }
+}
+
+impl ModelTxn {
+    fn visible(&self, key: u32) -> Option<String> {
+        match self.writes.get(&key) {
+            Some(write) => write.clone(),
+            None => self.snapshot.get(&key).cloned(),
+        }
+    }
+}
+
+/// Runs `ops` against both the engine and the model, returning the first diverging step.
+pub fn run(ops: &[Op]) -> Result<Engine, String> {
+    let mut engine = Engine::new();
+    let mut model = Model::default();
+
+    for (step, op) in ops.iter().enumerate() {
+        let expected = model.apply(op);
+        let actual = engine.apply(op);
+        if expected != actual {
+            return Err(format!("step {} {:?}: model {:?}, engine {:?}", step, op, expected, actual));
+        }
+    }
+
+    let report = Node::check(engine.server.table(DEFAULT_TABLE).unwrap());
+    if !report.is_clean() {
+        return Err(report.to_json_lines());
+    }
+    Ok(engine)
+}
+
+/// A fresh scratch directory per test, removed when dropped.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("asmt-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self, file_name: &str) -> String {
+        self.0.join(file_name).to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs command lines through `cli` exactly like `process_tcp_stream` does, with a WAL in a scratch directory.
+pub struct Server {
+    pub dir: TempDir,
+    pub txd_count: Arc<RwLock<u64>>,
+    pub transaction: Arc<RwLock<Transaction>>,
+    pub file: Arc<RwLock<File>>,
+    pub catalog: Arc<RwLock<Catalog>>,
+    pub services: Arc<Services>,
+}
+
+impl Server {
+    pub fn new(name: &str) -> Server {
+        init();
+        let dir = TempDir::new(name);
+        let file = OpenOptions::new().append(true).create(true).open(dir.path("WAL.txt")).unwrap();
+        Server {
+            txd_count: Arc::new(RwLock::new(0)),
+            transaction: Arc::new(RwLock::new(Transaction { items: HashMap::new(), ip_txd: HashMap::new() })),
+            file: Arc::new(RwLock::new(file)),
+            catalog: Arc::new(RwLock::new(Catalog::new(Node::new()))),
+            services: Arc::new(Services { checkpoints: Some(CheckpointPaths { image: dir.path("example.txt"), wal: dir.path("WAL.txt") }), ..Services::default() }),
+            dir,
+        }
+    }
+
+    pub fn addr(client: usize) -> SocketAddr {
+        format!("127.0.0.1:{}", 40000 + client).parse().unwrap()
+    }
+
+    pub fn active_txd(&self, client: usize) -> Option<u64> {
+        active_txd(&self.transaction.read().unwrap(), &Server::addr(client))
+    }
+
+    /// Runs `command` on behalf of `client` and returns `cli`'s status code.
+    pub fn run(&self, client: usize, command: &str) -> u8 {
+        let line = format!("{} {}", command, Server::addr(client));
+        cli(line, Arc::clone(&self.txd_count), Arc::clone(&self.transaction), Arc::clone(&self.file), Arc::clone(&self.catalog), None, Arc::clone(&self.services)).unwrap()
+    }
+
+    /// Runs `command` on behalf of `client` over a connection, like `process_tcp_stream` does, and returns what the
+    /// client is sent back.
+    pub fn reply(&self, client: usize, command: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client_end = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (connection, _) = listener.accept().unwrap();
+
+        let line = format!("{} {}", command, Server::addr(client));
+        cli(line, Arc::clone(&self.txd_count), Arc::clone(&self.transaction), Arc::clone(&self.file), Arc::clone(&self.catalog), Some(&connection), Arc::clone(&self.services)).unwrap();
+        drop(connection);
+        let mut sent = String::new();
+        client_end.read_to_string(&mut sent).unwrap();
+        sent.trim_end().to_string()
+    }
+
+    pub fn table(&self, name: &str) -> Option<Arc<RwLock<Node>>> {
+        self.catalog.read().unwrap().table(name)
+    }
+
+    /// What `client`'s current transaction sees for `key` in `table`.
+    pub fn select(&self, client: usize, table: &str, key: u32) -> Option<String> {
+        let txd = *self.transaction.read().unwrap().ip_txd.get(&Server::addr(client))?;
+        select_key(self.table(table)?, key, txd, Arc::clone(&self.transaction))
+    }
+
+    pub fn wal(&self) -> String {
+        fs::read_to_string(self.dir.path("WAL.txt")).unwrap()
+    }
+}
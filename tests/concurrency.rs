@@ -0,0 +1,143 @@
+mod common;
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::thread;
+
+use ASMT::btree::node::Node;
+use ASMT::MVCC::visibility::{commit_abort_handler, fetch_version_vec_for_key, select_key};
+use ASMT::transactions::timeout::Lifetime;
+use ASMT::transactions::transactions::{Transaction, TransactionItems, TransactionStatus};
+
+const WRITERS: u32 = 4;
+const KEYS_PER_WRITER: u32 = 300;
+const PRELOADED: u32 = 200;
+
+/// Writers split nodes all over the tree while readers look up keys that were there from the start.
+/// With latch crabbing a reader can never lose track of a key moved by a concurrent split.
+#[test]
+fn readers_never_miss_keys_during_splits() {
+    common::init();
+    let root = Node::new();
+    for k in 0..PRELOADED {
+        Node::insert(Arc::clone(&root), k * 1000, format!("p{}", k), 1).unwrap();
+    }
+
+    let mut handles = Vec::new();
+    for w in 0..WRITERS {
+        let root = Arc::clone(&root);
+        handles.push(thread::spawn(move || {
+            for i in 0..KEYS_PER_WRITER {
+                // Interleaved ranges, so writers keep splitting each other's leaves.
+                let key = (i * WRITERS + w) * 7 + 1;
+                if key.is_multiple_of(1000) {
+                    // Preloaded already, a second insert would be refused.
+                    continue;
+                }
+                let txd = u64::from(w) + 2;
+                Node::insert(Arc::clone(&root), key, format!("w{}", w), txd).unwrap();
+                commit_abort_handler(Arc::clone(&root), key, txd, true);
+            }
+        }));
+    }
+    for r in 0..4 {
+        let root = Arc::clone(&root);
+        handles.push(thread::spawn(move || {
+            for round in 0..20 {
+                for k in (r..PRELOADED).step_by(3) {
+                    let versions = fetch_version_vec_for_key(Arc::clone(&root), k * 1000);
+                    assert!(versions.is_some(), "round {}: key {} vanished", round, k * 1000);
+                }
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let report = Node::check(Arc::clone(&root));
+    assert!(report.is_clean(), "{}", report.to_json_lines());
+    for w in 0..WRITERS {
+        for i in 0..KEYS_PER_WRITER {
+            let key = (i * WRITERS + w) * 7 + 1;
+            assert!(fetch_version_vec_for_key(Arc::clone(&root), key).is_some(), "key {} lost", key);
+        }
+    }
+}
+
+/// Concurrent writers racing to insert the same keys: exactly one version per key survives.
+#[test]
+fn racing_inserts_of_the_same_key_keep_one_live_version() {
+    common::init();
+    let root = Node::new();
+
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let root = Arc::clone(&root);
+            thread::spawn(move || {
+                for key in 0..400 {
+                    if let Err(e) = Node::insert(Arc::clone(&root), key, format!("t{}", t), t + 1) {
+                        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let report = Node::check(Arc::clone(&root));
+    assert!(report.is_clean(), "{}", report.to_json_lines());
+    for key in 0..400 {
+        let versions = fetch_version_vec_for_key(Arc::clone(&root), key).unwrap();
+        assert_eq!(versions.len(), 1, "key {}: {:?}", key, versions);
+    }
+}
+
+/// Selects run in parallel with an uncommitted writer rewriting every key and never see its versions.
+#[test]
+fn parallel_selects_skip_uncommitted_writes() {
+    common::init();
+    let root = Node::new();
+    for k in 0..200 {
+        Node::insert(Arc::clone(&root), k, format!("v{}", k), 1).unwrap();
+        commit_abort_handler(Arc::clone(&root), k, 1, true);
+    }
+
+    let mut items = HashMap::new();
+    for (txd, port) in [(5, 40005), (10, 40010)] {
+        let socket_addr = format!("127.0.0.1:{}", port).parse().unwrap();
+        items.insert(txd, TransactionItems { status: TransactionStatus::Active, socket_addr, last_txd: 0, modified_keys: Vec::new(), snapshot: Vec::new(), lifetime: Lifetime::new(), savepoints: Vec::new(), parent: None, prepared: None, read_only: false });
+    }
+    let transaction = Arc::new(RwLock::new(Transaction { items, ip_txd: HashMap::new() }));
+
+    let writer = {
+        let root = Arc::clone(&root);
+        thread::spawn(move || {
+            for k in 0..200 {
+                Node::find_and_update_key_version(Arc::clone(&root), k, Some(String::from("dirty")), 5, false).unwrap();
+            }
+        })
+    };
+    let readers: Vec<_> = (0..8)
+        .map(|_| {
+            let root = Arc::clone(&root);
+            let transaction = Arc::clone(&transaction);
+            thread::spawn(move || {
+                for _ in 0..5 {
+                    for k in 0..200 {
+                        let value = select_key(Arc::clone(&root), k, 10, Arc::clone(&transaction));
+                        assert_eq!(value, Some(format!("v{}", k)));
+                    }
+                }
+            })
+        })
+        .collect();
+
+    writer.join().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
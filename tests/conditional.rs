@@ -0,0 +1,107 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::Server;
+use ASMT::catalog::tables::DEFAULT_TABLE;
+use ASMT::transactions::conditional::{conditional_write, Condition, ConditionalStatus, ConditionalWrite, Target};
+
+/// Runs a conditional write on the default table for `client`, returning its status like `cli` reports it.
+fn write_if(server: &Server, client: usize, key: u32, condition: Condition, value: Option<&str>) -> ConditionalStatus {
+    let services = &server.services;
+    let target = Target { table: DEFAULT_TABLE, node: server.table(DEFAULT_TABLE).unwrap(), indexes: &[], changes: &services.changes, watches: &services.watches, txids: &services.txids, locks: &services.locks };
+    let addr = Server::addr(client);
+    let addr_string = addr.to_string();
+    let args = vec!["conditional", &addr_string];
+    let write = ConditionalWrite { key, condition, value: value.map(String::from) };
+    conditional_write(target, write, addr, Arc::clone(&server.txd_count), Arc::clone(&server.transaction), Arc::clone(&server.file), args).unwrap()
+}
+
+fn committed(server: &Server, key: u32) -> Option<String> {
+    server.run(9, "begin");
+    let value = server.select(9, DEFAULT_TABLE, key);
+    server.run(9, "commit");
+    value
+}
+
+#[test]
+fn implicit_conditional_writes_commit_immediately() {
+    let server = Server::new("cond-implicit");
+    assert_eq!(write_if(&server, 0, 1, Condition::Absent, Some("a")), ConditionalStatus::Applied);
+    assert_eq!(write_if(&server, 0, 1, Condition::Absent, Some("b")), ConditionalStatus::Mismatch);
+    assert_eq!(committed(&server, 1), Some(String::from("a")));
+
+    assert_eq!(write_if(&server, 1, 1, Condition::Equals(String::from("x")), Some("c")), ConditionalStatus::Mismatch);
+    assert_eq!(write_if(&server, 1, 1, Condition::Equals(String::from("a")), Some("c")), ConditionalStatus::Applied);
+    assert_eq!(committed(&server, 1), Some(String::from("c")));
+
+    assert_eq!(write_if(&server, 1, 1, Condition::Equals(String::from("a")), None), ConditionalStatus::Mismatch);
+    assert_eq!(write_if(&server, 1, 1, Condition::Equals(String::from("c")), None), ConditionalStatus::Applied);
+    assert_eq!(committed(&server, 1), None);
+    assert_eq!(write_if(&server, 0, 1, Condition::Absent, Some("d")), ConditionalStatus::Applied);
+    assert_eq!(committed(&server, 1), Some(String::from("d")));
+}
+
+#[test]
+fn update_if_version_compares_the_visible_xmin() {
+    let server = Server::new("cond-version");
+    assert_eq!(write_if(&server, 0, 4, Condition::Absent, Some("a")), ConditionalStatus::Applied);
+    let xmin = *server.transaction.read().unwrap().ip_txd.get(&Server::addr(0)).unwrap();
+
+    assert_eq!(write_if(&server, 1, 4, Condition::Version(xmin + 100), Some("b")), ConditionalStatus::Mismatch);
+    assert_eq!(write_if(&server, 1, 4, Condition::Version(xmin), Some("b")), ConditionalStatus::Applied);
+    // The version is now the one written by client 1, the old xmin is stale.
+    assert_eq!(write_if(&server, 2, 4, Condition::Version(xmin), Some("c")), ConditionalStatus::Mismatch);
+    assert_eq!(committed(&server, 4), Some(String::from("b")));
+}
+
+#[test]
+fn conditional_writes_conflict_with_open_transactions() {
+    let server = Server::new("cond-conflict");
+    server.run(0, "begin");
+    server.run(0, "insert 2 mine");
+
+    assert_eq!(write_if(&server, 1, 2, Condition::Absent, Some("theirs")), ConditionalStatus::Conflict);
+    server.run(0, "commit");
+    assert_eq!(write_if(&server, 1, 2, Condition::Equals(String::from("mine")), Some("theirs")), ConditionalStatus::Applied);
+
+    // Inside an explicit transaction the write only becomes visible on commit.
+    server.run(2, "begin");
+    assert_eq!(write_if(&server, 2, 2, Condition::Equals(String::from("theirs")), Some("later")), ConditionalStatus::Applied);
+    assert_eq!(committed(&server, 2), Some(String::from("theirs")));
+    server.run(2, "abort");
+    assert_eq!(committed(&server, 2), Some(String::from("theirs")));
+}
+
+#[test]
+fn failed_conditions_keep_no_lock_in_a_transaction() {
+    let server = Server::new("cond-locks");
+    assert_eq!(write_if(&server, 0, 5, Condition::Absent, Some("a")), ConditionalStatus::Applied);
+
+    server.run(1, "begin");
+    assert_eq!(write_if(&server, 1, 5, Condition::Equals(String::from("x")), Some("b")), ConditionalStatus::Mismatch);
+    assert_eq!(write_if(&server, 1, 6, Condition::Equals(String::from("x")), None), ConditionalStatus::Mismatch);
+    let txd = server.active_txd(1).unwrap();
+    assert_eq!(server.services.locks.held(txd), 0);
+    assert!(server.transaction.read().unwrap().items[&txd].modified_keys.is_empty());
+
+    // Other writers aren't held up until client 1 ends.
+    assert_eq!(write_if(&server, 2, 5, Condition::Equals(String::from("a")), Some("c")), ConditionalStatus::Applied);
+    server.run(1, "commit");
+    assert_eq!(committed(&server, 5), Some(String::from("c")));
+}
+
+#[test]
+fn cli_commands_and_wal_lines() {
+    let server = Server::new("cond-cli");
+    server.run(0, "insert_if_absent 3 x");
+    server.run(0, "cas 3 x y");
+    server.run(0, "cas 3 x z");
+    assert_eq!(committed(&server, 3), Some(String::from("y")));
+
+    let wal = server.wal();
+    assert!(wal.contains("insert_if_absent 3 x"));
+    assert!(wal.contains("cas 3 x y"));
+    assert!(!wal.contains("cas 3 x z"));
+    assert_eq!(wal.matches("commit").count(), wal.matches("begin").count());
+}
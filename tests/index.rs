@@ -0,0 +1,117 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::Server;
+use ASMT::catalog::tables::{Catalog, DEFAULT_TABLE};
+use ASMT::engine::services::Services;
+use ASMT::catalog::index::lookup;
+use ASMT::engine::vacuum::vacuum;
+
+/// What `select_by <index> <value>` would print for `client`, as `(key, value)` pairs.
+fn select_by(server: &Server, client: usize, index: &str, value: &str) -> Vec<(u32, String)> {
+    let index = server.catalog.read().unwrap().index(index).unwrap();
+    let txd = *server.transaction.read().unwrap().ip_txd.get(&Server::addr(client)).unwrap();
+    lookup(&index, server.table(&index.table).unwrap(), value, txd, Arc::clone(&server.transaction))
+}
+
+#[test]
+fn index_follows_committed_writes() {
+    let server = Server::new("index-committed");
+    server.run(0, "create index by_city on default 1");
+    server.run(0, "begin");
+    server.run(0, "insert 1 alice,paris");
+    server.run(0, "insert 2 bob,rome");
+    server.run(0, "insert 3 carol,paris");
+    server.run(0, "commit");
+
+    server.run(1, "begin");
+    assert_eq!(select_by(&server, 1, "by_city", "paris"), vec![(1, String::from("alice,paris")), (3, String::from("carol,paris"))]);
+
+    server.run(0, "begin");
+    server.run(0, "update 1 alice,rome");
+    server.run(0, "delete 3");
+    server.run(0, "commit");
+
+    // Client 1's snapshot still predates the update.
+    assert_eq!(select_by(&server, 1, "by_city", "rome"), vec![(2, String::from("bob,rome"))]);
+    server.run(1, "commit");
+    server.run(1, "begin");
+    assert_eq!(select_by(&server, 1, "by_city", "paris"), vec![]);
+    assert_eq!(select_by(&server, 1, "by_city", "rome"), vec![(1, String::from("alice,rome")), (2, String::from("bob,rome"))]);
+}
+
+#[test]
+fn uncommitted_and_aborted_writes_are_invisible() {
+    let server = Server::new("index-uncommitted");
+    server.run(0, "create index by_value on default");
+    server.run(0, "begin");
+    server.run(0, "insert 1 x");
+
+    server.run(1, "begin");
+    assert_eq!(select_by(&server, 1, "by_value", "x"), vec![]);
+    assert_eq!(select_by(&server, 0, "by_value", "x"), vec![(1, String::from("x"))]);
+
+    server.run(0, "abort");
+    server.run(0, "begin");
+    assert_eq!(select_by(&server, 0, "by_value", "x"), vec![]);
+}
+
+#[test]
+fn index_is_built_from_existing_rows_and_rebuilt_on_load() {
+    let server = Server::new("index-build");
+    server.run(0, "create table people");
+    server.run(0, "use people");
+    server.run(0, "begin");
+    server.run(0, "insert 5 dave,oslo");
+    server.run(0, "commit");
+    server.run(0, "create index by_name on people 0");
+
+    server.run(0, "begin");
+    assert_eq!(select_by(&server, 0, "by_name", "dave"), vec![(5, String::from("dave,oslo"))]);
+    server.run(0, "commit");
+
+    let path = server.dir.path("example.txt");
+    server.catalog.read().unwrap().store(&path, &server.services).unwrap();
+    let loaded = Catalog::load(&path, &Services::default()).unwrap();
+    let index = loaded.index("by_name").unwrap();
+    assert_eq!(index.table, "people");
+    assert_eq!(index.field, Some(0));
+    assert!(index.entries.read().unwrap().contains_key("dave"));
+
+    server.run(0, "drop table people");
+    assert!(server.catalog.read().unwrap().index("by_name").is_none());
+    assert!(server.table(DEFAULT_TABLE).is_some());
+}
+
+#[test]
+fn vacuum_prunes_entries_of_reclaimed_versions() {
+    let server = Server::new("index-vacuum");
+    server.run(0, "create index by_city on default 1");
+    server.run(0, "begin");
+    server.run(0, "insert 1 alice,paris");
+    server.run(0, "insert 2 bob,paris");
+    server.run(0, "insert 3 carol,rome");
+    server.run(0, "commit");
+    server.run(0, "begin");
+    server.run(0, "update 1 alice,rome");
+    server.run(0, "update 2 bob,paris");
+    server.run(0, "delete 3");
+    server.run(0, "commit");
+    server.run(0, "retention transactions 1");
+    server.run(0, "begin");
+    server.run(0, "commit");
+
+    vacuum(Arc::clone(&server.catalog), Arc::clone(&server.services), Arc::clone(&server.txd_count), Arc::clone(&server.transaction)).unwrap();
+
+    // Key 2 is still in paris through its live version, key 1 left it and key 3 went altogether.
+    let index = server.catalog.read().unwrap().index("by_city").unwrap();
+    let entries = index.entries.read().unwrap();
+    assert_eq!(entries.keys().collect::<Vec<_>>(), vec!["paris", "rome"]);
+    assert_eq!(entries["paris"].iter().copied().collect::<Vec<_>>(), vec![2]);
+    assert_eq!(entries["rome"].iter().copied().collect::<Vec<_>>(), vec![1]);
+    drop(entries);
+
+    server.run(1, "begin");
+    assert_eq!(select_by(&server, 1, "by_city", "rome"), vec![(1, String::from("alice,rome"))]);
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use std::fs;
+use std::io::BufReader;
+use std::process::Command;
+
+use common::{Server, TempDir};
+use ASMT::catalog::tables::{Catalog, DEFAULT_TABLE};
+use ASMT::engine::services::Services;
+use ASMT::MVCC::versions::VersionStatus;
+use ASMT::storage::interchange::{read_records, write_record, Format, History, Record};
+
+fn awkward_records() -> Vec<Record> {
+    ["plain", "", "a,b", "say \"hi\"", "two\nlines", " padded ", "tab\there", "ünïcödé ✓", "back\\slash"]
+        .iter()
+        .enumerate()
+        .map(|(key, value)| Record { key: key as u32, value: value.to_string(), history: None })
+        .collect()
+}
+
+#[test]
+fn records_roundtrip_through_both_formats() {
+    let mut records = awkward_records();
+    records.push(Record { key: 100, value: String::from("old"), history: Some(History { xmin: 3, xmax: Some(7), version_status: VersionStatus::Commit }) });
+    records.push(Record { key: 100, value: String::from("new"), history: Some(History { xmin: 7, xmax: None, version_status: VersionStatus::Commit }) });
+
+    for format in [Format::Jsonl, Format::Csv] {
+        let (plain, history) = records.split_at(records.len() - 2);
+        for part in [plain, history] {
+            let mut out = Vec::new();
+            if format == Format::Csv {
+                let header = if part[0].history.is_some() { "key,value,xmin,xmax,version_status\n" } else { "key,value\n" };
+                out.extend_from_slice(header.as_bytes());
+            }
+            for record in part {
+                write_record(&mut out, format, record).unwrap();
+            }
+            let read = read_records(BufReader::new(out.as_slice()), format).unwrap();
+            assert_eq!(read, part, "{:?}: {}", format, String::from_utf8_lossy(&out));
+        }
+    }
+}
+
+#[test]
+fn malformed_input_is_rejected() {
+    let bad_json = "{\"key\":1,\"value\":\"a\"}\n{\"key\":\"x\",\"value\":\"b\"}\n";
+    assert!(read_records(BufReader::new(bad_json.as_bytes()), Format::Jsonl).is_err());
+    let bad_csv = "key,value\n1,\"unterminated\n";
+    assert!(read_records(BufReader::new(bad_csv.as_bytes()), Format::Csv).is_err());
+    let bad_status = "key,value,xmin,xmax,version_status\n1,a,2,,Maybe\n";
+    assert!(read_records(BufReader::new(bad_status.as_bytes()), Format::Csv).is_err());
+}
+
+#[test]
+fn export_reads_at_the_clients_snapshot() {
+    let server = Server::new("interchange-export");
+    server.run(0, "begin");
+    for k in 0..20 {
+        server.run(0, &format!("insert {} v{}", k, k));
+    }
+    server.run(0, "commit");
+
+    server.run(1, "begin");
+    server.run(0, "begin");
+    server.run(0, "update 5 changed");
+    server.run(0, "delete 6");
+    server.run(0, "commit");
+
+    let old = server.dir.path("old.jsonl");
+    server.run(1, &format!("export {} jsonl", old));
+    let records = read_records(BufReader::new(fs::File::open(&old).unwrap()), Format::Jsonl).unwrap();
+    assert_eq!(records.len(), 20);
+    assert_eq!(records[5].value, "v5");
+
+    // Without a transaction the export sees everything committed so far.
+    let latest = server.dir.path("latest.csv");
+    server.run(2, &format!("export {} csv", latest));
+    let records = read_records(BufReader::new(fs::File::open(&latest).unwrap()), Format::Csv).unwrap();
+    assert_eq!(records.len(), 19);
+    assert!(records.iter().any(|record| record.key == 5 && record.value == "changed"));
+
+    // History at the old snapshot leaves out the later update entirely.
+    let history = server.dir.path("history.jsonl");
+    server.run(1, &format!("export {} jsonl history", history));
+    let records = read_records(BufReader::new(fs::File::open(&history).unwrap()), Format::Jsonl).unwrap();
+    let key5: Vec<&Record> = records.iter().filter(|record| record.key == 5).collect();
+    assert_eq!(key5.len(), 1);
+    assert_eq!(key5[0].history.as_ref().unwrap().xmax, None);
+}
+
+#[test]
+fn history_import_restores_the_same_visible_state() {
+    let source = Server::new("interchange-source");
+    source.run(0, "begin");
+    for k in 0..50 {
+        source.run(0, &format!("insert {} v{}", k, k));
+    }
+    source.run(0, "commit");
+    source.run(0, "begin");
+    source.run(0, "update 3 three");
+    source.run(0, "delete 4");
+    source.run(0, "commit");
+
+    let path = source.dir.path("history.csv");
+    source.run(1, &format!("export {} csv history", path));
+
+    let target = Server::new("interchange-target");
+    target.run(0, "create table copy");
+    target.run(0, "use copy");
+    assert_eq!(target.run(0, &format!("import {} csv", path)), 0);
+    // The history is in the image before `import` returns, recovery no longer needs the file.
+    fs::remove_file(&path).unwrap();
+    assert!(!target.wal().lines().any(|line| line.starts_with("\"import")));
+    let image = Catalog::load(&target.dir.path("example.txt"), &Services::default()).unwrap();
+    assert!(image.table("copy").is_some_and(|table| !table.read().unwrap().input.is_empty()));
+    // New transactions get txids above the imported ones (the source committed txids 1 and 2).
+    assert!(*target.txd_count.read().unwrap() >= 2);
+
+    // A second history import would overwrite the table.
+    source.run(1, &format!("export {} csv history", path));
+    assert_eq!(target.run(0, &format!("import {} csv", path)), 1);
+
+    target.run(0, "begin");
+    source.run(2, "begin");
+    for k in 0..50 {
+        assert_eq!(target.select(0, "copy", k), source.select(2, DEFAULT_TABLE, k), "key {}", k);
+    }
+}
+
+#[test]
+fn plain_import_upserts_inside_the_transaction() {
+    let server = Server::new("interchange-plain");
+    let path = server.dir.path("values.jsonl");
+    fs::write(&path, "{\"key\":1,\"value\":\"a\"}\n{\"key\":2,\"value\":\"b\"}\n{\"key\":1,\"value\":\"c\"}\n").unwrap();
+
+    server.run(0, "begin");
+    server.run(0, "insert 2 old");
+    server.run(0, "commit");
+
+    server.run(0, "begin");
+    server.run(0, &format!("import {} jsonl", path));
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 2), Some(String::from("old")));
+    server.run(0, "abort");
+
+    server.run(0, "begin");
+    server.run(0, &format!("import {} jsonl", path));
+    server.run(0, "commit");
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 1), Some(String::from("c")));
+    assert_eq!(server.select(2, DEFAULT_TABLE, 2), Some(String::from("b")));
+
+    // The WAL holds the values themselves, so replay and followers never read the file.
+    let wal = server.wal();
+    assert!(wal.lines().any(|line| line == format!("\"mset 1 c 2 b {}\"", Server::addr(0))), "{}", wal);
+    assert!(!wal.contains("import"));
+
+    // A value a WAL record can't hold as one operand is refused before anything is written.
+    fs::write(&path, "{\"key\":3,\"value\":\"two words\"}\n").unwrap();
+    server.run(0, "begin");
+    server.run(0, &format!("import {} jsonl", path));
+    server.run(0, "commit");
+    assert_eq!(server.wal(), format!("{}\"begin {addr}\"\n\"commit {addr}\"\n", wal, addr = Server::addr(0)));
+}
+
+#[test]
+fn interchange_tool_exports_and_imports_checkpoint_images() {
+    let server = Server::new("interchange-tool");
+    server.run(0, "begin");
+    for k in 0..30 {
+        server.run(0, &format!("insert {} v{}", k, k));
+    }
+    server.run(0, "commit");
+    let image = server.dir.path("image");
+    server.catalog.read().unwrap().store(&image, &server.services).unwrap();
+
+    let tool = env!("CARGO_BIN_EXE_interchange");
+    let exported = Command::new(tool).args(["export", &image, DEFAULT_TABLE, "jsonl"]).output().unwrap();
+    assert!(exported.status.success(), "{}", String::from_utf8_lossy(&exported.stderr));
+    let input = server.dir.path("input.jsonl");
+    fs::write(&input, &exported.stdout).unwrap();
+
+    let copy = TempDir::new("interchange-tool-copy");
+    let copy_image = copy.path("image");
+    let imported = Command::new(tool).args(["import", &copy_image, "copy", &input, "jsonl"]).output().unwrap();
+    assert!(imported.status.success(), "{}", String::from_utf8_lossy(&imported.stderr));
+
+    common::init();
+    let catalog = Catalog::load(&copy_image, &Services::default()).unwrap();
+    let mut keys = Vec::new();
+    ASMT::btree::node::Node::for_each_item(&catalog.table("copy").unwrap(), &mut |item| keys.push((item.key, item.version.last().unwrap().value.clone())));
+    assert_eq!(keys, (0..30).map(|k| (k, format!("v{}", k))).collect::<Vec<_>>());
+
+    // Importing into the now non-empty table is refused.
+    let again = Command::new(tool).args(["import", &copy_image, "copy", &input, "jsonl"]).output().unwrap();
+    assert_eq!(again.status.code(), Some(1));
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::{run, Op};
+
+/// Every order of `a` and `b` that keeps each client's own operations in sequence.
+fn interleavings(a: &[Op], b: &[Op]) -> Vec<Vec<Op>> {
+    if a.is_empty() {
+        return vec![b.to_vec()];
+    }
+    if b.is_empty() {
+        return vec![a.to_vec()];
+    }
+
+    let mut all = Vec::new();
+    for mut rest in interleavings(&a[1..], b) {
+        rest.insert(0, a[0].clone());
+        all.push(rest);
+    }
+    for mut rest in interleavings(a, &b[1..]) {
+        rest.insert(0, b[0].clone());
+        all.push(rest);
+    }
+    all
+}
+
+/// Runs every interleaving of the two clients after `setup`, which runs alone on client 2, through `cli` one statement at
+/// a time. Races inside a statement, between the latches of the tree, are explored by `latch_schedules.rs`.
+fn check_all(setup: &[Op], a: &[Op], b: &[Op]) {
+    for schedule in interleavings(a, b) {
+        let ops: Vec<Op> = setup.iter().cloned().chain(schedule).collect();
+        if let Err(e) = run(&ops) {
+            panic!("{}\nschedule: {:#?}", e, ops);
+        }
+    }
+}
+
+fn seed(key: u32, value: &str) -> Vec<Op> {
+    vec![Op::Begin(2), Op::Write(2, key, value.to_string()), Op::Commit(2)]
+}
+
+#[test]
+fn lost_update_is_prevented() {
+    let a = [Op::Begin(0), Op::Select(0, 1), Op::Write(0, 1, "a".into()), Op::Commit(0), Op::Begin(0), Op::Select(0, 1), Op::Commit(0)];
+    let b = [Op::Begin(1), Op::Select(1, 1), Op::Write(1, 1, "b".into()), Op::Commit(1)];
+    check_all(&seed(1, "x"), &a, &b);
+}
+
+#[test]
+fn abort_restores_previous_version() {
+    let a = [Op::Begin(0), Op::Write(0, 1, "a".into()), Op::Delete(0, 1), Op::Abort(0)];
+    let b = [Op::Begin(1), Op::Select(1, 1), Op::Write(1, 1, "b".into()), Op::Select(1, 1), Op::Commit(1)];
+    check_all(&seed(1, "x"), &a, &b);
+}
+
+#[test]
+fn delete_and_reinsert_race() {
+    let a = [Op::Begin(0), Op::Delete(0, 1), Op::Insert(0, 1, "a".into()), Op::Commit(0)];
+    let b = [Op::Begin(1), Op::Insert(1, 1, "b".into()), Op::Select(1, 1), Op::Delete(1, 1), Op::Commit(1)];
+    check_all(&seed(1, "x"), &a, &b);
+}
+
+#[test]
+fn snapshot_reads_are_repeatable() {
+    let a = [Op::Begin(0), Op::Select(0, 1), Op::Select(0, 2), Op::Select(0, 1), Op::Select(0, 2), Op::Commit(0)];
+    let b = [Op::Begin(1), Op::Write(1, 1, "b".into()), Op::Delete(1, 2), Op::Commit(1)];
+    let mut setup = seed(1, "x");
+    setup.extend(seed(2, "y"));
+    check_all(&setup, &a, &b);
+}
+
+#[test]
+fn inserts_into_an_empty_tree_race() {
+    let a = [Op::Begin(0), Op::Insert(0, 1, "a".into()), Op::Insert(0, 2, "a".into()), Op::Commit(0)];
+    let b = [Op::Begin(1), Op::Insert(1, 2, "b".into()), Op::Insert(1, 1, "b".into()), Op::Abort(1)];
+    check_all(&[], &a, &b);
+}
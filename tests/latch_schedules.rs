@@ -0,0 +1,249 @@
+mod common;
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use ASMT::NODE_SIZE;
+use ASMT::btree::node::Node;
+use ASMT::MVCC::visibility::{commit_abort_handler, fetch_version_vec_for_key};
+
+/// Long enough for anything that isn't blocked to finish.
+const WAIT: Duration = Duration::from_secs(5);
+/// How long a step that must be blocked is watched for finishing anyway.
+const BLOCKED: Duration = Duration::from_millis(10);
+const KEYS: u32 = 40;
+
+/// What the first thread holds while it's parked, inside the callback of a latch crabbing walk.
+#[derive(Debug, Clone, Copy)]
+enum Hold {
+    /// [`Node::with_key_mut`] on this key: the node holding it is write-latched.
+    Write(u32),
+    /// [`Node::for_each_item`] at this key: the path from the root to the node holding it is read-latched.
+    Read(u32),
+}
+
+/// What the second thread runs while the first one is parked.
+#[derive(Debug, Clone, Copy)]
+enum Step {
+    Read(u32),
+    Update(u32),
+    Insert(u32),
+}
+
+/// A committed tree of the keys `10, 20, ..` three levels deep, built by inserting them in a scrambled order.
+fn tree() -> Arc<RwLock<Node>> {
+    common::init();
+    let root = Node::new();
+    for i in 0..KEYS {
+        let key = (i * 17 % KEYS + 1) * 10;
+        Node::insert(Arc::clone(&root), key, format!("v{}", key), 1).unwrap();
+        commit_abort_handler(Arc::clone(&root), key, 1, true);
+    }
+    root
+}
+
+/// The nodes from the root down to the one holding `key`, or to the leaf it would be inserted into.
+fn path(root: &Arc<RwLock<Node>>, key: u32) -> Vec<Arc<RwLock<Node>>> {
+    let mut path = vec![Arc::clone(root)];
+    loop {
+        let next = {
+            let node = path.last().unwrap().read().unwrap();
+            match node.locate(key) {
+                Err(i) if !node.children.is_empty() => Arc::clone(&node.children[i]),
+                _ => break,
+            }
+        };
+        path.push(next);
+    }
+    path
+}
+
+fn full(node: &Arc<RwLock<Node>>) -> bool {
+    node.read().unwrap().input.len() == *NODE_SIZE.get().unwrap()
+}
+
+/// Whether `step` has to wait for `hold`, by the latch protocol of `btree/latch.rs`.
+///
+/// - Readers and writers crab down with read latches, so they wait for a write-latched node on their way only.
+/// - A write only write-latches its target, which waits for any reader holding it.
+/// - An insert into a full leaf write-latches the root and the whole path, and growing the root write-latches every
+///   node while the ranks are corrected.
+fn blocked(root: &Arc<RwLock<Node>>, hold: Hold, step: Step) -> bool {
+    let (Step::Read(key) | Step::Update(key) | Step::Insert(key)) = step;
+    let on_path = |node: &Arc<RwLock<Node>>, path: &[Arc<RwLock<Node>>]| path.iter().any(|other| Arc::ptr_eq(node, other));
+    let step_path = path(root, key);
+    let target = step_path.last().unwrap();
+    match hold {
+        Hold::Write(held) => {
+            let held = Arc::clone(path(root, held).last().unwrap());
+            let grows_root = matches!(step, Step::Insert(_)) && step_path.iter().all(full);
+            on_path(&held, &step_path) || grows_root
+        }
+        Hold::Read(held) => match step {
+            Step::Read(_) => false,
+            Step::Update(_) => on_path(target, &path(root, held)),
+            Step::Insert(_) => on_path(target, &path(root, held)) || full(target),
+        },
+    }
+}
+
+/// Runs `f` on its own thread, returning a receiver that gets a message once it returned.
+fn spawn(f: impl FnOnce() + Send + 'static) -> mpsc::Receiver<()> {
+    let (done, finished) = mpsc::channel();
+    thread::spawn(move || {
+        f();
+        let _ = done.send(());
+    });
+    finished
+}
+
+/// Parks one thread holding `hold`, runs `step` on another, checks that it waits exactly when the latch protocol says
+/// it must, then lets both finish and checks the tree.
+fn run_schedule(hold: Hold, step: Step) {
+    let root = tree();
+    let expect_blocked = blocked(&root, hold, step);
+    let (parked_send, parked) = mpsc::channel();
+    let (release, released) = mpsc::channel::<()>();
+
+    let holder = {
+        let root = Arc::clone(&root);
+        spawn(move || {
+            let park = || {
+                parked_send.send(()).unwrap();
+                released.recv().unwrap();
+            };
+            match hold {
+                Hold::Write(key) => Node::with_key_mut(&root, key, |node, position| {
+                    park();
+                    node.input[position.unwrap()].version[0].value = String::from("held");
+                }),
+                Hold::Read(key) => Node::for_each_item(&root, &mut |item| if item.key == key { park() }),
+            }
+        })
+    };
+    parked.recv_timeout(WAIT).unwrap_or_else(|_| panic!("{:?} never parked", hold));
+
+    let stepper = {
+        let root = Arc::clone(&root);
+        spawn(move || match step {
+            Step::Read(key) => assert!(fetch_version_vec_for_key(root, key).is_some()),
+            Step::Update(key) => assert!(Node::find_and_update_key_version(root, key, Some(String::from("stepped")), 2, false).is_some()),
+            Step::Insert(key) => Node::insert(root, key, String::from("stepped"), 2).unwrap(),
+        })
+    };
+    let finished = stepper.recv_timeout(if expect_blocked { BLOCKED } else { WAIT });
+    match (expect_blocked, finished) {
+        (true, Err(RecvTimeoutError::Timeout)) | (false, Ok(())) => {}
+        (true, _) => panic!("{:?} went past {:?}", step, hold),
+        (false, _) => panic!("{:?} waited for {:?}", step, hold),
+    }
+
+    release.send(()).unwrap();
+    holder.recv_timeout(WAIT).unwrap_or_else(|_| panic!("{:?} never finished after {:?}", hold, step));
+    if expect_blocked {
+        stepper.recv_timeout(WAIT).unwrap_or_else(|_| panic!("{:?} never finished after {:?}", step, hold));
+    }
+
+    let report = Node::check(Arc::clone(&root));
+    assert!(report.is_clean(), "{:?} then {:?}: {}", hold, step, report.to_json_lines());
+    for key in (1..=KEYS).map(|i| i * 10) {
+        assert!(fetch_version_vec_for_key(Arc::clone(&root), key).is_some(), "{:?} then {:?}: key {} lost", hold, step, key);
+    }
+    let value = |key| fetch_version_vec_for_key(Arc::clone(&root), key).unwrap().last().unwrap().value.clone();
+    if let Hold::Write(key) = hold && !matches!(step, Step::Update(stepped) if stepped == key) {
+        assert_eq!(value(key), "held");
+    }
+    if let Step::Update(key) | Step::Insert(key) = step {
+        assert_eq!(value(key), "stepped");
+    }
+}
+
+/// Every key of the tree, and a new key next to each one.
+fn keys() -> impl Iterator<Item = (u32, u32)> {
+    (1..=KEYS).map(|i| (i * 10, i * 10 + 5))
+}
+
+/// One key of every node of the tree, so each node gets latched by the parked thread once.
+fn one_key_per_node() -> Vec<u32> {
+    let root = tree();
+    let mut nodes: Vec<Arc<RwLock<Node>>> = Vec::new();
+    let mut held = Vec::new();
+    for (key, _) in keys() {
+        let node = Arc::clone(path(&root, key).last().unwrap());
+        if !nodes.iter().any(|other| Arc::ptr_eq(&node, other)) {
+            nodes.push(node);
+            held.push(key);
+        }
+    }
+    held
+}
+
+#[test]
+fn writes_parked_on_a_node_only_hold_up_the_paths_through_it() {
+    for held in one_key_per_node() {
+        for (key, new) in keys() {
+            for step in [Step::Read(key), Step::Update(key), Step::Insert(new)] {
+                run_schedule(Hold::Write(held), step);
+            }
+        }
+    }
+}
+
+#[test]
+fn readers_parked_on_a_path_only_hold_up_writes_to_it() {
+    for held in one_key_per_node() {
+        for (key, new) in keys() {
+            for step in [Step::Read(key), Step::Update(key), Step::Insert(new)] {
+                run_schedule(Hold::Read(held), step);
+            }
+        }
+    }
+}
+
+/// A write that found its target in the root swaps its read latch for a write latch, and an insert growing the root can
+/// get in between. The write must then restart from the new root instead of writing to a node that lost the key.
+#[test]
+fn writes_restart_when_the_root_grows_under_them() {
+    common::init();
+    for round in 0..50 {
+        let root = Node::new();
+        for key in [10, 20, 30, 40] {
+            Node::insert(Arc::clone(&root), key, format!("v{}", key), 1).unwrap();
+            commit_abort_handler(Arc::clone(&root), key, 1, true);
+        }
+
+        // A parked reader keeps both writers waiting on the root, whichever gets it first.
+        let (parked_send, parked) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let reader = {
+            let root = Arc::clone(&root);
+            spawn(move || Node::for_each_item(&root, &mut |item| if item.key == 10 {
+                parked_send.send(()).unwrap();
+                released.recv().unwrap();
+            }))
+        };
+        parked.recv_timeout(WAIT).unwrap();
+
+        let update = {
+            let root = Arc::clone(&root);
+            spawn(move || assert!(Node::find_and_update_key_version(root, 10, Some(String::from("updated")), 2, false).is_some()))
+        };
+        let insert = {
+            let root = Arc::clone(&root);
+            spawn(move || Node::insert(root, 25, String::from("v25"), 3).unwrap())
+        };
+        thread::sleep(BLOCKED);
+        release.send(()).unwrap();
+        for finished in [reader, update, insert] {
+            finished.recv_timeout(WAIT).unwrap_or_else(|_| panic!("round {}: a thread never finished", round));
+        }
+
+        let report = Node::check(Arc::clone(&root));
+        assert!(report.is_clean(), "round {}: {}", round, report.to_json_lines());
+        assert_eq!(fetch_version_vec_for_key(Arc::clone(&root), 10).unwrap().last().unwrap().value, "updated");
+        assert!(fetch_version_vec_for_key(Arc::clone(&root), 25).is_some());
+    }
+}
+
@@ -0,0 +1,234 @@
+mod common;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use common::Server;
+use ASMT::catalog::tables::DEFAULT_TABLE;
+use ASMT::transactions::locks::{LockManager, LockMode, LockOutcome};
+use ASMT::transactions::transactions::TransactionStatus;
+
+const WAIT: Duration = Duration::from_secs(5);
+
+fn txd(server: &Server, client: usize) -> u64 {
+    *server.transaction.read().unwrap().ip_txd.get(&Server::addr(client)).unwrap()
+}
+
+fn locks(server: &Server) -> Arc<LockManager> {
+    Arc::clone(&server.services.locks)
+}
+
+fn status(server: &Server, client: usize) -> TransactionStatus {
+    server.transaction.read().unwrap().items[&txd(server, client)].status.clone()
+}
+
+#[test]
+fn shared_locks_are_compatible_and_exclusive_ones_wait() {
+    let locks = LockManager::default();
+    assert_eq!(locks.acquire(1, DEFAULT_TABLE, 7, LockMode::Shared, WAIT), LockOutcome::Granted);
+    assert_eq!(locks.acquire(2, DEFAULT_TABLE, 7, LockMode::Shared, WAIT), LockOutcome::Granted);
+    assert_eq!(locks.acquire(3, DEFAULT_TABLE, 7, LockMode::Exclusive, Duration::from_millis(50)), LockOutcome::TimedOut);
+    assert_eq!(locks.try_acquire_all(3, DEFAULT_TABLE, &[6, 7]), Err(7));
+    assert_eq!(locks.held(3), 0);
+
+    // Upgrading waits for the other shared holder only.
+    locks.release_all(2);
+    assert_eq!(locks.acquire(1, DEFAULT_TABLE, 7, LockMode::Exclusive, WAIT), LockOutcome::Granted);
+    assert_eq!(locks.mode(1, DEFAULT_TABLE, 7), Some(LockMode::Exclusive));
+    assert_eq!(locks.held(1), 1);
+}
+
+#[test]
+fn waiters_are_granted_once_the_holder_releases() {
+    let locks = Arc::new(LockManager::default());
+    assert_eq!(locks.acquire(1, DEFAULT_TABLE, 7, LockMode::Exclusive, WAIT), LockOutcome::Granted);
+
+    let waiter = {
+        let locks = Arc::clone(&locks);
+        thread::spawn(move || locks.acquire(2, DEFAULT_TABLE, 7, LockMode::Shared, WAIT))
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(!waiter.is_finished());
+    locks.release_all(1);
+    assert_eq!(waiter.join().unwrap(), LockOutcome::Granted);
+    assert_eq!(locks.mode(2, DEFAULT_TABLE, 7), Some(LockMode::Shared));
+}
+
+#[test]
+fn the_youngest_transaction_on_a_cycle_is_the_deadlock_victim() {
+    let locks = Arc::new(LockManager::default());
+    assert_eq!(locks.acquire(1, DEFAULT_TABLE, 1, LockMode::Exclusive, WAIT), LockOutcome::Granted);
+    assert_eq!(locks.acquire(2, DEFAULT_TABLE, 2, LockMode::Exclusive, WAIT), LockOutcome::Granted);
+
+    let older = {
+        let locks = Arc::clone(&locks);
+        thread::spawn(move || locks.acquire(1, DEFAULT_TABLE, 2, LockMode::Exclusive, WAIT))
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(locks.acquire(2, DEFAULT_TABLE, 1, LockMode::Exclusive, WAIT), LockOutcome::Deadlock);
+    locks.release_all(2);
+    assert_eq!(older.join().unwrap(), LockOutcome::Granted);
+
+    // A younger waiter already blocked is woken up as the victim.
+    assert_eq!(locks.acquire(3, DEFAULT_TABLE, 3, LockMode::Exclusive, WAIT), LockOutcome::Granted);
+    let younger = {
+        let locks = Arc::clone(&locks);
+        thread::spawn(move || locks.acquire(3, DEFAULT_TABLE, 2, LockMode::Exclusive, WAIT))
+    };
+    thread::sleep(Duration::from_millis(50));
+    let older = {
+        let locks = Arc::clone(&locks);
+        thread::spawn(move || locks.acquire(1, DEFAULT_TABLE, 3, LockMode::Exclusive, WAIT))
+    };
+    assert_eq!(younger.join().unwrap(), LockOutcome::Deadlock);
+    locks.release_all(3);
+    assert_eq!(older.join().unwrap(), LockOutcome::Granted);
+}
+
+#[test]
+fn tried_locks_dont_overtake_waiters() {
+    let locks = Arc::new(LockManager::default());
+    assert_eq!(locks.acquire(1, DEFAULT_TABLE, 7, LockMode::Exclusive, WAIT), LockOutcome::Granted);
+    let waiter = {
+        let locks = Arc::clone(&locks);
+        thread::spawn(move || locks.acquire(2, DEFAULT_TABLE, 7, LockMode::Exclusive, WAIT))
+    };
+    thread::sleep(Duration::from_millis(50));
+
+    // Whether the waiter woke up yet or not, the key is its next.
+    locks.release_all(1);
+    assert_eq!(locks.try_acquire_all(3, DEFAULT_TABLE, &[7]), Err(7));
+    assert_eq!(waiter.join().unwrap(), LockOutcome::Granted);
+    assert_eq!(locks.held(3), 0);
+}
+
+#[test]
+fn locked_keys_refuse_other_writers_until_the_holder_ends() {
+    let server = Server::new("locks-writers");
+    server.services.timeouts.write().unwrap().lock_wait = Duration::from_millis(50);
+    server.run(0, "begin");
+    server.run(0, "insert 1 before");
+    server.run(0, "commit");
+
+    server.run(0, "begin");
+    server.run(0, "select_for_update 1");
+    assert_eq!(locks(&server).mode(txd(&server, 0), DEFAULT_TABLE, 1), Some(LockMode::Exclusive));
+    server.run(1, "begin");
+    server.run(1, "update 1 refused");
+    server.run(1, "upsert 1 refused");
+    server.run(1, "commit");
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 1), Some(String::from("before")));
+
+    server.run(0, "commit");
+    assert_eq!(locks(&server).held(txd(&server, 0)), 0);
+    server.run(1, "begin");
+    server.run(1, "update 1 after");
+    server.run(1, "commit");
+    server.run(2, "commit");
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 1), Some(String::from("after")));
+}
+
+#[test]
+fn writes_lock_their_keys_and_lock_waits_time_out() {
+    let server = Server::new("locks-timeout");
+    server.services.timeouts.write().unwrap().lock_wait = Duration::from_millis(50);
+    server.run(0, "begin");
+    server.run(0, "insert 1 one");
+    assert_eq!(locks(&server).mode(txd(&server, 0), DEFAULT_TABLE, 1), Some(LockMode::Exclusive));
+
+    server.run(1, "begin");
+    server.run(1, "lock 1 shared");
+    assert_eq!(locks(&server).mode(txd(&server, 1), DEFAULT_TABLE, 1), None);
+    assert_eq!(status(&server, 1), TransactionStatus::Active);
+
+    server.run(0, "abort");
+    server.run(1, "lock 1 shared");
+    assert_eq!(locks(&server).mode(txd(&server, 1), DEFAULT_TABLE, 1), Some(LockMode::Shared));
+}
+
+#[test]
+fn writes_of_missing_keys_keep_no_lock() {
+    let server = Server::new("locks-missing");
+    server.services.timeouts.write().unwrap().lock_wait = Duration::from_millis(50);
+    server.run(0, "begin");
+    server.run(0, "update 1 one");
+    server.run(0, "delete 2");
+    assert_eq!(locks(&server).held(txd(&server, 0)), 0);
+    assert!(server.transaction.read().unwrap().items[&txd(&server, 0)].modified_keys.is_empty());
+
+    // Other writers get both keys right away.
+    server.run(1, "begin");
+    server.run(1, "insert 1 one");
+    server.run(1, "insert 2 two");
+    server.run(1, "commit");
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 1), Some(String::from("one")));
+    assert_eq!(server.select(2, DEFAULT_TABLE, 2), Some(String::from("two")));
+}
+
+#[test]
+fn deadlocks_abort_the_victims_transaction() {
+    let server = Arc::new(Server::new("locks-deadlock"));
+    server.run(0, "begin");
+    server.run(0, "lock 1");
+    server.run(1, "begin");
+    server.run(1, "lock 2");
+
+    let older = {
+        let server = Arc::clone(&server);
+        thread::spawn(move || server.run(0, "lock 2"))
+    };
+    thread::sleep(Duration::from_millis(50));
+    server.run(1, "lock 1");
+    older.join().unwrap();
+
+    assert_eq!(status(&server, 1), TransactionStatus::Aborted);
+    assert!(server.wal().lines().last().unwrap().starts_with("\"abort "));
+    assert_eq!(status(&server, 0), TransactionStatus::Active);
+    assert_eq!(locks(&server).mode(txd(&server, 0), DEFAULT_TABLE, 2), Some(LockMode::Exclusive));
+}
+
+#[test]
+fn rolling_back_to_a_savepoint_releases_the_locks_taken_since() {
+    let server = Server::new("locks-savepoint");
+    server.run(0, "begin");
+    server.run(0, "lock 1");
+    server.run(0, "savepoint s");
+    server.run(0, "insert 2 two");
+    server.run(0, "lock 3 shared");
+    server.run(0, "rollback to s");
+
+    let locks = locks(&server);
+    let x = txd(&server, 0);
+    assert_eq!(locks.mode(x, DEFAULT_TABLE, 1), Some(LockMode::Exclusive));
+    assert_eq!((locks.mode(x, DEFAULT_TABLE, 2), locks.mode(x, DEFAULT_TABLE, 3)), (None, None));
+}
+
+#[test]
+fn writes_wait_for_their_lock_and_can_be_deadlock_victims() {
+    let server = Arc::new(Server::new("locks-write-wait"));
+    server.run(0, "insert 1 one");
+    server.run(0, "insert 2 two");
+    server.run(0, "begin");
+    server.run(0, "lock 1");
+    server.run(1, "begin");
+    server.run(1, "lock 2");
+
+    let older = {
+        let server = Arc::clone(&server);
+        thread::spawn(move || server.run(0, "update 2 older"))
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(!older.is_finished());
+    server.run(1, "update 1 younger");
+    older.join().unwrap();
+
+    assert_eq!(status(&server, 1), TransactionStatus::Aborted);
+    server.run(0, "commit");
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 1), Some(String::from("one")));
+    assert_eq!(server.select(2, DEFAULT_TABLE, 2), Some(String::from("older")));
+}
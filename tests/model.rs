@@ -0,0 +1,42 @@
+mod common;
+
+use proptest::prelude::*;
+
+use common::{run, Op};
+
+fn op(clients: usize, keys: u32) -> impl Strategy<Value = Op> {
+    prop_oneof![
+        2 => (0..clients).prop_map(Op::Begin),
+        4 => (0..clients, 0..keys, "[a-z]{1,3}").prop_map(|(c, k, v)| Op::Write(c, k, v)),
+        2 => (0..clients, 0..keys, "[a-z]{1,3}").prop_map(|(c, k, v)| Op::Insert(c, k, v)),
+        2 => (0..clients, 0..keys).prop_map(|(c, k)| Op::Delete(c, k)),
+        3 => (0..clients, 0..keys).prop_map(|(c, k)| Op::Select(c, k)),
+        2 => (0..clients).prop_map(Op::Commit),
+        1 => (0..clients).prop_map(Op::Abort),
+    ]
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn single_client_matches_model(ops in prop::collection::vec(op(1, 16), 1..80)) {
+        if let Err(e) = run(&ops) {
+            prop_assert!(false, "{}", e);
+        }
+    }
+
+    #[test]
+    fn concurrent_clients_match_model(ops in prop::collection::vec(op(3, 8), 1..80)) {
+        if let Err(e) = run(&ops) {
+            prop_assert!(false, "{}", e);
+        }
+    }
+
+    #[test]
+    fn wide_key_space_keeps_tree_valid(ops in prop::collection::vec(op(2, 200), 1..120)) {
+        if let Err(e) = run(&ops) {
+            prop_assert!(false, "{}", e);
+        }
+    }
+}
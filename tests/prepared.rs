@@ -0,0 +1,208 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::Server;
+use ASMT::catalog::tables::{Catalog, DEFAULT_TABLE};
+use ASMT::engine::checkpoint::checkpoint;
+use ASMT::replication::failover::highest_txid;
+use ASMT::replication::follower::{Applied, Applier};
+use ASMT::storage::wal::recovery::recover_from_wal;
+use ASMT::transactions::locks::LockMode;
+use ASMT::transactions::prepared::{can_prepare, prepared_record, prepared_txd, resolvable, PreparedRecord, PreparedStatus};
+use ASMT::transactions::transactions::TransactionStatus;
+
+fn txd(server: &Server, client: usize) -> u64 {
+    *server.transaction.read().unwrap().ip_txd.get(&Server::addr(client)).unwrap()
+}
+
+fn prepared(server: &Server, gid: &str) -> Option<u64> {
+    prepared_txd(&server.transaction.read().unwrap(), gid)
+}
+
+/// A server restarted from the checkpoint image and the WAL `server` left, with its prepared transactions recovered.
+fn restart(server: &Server, image: &str, name: &str) -> Server {
+    let restarted = Server::new(name);
+    std::fs::copy(server.dir.path("WAL.txt"), restarted.dir.path("WAL.txt")).unwrap();
+    *restarted.catalog.write().unwrap() = Catalog::load(image, &restarted.services).unwrap();
+    {
+        let catalog = restarted.catalog.read().unwrap();
+        let reserved = restarted.services.txids.recover(&restarted.dir.path("WAL.txt")).unwrap();
+        *restarted.txd_count.write().unwrap() = reserved.max(highest_txid(&catalog, &restarted.transaction.read().unwrap()));
+    }
+    recover_from_wal(&restarted.dir.path("WAL.txt"), Arc::clone(&restarted.txd_count), Arc::clone(&restarted.transaction), Arc::clone(&restarted.file), Arc::clone(&restarted.catalog), Arc::clone(&restarted.services)).unwrap();
+    restarted
+}
+
+#[test]
+fn prepared_transactions_are_committed_from_any_client() {
+    let server = Server::new("prepared-commit");
+    server.run(0, "begin");
+    server.run(0, "insert 1 one");
+    server.run(0, "savepoint s");
+    server.run(0, "insert 2 two");
+    let x = txd(&server, 0);
+    server.run(0, "prepare g1");
+    assert_eq!(prepared(&server, "g1"), Some(x));
+    assert_eq!(server.transaction.read().unwrap().items.len(), 1);
+
+    // The client is free to go on, its prepared transaction isn't its own any more.
+    server.run(0, "begin");
+    assert_ne!(txd(&server, 0), x);
+    assert_eq!(server.select(0, DEFAULT_TABLE, 1), None);
+    server.run(0, "commit");
+
+    server.run(1, "commit prepared g1");
+    assert_eq!(server.transaction.read().unwrap().items[&x].status, TransactionStatus::Committed);
+    assert_eq!(prepared(&server, "g1"), None);
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 1), Some(String::from("one")));
+    assert_eq!(server.select(2, DEFAULT_TABLE, 2), Some(String::from("two")));
+
+    let wal = server.wal();
+    assert!(wal.contains(&format!("\"prepare g1 {}\"", Server::addr(0))));
+    assert!(wal.contains(&format!("\"commit prepared g1 {}\"", Server::addr(1))));
+}
+
+#[test]
+fn prepared_transactions_hold_their_write_intents_until_rolled_back() {
+    let server = Server::new("prepared-rollback");
+    server.services.timeouts.write().unwrap().lock_wait = Duration::from_millis(50);
+    server.run(0, "begin");
+    server.run(0, "insert 1 committed");
+    server.run(0, "commit");
+
+    server.run(0, "begin");
+    server.run(0, "update 1 prepared");
+    server.run(0, "insert 2 prepared");
+    server.run(0, "prepare g1");
+    let x = prepared(&server, "g1").unwrap();
+
+    server.run(1, "begin");
+    server.run(1, "update 1 refused");
+    server.run(1, "insert 2 refused");
+    server.run(1, "lock 1 shared");
+    let locks = Arc::clone(&server.services.locks);
+    assert_eq!(locks.mode(txd(&server, 1), DEFAULT_TABLE, 1), None);
+    assert_eq!(locks.mode(x, DEFAULT_TABLE, 2), Some(LockMode::Exclusive));
+    server.run(1, "abort");
+
+    server.run(2, "rollback prepared g1");
+    assert_eq!(locks.held(x), 0);
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("committed")));
+    assert_eq!(server.select(1, DEFAULT_TABLE, 2), None);
+    server.run(1, "update 1 after");
+    server.run(1, "commit");
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 1), Some(String::from("after")));
+}
+
+#[test]
+fn prepare_needs_a_transaction_and_a_free_gid() {
+    let server = Server::new("prepared-errors");
+    let (a, b) = (Server::addr(0), Server::addr(1));
+    assert_eq!(can_prepare(&server.transaction.read().unwrap(), &a, "g1"), PreparedStatus::NoTransaction);
+    server.run(0, "prepare g1");
+    assert!(!server.wal().contains("prepare"));
+
+    server.run(0, "begin");
+    server.run(0, "prepare g1");
+    server.run(0, "begin");
+    assert_eq!(can_prepare(&server.transaction.read().unwrap(), &a, "g1"), PreparedStatus::AlreadyPrepared(String::from("g1")));
+    assert_eq!(resolvable(&server.transaction.read().unwrap(), &a, "g1"), Err(PreparedStatus::InTransaction));
+    assert_eq!(resolvable(&server.transaction.read().unwrap(), &b, "g2"), Err(PreparedStatus::NotFound(String::from("g2"))));
+
+    // Refused commands aren't logged, the transaction stays prepared.
+    server.run(0, "commit prepared g1");
+    server.run(1, "rollback prepared g2");
+    assert_eq!(server.wal().matches("prepared g").count(), 0);
+    assert!(prepared(&server, "g1").is_some());
+}
+
+#[test]
+fn prepared_records_are_told_from_a_clients_own_commit() {
+    assert_eq!(prepared_record("prepare g1 127.0.0.1:1"), Some(PreparedRecord::Prepare(String::from("g1"))));
+    assert_eq!(prepared_record("COMMIT PREPARED g1 127.0.0.1:1"), Some(PreparedRecord::Commit(String::from("g1"))));
+    assert_eq!(prepared_record("rollback prepared g1 127.0.0.1:1"), Some(PreparedRecord::Rollback(String::from("g1"))));
+    assert_eq!(prepared_record("commit 127.0.0.1:1"), None);
+    assert_eq!(prepared_record("rollback to s 127.0.0.1:1"), None);
+}
+
+#[test]
+fn prepared_transactions_survive_checkpoints_and_restarts() {
+    let server = Server::new("prepared-restart");
+    server.run(0, "begin");
+    server.run(0, "insert 1 committed");
+    server.run(0, "commit");
+    server.run(0, "create table other");
+
+    server.run(0, "use other");
+    server.run(0, "begin");
+    server.run(0, "insert 1 before_checkpoint");
+    server.run(2, "begin");
+    server.run(2, "insert 5 never");
+
+    // The checkpoint records them as in progress and carries their records over.
+    let image = server.dir.path("example.txt");
+    checkpoint(Arc::clone(&server.catalog), Arc::clone(&server.services), Arc::clone(&server.transaction), &image, &server.dir.path("WAL.txt"), Arc::clone(&server.file));
+    server.run(0, "insert 2 after_checkpoint");
+    server.run(0, "prepare g1");
+    server.run(1, "begin");
+    server.run(1, "update 1 resolved");
+    server.run(1, "prepare g2");
+    server.run(4, "commit prepared g2");
+
+    let original = prepared(&server, "g1").unwrap();
+    let restarted = restart(&server, &image, "prepared-restart-after");
+    let x = prepared(&restarted, "g1").unwrap();
+    assert_eq!(prepared(&restarted, "g2"), None);
+    assert_eq!(x, original);
+    assert_eq!(restarted.transaction.read().unwrap().items[&x].status, TransactionStatus::Active);
+    assert!(!std::path::Path::new(&restarted.dir.path("WAL.txt.tmp")).exists());
+
+    // g2 was committed after the checkpoint, recovery replayed it.
+    restarted.run(4, "begin");
+    assert_eq!(restarted.select(4, DEFAULT_TABLE, 1), Some(String::from("resolved")));
+    restarted.run(4, "commit");
+
+    restarted.services.timeouts.write().unwrap().lock_wait = Duration::from_millis(50);
+    restarted.run(3, "begin");
+    restarted.run(3, "use other");
+    assert_eq!(restarted.select(3, "other", 1), None);
+    restarted.run(3, "insert 2 refused");
+    restarted.run(3, "commit");
+    restarted.run(4, "begin");
+    assert_eq!(restarted.select(4, DEFAULT_TABLE, 5), None);
+    restarted.run(4, "commit");
+
+    // Recovery logged it again, another restart still finds it prepared.
+    let image = restarted.dir.path("example.txt");
+    checkpoint(Arc::clone(&restarted.catalog), Arc::clone(&restarted.services), Arc::clone(&restarted.transaction), &image, &restarted.dir.path("WAL.txt"), Arc::clone(&restarted.file));
+    let again = restart(&restarted, &image, "prepared-restart-again");
+    assert_eq!(prepared(&again, "g1"), Some(original));
+    again.run(0, "commit prepared g1");
+    again.run(1, "begin");
+    assert_eq!(again.select(1, "other", 1), Some(String::from("before_checkpoint")));
+    assert_eq!(again.select(1, "other", 2), Some(String::from("after_checkpoint")));
+    assert_eq!(again.select(1, DEFAULT_TABLE, 1), Some(String::from("resolved")));
+}
+
+#[test]
+fn followers_prepare_and_resolve_like_their_primary() {
+    let replica = Server::new("prepared-follower");
+    let mut applier = Applier::new(Arc::clone(&replica.txd_count), Arc::clone(&replica.transaction), Arc::clone(&replica.file), Arc::clone(&replica.catalog), Arc::clone(&replica.services));
+    let (a, b) = (Server::addr(0), Server::addr(1));
+    assert_eq!(applier.apply(&format!("begin {}", a)).unwrap(), Applied::Buffered);
+    assert_eq!(applier.apply(&format!("insert 1 one {}", a)).unwrap(), Applied::Buffered);
+    assert_eq!(applier.apply(&format!("prepare g1 {}", a)).unwrap(), Applied::Replayed);
+    assert!(prepared(&replica, "g1").is_some());
+
+    replica.run(2, "begin");
+    assert_eq!(replica.select(2, DEFAULT_TABLE, 1), None);
+    replica.run(2, "commit");
+    assert_eq!(applier.apply(&format!("commit prepared g1 {}", b)).unwrap(), Applied::Replayed);
+    replica.run(2, "begin");
+    assert_eq!(replica.select(2, DEFAULT_TABLE, 1), Some(String::from("one")));
+}
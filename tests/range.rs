@@ -0,0 +1,94 @@
+mod common;
+
+use std::ops::{ControlFlow, RangeInclusive};
+
+use common::Server;
+use ASMT::btree::node::{Items, Node};
+use ASMT::catalog::tables::DEFAULT_TABLE;
+use ASMT::MVCC::range::{aggregate_in, Aggregate};
+
+#[test]
+fn range_walks_visit_only_their_keys_in_order() {
+    let server = Server::new("range-walk");
+    server.run(0, "begin");
+    for key in (1..=200).rev() {
+        server.run(0, &format!("insert {} v{}", key, key));
+    }
+    server.run(0, "commit");
+    let node = server.table(DEFAULT_TABLE).unwrap();
+
+    let mut keys = Vec::new();
+    let flow = Node::for_each_item_in(&node, &(50..=60), &mut |item: &Items| {
+        keys.push(item.key);
+        ControlFlow::Continue(())
+    });
+    assert!(flow.is_continue());
+    assert_eq!(keys, (50..=60).collect::<Vec<u32>>());
+
+    keys.clear();
+    let flow = Node::for_each_item_in(&node, &(0..=u32::MAX), &mut |item: &Items| {
+        keys.push(item.key);
+        if keys.len() == 3 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    });
+    assert!(flow.is_break());
+    assert_eq!(keys, vec![1, 2, 3]);
+}
+
+#[test]
+fn queries_see_only_visible_versions() {
+    let server = Server::new("range-visibility");
+    server.run(0, "begin");
+    for key in [1, 12, 120, 125, 13, 1299, 200] {
+        server.run(0, &format!("insert {} v{}", key, key));
+    }
+    server.run(0, "commit");
+
+    server.run(1, "begin");
+    server.run(1, "delete 120");
+    server.run(1, "insert 121 mine");
+    server.run(1, "update 12 twelve");
+    // Neither committed yet nor in client 1's snapshot.
+    server.run(2, "begin");
+    server.run(2, "insert 122 theirs");
+    server.run(2, "delete 125");
+
+    let node = server.table(DEFAULT_TABLE).unwrap();
+    let txd = *server.transaction.read().unwrap().ip_txd.get(&Server::addr(1)).unwrap();
+    let aggregate = |aggregate, ranges: &[RangeInclusive<u32>]| aggregate_in(&node, ranges, aggregate, txd, &server.transaction);
+    assert_eq!(aggregate(Aggregate::Count, &[0..=u32::MAX]), Some(7));
+    assert_eq!(aggregate(Aggregate::Count, &[100..=199]), Some(2));
+    assert_eq!(aggregate(Aggregate::Min, &[100..=199]), Some(121));
+    assert_eq!(aggregate(Aggregate::Max, &[100..=199]), Some(125));
+    assert_eq!(aggregate(Aggregate::Max, &[300..=400]), None);
+    assert_eq!(aggregate(Aggregate::Count, &[300..=400]), Some(0));
+}
+
+#[test]
+fn commands_reply_with_keys_and_aggregates() {
+    let server = Server::new("range-commands");
+    server.run(0, "begin");
+    for key in [5, 12, 120, 300] {
+        server.run(0, &format!("insert {} v{}", key, key));
+    }
+    server.run(0, "commit");
+    let wal = server.wal();
+
+    // Outside a transaction each runs in a read only one of its own.
+    assert_eq!(server.reply(1, "count 10 300"), "Count: 3");
+    assert_eq!(server.reply(1, "aggregate count"), "Count: 4");
+    assert_eq!(server.reply(1, "aggregate min 6 1000"), "Min: 12");
+    assert_eq!(server.reply(1, "aggregate max 0 299"), "Max: 120");
+    assert_eq!(server.reply(1, "aggregate max 301 1000"), "Key not found");
+    assert_eq!(server.wal(), wal);
+
+    server.run(1, "begin");
+    server.run(1, "delete 300");
+    assert_eq!(server.reply(1, "aggregate max"), "Max: 120");
+    assert_eq!(server.reply(2, "aggregate max"), "Max: 300");
+    server.run(1, "abort");
+
+    assert_eq!(server.reply(1, "aggregate max 1*"), "Invalid argument");
+    assert_eq!(server.reply(1, "count 10"), "Invalid argument");
+    assert_eq!(server.reply(1, "aggregate sum"), "Invalid argument");
+    assert_eq!(server.reply(1, "aggregate min 1 2 3"), "Invalid argument");
+}
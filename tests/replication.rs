@@ -0,0 +1,293 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{Server, TempDir};
+use ASMT::WAL_SHIPPER;
+use ASMT::catalog::tables::Catalog;
+use ASMT::engine::services::Services;
+use ASMT::replication::bootstrap::Bootstrap;
+use ASMT::replication::follower::{Applied, Applier};
+use ASMT::replication::role::Role;
+use ASMT::replication::shipper::{ShipError, Shipper, RETAINED_RECORDS};
+
+/// Every server of this process ships to the same [`WAL_SHIPPER`], tests reading it take turns.
+static SHIPPING: Mutex<()> = Mutex::new(());
+
+fn shipping() -> MutexGuard<'static, ()> {
+    SHIPPING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn commit(server: &Server, client: usize, commands: &[&str]) {
+    server.run(client, "begin");
+    for command in commands {
+        server.run(client, command);
+    }
+    server.run(client, "commit");
+}
+
+fn follower(name: &str) -> Server {
+    let server = Server::new(name);
+    server.services.replication.set_role(Role::Follower(String::from("127.0.0.1:1")));
+    server
+}
+
+fn applier(server: &Server) -> Applier {
+    Applier::new(Arc::clone(&server.txd_count), Arc::clone(&server.transaction), Arc::clone(&server.file), Arc::clone(&server.catalog), Arc::clone(&server.services))
+}
+
+#[test]
+fn shipped_records_are_numbered_and_followers_lag_until_they_ack() {
+    let shipper = Shipper::default();
+    for record in ["begin a", "insert 1 x a", "commit a"] {
+        shipper.ship(String::from(record));
+    }
+    assert_eq!(shipper.next_seq(), 4);
+    assert_eq!(shipper.read_from(2, Duration::ZERO).unwrap(), vec![(2, String::from("insert 1 x a")), (3, String::from("commit a"))]);
+    assert_eq!(shipper.read_from(4, Duration::from_millis(10)).unwrap(), Vec::new());
+
+    let id = shipper.attach("127.0.0.1:7000".parse().unwrap(), 2);
+    shipper.sent(id, 3);
+    let lag = &shipper.lag()[0];
+    assert_eq!((lag.sent, lag.applied, lag.records), (3, 1, 2));
+
+    shipper.acknowledge(id, 3);
+    let lag = &shipper.lag()[0];
+    assert_eq!((lag.applied, lag.records, lag.behind), (3, 0, Duration::ZERO));
+    assert!(lag.message().ends_with("0 records behind (0 ms)"));
+
+    shipper.detach(id);
+    assert!(shipper.lag().is_empty());
+
+    for _ in 0..RETAINED_RECORDS {
+        shipper.ship(String::from("filler"));
+    }
+    assert_eq!(shipper.read_from(1, Duration::ZERO), Err(ShipError::Truncated { oldest: 4 }));
+}
+
+#[test]
+fn followers_apply_committed_transactions_only() {
+    let _shipping = shipping();
+    let primary = Server::new("replication-primary");
+    commit(&primary, 0, &["insert 1 a", "insert 2 b"]);
+    primary.run(0, "create table other");
+    primary.run(0, "create index by_value on default");
+    primary.run(1, "begin");
+    primary.run(1, "insert 3 c");
+
+    let bootstrap = Bootstrap::capture(&WAL_SHIPPER, &primary.txd_count, &primary.transaction, &primary.catalog, &primary.services).unwrap();
+    let client = Server::addr(1);
+    assert_eq!(bootstrap.pending, vec![format!("begin {}", client), format!("insert 3 c {}", client)]);
+    let keys: Vec<(&str, Vec<u32>)> = bootstrap.tables.iter().map(|(name, records)| (name.as_str(), records.iter().map(|record| record.key).collect())).collect();
+    assert_eq!(keys, vec![("default", vec![1, 2]), ("other", vec![])]);
+
+    let mut wire = Vec::new();
+    bootstrap.write_to(&mut wire).unwrap();
+    assert_eq!(Bootstrap::read_from(&mut wire.as_slice()).unwrap(), bootstrap);
+
+    // Shipped after the bootstrap: the open transaction commits, another one aborts.
+    primary.run(1, "commit");
+    commit(&primary, 0, &["update 2 b2"]);
+    primary.run(2, "begin");
+    primary.run(2, "insert 4 never");
+    primary.run(2, "abort");
+    let shipped = WAL_SHIPPER.read_from(bootstrap.seq, Duration::ZERO).unwrap();
+
+    let replica = follower("replication-follower");
+    let mut applier = applier(&replica);
+    assert_eq!(applier.install(bootstrap).unwrap(), Applied::Replayed);
+    replica.run(5, "begin");
+    assert_eq!(replica.select(5, "default", 1).as_deref(), Some("a"));
+    assert_eq!(replica.select(5, "default", 3), None);
+    replica.run(5, "commit");
+
+    let applied: Vec<Applied> = shipped.iter().map(|(_, record)| applier.apply(record).unwrap()).collect();
+    assert_eq!(applied.iter().filter(|applied| **applied == Applied::Replayed).count(), 2);
+    assert_eq!(applied.last(), Some(&Applied::Dropped));
+
+    replica.run(5, "begin");
+    assert_eq!(replica.select(5, "default", 2).as_deref(), Some("b2"));
+    assert_eq!(replica.select(5, "default", 3).as_deref(), Some("c"));
+    assert_eq!(replica.select(5, "default", 4), None);
+    replica.run(5, "commit");
+    assert!(replica.table("other").is_some());
+    assert!(replica.catalog.read().unwrap().index("by_value").is_some());
+}
+
+#[test]
+fn followers_refuse_writes_from_their_clients() {
+    let _shipping = shipping();
+    let replica = follower("replication-read-only");
+    assert_eq!(replica.run(0, "begin"), 0);
+    assert_eq!(replica.run(0, "insert 1 x"), 1);
+    assert_eq!(replica.run(0, "create table nope"), 1);
+    assert_eq!(replica.run(0, "retention versions 2"), 1);
+    assert_eq!(replica.run(0, "retention"), 1);
+    assert_eq!(replica.select(0, "default", 1), None);
+    assert!(replica.table("nope").is_none());
+    assert_eq!(replica.run(0, "commit"), 0);
+    assert!(!replica.wal().contains("insert"));
+}
+
+#[test]
+fn promotion_resumes_txids_and_takes_writes() {
+    let _shipping = shipping();
+    let primary = Server::new("replication-promote-primary");
+    for key in 1..=3 {
+        commit(&primary, 0, &[&format!("insert {} v{}", key, key)]);
+    }
+    let bootstrap = Bootstrap::capture(&WAL_SHIPPER, &primary.txd_count, &primary.transaction, &primary.catalog, &primary.services).unwrap();
+
+    let replica = follower("replication-promote-follower");
+    applier(&replica).install(bootstrap).unwrap();
+    // As if the follower restarted since, its txid counter starts over while its tables don't.
+    *replica.txd_count.write().unwrap() = 0;
+    assert_eq!(replica.run(0, "insert 4 early"), 1);
+
+    assert_eq!(replica.run(0, "promote"), 3);
+    let replication = Arc::clone(&replica.services.replication);
+    assert_eq!((replication.role(), replication.epoch()), (Role::Primary, 2));
+    assert_eq!(replica.run(0, "promote"), 1);
+
+    commit(&replica, 0, &["insert 4 promoted"]);
+    assert_eq!(replica.transaction.read().unwrap().ip_txd[&Server::addr(0)], 2);
+    replica.run(1, "begin");
+    assert_eq!(replica.select(1, "default", 3).as_deref(), Some("v3"));
+    assert_eq!(replica.select(1, "default", 4).as_deref(), Some("promoted"));
+    replica.run(1, "commit");
+}
+
+#[test]
+fn fenced_primaries_refuse_writes_across_restarts() {
+    let _shipping = shipping();
+    let server = Server::new("replication-fenced");
+    commit(&server, 0, &["insert 1 before"]);
+    assert_eq!(server.run(0, "fence 1"), 1);
+    assert_eq!(server.run(0, "fence 2"), 3);
+
+    assert_eq!(server.run(0, "begin"), 0);
+    assert_eq!(server.run(0, "insert 2 after"), 1);
+    assert_eq!(server.select(0, "default", 1).as_deref(), Some("before"));
+    assert_eq!(server.select(0, "default", 2), None);
+    server.run(0, "commit");
+
+    let path = server.dir.path("example.txt");
+    server.catalog.read().unwrap().store(&path, &server.services).unwrap();
+    let reloaded = Services::default();
+    Catalog::load(&path, &reloaded).unwrap();
+    assert_eq!((reloaded.replication.role(), reloaded.replication.epoch()), (Role::Fenced(2), 1));
+}
+
+/// A server process, killed when dropped.
+struct Process(Child);
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn start(dir: &TempDir, port: u16, follow: Option<u16>) -> Process {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_ASMT"));
+    command.args(["--data", &dir.0.to_string_lossy(), "--port", &port.to_string()]);
+    if let Some(primary) = follow {
+        command.args(["--follow", &format!("127.0.0.1:{}", primary)]);
+    }
+    Process(command.stdout(Stdio::null()).stderr(Stdio::null()).spawn().unwrap())
+}
+
+/// Sends `commands` over one connection and returns everything the server answered.
+fn session(port: u16, commands: &[&str]) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut stream = loop {
+        match TcpStream::connect(("127.0.0.1", port)) {
+            Ok(stream) => break stream,
+            Err(e) => {
+                assert!(Instant::now() < deadline, "server on port {} never came up: {}", port, e);
+                thread::sleep(Duration::from_millis(20));
+            }
+        }
+    };
+    for command in commands.iter().chain(&["exit"]) {
+        writeln!(stream, "{}", command).unwrap();
+    }
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).unwrap();
+    answer
+}
+
+fn eventually(port: u16, commands: &[&str], expected: &str) -> String {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        let answer = session(port, commands);
+        if answer.contains(expected) {
+            return answer;
+        }
+        assert!(Instant::now() < deadline, "never got {:?}, last answer {:?}", expected, answer);
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn two_processes_replicate_over_tcp() {
+    let (primary_dir, follower_dir) = (TempDir::new("replication-primary-process"), TempDir::new("replication-follower-process"));
+    let (primary_port, follower_port) = (free_port(), free_port());
+    let _primary = start(&primary_dir, primary_port, None);
+    session(primary_port, &["begin", "insert 1 one", "insert 2 two", "commit"]);
+
+    let _follower = start(&follower_dir, follower_port, Some(primary_port));
+    eventually(follower_port, &["replication"], "(connected)");
+    eventually(follower_port, &["begin", "select 2", "commit"], "Value: \"two\"");
+
+    session(primary_port, &["begin", "update 1 uno", "commit"]);
+    eventually(follower_port, &["begin", "select 1", "commit"], "Value: \"uno\"");
+    assert!(session(follower_port, &["begin", "insert 3 three", "commit"]).contains("read-only follower"));
+
+    let status = eventually(primary_port, &["replication"], " 0 records behind");
+    assert!(status.contains("Role: primary at epoch 1"));
+}
+
+#[test]
+fn promoted_followers_fence_their_old_primary() {
+    let (primary_dir, follower_dir) = (TempDir::new("replication-failover-primary"), TempDir::new("replication-failover-follower"));
+    let (primary_port, follower_port) = (free_port(), free_port());
+    let primary = start(&primary_dir, primary_port, None);
+    session(primary_port, &["begin", "insert 1 one", "commit"]);
+    let _follower = start(&follower_dir, follower_port, Some(primary_port));
+    eventually(follower_port, &["begin", "select 1", "commit"], "Value: \"one\"");
+
+    let promoted = session(follower_port, &["promote"]);
+    assert!(promoted.contains("Promoted to primary at epoch 2"), "{}", promoted);
+    assert!(promoted.contains("Fenced by epoch 2"), "{}", promoted);
+    assert!(session(primary_port, &["begin", "insert 2 lost", "commit"]).contains("fenced by epoch 2"));
+    session(follower_port, &["begin", "insert 2 two", "commit"]);
+
+    // The fence is checkpointed, a restart doesn't lift it.
+    let catalog = primary_dir.path("example.txt.catalog");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !std::fs::read_to_string(&catalog).unwrap_or_default().contains("fenced 2") {
+        assert!(Instant::now() < deadline, "fence never checkpointed");
+        thread::sleep(Duration::from_millis(20));
+    }
+    drop(primary);
+    let restarted_port = free_port();
+    let restarted = start(&primary_dir, restarted_port, None);
+    assert!(session(restarted_port, &["begin", "insert 3 lost", "commit"]).contains("fenced by epoch 2"));
+    drop(restarted);
+
+    // Rejoining as a follower of the new primary catches up on what was written since.
+    let rejoined_port = free_port();
+    let _rejoined = start(&primary_dir, rejoined_port, Some(follower_port));
+    eventually(rejoined_port, &["begin", "select 2", "commit"], "Value: \"two\"");
+    eventually(rejoined_port, &["replication"], "at epoch 2");
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::Server;
+use ASMT::catalog::tables::DEFAULT_TABLE;
+use ASMT::MVCC::visibility::fetch_version_vec_for_key;
+use ASMT::transactions::locks::LockManager;
+use ASMT::transactions::savepoint::{release, savepoint, SavepointStatus};
+
+fn txd(server: &Server, client: usize) -> u64 {
+    *server.transaction.read().unwrap().ip_txd.get(&Server::addr(client)).unwrap()
+}
+
+/// The txids of the changes published so far.
+fn published_txids(server: &Server) -> Vec<u64> {
+    let changes = Arc::clone(&server.services.changes);
+    let mut subscription = changes.subscribe(Some(1)).unwrap();
+    let mut txids = Vec::new();
+    while let Some(event) = subscription.next_timeout(Duration::ZERO).unwrap() {
+        txids.push(event.txid);
+    }
+    txids
+}
+
+#[test]
+fn rolling_back_to_a_savepoint_undoes_only_later_writes() {
+    let server = Server::new("savepoint-rollback");
+    server.run(0, "begin");
+    server.run(0, "insert 1 kept");
+    server.run(0, "savepoint s");
+    server.run(0, "update 1 undone");
+    server.run(0, "insert 2 undone");
+    assert_eq!(server.select(0, DEFAULT_TABLE, 1), Some(String::from("undone")));
+
+    server.run(0, "rollback to s");
+    assert_eq!(server.select(0, DEFAULT_TABLE, 1), Some(String::from("kept")));
+    assert_eq!(server.select(0, DEFAULT_TABLE, 2), None);
+
+    // The savepoint stays set and can be rolled back to again.
+    server.run(0, "insert 3 undone");
+    server.run(0, "rollback to s");
+    assert_eq!(server.select(0, DEFAULT_TABLE, 3), None);
+    server.run(0, "commit");
+
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("kept")));
+    assert_eq!(server.select(1, DEFAULT_TABLE, 2), None);
+    assert_eq!(server.select(1, DEFAULT_TABLE, 3), None);
+}
+
+#[test]
+fn nested_savepoints_roll_back_and_release() {
+    let server = Server::new("savepoint-nested");
+    server.run(0, "begin");
+    server.run(0, "insert 1 a");
+    server.run(0, "savepoint outer");
+    server.run(0, "update 1 b");
+    server.run(0, "savepoint inner");
+    server.run(0, "update 1 c");
+    server.run(0, "insert 2 c");
+
+    server.run(0, "rollback to inner");
+    assert_eq!(server.select(0, DEFAULT_TABLE, 1), Some(String::from("b")));
+    assert_eq!(server.select(0, DEFAULT_TABLE, 2), None);
+
+    // Released writes belong to the enclosing savepoint and go with it.
+    server.run(0, "update 1 d");
+    server.run(0, "release inner");
+    assert_eq!(server.select(0, DEFAULT_TABLE, 1), Some(String::from("d")));
+    server.run(0, "rollback to outer");
+    assert_eq!(server.select(0, DEFAULT_TABLE, 1), Some(String::from("a")));
+
+    server.run(0, "update 1 e");
+    server.run(0, "release outer");
+    server.run(0, "commit");
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("e")));
+}
+
+#[test]
+fn committed_versions_carry_the_transactions_own_txid() {
+    let server = Server::new("savepoint-commit");
+    server.run(0, "begin");
+    server.run(0, "insert 1 before");
+    server.run(0, "savepoint s");
+    server.run(0, "update 1 after");
+    server.run(0, "insert 2 after");
+    let x = txd(&server, 0);
+    assert_eq!(server.transaction.read().unwrap().items.len(), 2);
+    server.run(0, "commit");
+
+    assert_eq!(server.transaction.read().unwrap().items.len(), 1);
+    let node = server.table(DEFAULT_TABLE).unwrap();
+    for key in [1, 2] {
+        let versions = fetch_version_vec_for_key(Arc::clone(&node), key).unwrap();
+        assert!(versions.iter().all(|version| version.xmin == x && version.xmax.is_none_or(|xmax| xmax == x)), "{:?}", versions);
+    }
+    assert_eq!(published_txids(&server), vec![x, x]);
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("after")));
+}
+
+#[test]
+fn savepoint_writes_stay_invisible_to_others_and_rolled_back_keys_are_free() {
+    let server = Server::new("savepoint-isolation");
+    server.run(0, "begin");
+    server.run(0, "insert 1 committed");
+    server.run(0, "commit");
+
+    server.run(0, "begin");
+    server.run(0, "savepoint s");
+    server.run(0, "update 1 uncommitted");
+    server.run(0, "insert 2 rolled_back");
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("committed")));
+
+    server.run(0, "rollback to s");
+    server.run(1, "insert 2 other");
+    server.run(1, "commit");
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 2), Some(String::from("other")));
+
+    server.run(0, "update 1 later");
+    server.run(0, "commit");
+    server.run(3, "begin");
+    assert_eq!(server.select(3, DEFAULT_TABLE, 1), Some(String::from("later")));
+    assert_eq!(server.select(2, DEFAULT_TABLE, 1), Some(String::from("committed")));
+}
+
+#[test]
+fn aborting_undoes_writes_made_under_savepoints() {
+    let server = Server::new("savepoint-abort");
+    server.run(0, "begin");
+    server.run(0, "insert 1 committed");
+    server.run(0, "commit");
+
+    server.run(0, "begin");
+    server.run(0, "update 1 a");
+    server.run(0, "savepoint s");
+    server.run(0, "update 1 b");
+    server.run(0, "insert 2 b");
+    server.run(0, "abort");
+
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("committed")));
+    assert_eq!(server.select(1, DEFAULT_TABLE, 2), None);
+    assert!(server.wal().contains(&format!("\"savepoint s {}\"", Server::addr(0))));
+}
+
+#[test]
+fn savepoints_need_an_active_transaction_and_a_known_name() {
+    let server = Server::new("savepoint-errors");
+    let addr = Server::addr(0);
+    let table = |_: &str| None;
+    assert_eq!(savepoint(&mut server.transaction.write().unwrap(), &addr, "s", 99, &LockManager::default()), SavepointStatus::NoTransaction);
+
+    server.run(0, "begin");
+    assert_eq!(release(&mut server.transaction.write().unwrap(), &addr, "missing", table), SavepointStatus::NotFound(String::from("missing")));
+    let count = *server.txd_count.read().unwrap();
+    server.run(0, "rollback to missing");
+    assert_eq!(*server.txd_count.read().unwrap(), count);
+    assert_eq!(server.transaction.read().unwrap().items.len(), 1);
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use common::Server;
+use ASMT::catalog::tables::DEFAULT_TABLE;
+use ASMT::engine::stream_processor::process_tcp_stream;
+use ASMT::transactions::timeout::{expired, Expiry, TransactionTimeouts};
+use ASMT::transactions::transactions::TransactionStatus;
+
+fn txd(server: &Server, client: usize) -> u64 {
+    *server.transaction.read().unwrap().ip_txd.get(&Server::addr(client)).unwrap()
+}
+
+fn status(server: &Server, txd: u64) -> TransactionStatus {
+    server.transaction.read().unwrap().items[&txd].status.clone()
+}
+
+/// Moves `client`'s transaction back in time: it began `age` ago and was last used `idle` ago.
+fn age(server: &Server, client: usize, age: Duration, idle: Duration) {
+    let txd = txd(server, client);
+    let now = Instant::now();
+    let mut tx = server.transaction.write().unwrap();
+    let lifetime = &mut tx.items.get_mut(&txd).unwrap().lifetime;
+    lifetime.began = now.checked_sub(age).unwrap();
+    lifetime.last_active = now.checked_sub(idle).unwrap();
+}
+
+fn expiry(server: &Server, client: usize) -> Option<Expiry> {
+    let timeouts = server.services.timeouts();
+    expired(&server.transaction.read().unwrap().items[&txd(server, client)], timeouts, Instant::now())
+}
+
+/// Serves a TCP client the way the server does, returning the client's end.
+fn connect(server: &Arc<Server>) -> (TcpStream, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (connection, _) = listener.accept().unwrap();
+
+    let server = Arc::clone(server);
+    let handle = thread::spawn(move || {
+        let (tx, _rx) = mpsc::channel();
+        let wal = server.dir.path("WAL.txt");
+        process_tcp_stream(connection, &wal, Arc::clone(&server.txd_count), Arc::clone(&server.transaction), Arc::clone(&server.file), Arc::clone(&server.catalog), Arc::clone(&server.services), tx).unwrap();
+    });
+    (client, handle)
+}
+
+/// The txid of the latest transaction, once one began after `after`.
+fn began_after(server: &Server, after: u64) -> u64 {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        if let Some(&txd) = server.transaction.read().unwrap().items.keys().max().filter(|&&txd| txd > after) {
+            return txd;
+        }
+        assert!(Instant::now() < deadline, "transaction never began");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn transactions_expire_when_idle_or_too_old() {
+    let server = Server::new("timeout-expired");
+    *server.services.timeouts.write().unwrap() = TransactionTimeouts { idle: Duration::from_secs(10), max_age: Duration::from_secs(60), ..TransactionTimeouts::default() };
+    server.run(0, "begin");
+    assert_eq!(expiry(&server, 0), None);
+
+    age(&server, 0, Duration::from_secs(11), Duration::from_secs(11));
+    assert_eq!(expiry(&server, 0), Some(Expiry::Idle(Duration::from_secs(10))));
+
+    age(&server, 0, Duration::from_secs(61), Duration::from_secs(1));
+    assert_eq!(expiry(&server, 0), Some(Expiry::Age(Duration::from_secs(60))));
+
+    server.run(0, "abort");
+    assert_eq!(expiry(&server, 0), None);
+}
+
+#[test]
+fn the_timeout_command_sets_the_transactions_own_timeouts() {
+    let server = Server::new("timeout-command");
+    *server.services.timeouts.write().unwrap() = TransactionTimeouts { idle: Duration::from_secs(10), max_age: Duration::from_secs(60), ..TransactionTimeouts::default() };
+    assert_eq!(server.run(0, "timeout idle 1"), 1);
+
+    server.run(0, "begin");
+    server.run(0, "timeout idle 1.5");
+    age(&server, 0, Duration::from_secs(2), Duration::from_secs(2));
+    assert_eq!(expiry(&server, 0), Some(Expiry::Idle(Duration::from_millis(1500))));
+
+    // The server's maximum age still applies to a longer total timeout.
+    server.run(0, "timeout idle 100");
+    server.run(0, "timeout total 600");
+    age(&server, 0, Duration::from_secs(61), Duration::from_secs(0));
+    assert_eq!(expiry(&server, 0), Some(Expiry::Age(Duration::from_secs(60))));
+
+    server.run(0, "timeout total 0");
+    server.run(0, "timeout idle none");
+    let lifetime = server.transaction.read().unwrap().items[&txd(&server, 0)].lifetime.clone();
+    assert_eq!((lifetime.idle, lifetime.total), (Some(Duration::from_secs(100)), Some(Duration::from_secs(600))));
+}
+
+#[test]
+fn dropped_connections_abort_their_transaction() {
+    let server = Arc::new(Server::new("timeout-disconnect"));
+    server.run(1, "begin");
+    server.run(1, "insert 1 committed");
+    server.run(1, "commit");
+
+    let (mut client, handle) = connect(&server);
+    client.write_all(b"begin\nupdate 1 uncommitted\n").unwrap();
+    let txd = began_after(&server, txd(&server, 1));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !server.wal().contains("update 1 uncommitted") {
+        assert!(Instant::now() < deadline, "update never ran");
+        thread::sleep(Duration::from_millis(10));
+    }
+    drop(client);
+    handle.join().unwrap();
+
+    assert_eq!(status(&server, txd), TransactionStatus::Aborted);
+    assert!(server.wal().lines().last().unwrap().starts_with("\"abort "));
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 1), Some(String::from("committed")));
+}
+
+#[test]
+fn idle_transactions_are_aborted_and_their_connection_closed() {
+    let server = Arc::new(Server::new("timeout-idle"));
+    server.services.timeouts.write().unwrap().idle = Duration::from_millis(300);
+
+    let (mut client, handle) = connect(&server);
+    client.write_all(b"begin\ninsert 1 one\n").unwrap();
+    let txd = began_after(&server, 0);
+
+    let mut lines = BufReader::new(client.try_clone().unwrap()).lines();
+    let message = lines.by_ref().map(|line| line.unwrap()).find(|line| line.starts_with("FAILED")).unwrap();
+    assert_eq!(message, format!("FAILED: transaction {} aborted, idle for more than 0.3s", txd));
+    assert!(lines.next().is_none());
+    handle.join().unwrap();
+
+    assert_eq!(status(&server, txd), TransactionStatus::Aborted);
+    server.run(0, "begin");
+    assert_eq!(server.select(0, DEFAULT_TABLE, 1), None);
+}
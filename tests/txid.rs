@@ -0,0 +1,129 @@
+mod common;
+
+use ASMT::catalog::tables::{Catalog, DEFAULT_TABLE};
+use ASMT::replication::failover::highest_txid;
+use ASMT::storage::wal::reader::get_uncommitted_transactions;
+use ASMT::transactions::manager::get_active_txd_snapshot;
+use ASMT::transactions::txid::{TxidReservation, TXID_BLOCK};
+use common::Server;
+
+/// Loads `server`'s checkpoint at `image` into a fresh server and picks the txid counter up the way startup does.
+fn restart(server: &Server, image: &str, name: &str) -> Server {
+    let restarted = Server::new(name);
+    std::fs::copy(server.dir.path("WAL.txt"), restarted.dir.path("WAL.txt")).unwrap();
+    *restarted.catalog.write().unwrap() = Catalog::load(image, &restarted.services).unwrap();
+
+    let catalog = restarted.catalog.read().unwrap();
+    let reserved = restarted.services.txids.recover(&restarted.dir.path("WAL.txt")).unwrap();
+    *restarted.txd_count.write().unwrap() = reserved.max(highest_txid(&catalog, &restarted.transaction.read().unwrap()));
+    drop(catalog);
+    restarted
+}
+
+#[test]
+fn txids_are_reserved_in_the_wal_a_block_at_a_time() {
+    let server = Server::new("txid-blocks");
+    server.run(0, "begin");
+    server.run(0, "commit");
+    server.run(1, "begin");
+    assert_eq!(server.wal().matches("txid").count(), 1);
+    assert!(server.wal().contains(&format!("\"txid {}\"", TXID_BLOCK)));
+
+    *server.txd_count.write().unwrap() = TXID_BLOCK;
+    server.run(0, "begin");
+    assert!(server.wal().contains(&format!("\"txid {}\"", 2 * TXID_BLOCK)));
+    assert_eq!(server.services.txids.reserved(), 2 * TXID_BLOCK);
+
+    let recovered = TxidReservation::default();
+    assert_eq!(recovered.recover(&server.dir.path("WAL.txt")).unwrap(), 2 * TXID_BLOCK);
+}
+
+#[test]
+fn restarts_carry_on_after_every_reserved_txid() {
+    let server = Server::new("txid-restart");
+    server.run(0, "begin");
+    server.run(0, "insert 1 before");
+    server.run(0, "commit");
+
+    let image = server.dir.path("example.txt");
+    server.catalog.read().unwrap().store(&image, &server.services).unwrap();
+    assert!(std::fs::read_to_string(format!("{}.catalog", image)).unwrap().contains(&format!("txid {}", TXID_BLOCK)));
+
+    let restarted = restart(&server, &image, "txid-restart-after");
+    restarted.run(0, "begin");
+    let txd = *restarted.transaction.read().unwrap().ip_txd.get(&Server::addr(0)).unwrap();
+    assert_eq!(txd, TXID_BLOCK + 1);
+    assert_eq!(restarted.select(0, DEFAULT_TABLE, 1), Some(String::from("before")));
+}
+
+#[test]
+fn checkpoints_roll_back_transactions_in_progress_on_load() {
+    let server = Server::new("txid-in-progress");
+    server.run(0, "begin");
+    server.run(0, "insert 1 one");
+    server.run(0, "insert 2 two");
+    server.run(0, "commit");
+    server.run(1, "begin");
+    server.run(1, "insert 3 three");
+    server.run(1, "update 1 changed");
+    server.run(1, "delete 2");
+
+    let image = server.dir.path("example.txt");
+    {
+        let catalog = server.catalog.read().unwrap();
+        let tx = server.transaction.read().unwrap();
+        catalog.store_with(&image, &server.services, &get_active_txd_snapshot(&tx)).unwrap();
+    }
+
+    let restarted = restart(&server, &image, "txid-in-progress-after");
+    restarted.run(0, "begin");
+    assert_eq!(restarted.select(0, DEFAULT_TABLE, 1), Some(String::from("one")));
+    assert_eq!(restarted.select(0, DEFAULT_TABLE, 2), Some(String::from("two")));
+    assert_eq!(restarted.select(0, DEFAULT_TABLE, 3), None);
+    assert!(restarted.table(DEFAULT_TABLE).unwrap().read().unwrap().dead > 0);
+}
+
+#[test]
+fn txids_past_32_bits_survive_a_checkpoint() {
+    let server = Server::new("txid-wide");
+    *server.txd_count.write().unwrap() = u64::from(u32::MAX);
+    server.run(0, "begin");
+    server.run(0, "insert 7 wide");
+    server.run(0, "commit");
+
+    let image = server.dir.path("example.txt");
+    server.catalog.read().unwrap().store(&image, &server.services).unwrap();
+    let restarted = restart(&server, &image, "txid-wide-after");
+    assert!(*restarted.txd_count.read().unwrap() > u64::from(u32::MAX));
+    restarted.run(0, "begin");
+    assert_eq!(restarted.select(0, DEFAULT_TABLE, 7), Some(String::from("wide")));
+}
+
+#[test]
+fn running_out_of_txids_is_an_error() {
+    let server = Server::new("txid-exhausted");
+    let mut txd_count = u64::MAX;
+    assert!(TxidReservation::default().allocate(&mut txd_count, &server.file).is_err());
+    assert_eq!(txd_count, u64::MAX);
+}
+
+#[test]
+fn truncating_the_wal_keeps_open_transactions_and_the_last_reservation() {
+    let server = Server::new("txid-truncate");
+    server.run(0, "begin");
+    server.run(0, "insert 1 one");
+    server.run(0, "commit");
+    server.run(1, "begin");
+    server.run(1, "insert 2 two");
+    *server.txd_count.write().unwrap() = TXID_BLOCK;
+    server.run(0, "begin");
+
+    let kept = get_uncommitted_transactions(&server.dir.path("WAL.txt")).unwrap();
+    let client = |n: usize| Server::addr(n).to_string();
+    assert_eq!(kept, vec![
+        format!("begin {}", client(1)),
+        format!("insert 2 two {}", client(1)),
+        format!("begin {}", client(0)),
+        format!("txid {}", 2 * TXID_BLOCK),
+    ]);
+}
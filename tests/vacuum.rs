@@ -0,0 +1,325 @@
+mod common;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use common::Server;
+use std::sync::RwLock;
+use ASMT::btree::node::{closed_versions, Items, Node};
+use ASMT::catalog::tables::{Catalog, DEFAULT_TABLE};
+use ASMT::engine::services::Services;
+use ASMT::engine::vacuum::{vacuum, vacuum_tick};
+use ASMT::MVCC::gc::{vacuum_step, GcStats, RetentionPolicy};
+use ASMT::MVCC::versions::{Version, VersionStatus};
+use ASMT::MVCC::visibility::fetch_version_vec_for_key;
+
+fn run_vacuum(server: &Server) -> Option<GcStats> {
+    vacuum(Arc::clone(&server.catalog), Arc::clone(&server.services), Arc::clone(&server.txd_count), Arc::clone(&server.transaction))
+}
+
+fn versions(server: &Server, key: u32) -> usize {
+    fetch_version_vec_for_key(server.table(DEFAULT_TABLE).unwrap(), key).map_or(0, |versions| versions.len())
+}
+
+fn assert_clean(server: &Server) {
+    let report = Node::check(server.table(DEFAULT_TABLE).unwrap());
+    assert!(report.is_clean(), "{}", report.to_json_lines());
+}
+
+/// txid 1 inserts keys 1..=3, txid 2 updates key 1, txid 3 deletes key 2.
+fn history(name: &str) -> Server {
+    let server = Server::new(name);
+    server.run(0, "begin");
+    for k in 1..=3 {
+        server.run(0, &format!("insert {} v{}", k, k));
+    }
+    server.run(0, "commit");
+    server.run(0, "begin");
+    server.run(0, "update 1 changed");
+    server.run(0, "commit");
+    server.run(0, "begin");
+    server.run(0, "delete 2");
+    server.run(0, "commit");
+    server
+}
+
+#[test]
+fn transaction_retention_reclaims_versions_and_removes_dead_keys() {
+    let server = history("vacuum-transactions");
+    server.run(0, "retention transactions 1");
+
+    // Next txid is 4, so only versions closed before txid 3 go.
+    assert_eq!(run_vacuum(&server), Some(GcStats { versions_reclaimed: 1, keys_removed: 0 }));
+    assert_eq!(versions(&server, 1), 1);
+    assert_eq!(versions(&server, 2), 1);
+
+    server.run(0, "begin");
+    server.run(0, "commit");
+    assert_eq!(run_vacuum(&server), Some(GcStats { versions_reclaimed: 1, keys_removed: 1 }));
+    assert_eq!(fetch_version_vec_for_key(server.table(DEFAULT_TABLE).unwrap(), 2), None);
+    assert_clean(&server);
+
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("changed")));
+    assert_eq!(server.select(1, DEFAULT_TABLE, 3), Some(String::from("v3")));
+    server.run(1, "insert 2 back");
+    server.run(1, "commit");
+
+    let state = Arc::clone(&server.services.vacuum);
+    let state = state.lock().unwrap();
+    assert_eq!((state.runs, state.totals.versions_reclaimed, state.totals.keys_removed), (2, 2, 1));
+}
+
+#[test]
+fn versions_visible_to_running_transactions_are_kept() {
+    let server = Server::new("vacuum-running");
+    server.run(0, "retention transactions 1");
+    server.run(0, "begin");
+    server.run(0, "insert 1 old");
+    server.run(0, "commit");
+
+    server.run(1, "begin");
+    server.run(0, "begin");
+    server.run(0, "update 1 new");
+    server.run(0, "commit");
+
+    assert_eq!(run_vacuum(&server), Some(GcStats::default()));
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("old")));
+
+    server.run(1, "commit");
+    server.run(0, "begin");
+    server.run(0, "commit");
+    assert_eq!(run_vacuum(&server).unwrap().versions_reclaimed, 1);
+}
+
+#[test]
+fn aborted_writes_are_kept_until_their_client_moves_on() {
+    let server = Server::new("vacuum-aborted");
+    server.run(0, "retention transactions 1");
+    server.run(0, "begin");
+    server.run(0, "insert 1 a");
+    server.run(0, "commit");
+    server.run(1, "begin");
+    server.run(1, "update 1 b");
+    server.run(1, "abort");
+
+    run_vacuum(&server);
+    server.run(2, "begin");
+    assert_eq!(server.select(2, DEFAULT_TABLE, 1), Some(String::from("a")));
+    assert_clean(&server);
+}
+
+#[test]
+fn last_versions_retention_keeps_the_newest_per_key() {
+    let server = Server::new("vacuum-last");
+    server.run(0, "retention versions 2");
+    for i in 0..6 {
+        server.run(0, "begin");
+        server.run(0, &format!("upsert 1 v{}", i));
+        server.run(0, "commit");
+    }
+    server.run(0, "begin");
+    server.run(0, "insert 2 x");
+    server.run(0, "delete 2");
+    server.run(0, "commit");
+
+    let stats = run_vacuum(&server).unwrap();
+    assert_eq!(versions(&server, 1), 2);
+    assert!(versions(&server, 2) <= 1);
+    assert_eq!(stats.keys_removed, 0);
+    server.run(1, "begin");
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("v5")));
+}
+
+#[test]
+fn keep_all_and_seconds_retention() {
+    let server = history("vacuum-keep-all");
+    server.run(0, "retention keep_all");
+    assert_eq!(run_vacuum(&server), None);
+    assert_eq!(versions(&server, 1), 2);
+
+    // Nothing is a second old yet, the first pass only takes a sample.
+    server.run(0, "retention seconds 1");
+    assert_eq!(run_vacuum(&server), Some(GcStats::default()));
+    thread::sleep(Duration::from_millis(1100));
+    assert_eq!(run_vacuum(&server), Some(GcStats { versions_reclaimed: 2, keys_removed: 1 }));
+}
+
+#[test]
+fn retention_is_persisted_and_rejects_bad_policies() {
+    let server = Server::new("vacuum-persist");
+    server.run(0, "retention seconds 60");
+    server.run(0, "retention forever");
+    server.run(0, "retention transactions 0");
+    assert_eq!(server.catalog.read().unwrap().retention, RetentionPolicy::Seconds(60));
+
+    let image = server.dir.path("image");
+    server.catalog.read().unwrap().store(&image, &server.services).unwrap();
+    assert_eq!(Catalog::load(&image, &Services::default()).unwrap().retention, RetentionPolicy::Seconds(60));
+}
+
+/// Removing keys rebuilds the tree while readers and writers keep working on it.
+#[test]
+fn rebuild_runs_alongside_readers_and_writers() {
+    let server = Server::new("vacuum-concurrent");
+    server.run(0, "retention transactions 1");
+    server.run(0, "begin");
+    for k in 0..400 {
+        server.run(0, &format!("insert {} v{}", k, k));
+    }
+    server.run(0, "commit");
+    server.run(0, "begin");
+    for k in (0..400).filter(|k| k % 2 == 1) {
+        server.run(0, &format!("delete {}", k));
+    }
+    server.run(0, "commit");
+    server.run(0, "begin");
+    server.run(0, "commit");
+
+    let root = server.table(DEFAULT_TABLE).unwrap();
+    let mut handles = Vec::new();
+    for w in 0..2u32 {
+        let root = Arc::clone(&root);
+        handles.push(thread::spawn(move || {
+            for i in 0..200 {
+                let key = 1000 + i * 2 + w;
+                Node::insert(Arc::clone(&root), key, String::from("w"), 100 + u64::from(w)).unwrap();
+            }
+        }));
+    }
+    for _ in 0..2 {
+        let root = Arc::clone(&root);
+        handles.push(thread::spawn(move || {
+            for _ in 0..5 {
+                for k in (0..400).step_by(2) {
+                    assert!(fetch_version_vec_for_key(Arc::clone(&root), k).is_some(), "key {} vanished", k);
+                }
+            }
+        }));
+    }
+
+    let stats = run_vacuum(&server).unwrap();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    assert_eq!(stats.keys_removed, 200);
+    assert_clean(&server);
+    for key in 1000..1400 {
+        assert!(fetch_version_vec_for_key(Arc::clone(&root), key).is_some(), "key {} lost", key);
+    }
+}
+
+/// Sums [`Node::dead`] and the versions actually closed over the whole tree.
+fn dead_counts(node: &Arc<RwLock<Node>>) -> (usize, usize) {
+    let node_read = node.read().unwrap();
+    let mut counts = (node_read.dead, closed_versions(&node_read.input));
+    for child in node_read.children.iter() {
+        let (dead, closed) = dead_counts(child);
+        counts.0 += dead;
+        counts.1 += closed;
+    }
+    counts
+}
+
+#[test]
+fn dead_counters_follow_commits_aborts_and_vacuum() {
+    let server = history("vacuum-counters");
+    server.run(1, "begin");
+    server.run(1, "update 3 gone");
+    server.run(1, "abort");
+
+    let root = server.table(DEFAULT_TABLE).unwrap();
+    // The update and delete closed one version each, the abort closed its own.
+    assert_eq!(dead_counts(&root), (3, 3));
+
+    server.run(0, "retention transactions 1");
+    server.run(1, "begin");
+    server.run(1, "commit");
+    while server.services.vacuum.lock().unwrap().ticks < 4 {
+        vacuum_tick(Arc::clone(&server.catalog), Arc::clone(&server.services), Arc::clone(&server.txd_count), Arc::clone(&server.transaction), 1).unwrap();
+    }
+    assert_eq!(dead_counts(&root), (0, 0));
+    assert_eq!(fetch_version_vec_for_key(Arc::clone(&root), 2), None);
+    assert_clean(&server);
+}
+
+fn closed_item(key: u32, dead_versions: u32) -> Items {
+    let mut version: Vec<Version> = (0..dead_versions)
+        .map(|i| Version { value: format!("old{}", i), xmin: 1, xmax: Some(2), version_status: VersionStatus::Commit })
+        .collect();
+    version.push(Version { value: String::from("live"), xmin: 2, xmax: None, version_status: VersionStatus::Commit });
+    Items { key, rank: 1, version }
+}
+
+#[test]
+fn steps_go_for_the_deadest_nodes_and_skip_busy_ones() {
+    common::init();
+    let items = (0..40).map(|key| closed_item(key, if key == 5 { 10 } else { 1 })).collect();
+    let root = Arc::new(RwLock::new(Node::bulk_load_items(items, 0.75)));
+
+    // Keys 4..=6 share the third node in key order, the one with key 5's ten dead versions.
+    let step = vacuum_step(&root, 0, 10, None, 1, &[]);
+    assert_eq!(step.reclaimed.versions_reclaimed, 12);
+    assert_eq!(step.next_key, Some(9));
+    assert_eq!(fetch_version_vec_for_key(Arc::clone(&root), 5).unwrap().len(), 1);
+
+    let mut busy = Arc::clone(&root);
+    loop {
+        let first_child = busy.read().unwrap().children.first().cloned();
+        match first_child {
+            Some(child) => busy = child,
+            None => break,
+        }
+    }
+    let busy_read = busy.read().unwrap();
+    let step = vacuum_step(&root, 0, 10, None, 1, &[]);
+    drop(busy_read);
+    assert_eq!(fetch_version_vec_for_key(Arc::clone(&root), 0).unwrap().len(), 2);
+    assert!(step.reclaimed.versions_reclaimed > 0);
+
+    // Each step only takes the deadest nodes of its window, the others are left for the next sweep.
+    let mut sweeps = 0;
+    while dead_counts(&root).0 > 0 {
+        let mut from_key = Some(0);
+        while let Some(key) = from_key {
+            from_key = vacuum_step(&root, key, 10, None, 2, &[]).next_key;
+        }
+        sweeps += 1;
+        assert!(sweeps <= 4);
+    }
+    assert!(sweeps > 1);
+    assert_eq!(dead_counts(&root), (0, 0));
+    assert!(Node::check(Arc::clone(&root)).is_clean());
+}
+
+#[test]
+fn versions_closed_by_a_transaction_in_a_running_snapshot_are_kept() {
+    let server = Server::new("vacuum-snapshot");
+    server.run(0, "begin");
+    server.run(0, "insert 1 v0");
+    server.run(0, "commit");
+    server.run(0, "retention transactions 1");
+
+    *server.txd_count.write().unwrap() = 14;
+    server.run(0, "begin");
+    *server.txd_count.write().unwrap() = 19;
+    server.run(1, "begin");
+    let txd = *server.transaction.read().unwrap().ip_txd.get(&Server::addr(1)).unwrap();
+    assert_eq!(txd, 20);
+    assert!(server.transaction.read().unwrap().items[&20].snapshot.contains(&15));
+
+    // T15 closes v0 and commits, leaving T20 the oldest running transaction.
+    server.run(0, "update 1 v1");
+    server.run(0, "commit");
+    run_vacuum(&server);
+
+    assert_eq!(server.services.vacuum.lock().unwrap().last_horizon, 15);
+    assert_eq!(server.select(1, DEFAULT_TABLE, 1), Some(String::from("v0")));
+    server.run(1, "commit");
+
+    run_vacuum(&server);
+    assert_eq!(versions(&server, 1), 1);
+    assert_clean(&server);
+}
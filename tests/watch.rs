@@ -0,0 +1,141 @@
+mod common;
+
+use std::io::{BufRead, BufReader};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use common::Server;
+use ASMT::catalog::tables::DEFAULT_TABLE;
+use ASMT::cli::cli::cli;
+use ASMT::engine::watch::{KeyPattern, Notification, Watch, Watches};
+
+fn watches(server: &Server) -> Arc<Watches> {
+    Arc::clone(&server.services.watches)
+}
+
+fn watch(server: &Server, pattern: &str) -> Watch {
+    watches(server).register(DEFAULT_TABLE, KeyPattern::parse(pattern).unwrap())
+}
+
+fn commit(server: &Server, client: usize, commands: &[&str]) {
+    server.run(client, "begin");
+    for command in commands {
+        server.run(client, command);
+    }
+    server.run(client, "commit");
+}
+
+/// Runs `command` for a TCP client in its own thread, returning the client's end and the session's status code.
+fn session(server: &Arc<Server>, command: &str) -> (BufReader<TcpStream>, JoinHandle<u8>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (connection, peer) = listener.accept().unwrap();
+
+    let server = Arc::clone(server);
+    let line = format!("{} {}", command, peer);
+    let handle = thread::spawn(move || {
+        cli(line, Arc::clone(&server.txd_count), Arc::clone(&server.transaction), Arc::clone(&server.file), Arc::clone(&server.catalog), Some(&connection), Arc::clone(&server.services)).unwrap()
+    });
+    (BufReader::new(client), handle)
+}
+
+fn wait_for_watchers(server: &Server, count: usize) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while watches(server).len() != count {
+        assert!(Instant::now() < deadline, "watchers never registered");
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn patterns_match_keys_and_key_ranges() {
+    assert_eq!(KeyPattern::parse("12"), Some(KeyPattern::Key(12)));
+    assert_eq!(KeyPattern::parse("10-19"), Some(KeyPattern::Range(10..=19)));
+    assert_eq!(KeyPattern::parse("19-10"), None);
+    assert_eq!(KeyPattern::parse("x-1"), None);
+    assert_eq!(KeyPattern::parse("12*"), None);
+    assert_eq!(KeyPattern::parse("-1"), None);
+
+    let range = KeyPattern::parse("10-19").unwrap();
+    assert!(range.matches(10) && range.matches(15) && range.matches(19));
+    assert!(!range.matches(9) && !range.matches(20) && !range.matches(100));
+    assert!(KeyPattern::parse("7-7").unwrap().matches(7));
+}
+
+#[test]
+fn commits_wake_watchers_with_the_new_value() {
+    let server = Server::new("watch-commit");
+    commit(&server, 0, &["insert 5 old"]);
+
+    let watch = watch(&server, "5");
+    server.run(1, "begin");
+    server.run(1, "update 5 new");
+    // Nothing is announced before the commit, nor for other keys.
+    commit(&server, 2, &["insert 6 other"]);
+    assert_eq!(watch.wait(Some(Duration::from_millis(20))), None);
+
+    server.run(1, "commit");
+    assert_eq!(watch.wait(Some(Duration::ZERO)), Some(Notification { txid: 2, key: 5, value: Some(String::from("new")) }));
+
+    commit(&server, 1, &["delete 5"]);
+    assert_eq!(watch.wait(Some(Duration::ZERO)).unwrap().message(), "5 deleted");
+}
+
+#[test]
+fn aborts_and_other_tables_are_ignored() {
+    let server = Server::new("watch-ignored");
+    let watch = watch(&server, "10-19");
+
+    server.run(0, "begin");
+    server.run(0, "insert 10 rolled_back");
+    server.run(0, "abort");
+
+    server.run(1, "create table other");
+    server.run(1, "use other");
+    commit(&server, 1, &["insert 11 elsewhere"]);
+    assert_eq!(watch.wait(Some(Duration::from_millis(20))), None);
+
+    server.run(0, "insert_if_absent 12 implicit");
+    assert_eq!(watch.wait(Some(Duration::ZERO)).unwrap().message(), "12 implicit");
+
+    drop(watch);
+    assert!(watches(&server).is_empty());
+}
+
+#[test]
+fn watch_blocks_until_another_client_commits() {
+    let server = Arc::new(Server::new("watch-tcp"));
+    let (mut client, handle) = session(&server, "watch 3");
+    wait_for_watchers(&server, 1);
+
+    commit(&server, 0, &["insert 3 arrived"]);
+    let mut line = String::new();
+    client.read_line(&mut line).unwrap();
+    assert_eq!(line.trim(), "3 arrived");
+    assert_eq!(handle.join().unwrap(), 0);
+    assert!(watches(&server).is_empty());
+}
+
+#[test]
+fn wait_times_out_and_disconnects_end_a_watch() {
+    let server = Arc::new(Server::new("watch-timeout"));
+    let started = Instant::now();
+    let (mut client, handle) = session(&server, "wait 3 100");
+    let mut line = String::new();
+    client.read_line(&mut line).unwrap();
+    assert_eq!(line.trim(), "TIMEOUT");
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(handle.join().unwrap(), 0);
+
+    let (client, handle) = session(&server, "watch 3");
+    wait_for_watchers(&server, 1);
+    client.get_ref().shutdown(Shutdown::Both).unwrap();
+    assert_eq!(handle.join().unwrap(), 2);
+    assert!(watches(&server).is_empty());
+
+    assert_eq!(server.run(0, "wait 3"), 1);
+    assert_eq!(server.run(0, "wait 3 soon"), 1);
+    assert_eq!(server.run(0, "watch x"), 1);
+}